@@ -0,0 +1,76 @@
+use bevy::prelude::*;
+use bevy_math::bounding::{Aabb3d, BoundingVolume};
+use bevy_panorbit_camera::{PanOrbitCamera, PanOrbitCameraPlugin};
+use bevy_render::primitives::Aabb;
+use rendering::marching_cubes::automaton::AutomatonStepping;
+use rendering::marching_cubes::display_stage::VoxeledRendered;
+use rendering::marching_cubes::{
+    AutomatonRule, CellularAutomaton, DensitySource, MarchingCubesPlugin, StepAutomaton,
+    VoxelVolume,
+};
+
+fn main() {
+    App::new()
+        .insert_resource(VoxelVolume {
+            aabb: Aabb3d {
+                min: Vec3A::ZERO,
+                max: Vec3A::splat(1.0),
+            },
+            voxel_size: 0.02,
+            isovalue: 0.5,
+            density: DensitySource::Automaton,
+            ..Default::default()
+        })
+        .insert_resource(CellularAutomaton {
+            rule: AutomatonRule::caves(),
+            stepping: AutomatonStepping::OnDemand,
+            ..Default::default()
+        })
+        .add_plugins((
+            DefaultPlugins,
+            PanOrbitCameraPlugin,
+            MarchingCubesPlugin::default(),
+        ))
+        .add_systems(Startup, setup)
+        .add_systems(Update, control)
+        .run();
+}
+
+fn setup(mut commands: Commands, voxel_volume: Res<VoxelVolume>) {
+    let center = voxel_volume.aabb.center();
+    commands.spawn((
+        PanOrbitCamera {
+            focus: center.into(),
+            ..Default::default()
+        },
+        Camera3d::default(),
+        Transform::from_xyz(1.5, 1.5, -1.5).looking_at(center.into(), Vec3::Y),
+    ));
+
+    commands.spawn((
+        Visibility::default(),
+        Transform::default(),
+        Aabb {
+            center,
+            half_extents: voxel_volume.aabb.half_size(),
+        },
+        VoxeledRendered,
+    ));
+}
+
+// space steps once, enter steps ten times, r restarts with a new seed
+fn control(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut automaton: ResMut<CellularAutomaton>,
+    mut steps: EventWriter<StepAutomaton>,
+) {
+    if keys.just_pressed(KeyCode::Space) {
+        steps.write(StepAutomaton(1));
+    }
+    if keys.just_pressed(KeyCode::Enter) {
+        steps.write(StepAutomaton(10));
+    }
+    if keys.just_pressed(KeyCode::KeyR) {
+        automaton.seed += 1;
+    }
+}
@@ -0,0 +1,89 @@
+use bevy::prelude::*;
+use bevy_math::bounding::{Aabb3d, BoundingVolume};
+use bevy_panorbit_camera::{PanOrbitCamera, PanOrbitCameraPlugin};
+use bevy_render::primitives::Aabb;
+use rendering::marching_cubes::display_stage::VoxeledRendered;
+use rendering::marching_cubes::{
+    DensitySource, GridFluid, GridFluidMode, GridFluidSettings, MarchingCubesPlugin, VoxelVolume,
+};
+
+fn main() {
+    App::new()
+        .insert_resource(VoxelVolume {
+            aabb: Aabb3d {
+                min: Vec3A::ZERO,
+                max: Vec3A::new(1.0, 0.6, 0.4),
+            },
+            voxel_size: 0.02,
+            isovalue: 0.0,
+            density: DensitySource::GridFluid,
+            ..Default::default()
+        })
+        .add_plugins((
+            DefaultPlugins,
+            PanOrbitCameraPlugin,
+            MarchingCubesPlugin::default(),
+        ))
+        .add_systems(Startup, setup)
+        .add_systems(Update, switch_mode)
+        .run();
+}
+
+fn setup(mut commands: Commands, voxel_volume: Res<VoxelVolume>) {
+    let center = voxel_volume.aabb.center();
+    commands.spawn((
+        PanOrbitCamera {
+            focus: center.into(),
+            ..Default::default()
+        },
+        Camera3d::default(),
+        Transform::from_xyz(0.5, 1.0, -1.5).looking_at(center.into(), Vec3::Y),
+    ));
+
+    commands.spawn((
+        Visibility::default(),
+        Transform::default(),
+        Aabb {
+            center,
+            half_extents: voxel_volume.aabb.half_size(),
+        },
+        VoxeledRendered,
+    ));
+
+    // dam break, a column of liquid collapsing into the empty side of the box
+    commands.spawn(GridFluid::new(
+        GridFluidSettings::default(),
+        [Aabb3d {
+            min: Vec3A::ZERO,
+            max: Vec3A::new(0.3, 0.45, 0.4),
+        }],
+    ));
+}
+
+// space switches between the liquid and a rising puff of smoke
+fn switch_mode(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut voxel_volume: ResMut<VoxelVolume>,
+    mut fluids: Query<&mut GridFluid>,
+) {
+    if !keys.just_pressed(KeyCode::Space) {
+        return;
+    }
+    for mut fluid in &mut fluids {
+        if fluid.settings.mode == GridFluidMode::Liquid {
+            fluid.settings.mode = GridFluidMode::Smoke;
+            fluid.regions = vec![Aabb3d {
+                min: Vec3A::new(0.4, 0.0, 0.1),
+                max: Vec3A::new(0.6, 0.15, 0.3),
+            }];
+            voxel_volume.isovalue = 0.2;
+        } else {
+            fluid.settings.mode = GridFluidMode::Liquid;
+            fluid.regions = vec![Aabb3d {
+                min: Vec3A::ZERO,
+                max: Vec3A::new(0.3, 0.45, 0.4),
+            }];
+            voxel_volume.isovalue = 0.0;
+        }
+    }
+}
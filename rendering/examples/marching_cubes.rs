@@ -5,10 +5,23 @@ use bevy_color::palettes::css;
 use bevy_math::bounding::BoundingVolume;
 use bevy_panorbit_camera::{PanOrbitCamera, PanOrbitCameraPlugin};
 use bevy_render::primitives::Aabb;
-use rendering::marching_cubes::display_stage::VoxeledRendered;
-use rendering::marching_cubes::{MarchingCubesPlugin, VoxelVolume};
+use rendering::edge_detection::{EdgeDetection, EdgeDetectionPlugin};
+use rendering::marching_cubes::display_stage::{
+    CaseHighlight, DensitySlice, VoxelDebug, VoxelRaymarched, VoxelShading, VoxeledRendered,
+};
+use rendering::marching_cubes::export::{ExportIsoSurface, ExportSource};
+use rendering::marching_cubes::{
+    ClipCap, ClipPlane, MarchingCubesPlugin, SurfaceExtraction, VoxelVolume,
+};
 
 fn main() {
+    // `cargo run --example marching_cubes -- --histo-pyramid`
+    let extraction = if std::env::args().any(|arg| arg == "--histo-pyramid") {
+        SurfaceExtraction::HistoPyramid
+    } else {
+        SurfaceExtraction::Scan
+    };
+
     App::new()
         .add_plugins((
             DefaultPlugins.set(WindowPlugin {
@@ -19,19 +32,30 @@ fn main() {
                 ..Default::default()
             }),
             PanOrbitCameraPlugin,
-            MarchingCubesPlugin,
+            MarchingCubesPlugin { extraction },
+            EdgeDetectionPlugin,
         ))
         .add_plugins((
             FrameTimeDiagnosticsPlugin::default(),
             LogDiagnosticsPlugin::default(),
         ))
         .add_systems(Startup, setup)
+        .add_systems(
+            Update,
+            (
+                export_on_key,
+                toggle_edge_detection,
+                toggle_debug_views,
+                toggle_raymarching,
+                cycle_clipping,
+            ),
+        )
         .run();
 }
 
 fn setup(
     mut commands: Commands,
-    voxel_volume: Res<VoxelVolume>,
+    mut voxel_volume: ResMut<VoxelVolume>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
@@ -39,9 +63,12 @@ fn setup(
         PanOrbitCamera::default(),
         Camera3d::default(),
         Transform::from_xyz(0.0, 2.0, -4.0).looking_at(Vec3::ZERO, Vec3::Y),
+        // edge detection needs Msaa::Off
+        Msaa::Off,
     ));
 
-    // Voxel volume
+    // Voxel volume, the torus tube pulses by 0.05 twice a second
+    voxel_volume.user_params[0] = Vec4::new(0.0, 0.0, 0.05, 4.0 * core::f32::consts::PI);
     commands.spawn((
         Visibility::default(),
         Transform::default(),
@@ -50,6 +77,8 @@ fn setup(
             half_extents: voxel_volume.aabb.half_size(),
         },
         VoxeledRendered,
+        VoxelShading::Smooth,
+        VoxelDebug::default(),
     ));
 
     // Plane
@@ -66,14 +95,109 @@ fn setup(
         Transform::from_xyz(2.0, 0.0, 2.0),
     ));
 
-    // Light
-    // commands.spawn((
-    //     PointLight {
-    //         shadows_enabled: true,
-    //         intensity: 10_000_000.,
-    //         range: 10.0,
-    //         ..default()
-    //     },
-    //     Transform::from_xyz(0.0, 8.0, 0.0),
-    // ));
+    // Light, the voxel surface casts its shadow onto the plane
+    commands.spawn((
+        DirectionalLight {
+            shadows_enabled: true,
+            ..default()
+        },
+        Transform::from_xyz(-2.0, 4.0, -1.0).looking_at(Vec3::ZERO, Vec3::Y),
+    ));
+}
+
+fn export_on_key(keyboard: Res<ButtonInput<KeyCode>>, mut exports: EventWriter<ExportIsoSurface>) {
+    if keyboard.just_pressed(KeyCode::KeyE) {
+        exports.write(ExportIsoSurface::new("isosurface.glb", ExportSource::Gpu));
+    }
+    if keyboard.just_pressed(KeyCode::KeyO) {
+        exports.write(ExportIsoSurface::new("isosurface.obj", ExportSource::Cpu));
+    }
+}
+
+// the voxel surface is drawn into the depth and normal prepass, so it gets outlined too
+fn toggle_edge_detection(
+    mut commands: Commands,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    cameras: Query<(Entity, Has<EdgeDetection>), With<Camera3d>>,
+) {
+    if !keyboard.just_pressed(KeyCode::KeyP) {
+        return;
+    }
+    for (camera, edge_detection) in &cameras {
+        if edge_detection {
+            commands.entity(camera).remove::<EdgeDetection>();
+        } else {
+            commands.entity(camera).insert(EdgeDetection::default());
+        }
+    }
+}
+
+// the raymarched surface writes its depth and normals too, edge detection still outlines it
+fn toggle_raymarching(
+    mut commands: Commands,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    surfaces: Query<(Entity, Has<VoxelRaymarched>), With<VoxeledRendered>>,
+) {
+    if !keyboard.just_pressed(KeyCode::KeyR) {
+        return;
+    }
+    for (surface, raymarched) in &surfaces {
+        if raymarched {
+            commands.entity(surface).remove::<VoxelRaymarched>();
+        } else {
+            commands.entity(surface).insert(VoxelRaymarched::default());
+        }
+    }
+}
+
+// 1 wireframe, 2 bounds, 3 cell grid, 4 active cells, 5 density heatmap
+fn toggle_debug_views(keyboard: Res<ButtonInput<KeyCode>>, mut debugs: Query<&mut VoxelDebug>) {
+    for mut debug in &mut debugs {
+        if keyboard.just_pressed(KeyCode::Digit1) {
+            debug.wireframe = !debug.wireframe;
+        }
+        if keyboard.just_pressed(KeyCode::Digit2) {
+            debug.bounds = !debug.bounds;
+        }
+        if keyboard.just_pressed(KeyCode::Digit3) {
+            debug.cell_grid = !debug.cell_grid;
+        }
+        if keyboard.just_pressed(KeyCode::Digit4) {
+            debug.cases = match debug.cases {
+                CaseHighlight::Off => CaseHighlight::All,
+                _ => CaseHighlight::Off,
+            };
+        }
+        if keyboard.just_pressed(KeyCode::Digit5) {
+            debug.heatmap = match debug.heatmap {
+                None => Some(DensitySlice {
+                    range: 0.25,
+                    ..Default::default()
+                }),
+                Some(_) => None,
+            };
+        }
+    }
+}
+
+// no cut, open, capped, capped with the density colormap
+fn cycle_clipping(keyboard: Res<ButtonInput<KeyCode>>, mut voxel_volume: ResMut<VoxelVolume>) {
+    if !keyboard.just_pressed(KeyCode::KeyC) {
+        return;
+    }
+    let clipping = &mut voxel_volume.clipping;
+    if !clipping.is_active() {
+        clipping.planes = vec![ClipPlane::new(Vec3::ZERO, Vec3::Z)];
+        clipping.cap = ClipCap::Open;
+        return;
+    }
+    clipping.cap = match clipping.cap {
+        ClipCap::Open => ClipCap::Solid,
+        ClipCap::Solid => ClipCap::Colormap { range: 0.25 },
+        ClipCap::Colormap { .. } => {
+            clipping.planes.clear();
+            clipping.clip_box = None;
+            ClipCap::Open
+        }
+    };
 }
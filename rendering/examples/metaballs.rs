@@ -0,0 +1,120 @@
+use bevy::prelude::*;
+use bevy_math::bounding::{Aabb3d, BoundingVolume};
+use bevy_panorbit_camera::{PanOrbitCamera, PanOrbitCameraPlugin};
+use bevy_render::primitives::Aabb;
+use rendering::marching_cubes::display_stage::{
+    VoxelFaces, VoxelShading, VoxelTransparency, VoxeledRendered,
+};
+use rendering::marching_cubes::{
+    DensitySource, MarchingCubesPlugin, Metaball, VoxelMaterials, VoxelVolume,
+};
+
+fn main() {
+    App::new()
+        .insert_resource(VoxelVolume {
+            aabb: Aabb3d::new(Vec3::ZERO, Vec3::splat(1.5)),
+            voxel_size: 0.05,
+            isovalue: 0.3,
+            density: DensitySource::Metaballs,
+            ..Default::default()
+        })
+        // translucent blobs, the alpha of the materials is blended in the transparent pass
+        .insert_resource(VoxelMaterials {
+            colors: [LinearRgba::new(0.2, 0.5, 0.9, 0.4); 4],
+        })
+        .add_plugins((
+            DefaultPlugins,
+            PanOrbitCameraPlugin,
+            MarchingCubesPlugin::default(),
+        ))
+        .add_systems(Startup, setup)
+        .add_systems(Update, (orbit_metaballs, cycle_faces))
+        .run();
+}
+
+#[derive(Component)]
+struct Orbit {
+    radius: f32,
+    speed: f32,
+    phase: f32,
+}
+
+fn setup(
+    mut commands: Commands,
+    voxel_volume: Res<VoxelVolume>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.spawn((
+        PanOrbitCamera::default(),
+        Camera3d::default(),
+        Transform::from_xyz(0.0, 2.0, -4.0).looking_at(Vec3::ZERO, Vec3::Y),
+    ));
+
+    commands.spawn((
+        DirectionalLight::default(),
+        Transform::from_xyz(1.0, 2.0, -0.5).looking_at(Vec3::ZERO, Vec3::Y),
+    ));
+
+    // seen through the blobs
+    commands.spawn((
+        Mesh3d(meshes.add(Cuboid::from_length(0.4))),
+        MeshMaterial3d(materials.add(Color::WHITE)),
+    ));
+
+    commands.spawn((
+        Visibility::default(),
+        Transform::default(),
+        Aabb {
+            center: voxel_volume.aabb.center(),
+            half_extents: voxel_volume.aabb.half_size(),
+        },
+        VoxeledRendered,
+        VoxelShading::Smooth,
+        VoxelTransparency {
+            faces: VoxelFaces::BackThenFront,
+            ..Default::default()
+        },
+    ));
+
+    for i in 0..5 {
+        commands.spawn((
+            Metaball {
+                radius: 0.4 + 0.05 * i as f32,
+                ..Default::default()
+            },
+            Orbit {
+                radius: 0.3 + 0.15 * i as f32,
+                speed: 0.5 + 0.2 * i as f32,
+                phase: i as f32,
+            },
+        ));
+    }
+}
+
+// balls drift in and out of each other, merging and splitting
+fn orbit_metaballs(time: Res<Time>, mut metaballs: Query<(&Orbit, &mut Transform)>) {
+    let t = time.elapsed_secs();
+    for (orbit, mut transform) in &mut metaballs {
+        let angle = t * orbit.speed + orbit.phase;
+        transform.translation = Vec3::new(
+            orbit.radius * angle.cos(),
+            0.3 * (angle * 1.7).sin(),
+            orbit.radius * angle.sin(),
+        );
+    }
+}
+
+// T cycles the drawn faces of the blobs
+fn cycle_faces(keys: Res<ButtonInput<KeyCode>>, mut transparencies: Query<&mut VoxelTransparency>) {
+    if !keys.just_pressed(KeyCode::KeyT) {
+        return;
+    }
+    for mut transparency in &mut transparencies {
+        transparency.faces = match transparency.faces {
+            VoxelFaces::Front => VoxelFaces::Both,
+            VoxelFaces::Both => VoxelFaces::BackThenFront,
+            VoxelFaces::BackThenFront => VoxelFaces::Front,
+        };
+    }
+}
@@ -0,0 +1,66 @@
+use bevy::prelude::*;
+use bevy_math::bounding::{Aabb3d, BoundingVolume};
+use bevy_panorbit_camera::{PanOrbitCamera, PanOrbitCameraPlugin};
+use bevy_render::primitives::Aabb;
+use rendering::marching_cubes::display_stage::VoxeledRendered;
+use rendering::marching_cubes::{
+    DensitySource, MarchingCubesPlugin, SphFluid, SphSettings, VoxelVolume,
+};
+
+fn main() {
+    let aabb = Aabb3d {
+        min: Vec3A::ZERO,
+        max: Vec3A::new(1.0, 0.6, 0.4),
+    };
+    // dam break, a column of water collapsing into the empty side of the box
+    let column = Aabb3d {
+        min: Vec3A::splat(0.01),
+        max: Vec3A::new(0.3, 0.5, 0.39),
+    };
+
+    App::new()
+        .insert_resource(VoxelVolume {
+            aabb,
+            voxel_size: 0.02,
+            isovalue: 0.5,
+            density: DensitySource::Fluid,
+            ..Default::default()
+        })
+        .insert_resource(SphFluid::block(SphSettings::default(), column))
+        .add_plugins((
+            DefaultPlugins,
+            PanOrbitCameraPlugin,
+            MarchingCubesPlugin::default(),
+        ))
+        .add_systems(Startup, setup)
+        .add_systems(Update, restart)
+        .run();
+}
+
+fn setup(mut commands: Commands, voxel_volume: Res<VoxelVolume>) {
+    commands.spawn((
+        PanOrbitCamera {
+            focus: voxel_volume.aabb.center().into(),
+            ..Default::default()
+        },
+        Camera3d::default(),
+        Transform::from_xyz(0.5, 1.0, -1.5).looking_at(voxel_volume.aabb.center().into(), Vec3::Y),
+    ));
+
+    commands.spawn((
+        Visibility::default(),
+        Transform::default(),
+        Aabb {
+            center: voxel_volume.aabb.center(),
+            half_extents: voxel_volume.aabb.half_size(),
+        },
+        VoxeledRendered,
+    ));
+}
+
+// space restarts the simulation from the initial column
+fn restart(keys: Res<ButtonInput<KeyCode>>, mut fluid: ResMut<SphFluid>) {
+    if keys.just_pressed(KeyCode::Space) {
+        fluid.set_changed();
+    }
+}
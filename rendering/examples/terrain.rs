@@ -0,0 +1,86 @@
+use bevy::prelude::*;
+use bevy_math::bounding::{Aabb3d, BoundingVolume};
+use bevy_panorbit_camera::{PanOrbitCamera, PanOrbitCameraPlugin};
+use bevy_render::primitives::Aabb;
+use rendering::marching_cubes::display_stage::{VoxelShading, VoxeledRendered};
+use rendering::marching_cubes::{
+    DensitySource, MarchingCubesPlugin, TerrainGenerator, VoxelVolume,
+};
+
+fn main() {
+    App::new()
+        .insert_resource(VoxelVolume {
+            aabb: Aabb3d {
+                min: Vec3A::new(-2.0, -1.0, -2.0),
+                max: Vec3A::new(2.0, 1.0, 2.0),
+            },
+            voxel_size: 0.02,
+            isovalue: 0.0,
+            density: DensitySource::Terrain(TerrainGenerator::default()),
+            ..Default::default()
+        })
+        .add_plugins((
+            DefaultPlugins,
+            PanOrbitCameraPlugin,
+            MarchingCubesPlugin::default(),
+        ))
+        .add_systems(Startup, setup)
+        .add_systems(Update, (reseed, cycle_shading))
+        .run();
+}
+
+fn setup(mut commands: Commands, voxel_volume: Res<VoxelVolume>) {
+    let center = voxel_volume.aabb.center();
+    commands.spawn((
+        PanOrbitCamera {
+            focus: center.into(),
+            ..Default::default()
+        },
+        Camera3d::default(),
+        Transform::from_xyz(3.0, 2.5, -3.0).looking_at(center.into(), Vec3::Y),
+    ));
+
+    commands.spawn((
+        DirectionalLight {
+            illuminance: light_consts::lux::OVERCAST_DAY,
+            shadows_enabled: true,
+            ..Default::default()
+        },
+        Transform::from_xyz(1.0, 2.0, 0.5).looking_at(Vec3::ZERO, Vec3::Y),
+    ));
+
+    commands.spawn((
+        Visibility::default(),
+        Transform::default(),
+        Aabb {
+            center,
+            half_extents: voxel_volume.aabb.half_size(),
+        },
+        VoxeledRendered,
+        VoxelShading::Smooth,
+    ));
+}
+
+// space generates the next seed, the same seed always gives the same terrain
+fn reseed(keys: Res<ButtonInput<KeyCode>>, mut voxel_volume: ResMut<VoxelVolume>) {
+    if !keys.just_pressed(KeyCode::Space) {
+        return;
+    }
+    if let DensitySource::Terrain(terrain) = &mut voxel_volume.density {
+        terrain.seed = terrain.seed.wrapping_add(1);
+    }
+}
+
+// s cycles through smooth, flat and unlit shading
+fn cycle_shading(keys: Res<ButtonInput<KeyCode>>, mut shadings: Query<&mut VoxelShading>) {
+    if !keys.just_pressed(KeyCode::KeyS) {
+        return;
+    }
+    for mut shading in &mut shadings {
+        *shading = match *shading {
+            VoxelShading::Smooth => VoxelShading::Flat,
+            VoxelShading::Flat => VoxelShading::Unlit,
+            VoxelShading::Unlit => VoxelShading::Smooth,
+        };
+    }
+}
@@ -0,0 +1,148 @@
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy_math::bounding::{Aabb3d, BoundingVolume};
+use bevy_panorbit_camera::{PanOrbitCamera, PanOrbitCameraPlugin};
+use bevy_render::primitives::Aabb;
+use rendering::marching_cubes::display_stage::{TriplanarMaterial, VoxeledRendered};
+use rendering::marching_cubes::{
+    DensitySource, MarchingCubesPlugin, TerrainGenerator, VoxelMaterials, VoxelVolume,
+};
+
+const TEXTURE_SIZE: u32 = 128;
+
+fn main() {
+    App::new()
+        .insert_resource(VoxelVolume {
+            aabb: Aabb3d {
+                min: Vec3A::new(-2.0, -1.0, -2.0),
+                max: Vec3A::new(2.0, 1.0, 2.0),
+            },
+            voxel_size: 0.02,
+            isovalue: 0.0,
+            density: DensitySource::Terrain(TerrainGenerator::default()),
+            ..Default::default()
+        })
+        // the textures carry the detail, the materials only tint them
+        .insert_resource(VoxelMaterials {
+            colors: [
+                LinearRgba::rgb(0.5, 0.8, 0.4),
+                LinearRgba::rgb(1.0, 0.9, 0.6),
+                LinearRgba::rgb(0.7, 0.7, 0.7),
+                LinearRgba::rgb(0.35, 0.35, 0.4),
+            ],
+        })
+        .add_plugins((
+            DefaultPlugins,
+            PanOrbitCameraPlugin,
+            MarchingCubesPlugin::default(),
+        ))
+        .add_systems(Startup, setup)
+        .add_systems(Update, adjust)
+        .run();
+}
+
+fn setup(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    voxel_volume: Res<VoxelVolume>,
+) {
+    let center = voxel_volume.aabb.center();
+    commands.spawn((
+        PanOrbitCamera {
+            focus: center.into(),
+            ..Default::default()
+        },
+        Camera3d::default(),
+        Transform::from_xyz(3.0, 2.5, -3.0).looking_at(center.into(), Vec3::Y),
+    ));
+
+    commands.spawn((
+        DirectionalLight {
+            illuminance: light_consts::lux::OVERCAST_DAY,
+            ..Default::default()
+        },
+        Transform::from_xyz(1.0, 2.0, 0.5).looking_at(Vec3::ZERO, Vec3::Y),
+    ));
+
+    commands.spawn((
+        Visibility::default(),
+        Transform::default(),
+        Aabb {
+            center,
+            half_extents: voxel_volume.aabb.half_size(),
+        },
+        VoxeledRendered,
+        TriplanarMaterial {
+            albedo: images.add(texture(TextureFormat::Rgba8UnormSrgb, |height, _| {
+                let shade = 0.6 + 0.4 * height;
+                [shade, shade, shade, 1.0]
+            })),
+            normal: Some(images.add(texture(TextureFormat::Rgba8Unorm, |_, normal| {
+                let normal = normal * 0.5 + 0.5;
+                [normal.x, normal.y, normal.z, 1.0]
+            }))),
+            roughness: Some(images.add(texture(TextureFormat::Rgba8Unorm, |height, _| {
+                let roughness = 1.0 - 0.5 * height;
+                [roughness, roughness, roughness, 1.0]
+            }))),
+            tiling: 2.0,
+            ..Default::default()
+        },
+    ));
+}
+
+// a tiling field of round cobbles, `pixel` gets the height and tangent space normal
+fn texture(format: TextureFormat, pixel: impl Fn(f32, Vec3) -> [f32; 4]) -> Image {
+    const CELLS: f32 = 4.0;
+    let height = |x: f32, y: f32| {
+        let cell = (Vec2::new(x, y) * CELLS).fract() - 0.5;
+        (1.0 - cell.length() * 2.0).max(0.0).sqrt()
+    };
+
+    let step = 1.0 / TEXTURE_SIZE as f32;
+    let mut data = Vec::with_capacity((TEXTURE_SIZE * TEXTURE_SIZE * 4) as usize);
+    for y in 0..TEXTURE_SIZE {
+        for x in 0..TEXTURE_SIZE {
+            let (u, v) = (x as f32 * step, y as f32 * step);
+            let h = height(u, v);
+            let normal = Vec3::new(
+                height(u - step, v) - height(u + step, v),
+                height(u, v - step) - height(u, v + step),
+                4.0 * step * CELLS,
+            )
+            .normalize();
+            data.extend(pixel(h, normal).map(|c| (c.clamp(0.0, 1.0) * 255.0) as u8));
+        }
+    }
+
+    Image::new(
+        Extent3d {
+            width: TEXTURE_SIZE,
+            height: TEXTURE_SIZE,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        format,
+        RenderAssetUsages::RENDER_WORLD,
+    )
+}
+
+// up and down change the tiling, left and right the blend sharpness
+fn adjust(keys: Res<ButtonInput<KeyCode>>, mut materials: Query<&mut TriplanarMaterial>) {
+    for mut material in &mut materials {
+        if keys.just_pressed(KeyCode::ArrowUp) {
+            material.tiling *= 1.5;
+        }
+        if keys.just_pressed(KeyCode::ArrowDown) {
+            material.tiling /= 1.5;
+        }
+        if keys.just_pressed(KeyCode::ArrowRight) {
+            material.blend_sharpness += 1.0;
+        }
+        if keys.just_pressed(KeyCode::ArrowLeft) {
+            material.blend_sharpness = (material.blend_sharpness - 1.0).max(1.0);
+        }
+    }
+}
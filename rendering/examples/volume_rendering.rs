@@ -0,0 +1,133 @@
+use bevy::core_pipeline::prepass::DepthPrepass;
+use bevy::prelude::*;
+use bevy_panorbit_camera::{PanOrbitCamera, PanOrbitCameraPlugin};
+use bevy_render::primitives::Aabb;
+use rendering::marching_cubes::display_stage::{TransferFunction, TransferStop, VolumeRendered};
+use rendering::marching_cubes::{MarchingCubesPlugin, ScalarVolume};
+
+const SIZE: u32 = 96;
+
+fn main() {
+    App::new()
+        .add_plugins((
+            DefaultPlugins,
+            PanOrbitCameraPlugin,
+            MarchingCubesPlugin::default(),
+        ))
+        .add_systems(Startup, setup)
+        .add_systems(Update, adjust)
+        .run();
+}
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut scalar_volumes: ResMut<Assets<ScalarVolume>>,
+    mut transfer_functions: ResMut<Assets<TransferFunction>>,
+) {
+    commands.spawn((
+        PanOrbitCamera::default(),
+        Camera3d::default(),
+        // ends the rays at the opaque cube inside the volume
+        DepthPrepass,
+        Transform::from_xyz(2.5, 1.5, 2.5).looking_at(Vec3::ZERO, Vec3::Y),
+    ));
+
+    commands.spawn((
+        DirectionalLight::default(),
+        Transform::from_xyz(1.0, 2.0, 0.5).looking_at(Vec3::ZERO, Vec3::Y),
+    ));
+
+    commands.spawn((
+        Mesh3d(meshes.add(Cuboid::from_length(0.3))),
+        MeshMaterial3d(materials.add(Color::srgb(0.3, 0.5, 0.9))),
+        Transform::from_xyz(0.4, -0.2, 0.3),
+    ));
+
+    let mut volume = ScalarVolume::new(UVec3::splat(SIZE), cloud());
+    volume.spacing = Vec3::splat(2.0 / (SIZE - 1) as f32);
+    volume.origin = Vec3::splat(-1.0);
+    let aabb = volume.aabb();
+
+    // a faint orange haze around dense white cores
+    let transfer_function = TransferFunction::new(vec![
+        TransferStop {
+            value: 0.2,
+            color: LinearRgba::NONE,
+        },
+        TransferStop {
+            value: 0.5,
+            color: LinearRgba::new(0.9, 0.4, 0.2, 0.02),
+        },
+        TransferStop {
+            value: 0.9,
+            color: LinearRgba::new(1.0, 1.0, 0.9, 0.3),
+        },
+    ]);
+
+    commands.spawn((
+        Visibility::default(),
+        Transform::default(),
+        Aabb {
+            center: aabb.center(),
+            half_extents: aabb.half_size(),
+        },
+        VolumeRendered {
+            volume: scalar_volumes.add(volume),
+            transfer_function: transfer_functions.add(transfer_function),
+            window: Some(Vec2::new(0.0, 1.0)),
+            ..Default::default()
+        },
+    ));
+}
+
+// a few overlapping gaussian blobs with some ripples, between 0 and about 1
+fn cloud() -> Vec<f32> {
+    let blobs = [
+        (Vec3::new(-0.3, 0.1, 0.0), 0.35),
+        (Vec3::new(0.35, 0.0, -0.2), 0.3),
+        (Vec3::new(0.0, -0.3, 0.4), 0.25),
+    ];
+    let step = 2.0 / (SIZE - 1) as f32;
+
+    let mut values = Vec::with_capacity(SIZE.pow(3) as usize);
+    for z in 0..SIZE {
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let pos = UVec3::new(x, y, z).as_vec3() * step - 1.0;
+                let density: f32 = blobs
+                    .iter()
+                    .map(|&(center, radius)| {
+                        (-(pos - center).length_squared() / (radius * radius)).exp()
+                    })
+                    .sum();
+                let ripples =
+                    0.1 * (pos.x * 17.0).sin() * (pos.y * 13.0).sin() * (pos.z * 11.0).sin();
+                values.push((density + ripples * density).min(1.0));
+            }
+        }
+    }
+    values
+}
+
+// up and down change the opacity, left and right the step size, J toggles the jitter
+fn adjust(keys: Res<ButtonInput<KeyCode>>, mut rendered: Query<&mut VolumeRendered>) {
+    for mut rendered in &mut rendered {
+        if keys.just_pressed(KeyCode::ArrowUp) {
+            rendered.opacity_scale *= 1.5;
+        }
+        if keys.just_pressed(KeyCode::ArrowDown) {
+            rendered.opacity_scale /= 1.5;
+        }
+        if keys.just_pressed(KeyCode::ArrowRight) {
+            rendered.step_size *= 1.5;
+        }
+        if keys.just_pressed(KeyCode::ArrowLeft) {
+            rendered.step_size /= 1.5;
+        }
+        if keys.just_pressed(KeyCode::KeyJ) {
+            rendered.jitter = !rendered.jitter;
+        }
+    }
+}
@@ -1,2 +1,7 @@
 pub mod edge_detection;
 pub mod marching_cubes;
+pub mod scan;
+
+/// Helpers for the tests running shaders on a device.
+#[cfg(test)]
+mod testing;
@@ -0,0 +1,70 @@
+// 3D cellular automaton on the voxel corners, mirrored by `AutomatonGrid::step`
+
+struct AutomatonParams {
+    dims: vec3<u32>,
+    // bit n set when a cell with n live neighbors is born / survives
+    birth: u32,
+    survival: u32,
+    neighborhood: u32,
+    // state of the cells past the bounds
+    boundary: u32,
+};
+
+@group(0) @binding(0) var<uniform> params: AutomatonParams;
+// ping-pong state, swapped by binding the other bind group
+@group(0) @binding(1) var<storage, read> source: array<u32>;
+@group(0) @binding(2) var<storage, read_write> destination: array<u32>;
+// read by the compute stage
+@group(0) @binding(3) var<storage, read_write> density: array<f32>;
+
+// Must match `Neighborhood::shader_index`
+const NEIGHBORHOOD_MOORE: u32 = 0;
+const NEIGHBORHOOD_VON_NEUMANN: u32 = 1;
+
+fn cell(c: vec3<u32>) -> u32 {
+    return c.x + params.dims.x * (c.y + params.dims.y * c.z);
+}
+
+fn alive(c: vec3<i32>) -> u32 {
+    if (any(c < vec3(0)) || any(c >= vec3<i32>(params.dims))) {
+        return params.boundary;
+    }
+    return source[cell(vec3<u32>(c))];
+}
+
+fn live_neighbors(c: vec3<i32>) -> u32 {
+    var count = 0u;
+    for (var z = -1; z <= 1; z++) {
+        for (var y = -1; y <= 1; y++) {
+            for (var x = -1; x <= 1; x++) {
+                let offset = vec3(x, y, z);
+                let distance = abs(x) + abs(y) + abs(z);
+                if (distance == 0 || (params.neighborhood == NEIGHBORHOOD_VON_NEUMANN && distance > 1)) {
+                    continue;
+                }
+                count += alive(c + offset);
+            }
+        }
+    }
+    return count;
+}
+
+@compute @workgroup_size(4, 4, 4)
+fn step_automaton(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (any(id >= params.dims)) {
+        return;
+    }
+    let i = cell(id);
+    let count = live_neighbors(vec3<i32>(id));
+    let rule = select(params.birth, params.survival, source[i] != 0u);
+    destination[i] = (rule >> count) & 1u;
+}
+
+@compute @workgroup_size(4, 4, 4)
+fn write_density(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (any(id >= params.dims)) {
+        return;
+    }
+    let i = cell(id);
+    density[i] = f32(source[i]);
+}
@@ -0,0 +1,193 @@
+use bevy_math::{IVec3, UVec3, Vec3};
+
+use super::{AutomatonRule, CellularAutomaton, Neighborhood};
+use crate::marching_cubes::VoxelVolume;
+use crate::marching_cubes::noise::{hash_cell, to_unit};
+use crate::marching_cubes::volume::ScalarVolume;
+
+/// Automaton cells on the corners of the [`VoxelVolume`] voxels, also a CPU reference of
+/// `automaton.wgsl`.
+#[derive(Clone, Debug)]
+pub struct AutomatonGrid {
+    pub dims: UVec3,
+    pub spacing: f32,
+    pub origin: Vec3,
+    pub cells: Vec<bool>,
+}
+
+impl AutomatonGrid {
+    /// Random fill of `automaton.fill` live cells, the same for a given seed.
+    pub fn new(automaton: &CellularAutomaton, volume: &VoxelVolume) -> Self {
+        let dims = volume.count_dims() + UVec3::ONE;
+        let mut cells = Vec::with_capacity(dims.element_product() as usize);
+        for z in 0..dims.z {
+            for y in 0..dims.y {
+                for x in 0..dims.x {
+                    let random = to_unit(hash_cell(
+                        IVec3::new(x as i32, y as i32, z as i32),
+                        automaton.seed,
+                    ))
+                    .x;
+                    cells.push(random < automaton.fill);
+                }
+            }
+        }
+
+        Self {
+            dims,
+            spacing: volume.voxel_size,
+            origin: volume.aabb.min.into(),
+            cells,
+        }
+    }
+
+    fn cell(&self, c: IVec3) -> usize {
+        let u = c.as_uvec3();
+        (u.x + self.dims.x * (u.y + self.dims.y * u.z)) as usize
+    }
+
+    fn alive(&self, c: IVec3, alive_boundary: bool) -> bool {
+        if c.cmplt(IVec3::ZERO).any() || c.cmpge(self.dims.as_ivec3()).any() {
+            return alive_boundary;
+        }
+        self.cells[self.cell(c)]
+    }
+
+    pub fn live_neighbors(
+        &self,
+        c: IVec3,
+        neighborhood: Neighborhood,
+        alive_boundary: bool,
+    ) -> u32 {
+        let mut count = 0;
+        for z in -1..=1 {
+            for y in -1..=1 {
+                for x in -1..=1 {
+                    let offset = IVec3::new(x, y, z);
+                    let distance = offset.abs().element_sum();
+                    if distance == 0 || (neighborhood == Neighborhood::VonNeumann && distance > 1) {
+                        continue;
+                    }
+                    count += self.alive(c + offset, alive_boundary) as u32;
+                }
+            }
+        }
+        count
+    }
+
+    /// Advances by one generation.
+    pub fn step(&mut self, rule: &AutomatonRule, alive_boundary: bool) {
+        let dims = self.dims.as_ivec3();
+        let mut next = Vec::with_capacity(self.cells.len());
+        for z in 0..dims.z {
+            for y in 0..dims.y {
+                for x in 0..dims.x {
+                    let c = IVec3::new(x, y, z);
+                    let count = self.live_neighbors(c, rule.neighborhood, alive_boundary);
+                    next.push(rule.next(self.cells[self.cell(c)], count));
+                }
+            }
+        }
+        self.cells = next;
+    }
+
+    /// Live cells at 1, dead ones at 0, as written for the compute stage.
+    pub fn density(&self) -> ScalarVolume {
+        let values = self
+            .cells
+            .iter()
+            .map(|&alive| alive as u32 as f32)
+            .collect();
+        let mut density = ScalarVolume::new(self.dims, values);
+        density.spacing = Vec3::splat(self.spacing);
+        density.origin = self.origin;
+        density
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid(live: &[IVec3]) -> AutomatonGrid {
+        let mut grid = AutomatonGrid {
+            dims: UVec3::splat(6),
+            spacing: 1.0,
+            origin: Vec3::ZERO,
+            cells: vec![false; 216],
+        };
+        for &c in live {
+            let index = grid.cell(c);
+            grid.cells[index] = true;
+        }
+        grid
+    }
+
+    fn live(grid: &AutomatonGrid) -> Vec<IVec3> {
+        let dims = grid.dims.as_ivec3();
+        (0..dims.z)
+            .flat_map(|z| (0..dims.y).flat_map(move |y| (0..dims.x).map(move |x| (x, y, z))))
+            .map(|(x, y, z)| IVec3::new(x, y, z))
+            .filter(|&c| grid.cells[grid.cell(c)])
+            .collect()
+    }
+
+    #[test]
+    fn rule_masks() {
+        let life = AutomatonRule::life();
+        assert_eq!(life.birth, 1 << 5);
+        assert_eq!(life.survival, 1 << 4 | 1 << 5);
+        assert!(life.next(false, 5) && !life.next(false, 4));
+        assert!(life.next(true, 4) && life.next(true, 5) && !life.next(true, 6));
+        // counts past the Moore neighborhood are ignored
+        assert_eq!(
+            AutomatonRule::new([27, 40], [], Neighborhood::Moore).birth,
+            0
+        );
+    }
+
+    #[test]
+    fn life_still_life() {
+        // 2x2x2 block without one edge, in x fastest order, every cell has 4 or 5 live neighbors and no dead
+        // cell has exactly 5
+        let block: Vec<IVec3> = [
+            (2, 2, 2),
+            (3, 2, 2),
+            (2, 3, 2),
+            (2, 2, 3),
+            (3, 2, 3),
+            (2, 3, 3),
+        ]
+        .map(|(x, y, z)| IVec3::new(x, y, z))
+        .to_vec();
+        let mut grid = grid(&block);
+        for _ in 0..4 {
+            grid.step(&AutomatonRule::life(), false);
+            assert_eq!(live(&grid), block);
+        }
+    }
+
+    #[test]
+    fn full_block_dies_out() {
+        // 7 live neighbors is too crowded, and no dead cell sees 5
+        let block: Vec<IVec3> = (0..8)
+            .map(|i| IVec3::new(2 + (i & 1), 2 + ((i >> 1) & 1), 2 + (i >> 2)))
+            .collect();
+        let mut grid = grid(&block);
+        grid.step(&AutomatonRule::life(), false);
+        assert!(live(&grid).is_empty());
+    }
+
+    #[test]
+    fn boundary_counts_as_neighbors() {
+        let grid = grid(&[]);
+        let corner = IVec3::ZERO;
+        assert_eq!(grid.live_neighbors(corner, Neighborhood::Moore, false), 0);
+        // 26 minus the 7 cells inside the grid
+        assert_eq!(grid.live_neighbors(corner, Neighborhood::Moore, true), 19);
+        assert_eq!(
+            grid.live_neighbors(corner, Neighborhood::VonNeumann, true),
+            3
+        );
+    }
+}
@@ -0,0 +1,315 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use bevy_app::{App, Plugin};
+use bevy_asset::{AssetId, Handle, load_internal_asset, weak_handle};
+use bevy_ecs::resource::Resource;
+use bevy_ecs::schedule::IntoScheduleConfigs;
+use bevy_ecs::system::{Commands, Res, ResMut};
+use bevy_ecs::world::{FromWorld, World};
+use bevy_math::UVec3;
+use bevy_render::render_asset::RenderAssets;
+use bevy_render::render_graph::{self, RenderGraph, RenderLabel};
+use bevy_render::render_resource::binding_types::{
+    storage_buffer_read_only_sized, storage_buffer_sized, uniform_buffer,
+};
+use bevy_render::render_resource::{
+    BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, CachedComputePipelineId,
+    CachedPipelineState, ComputePassDescriptor, ComputePipelineDescriptor, PipelineCache, Shader,
+    ShaderStages, ShaderType, UniformBuffer,
+};
+use bevy_render::renderer::{RenderContext, RenderDevice, RenderQueue};
+use bevy_render::storage::{GpuShaderStorageBuffer, ShaderStorageBuffer};
+use bevy_render::{Extract, ExtractSchedule, Render, RenderApp, RenderSet};
+
+use super::{AutomatonBuffers, CellularAutomaton};
+use crate::marching_cubes::MarchingCubesBuffers;
+use crate::marching_cubes::compute_stage::MarchingCubesComputeLabel;
+
+pub const AUTOMATON_SHADER_HANDLE: Handle<Shader> =
+    weak_handle!("8b3f6c21-d47e-4a95-b0c8-2e9f51a7d34b");
+
+const WORKGROUP_SIZE: u32 = 4;
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+struct AutomatonLabel;
+
+pub struct GpuAutomatonPlugin;
+
+impl Plugin for GpuAutomatonPlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(
+            app,
+            AUTOMATON_SHADER_HANDLE,
+            "automaton.wgsl",
+            Shader::from_wgsl
+        );
+
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app.init_resource::<AutomatonUniformBuffer>();
+        render_app.add_systems(ExtractSchedule, extract_automaton);
+        render_app.add_systems(
+            Render,
+            (
+                prepare_automaton_uniform_buffer.in_set(RenderSet::PrepareResources),
+                prepare_automaton_bind_groups.in_set(RenderSet::PrepareBindGroups),
+            ),
+        );
+
+        let mut render_graph = render_app.world_mut().resource_mut::<RenderGraph>();
+        render_graph.add_node(AutomatonLabel, AutomatonNode::default());
+        // the compute stage reads the written density
+        render_graph.add_node_edge(AutomatonLabel, MarchingCubesComputeLabel);
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app.init_resource::<AutomatonPipeline>();
+    }
+}
+
+/// `AutomatonParams` in `automaton.wgsl`.
+#[derive(ShaderType, Clone, Default)]
+struct AutomatonUniform {
+    dims: UVec3,
+    birth: u32,
+    survival: u32,
+    neighborhood: u32,
+    boundary: u32,
+}
+
+#[derive(Resource)]
+struct ExtractedAutomaton {
+    uniform: AutomatonUniform,
+    states: [AssetId<ShaderStorageBuffer>; 2],
+    density: AssetId<ShaderStorageBuffer>,
+    steps: u32,
+}
+
+fn extract_automaton(
+    mut commands: Commands,
+    automaton: Extract<Option<Res<CellularAutomaton>>>,
+    automaton_buffers: Extract<Option<Res<AutomatonBuffers>>>,
+    marching_cubes_buffers: Extract<Res<MarchingCubesBuffers>>,
+) {
+    let (Some(automaton), Some(automaton_buffers)) = (&*automaton, &*automaton_buffers) else {
+        commands.remove_resource::<ExtractedAutomaton>();
+        return;
+    };
+
+    let rule = &automaton.rule;
+    let uniform = AutomatonUniform {
+        dims: automaton_buffers.dims,
+        birth: rule.birth,
+        survival: rule.survival,
+        neighborhood: rule.neighborhood.shader_index(),
+        boundary: automaton.alive_boundary as u32,
+    };
+
+    commands.insert_resource(ExtractedAutomaton {
+        uniform,
+        states: automaton_buffers.states.clone().map(|state| state.id()),
+        density: marching_cubes_buffers.densities.id(),
+        steps: automaton_buffers.steps,
+    });
+}
+
+#[derive(Resource, Default)]
+struct AutomatonUniformBuffer {
+    buffer: UniformBuffer<AutomatonUniform>,
+}
+
+fn prepare_automaton_uniform_buffer(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut automaton_uniform_buffer: ResMut<AutomatonUniformBuffer>,
+    automaton: Option<Res<ExtractedAutomaton>>,
+) {
+    let Some(automaton) = automaton else {
+        return;
+    };
+
+    automaton_uniform_buffer
+        .buffer
+        .set(automaton.uniform.clone());
+    automaton_uniform_buffer
+        .buffer
+        .write_buffer(&render_device, &render_queue);
+}
+
+/// Bind groups and work of this frame.
+#[derive(Resource)]
+struct AutomatonBindGroups {
+    /// `bind_groups[i]` reads `states[i]` and writes the other one.
+    bind_groups: [BindGroup; 2],
+    dims: UVec3,
+    steps: u32,
+}
+
+fn prepare_automaton_bind_groups(
+    mut commands: Commands,
+    pipeline: Res<AutomatonPipeline>,
+    automaton: Option<Res<ExtractedAutomaton>>,
+    automaton_uniform_buffer: Res<AutomatonUniformBuffer>,
+    gpu_buffers: Res<RenderAssets<GpuShaderStorageBuffer>>,
+    render_device: Res<RenderDevice>,
+) {
+    commands.remove_resource::<AutomatonBindGroups>();
+
+    let Some(automaton) = automaton else {
+        return;
+    };
+    let (Some(uniform), Some(front), Some(back), Some(density)) = (
+        automaton_uniform_buffer.buffer.binding(),
+        gpu_buffers.get(automaton.states[0]),
+        gpu_buffers.get(automaton.states[1]),
+        gpu_buffers.get(automaton.density),
+    ) else {
+        return;
+    };
+
+    let bind_group = |source: &GpuShaderStorageBuffer, destination: &GpuShaderStorageBuffer| {
+        render_device.create_bind_group(
+            Some("automaton_bind_group"),
+            &pipeline.bind_group_layout,
+            &BindGroupEntries::sequential((
+                uniform.clone(),
+                source.buffer.as_entire_buffer_binding(),
+                destination.buffer.as_entire_buffer_binding(),
+                density.buffer.as_entire_buffer_binding(),
+            )),
+        )
+    };
+
+    commands.insert_resource(AutomatonBindGroups {
+        bind_groups: [bind_group(front, back), bind_group(back, front)],
+        dims: automaton.uniform.dims,
+        steps: automaton.steps,
+    });
+}
+
+#[derive(Resource)]
+struct AutomatonPipeline {
+    bind_group_layout: BindGroupLayout,
+    step_pipeline: CachedComputePipelineId,
+    density_pipeline: CachedComputePipelineId,
+}
+
+impl FromWorld for AutomatonPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let bind_group_layout = render_device.create_bind_group_layout(
+            "automaton_bind_group",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::COMPUTE,
+                (
+                    uniform_buffer::<AutomatonUniform>(false),
+                    storage_buffer_read_only_sized(false, None),
+                    storage_buffer_sized(false, None),
+                    storage_buffer_sized(false, None),
+                ),
+            ),
+        );
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let queue = |entry_point: &'static str| {
+            pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                label: Some(format!("automaton_{entry_point}_pipeline").into()),
+                layout: vec![bind_group_layout.clone()],
+                push_constant_ranges: Vec::new(),
+                shader: AUTOMATON_SHADER_HANDLE,
+                shader_defs: vec![],
+                entry_point: entry_point.into(),
+                zero_initialize_workgroup_memory: false,
+            })
+        };
+        let step_pipeline = queue("step_automaton");
+        let density_pipeline = queue("write_density");
+
+        Self {
+            bind_group_layout,
+            step_pipeline,
+            density_pipeline,
+        }
+    }
+}
+
+#[derive(Default)]
+struct AutomatonNode {
+    pipeline_is_ready: bool,
+    /// State buffer holding the current generation, only swapped by the generations that ran.
+    front: AtomicUsize,
+}
+
+impl render_graph::Node for AutomatonNode {
+    fn update(&mut self, world: &mut World) {
+        let pipeline = world.resource::<AutomatonPipeline>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+
+        if !self.pipeline_is_ready {
+            let mut all_ready = true;
+            for id in [pipeline.step_pipeline, pipeline.density_pipeline] {
+                match pipeline_cache.get_compute_pipeline_state(id) {
+                    CachedPipelineState::Ok(_) => {}
+                    CachedPipelineState::Err(err) => {
+                        panic!("Initializing automaton.wgsl:\n{err}")
+                    }
+                    _ => all_ready = false,
+                }
+            }
+            self.pipeline_is_ready = all_ready;
+        }
+    }
+
+    fn run<'w>(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext<'w>,
+        world: &'w World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let Some(automaton) = world.get_resource::<AutomatonBindGroups>() else {
+            return Ok(());
+        };
+        if !self.pipeline_is_ready {
+            return Ok(());
+        }
+
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let pipeline = world.resource::<AutomatonPipeline>();
+        let step_pipeline = pipeline_cache
+            .get_compute_pipeline(pipeline.step_pipeline)
+            .unwrap();
+        let density_pipeline = pipeline_cache
+            .get_compute_pipeline(pipeline.density_pipeline)
+            .unwrap();
+
+        let workgroups = (automaton.dims + UVec3::splat(WORKGROUP_SIZE - 1)) / WORKGROUP_SIZE;
+        let mut pass =
+            render_context
+                .command_encoder()
+                .begin_compute_pass(&ComputePassDescriptor {
+                    label: Some("automaton"),
+                    ..Default::default()
+                });
+
+        // each generation reads the state the previous one wrote
+        let front = self.front.load(Ordering::Relaxed);
+        pass.set_pipeline(step_pipeline);
+        for step in 0..automaton.steps as usize {
+            pass.set_bind_group(0, &automaton.bind_groups[(front + step) % 2], &[]);
+            pass.dispatch_workgroups(workgroups.x, workgroups.y, workgroups.z);
+        }
+
+        let current = (front + automaton.steps as usize) % 2;
+        self.front.store(current, Ordering::Relaxed);
+        pass.set_pipeline(density_pipeline);
+        pass.set_bind_group(0, &automaton.bind_groups[current], &[]);
+        pass.dispatch_workgroups(workgroups.x, workgroups.y, workgroups.z);
+
+        Ok(())
+    }
+}
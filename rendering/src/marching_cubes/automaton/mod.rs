@@ -0,0 +1,234 @@
+use bevy_app::{App, Plugin, PostUpdate};
+use bevy_asset::{Assets, Handle, RenderAssetUsages};
+use bevy_ecs::change_detection::DetectChanges;
+use bevy_ecs::event::{Event, EventReader};
+use bevy_ecs::resource::Resource;
+use bevy_ecs::schedule::IntoScheduleConfigs;
+use bevy_ecs::system::{Commands, Res, ResMut};
+use bevy_math::UVec3;
+use bevy_render::RenderApp;
+use bevy_render::storage::ShaderStorageBuffer;
+use bevy_time::{Time, Virtual};
+
+use super::{DensitySource, MarchingCubesBuffers, VoxelVolume, density_buffer};
+
+mod cpu;
+pub mod gpu;
+
+pub use cpu::AutomatonGrid;
+
+/// Runs the [`CellularAutomaton`] on the GPU for [`DensitySource::Automaton`].
+pub struct CellularAutomatonPlugin;
+
+impl Plugin for CellularAutomatonPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<StepAutomaton>().add_systems(
+            PostUpdate,
+            (rebuild_automaton_buffers, advance_automaton).chain(),
+        );
+
+        if app.get_sub_app(RenderApp).is_some() {
+            app.add_plugins(gpu::GpuAutomatonPlugin);
+        }
+    }
+}
+
+/// Cells counted as neighbors.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Neighborhood {
+    /// The 26 cells sharing a face, edge or corner.
+    #[default]
+    Moore,
+    /// The 6 cells sharing a face.
+    VonNeumann,
+}
+
+impl Neighborhood {
+    // must match the NEIGHBORHOOD_* constants in automaton.wgsl
+    fn shader_index(self) -> u32 {
+        match self {
+            Neighborhood::Moore => 0,
+            Neighborhood::VonNeumann => 1,
+        }
+    }
+}
+
+/// Birth and survival rule, bit `n` of a mask is set when `n` live neighbors qualify.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AutomatonRule {
+    pub birth: u32,
+    pub survival: u32,
+    pub neighborhood: Neighborhood,
+}
+
+impl AutomatonRule {
+    pub fn new(
+        birth: impl IntoIterator<Item = u32>,
+        survival: impl IntoIterator<Item = u32>,
+        neighborhood: Neighborhood,
+    ) -> Self {
+        let mask = |counts: &mut dyn Iterator<Item = u32>| {
+            counts.filter(|&n| n <= 26).fold(0, |mask, n| mask | 1 << n)
+        };
+        Self {
+            birth: mask(&mut birth.into_iter()),
+            survival: mask(&mut survival.into_iter()),
+            neighborhood,
+        }
+    }
+
+    /// 3D Life 4555 (Bays), born with 5 neighbors, survives with 4 or 5.
+    pub fn life() -> Self {
+        Self::new([5], [4, 5], Neighborhood::Moore)
+    }
+
+    /// Majority rule, smooths random noise into cave like blobs in a few steps.
+    pub fn caves() -> Self {
+        Self::new(14..=26, 13..=26, Neighborhood::Moore)
+    }
+
+    /// Crystal like growth from a few seeds.
+    pub fn crystals() -> Self {
+        Self::new([1, 3], 0..=6, Neighborhood::VonNeumann)
+    }
+
+    #[inline]
+    pub fn next(&self, alive: bool, live_neighbors: u32) -> bool {
+        let mask = if alive { self.survival } else { self.birth };
+        (mask >> live_neighbors) & 1 != 0
+    }
+}
+
+impl Default for AutomatonRule {
+    fn default() -> Self {
+        Self::caves()
+    }
+}
+
+/// When generations advance.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AutomatonStepping {
+    /// Only on [`StepAutomaton`] events.
+    OnDemand,
+    /// Follows [`Time<Virtual>`].
+    FixedRate { steps_per_second: f32 },
+}
+
+impl Default for AutomatonStepping {
+    fn default() -> Self {
+        Self::FixedRate {
+            steps_per_second: 4.0,
+        }
+    }
+}
+
+/// Cellular automaton on the corners of the [`VoxelVolume`] voxels when its density is
+/// [`DensitySource::Automaton`], live cells have a density of 1.
+///
+/// Changing this resource, or the volume, restarts from a random fill.
+#[derive(Resource, Clone, Debug)]
+pub struct CellularAutomaton {
+    pub rule: AutomatonRule,
+    /// Fraction of live cells at the start.
+    pub fill: f32,
+    pub seed: u32,
+    /// Cells past the bounds count as alive, closes caves at the walls.
+    pub alive_boundary: bool,
+    pub stepping: AutomatonStepping,
+}
+
+impl Default for CellularAutomaton {
+    fn default() -> Self {
+        Self {
+            rule: AutomatonRule::default(),
+            fill: 0.5,
+            seed: 0,
+            alive_boundary: true,
+            stepping: AutomatonStepping::default(),
+        }
+    }
+}
+
+/// Advances the [`CellularAutomaton`] by this many generations.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct StepAutomaton(pub u32);
+
+/// GPU state of the running automaton.
+#[derive(Resource, Clone)]
+pub(crate) struct AutomatonBuffers {
+    /// Ping-pong state, both start with the same generation so the render world can swap them
+    /// as the generations actually run.
+    states: [Handle<ShaderStorageBuffer>; 2],
+    dims: UVec3,
+    /// Generations to run this frame.
+    steps: u32,
+    accumulator: f32,
+}
+
+fn rebuild_automaton_buffers(
+    mut commands: Commands,
+    automaton: Option<Res<CellularAutomaton>>,
+    voxel_volume: Res<VoxelVolume>,
+    automaton_buffers: Option<Res<AutomatonBuffers>>,
+    marching_cubes_buffers: Res<MarchingCubesBuffers>,
+    mut storage_buffers: ResMut<Assets<ShaderStorageBuffer>>,
+) {
+    let Some(automaton) =
+        automaton.filter(|_| matches!(voxel_volume.density, DensitySource::Automaton))
+    else {
+        if automaton_buffers.is_some() {
+            commands.remove_resource::<AutomatonBuffers>();
+        }
+        return;
+    };
+    if automaton_buffers.is_some() && !automaton.is_changed() && !voxel_volume.is_changed() {
+        return;
+    }
+
+    let grid = AutomatonGrid::new(&automaton, &voxel_volume);
+    let states: Vec<u32> = grid.cells.iter().map(|&alive| alive as u32).collect();
+    let state_buffer = || {
+        ShaderStorageBuffer::new(
+            bytemuck::cast_slice(&states),
+            RenderAssetUsages::RENDER_WORLD,
+        )
+    };
+
+    // live cells are written into the buffer the compute stage reads sampled volumes from
+    storage_buffers.insert(
+        &marching_cubes_buffers.densities,
+        density_buffer(&grid.density().values),
+    );
+
+    commands.insert_resource(AutomatonBuffers {
+        states: [
+            storage_buffers.add(state_buffer()),
+            storage_buffers.add(state_buffer()),
+        ],
+        dims: grid.dims,
+        steps: 0,
+        accumulator: 0.0,
+    });
+}
+
+fn advance_automaton(
+    automaton: Option<Res<CellularAutomaton>>,
+    automaton_buffers: Option<ResMut<AutomatonBuffers>>,
+    mut requests: EventReader<StepAutomaton>,
+    time: Res<Time<Virtual>>,
+) {
+    let (Some(automaton), Some(mut automaton_buffers)) = (automaton, automaton_buffers) else {
+        requests.clear();
+        return;
+    };
+    let automaton_buffers = &mut *automaton_buffers;
+
+    let mut steps = requests.read().map(|request| request.0).sum::<u32>();
+    if let AutomatonStepping::FixedRate { steps_per_second } = automaton.stepping {
+        automaton_buffers.accumulator += time.delta_secs() * steps_per_second;
+        let due = automaton_buffers.accumulator.floor();
+        automaton_buffers.accumulator -= due;
+        steps += due as u32;
+    }
+    automaton_buffers.steps = steps;
+}
@@ -0,0 +1,62 @@
+#define_import_path marching_cubes::clipping
+
+// Clip planes and box of `VoxelClipping`, shared by the compute and the display stage
+
+// Must match `MAX_CLIP_PLANES`
+const MAX_CLIP_PLANES: u32 = 4u;
+
+// Must match the `ClippingUniform` flags
+const CLIP_BOX: u32 = 1u;
+const CLIP_CAPPED: u32 = 2u;
+const CLIP_COLORMAP: u32 = 4u;
+
+// Must match `ClippingUniform`
+struct ClipParams {
+    // normal and offset of every plane, the normal points to the cut away side
+    planes: array<vec4<f32>, MAX_CLIP_PLANES>,
+    box_min: vec3<f32>,
+    plane_count: u32,
+    box_max: vec3<f32>,
+    flags: u32,
+    colormap_range: f32,
+};
+
+// Distance from the kept region, positive where it is cut away, mirrors `VoxelClipping::distance`
+fn clip_distance(pos: vec3<f32>, params: ClipParams) -> f32 {
+    var distance = -3.40282347e38;
+    for (var i = 0u; i < min(params.plane_count, MAX_CLIP_PLANES); i++) {
+        let plane = params.planes[i];
+        distance = max(distance, dot(plane.xyz, pos) - plane.w);
+    }
+    if ((params.flags & CLIP_BOX) != 0u) {
+        let center = (params.box_min + params.box_max) * 0.5;
+        let q = abs(pos - center) - (params.box_max - params.box_min) * 0.5;
+        distance = max(distance, length(max(q, vec3(0.0))) + min(max(q.x, max(q.y, q.z)), 0.0));
+    }
+    return distance;
+}
+
+// Whether the whole box from `min_corner` to `max_corner` is cut away, mirrors
+// `VoxelClipping::cell_clipped`
+fn clip_cell(min_corner: vec3<f32>, max_corner: vec3<f32>, params: ClipParams) -> bool {
+    for (var i = 0u; i < min(params.plane_count, MAX_CLIP_PLANES); i++) {
+        let plane = params.planes[i];
+        // the corner closest to the kept side
+        let corner = select(max_corner, min_corner, plane.xyz > vec3(0.0));
+        if (dot(plane.xyz, corner) - plane.w > 0.0) {
+            return true;
+        }
+    }
+    return (params.flags & CLIP_BOX) != 0u
+        && (any(max_corner < params.box_min) || any(min_corner > params.box_max));
+}
+
+// Diverging colormap of the capped cut, mirrors `heat_color` in `display_stage/debug.rs`
+fn clip_colormap(field: f32, params: ClipParams) -> vec3<f32> {
+    let t = clamp(field / max(params.colormap_range, 1e-6), -1.0, 1.0);
+    return select(
+        mix(vec3(1.0), vec3(0.1, 0.3, 0.85), t),
+        mix(vec3(1.0), vec3(0.85, 0.15, 0.1), -t),
+        t < 0.0,
+    );
+}
@@ -0,0 +1,183 @@
+use bevy_app::{App, Plugin};
+use bevy_asset::{Handle, load_internal_asset, weak_handle};
+use bevy_math::bounding::Aabb3d;
+use bevy_math::{Vec3, Vec4};
+use bevy_render::render_resource::{Shader, ShaderType};
+
+pub const CLIPPING_SHADER_HANDLE: Handle<Shader> =
+    weak_handle!("e5a0c3d7-2f91-4b68-8d4e-91b7f06a2c3b");
+
+/// Planes of a [`VoxelClipping`] beyond this count are ignored.
+pub const MAX_CLIP_PLANES: usize = 4;
+
+// must match the CLIP_* flags in clipping.wgsl
+const CLIP_BOX: u32 = 1;
+const CLIP_CAPPED: u32 = 2;
+const CLIP_COLORMAP: u32 = 4;
+
+/// Registers `clipping.wgsl` as the `marching_cubes::clipping` shader import.
+pub struct ClippingPlugin;
+
+impl Plugin for ClippingPlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(
+            app,
+            CLIPPING_SHADER_HANDLE,
+            "clipping.wgsl",
+            Shader::from_wgsl
+        );
+    }
+}
+
+/// Cuts into the surface of a [`VoxelVolume`](super::VoxelVolume) to inspect its inside.
+///
+/// Everything beyond any of the planes or outside the box is cut away. The compute stage skips
+/// the cells that are cut away entirely and closes the cut when it is capped, the display stage
+/// discards the rest of an open cut per pixel. [`VoxelRaymarched`] surfaces only see the first
+/// hit of every ray, an open cut discards it instead of looking inside.
+///
+/// [`VoxelRaymarched`]: super::display_stage::VoxelRaymarched
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VoxelClipping {
+    /// At most [`MAX_CLIP_PLANES`].
+    pub planes: Vec<ClipPlane>,
+    /// Only the inside of the box is kept.
+    pub clip_box: Option<Aabb3d>,
+    pub cap: ClipCap,
+}
+
+/// Plane cutting away everything on the side its normal points to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClipPlane {
+    pub normal: Vec3,
+    /// Distance of the plane from the origin along the normal.
+    pub offset: f32,
+}
+
+impl ClipPlane {
+    /// Plane through `point`, `normal` is normalized.
+    pub fn new(point: Vec3, normal: Vec3) -> Self {
+        let normal = normal.normalize();
+        Self {
+            normal,
+            offset: normal.dot(point),
+        }
+    }
+
+    /// Signed distance of `pos`, positive on the cut away side.
+    #[inline]
+    pub fn distance(&self, pos: Vec3) -> f32 {
+        self.normal.dot(pos) - self.offset
+    }
+}
+
+/// How the cut face of a [`VoxelClipping`] looks.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ClipCap {
+    /// The surface is cut open, showing its inside.
+    #[default]
+    Open,
+    /// The cut is closed in the material colors.
+    Solid,
+    /// The cut is closed and colored by the field behind it, red inside fading to white at the
+    /// surface like [`DensitySlice`](super::display_stage::DensitySlice).
+    Colormap {
+        /// Field value reaching the full color, in the units of the density.
+        range: f32,
+    },
+}
+
+impl VoxelClipping {
+    fn planes(&self) -> &[ClipPlane] {
+        &self.planes[..self.planes.len().min(MAX_CLIP_PLANES)]
+    }
+
+    /// Whether anything is cut away.
+    pub fn is_active(&self) -> bool {
+        !self.planes.is_empty() || self.clip_box.is_some()
+    }
+
+    /// Whether the cut is closed by the compute stage.
+    pub fn is_capped(&self) -> bool {
+        self.is_active() && self.cap != ClipCap::Open
+    }
+
+    /// Distance of `pos` from the kept region, positive where it is cut away and `f32::MIN`
+    /// without any clipping.
+    ///
+    /// Mirrors `clip_distance` in `clipping.wgsl`.
+    pub fn distance(&self, pos: Vec3) -> f32 {
+        let mut distance = f32::MIN;
+        for plane in self.planes() {
+            distance = distance.max(plane.distance(pos));
+        }
+        if let Some(clip_box) = &self.clip_box {
+            let center = Vec3::from(clip_box.min + clip_box.max) * 0.5;
+            let half_size = Vec3::from(clip_box.max - clip_box.min) * 0.5;
+            let q = (pos - center).abs() - half_size;
+            distance = distance.max(q.max(Vec3::ZERO).length() + q.max_element().min(0.0));
+        }
+        distance
+    }
+
+    /// `field` closed at the cut when capped, negative inside.
+    ///
+    /// Mirrors `field` in `density.wgsl`.
+    pub fn clip(&self, field: f32, pos: Vec3) -> f32 {
+        if self.is_capped() {
+            field.max(self.distance(pos))
+        } else {
+            field
+        }
+    }
+
+    /// Whether the whole box from `min` to `max` is cut away.
+    ///
+    /// Mirrors `clip_cell` in `clipping.wgsl`.
+    pub fn cell_clipped(&self, min: Vec3, max: Vec3) -> bool {
+        // the corner closest to the kept side of every plane
+        let plane_clipped = self.planes().iter().any(|plane| {
+            let corner = Vec3::select(plane.normal.cmpgt(Vec3::ZERO), min, max);
+            plane.distance(corner) > 0.0
+        });
+        let box_clipped = self.clip_box.is_some_and(|clip_box| {
+            max.cmplt(clip_box.min.into()).any() || min.cmpgt(clip_box.max.into()).any()
+        });
+        plane_clipped || box_clipped
+    }
+}
+
+/// `ClipParams` in `clipping.wgsl`.
+#[derive(ShaderType, Clone, Copy, Default)]
+pub struct ClippingUniform {
+    /// Normal and offset of every plane.
+    planes: [Vec4; MAX_CLIP_PLANES],
+    box_min: Vec3,
+    plane_count: u32,
+    box_max: Vec3,
+    flags: u32,
+    colormap_range: f32,
+}
+
+impl From<&VoxelClipping> for ClippingUniform {
+    fn from(clipping: &VoxelClipping) -> Self {
+        let mut uniform = Self::default();
+        for (i, plane) in clipping.planes().iter().enumerate() {
+            uniform.planes[i] = plane.normal.extend(plane.offset);
+        }
+        uniform.plane_count = clipping.planes().len() as u32;
+        if let Some(clip_box) = &clipping.clip_box {
+            uniform.box_min = clip_box.min.into();
+            uniform.box_max = clip_box.max.into();
+            uniform.flags |= CLIP_BOX;
+        }
+        if clipping.is_capped() {
+            uniform.flags |= CLIP_CAPPED;
+            if let ClipCap::Colormap { range } = clipping.cap {
+                uniform.flags |= CLIP_COLORMAP;
+                uniform.colormap_range = range;
+            }
+        }
+        uniform
+    }
+}
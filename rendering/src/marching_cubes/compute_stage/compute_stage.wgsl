@@ -1,12 +1,50 @@
-struct VoxelVolume {
-    min_bound: vec3<f32>,
-    max_bound: vec3<f32>,
-    voxel_size: f32,
+#import marching_cubes::density::{volume, field, source_field, field_normal, ambient_occlusion, material}
+// qualified, an imported `material_weights` would also rename the `Vertex` member
+#import marching_cubes::density
+#import marching_cubes::clipping::{CLIP_COLORMAP, clip_cell}
+
+// Must match `Vertex` in `marching_cubes/mod.rs`
+struct Vertex {
+    position: vec3<f32>,
+    // ambient light reaching the vertex, 1 is unoccluded
+    occlusion: f32,
+    // normalized field gradient, zero where the field is flat
+    normal: vec3<f32>,
+    // field before the clipping, colors a `ClipCap::Colormap` cut
+    section: f32,
+    // one weight per material
+    material_weights: vec4<f32>,
 };
 
-@group(0) @binding(0) var<storage, read_write> output: array<vec3<f32>>;
-@group(0) @binding(1) var<uniform> volume: VoxelVolume;
+@group(0) @binding(0) var<storage, read_write> output: array<Vertex>;
+
+// Must match `VoxelBrick` in `marching_cubes/mod.rs`, the draw indirect args of the brick
+struct Brick {
+    vertex_count: u32,
+    instance_count: u32,
+    first_vertex: u32,
+    first_instance: u32,
+};
+
+// field on every voxel corner, written by `sample_field`
+@group(0) @binding(7) var<storage, read_write> samples: array<f32>;
+@group(0) @binding(8) var<storage, read_write> bricks: array<Brick>;
+// vertices of every cell, laid out brick by brick, written by `classify_cells`
+@group(0) @binding(9) var<storage, read_write> cell_counts: array<u32>;
+// exclusive scan of `cell_counts` by `GpuScan`, followed by the total
+@group(0) @binding(10) var<storage, read> cell_offsets: array<u32>;
+
+// Dispatch args of `traverse_pyramid`, `HistoPyramid::traversal_args`
+struct TraversalArgs {
+    workgroups: vec3<u32>,
+};
 
+// `SurfaceExtraction::HistoPyramid`, triangle counts of the cells in level 0, every level up
+// sums the up to eight texels below it. Bound to a single level by `reduce_pyramid`
+@group(1) @binding(0) var pyramid: texture_3d<u32>;
+// the level written by `classify_pyramid_cells` and `reduce_pyramid`
+@group(1) @binding(1) var pyramid_level: texture_storage_3d<r32uint, write>;
+@group(1) @binding(2) var<storage, read_write> traversal_args: TraversalArgs;
 //
 // Lookup Tables for Marching Cubes
 //
@@ -318,66 +356,306 @@ const TRIANGLE_TABLE: array<u32, 2197> = array(
 
 const EPSILON: f32 = 0.00001; 
 const MAX_VERTS: u32 = 12;
+// Must match `BRICK_SIZE`
+const BRICK_SIZE: u32 = 8;
+// Pyramid level of the bricks, `BRICK_SIZE` is two to its power
+const BRICK_LEVEL: i32 = 3;
+// Must match `TRAVERSAL_WORKGROUP_SIZE`
+const TRAVERSAL_WORKGROUP_SIZE: u32 = 64;
+// Wider dispatches wrap into y
+const MAX_WORKGROUPS_PER_DIMENSION: u32 = 65535;
 
-fn sphere_sdf(pos: vec3<f32>, radius: f32) -> f32 {
-    return length(pos) - radius;
+fn cell_count() -> vec3<u32> {
+    let volume_size = volume.max_bound - volume.min_bound;
+    return vec3<u32>(floor(volume_size / volume.voxel_size));
 }
 
-fn torus_sdf(pos: vec3<f32>, radius: vec2<f32>) -> f32 {
-    let q = vec2<f32>(length(pos.xz) - radius.x, pos.y);
-    return length(q) - radius.y;
+fn brick_count() -> vec3<u32> {
+    return (cell_count() + BRICK_SIZE - 1u) / BRICK_SIZE;
 }
 
-fn scene_sdf(pos: vec3<f32>) -> f32 {
-    // return sphere_sdf(pos, 0.5); 
-    return torus_sdf(pos, vec2f(0.5, 0.15));
+fn sample_index(corner: vec3<u32>) -> u32 {
+    let dims = cell_count() + 1u;
+    return corner.x + dims.x * (corner.y + dims.y * corner.z);
 }
 
-@compute @workgroup_size(2, 2, 2)
-fn compute_vertices(@builtin(global_invocation_id) invocation_id: vec3<u32>) { 
-    let volume_size = volume.max_bound - volume.min_bound;
-    let coord = vec3<f32>(invocation_id) * volume.voxel_size - volume_size / 2.0;
+fn corner_position(corner: vec3<u32>) -> vec3<f32> {
+    return volume.min_bound + vec3<f32>(corner) * volume.voxel_size;
+}
 
-    var data: array<vec4<f32>, 8>;
+fn brick_index(brick: vec3<u32>) -> u32 {
+    let dims = brick_count();
+    return brick.x + dims.x * (brick.y + dims.y * brick.z);
+}
+
+// Evaluates the field once per voxel corner, shared by the up to eight cells around it
+@compute @workgroup_size(4, 4, 4)
+fn sample_field(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    if (any(invocation_id > cell_count())) {
+        return;
+    }
+    samples[sample_index(invocation_id)] = field(corner_position(invocation_id));
+}
+
+// Slot of the cell in `cell_counts`, the cells of a brick are contiguous so the scan keeps
+// the vertices of every brick in one range
+fn cell_index(cell: vec3<u32>) -> u32 {
+    let local = cell % BRICK_SIZE;
+    return brick_index(cell / BRICK_SIZE) * BRICK_SIZE * BRICK_SIZE * BRICK_SIZE
+        + local.x + BRICK_SIZE * (local.y + BRICK_SIZE * local.z);
+}
+
+// Bit per corner of the cell inside the surface
+fn cell_mask(cell: vec3<u32>) -> u32 {
     var mask: u32 = 0;
     for (var i: u32 = 0; i < 8; i++) {
-        var offset: vec3<f32>;
-        offset.x = f32((i & 1) >> 0);
-        offset.y = f32((i & 2) >> 1);
-        offset.z = f32((i & 4) >> 2);
-
-        let position = coord + offset * volume.voxel_size;
-        let distance = scene_sdf(position);
-        data[i] = vec4<f32>(position, distance);
-        if (distance < 0) {
+        let corner = cell + vec3((i & 1) >> 0, (i & 2) >> 1, (i & 4) >> 2);
+        if (samples[sample_index(corner)] < 0) {
             mask = mask | (1u << i);
         }
     }
+    return mask;
+}
+
+fn triangle_start(mask: u32) -> u32 {
+    return TRIANGLE_OFFSET_TABLE[max(i32(mask) - 1, 0)];
+}
+
+// Vertices of the cell, zero outside the volume, in the skipped bricks and in the clipped cells
+fn cell_vertex_count(cell: vec3<u32>) -> u32 {
+    if (any(cell >= cell_count())) {
+        return 0u;
+    }
+    let coord = corner_position(cell);
+    if (bricks[brick_index(cell / BRICK_SIZE)].vertex_count == 0u
+        || clip_cell(coord, coord + volume.voxel_size, volume.clipping)) {
+        return 0u;
+    }
+    let mask = cell_mask(cell);
+    return TRIANGLE_OFFSET_TABLE[mask] - triangle_start(mask);
+}
+
+// Corners of the cell with the field in w
+fn cell_corners(cell: vec3<u32>) -> array<vec4<f32>, 8> {
+    var data: array<vec4<f32>, 8>;
+    for (var i: u32 = 0; i < 8; i++) {
+        let corner = cell + vec3((i & 1) >> 0, (i & 2) >> 1, (i & 4) >> 2);
+        data[i] = vec4<f32>(corner_position(corner), samples[sample_index(corner)]);
+    }
+    return data;
+}
+
+// Vertex on the edge of the cell with the corners `data`
+fn edge_vertex(data: array<vec4<f32>, 8>, edge_id: u32) -> Vertex {
+    let first = EDGE_VERTEX_IDS[edge_id].x;
+    let second = EDGE_VERTEX_IDS[edge_id].y;
+
+    // Interpolation
+    var final_point: vec3<f32>;
+    if (abs(data[first].w) < EPSILON) {
+        final_point = data[first].xyz;
+    } else if (abs(data[second].w) < EPSILON) {
+        final_point = data[second].xyz;
+    } else if (abs(data[first].w - data[second].w) < EPSILON) {
+        final_point = data[first].xyz;
+    } else {
+        let factor = -data[first].w / (data[second].w - data[first].w);
+        final_point = mix(data[first].xyz, data[second].xyz, factor);
+    }
+
+    // the surface shows the material of the inside corner, triangles blend between them
+    let inside = select(second, first, data[first].w < 0.0);
+    var vertex: Vertex;
+    vertex.position = final_point;
+    vertex.normal = field_normal(final_point);
+    vertex.occlusion = ambient_occlusion(final_point, vertex.normal);
+    vertex.material_weights = density::material_weights(material(data[inside].xyz));
+    if ((volume.clipping.flags & CLIP_COLORMAP) != 0u) {
+        vertex.section = source_field(final_point);
+    }
+    return vertex;
+}
+
+// Range of the samples of every brick, the bricks without a sign change are skipped
+@compute @workgroup_size(4, 4, 4)
+fn classify_bricks(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    if (any(invocation_id >= brick_count())) {
+        return;
+    }
+    let first = invocation_id * BRICK_SIZE;
+    let last = min(first + BRICK_SIZE, cell_count());
+    var min_field = 3.40282347e38;
+    var max_field = -3.40282347e38;
+    for (var z = first.z; z <= last.z; z++) {
+        for (var y = first.y; y <= last.y; y++) {
+            for (var x = first.x; x <= last.x; x++) {
+                let sample = samples[sample_index(vec3(x, y, z))];
+                min_field = min(min_field, sample);
+                max_field = max(max_field, sample);
+            }
+        }
+    }
+
+    // same test as the cube mask, some corner inside and some outside, the draw args are
+    // written after the scan
+    let crossed = min_field < 0.0 && max_field >= 0.0;
+    var brick: Brick;
+    brick.vertex_count = select(0u, 1u, crossed);
+    brick.instance_count = 1u;
+    bricks[brick_index(invocation_id)] = brick;
+}
+
+// Vertex count of every cell, zero in the skipped bricks and the clipped cells
+@compute @workgroup_size(2, 2, 2)
+fn classify_cells(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    // the padding cells of the partial bricks stay at zero
+    let brick_cells = brick_count() * BRICK_SIZE;
+    if (any(invocation_id >= brick_cells)) {
+        return;
+    }
+    cell_counts[cell_index(invocation_id)] = cell_vertex_count(invocation_id);
+}
+
+// Vertex range of every brick from the scanned cell counts
+@compute @workgroup_size(4, 4, 4)
+fn write_brick_args(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    if (any(invocation_id >= brick_count())) {
+        return;
+    }
+    let index = brick_index(invocation_id);
+    let first_cell = index * BRICK_SIZE * BRICK_SIZE * BRICK_SIZE;
+    let last_cell = first_cell + BRICK_SIZE * BRICK_SIZE * BRICK_SIZE;
+    bricks[index].first_vertex = cell_offsets[first_cell];
+    bricks[index].vertex_count = cell_offsets[last_cell] - cell_offsets[first_cell];
+}
+
+// Writes the vertices of every cell at its scanned offset
+@compute @workgroup_size(2, 2, 2)
+fn compute_vertices(@builtin(global_invocation_id) invocation_id: vec3<u32>) { 
+    if (any(invocation_id >= cell_count())) {
+        return;
+    }
+    let idx = cell_index(invocation_id);
+    if (cell_counts[idx] == 0u) {
+        return;
+    }
 
-    let count = vec3<u32>(floor(volume_size / volume.voxel_size));
-    let idx = invocation_id.x + count.x * (invocation_id.y + count.y * invocation_id.z); 
+    let data = cell_corners(invocation_id);
+    let mask = cell_mask(invocation_id);
 
-    let start = TRIANGLE_OFFSET_TABLE[max(i32(mask) - 1, 0)];
+    let start = triangle_start(mask);
     let end = TRIANGLE_OFFSET_TABLE[mask];
     for (var i = start; i < end; i++) {
-        let edge_id = TRIANGLE_TABLE[i]; 
-        let first = EDGE_VERTEX_IDS[edge_id].x;
-        let second = EDGE_VERTEX_IDS[edge_id].y;
-
-        // Interpolation
-        var final_point: vec3<f32>;
-        if (abs(data[first].w) < EPSILON) {
-            final_point = data[first].xyz;
-        } else if (abs(data[second].w) < EPSILON) {
-            final_point = data[second].xyz;
-        } else if (abs(data[first].w - data[second].w) < EPSILON) {
-            final_point = data[first].xyz;
-        } else {
-            let factor = -data[first].w / (data[second].w - data[first].w);
-            final_point = mix(data[first].xyz, data[second].xyz, factor);
+        output[cell_offsets[idx] + i - start] = edge_vertex(data, TRIANGLE_TABLE[i]);
+    }
+}
+
+// Child `i` of a pyramid texel, in the order the traversal counts them
+fn child_offset(i: u32) -> vec3<u32> {
+    return vec3((i & 1) >> 0, (i & 2) >> 1, (i & 4) >> 2);
+}
+
+// Whether the level has the texel, the axes of the base are sized apart so the short ones run
+// out of texels before the top and leave their parents with a single child along them
+fn in_level(texel: vec3<u32>, level: i32) -> bool {
+    return all(texel < textureDimensions(pyramid, level));
+}
+
+// Triangle count of every cell of the pyramid base, the cells past the volume count none
+@compute @workgroup_size(4, 4, 4)
+fn classify_pyramid_cells(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    if (any(invocation_id >= textureDimensions(pyramid_level))) {
+        return;
+    }
+    let count = cell_vertex_count(invocation_id) / 3u;
+    textureStore(pyramid_level, invocation_id, vec4(count, 0u, 0u, 0u));
+}
+
+// Sums the eight texels of the level below into every texel of the level
+@compute @workgroup_size(4, 4, 4)
+fn reduce_pyramid(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    if (any(invocation_id >= textureDimensions(pyramid_level))) {
+        return;
+    }
+    var count = 0u;
+    for (var i = 0u; i < 8u; i++) {
+        let child = invocation_id * 2u + child_offset(i);
+        if (in_level(child, 0)) {
+            count += textureLoad(pyramid, child, 0).x;
+        }
+    }
+    textureStore(pyramid_level, invocation_id, vec4(count, 0u, 0u, 0u));
+}
+
+// Vertex range of every brick, the cells of a brick are one subtree of the pyramid so its
+// triangles are contiguous, and the dispatch of the traversal
+@compute @workgroup_size(4, 4, 4)
+fn write_pyramid_args(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let top = i32(textureNumLevels(pyramid)) - 1;
+    if (all(invocation_id == vec3(0u))) {
+        let total = textureLoad(pyramid, vec3(0u), top).x;
+        let workgroups = (total + TRAVERSAL_WORKGROUP_SIZE - 1u) / TRAVERSAL_WORKGROUP_SIZE;
+        let rows = (workgroups + MAX_WORKGROUPS_PER_DIMENSION - 1u) / MAX_WORKGROUPS_PER_DIMENSION;
+        traversal_args.workgroups = vec3(min(workgroups, MAX_WORKGROUPS_PER_DIMENSION), rows, 1u);
+    }
+    if (any(invocation_id >= brick_count())) {
+        return;
+    }
+
+    // the triangles of the siblings before every ancestor of the brick come first
+    var offset = 0u;
+    for (var level = top; level > BRICK_LEVEL; level--) {
+        let child = invocation_id >> vec3(u32(level - 1 - BRICK_LEVEL));
+        let first = (child >> vec3(1u)) * 2u;
+        let order = dot(child & vec3(1u), vec3(1u, 2u, 4u));
+        for (var i = 0u; i < order; i++) {
+            let sibling = first + child_offset(i);
+            if (in_level(sibling, level - 1)) {
+                offset += textureLoad(pyramid, sibling, level - 1).x;
+            }
         }
-        
-        output[MAX_VERTS * idx + i - start] = final_point;
     }
+    let index = brick_index(invocation_id);
+    bricks[index].first_vertex = 3u * offset;
+    bricks[index].vertex_count = 3u * textureLoad(pyramid, invocation_id, BRICK_LEVEL).x;
 }
 
+// Emits one triangle per invocation, found by walking down the pyramid
+@compute @workgroup_size(64)
+fn traverse_pyramid(
+    @builtin(local_invocation_index) local: u32,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>,
+) {
+    let workgroup = workgroup_id.x + workgroup_id.y * num_workgroups.x;
+    let index = workgroup * TRAVERSAL_WORKGROUP_SIZE + local;
+    let top = i32(textureNumLevels(pyramid)) - 1;
+    if (index >= textureLoad(pyramid, vec3(0u), top).x) {
+        return;
+    }
+
+    // skip the children holding the triangles before it, what remains indexes into the cell
+    var remaining = index;
+    var cell = vec3(0u);
+    for (var level = top; level > 0; level--) {
+        let first = cell * 2u;
+        for (var i = 0u; i < 8u; i++) {
+            let child = first + child_offset(i);
+            if (!in_level(child, level - 1)) {
+                continue;
+            }
+            cell = child;
+            let count = textureLoad(pyramid, cell, level - 1).x;
+            if (remaining < count) {
+                break;
+            }
+            remaining -= count;
+        }
+    }
+
+    let data = cell_corners(cell);
+    let start = triangle_start(cell_mask(cell)) + 3u * remaining;
+    for (var i = 0u; i < 3u; i++) {
+        output[3u * index + i] = edge_vertex(data, TRIANGLE_TABLE[start + i]);
+    }
+}
@@ -0,0 +1,296 @@
+#define_import_path marching_cubes::density
+
+#import marching_cubes::noise::{NoiseParams, noise_density}
+#import marching_cubes::terrain::{TerrainParams, terrain_material, terrain_solid, tunnel_distance}
+#import marching_cubes::clipping::{ClipParams, CLIP_CAPPED, clip_distance}
+
+// Density of the `VoxelVolume`, shared by the compute stage and the raymarched display stage.
+// `DENSITY_GROUP` is the bind group holding the bindings below in the importing pipeline.
+
+struct VoxelVolume {
+    min_bound: vec3<f32>,
+    max_bound: vec3<f32>,
+    voxel_size: f32,
+    isovalue: f32,
+    density_source: u32,
+    sampled_dims: vec3<u32>,
+    sampled_min: vec3<f32>,
+    sampled_max: vec3<f32>,
+    // seconds, from `VoxelVolume::time_source`
+    time: f32,
+    @align(16) noise: NoiseParams,
+    @align(16) terrain: TerrainParams,
+    user_params: array<vec4<f32>, 4>,
+    @align(16) ambient_occlusion: AmbientOcclusionParams,
+    @align(16) clipping: ClipParams,
+};
+
+// Must match `AmbientOcclusionUniform`
+struct AmbientOcclusionParams {
+    directions: u32,
+    steps: u32,
+    // in voxels
+    radius: f32,
+    strength: f32,
+};
+
+@group(#{DENSITY_GROUP}) @binding(1) var<uniform> volume: VoxelVolume;
+@group(#{DENSITY_GROUP}) @binding(2) var<storage, read> densities: array<f32>;
+
+// Must match `MetaballInstance`
+struct Metaball {
+    center: vec3<f32>,
+    radius: f32,
+    strength: f32,
+    kernel: u32,
+};
+
+// never empty, padded with a zero strength ball
+@group(#{DENSITY_GROUP}) @binding(3) var<storage, read> metaballs: array<Metaball>;
+
+// Must match `TunnelSegment`
+struct TunnelSegment {
+    start: vec3<f32>,
+    start_radius: f32,
+    end: vec3<f32>,
+    end_radius: f32,
+};
+
+// never empty, padded with a zero radius segment
+@group(#{DENSITY_GROUP}) @binding(4) var<storage, read> tunnels: array<TunnelSegment>;
+
+// material id per sample, a single zero when the sampled volume has none
+@group(#{DENSITY_GROUP}) @binding(5) var<storage, read> materials: array<u32>;
+
+// offsets of the tunnel segments of every brick followed by their indices, see `bin_tunnels`
+@group(#{DENSITY_GROUP}) @binding(6) var<storage, read> tunnel_bins: array<u32>;
+
+// Must match `DensitySource::shader_index`
+const DENSITY_ANALYTIC: u32 = 0;
+const DENSITY_SAMPLED: u32 = 1;
+const DENSITY_NOISE: u32 = 2;
+const DENSITY_METABALLS: u32 = 3;
+const DENSITY_FLUID: u32 = 4;
+const DENSITY_GRID_FLUID: u32 = 5;
+const DENSITY_AUTOMATON: u32 = 6;
+const DENSITY_TERRAIN: u32 = 7;
+
+// Must match `MetaballKernel::shader_index`
+const KERNEL_WYVILL: u32 = 0;
+const KERNEL_QUADRATIC: u32 = 1;
+const KERNEL_LINEAR: u32 = 2;
+
+// Must match `BRICK_SIZE`
+const TUNNEL_BIN_VOXELS: u32 = 8;
+
+// Must match `MAX_MATERIALS`
+const MAX_MATERIALS: u32 = 4;
+const GOLDEN_ANGLE: f32 = 2.399963;
+
+fn sphere_sdf(pos: vec3<f32>, radius: f32) -> f32 {
+    return length(pos) - radius;
+}
+
+fn torus_sdf(pos: vec3<f32>, radius: vec2<f32>) -> f32 {
+    let q = vec2<f32>(length(pos.xz) - radius.x, pos.y);
+    return length(q) - radius.y;
+}
+
+// `volume.time` and `volume.user_params` animate the scene, mirrored by `scene_sdf` in `cpu_mesher`
+fn scene_sdf(pos: vec3<f32>) -> f32 {
+    // return sphere_sdf(pos, 0.5); 
+    let params = volume.user_params[0];
+    let pulse = params.z * sin(volume.time * params.w);
+    return torus_sdf(pos, vec2f(0.5, 0.15) + vec2f(params.x, params.y + pulse));
+}
+
+fn sampled_value(coord: vec3<u32>) -> f32 {
+    let dims = volume.sampled_dims;
+    return densities[coord.x + dims.x * (coord.y + dims.y * coord.z)];
+}
+
+// Trilinear interpolation of the sampled volume, mirrors `ScalarVolume::sample`
+fn sampled_density(pos: vec3<f32>) -> f32 {
+    let last = volume.sampled_dims - 1u;
+    let extent = volume.sampled_max - volume.sampled_min;
+    let grid = clamp((pos - volume.sampled_min) / extent, vec3(0.0), vec3(1.0)) * vec3<f32>(last);
+    let base = min(vec3<u32>(floor(grid)), last - 1u);
+    let t = grid - vec3<f32>(base);
+
+    let x0 = mix(sampled_value(base), sampled_value(base + vec3(1u, 0u, 0u)), t.x);
+    let x1 = mix(sampled_value(base + vec3(0u, 1u, 0u)), sampled_value(base + vec3(1u, 1u, 0u)), t.x);
+    let x2 = mix(sampled_value(base + vec3(0u, 0u, 1u)), sampled_value(base + vec3(1u, 0u, 1u)), t.x);
+    let x3 = mix(sampled_value(base + vec3(0u, 1u, 1u)), sampled_value(base + vec3(1u, 1u, 1u)), t.x);
+    return mix(mix(x0, x1, t.y), mix(x2, x3, t.y), t.z);
+}
+
+// Mirrors `MetaballKernel::falloff`
+fn metaball_falloff(kernel: u32, t: f32) -> f32 {
+    if (t >= 1.0) {
+        return 0.0;
+    }
+    let s = 1.0 - t * t;
+    switch kernel {
+        case KERNEL_QUADRATIC: {
+            return s * s;
+        }
+        case KERNEL_LINEAR: {
+            return 1.0 - t;
+        }
+        default: {
+            return s * s * s;
+        }
+    }
+}
+
+// Summed field of all metaballs, mirrors `metaball_density` in `metaball/mod.rs`
+fn metaball_density(pos: vec3<f32>) -> f32 {
+    var sum = 0.0;
+    for (var i: u32 = 0; i < arrayLength(&metaballs); i++) {
+        let ball = metaballs[i];
+        if (ball.radius > 0.0) {
+            sum += ball.strength * metaball_falloff(ball.kernel, distance(pos, ball.center) / ball.radius);
+        }
+    }
+    return sum;
+}
+
+// Terrain with the worm tunnels near the brick of `pos` carved out, mirrors
+// `TerrainGenerator::density`
+fn terrain_density(pos: vec3<f32>) -> f32 {
+    let count_dims = vec3<u32>((volume.max_bound - volume.min_bound) / volume.voxel_size);
+    let bins = max((count_dims + TUNNEL_BIN_VOXELS - 1u) / TUNNEL_BIN_VOXELS, vec3(1u));
+    let bin_size = volume.voxel_size * f32(TUNNEL_BIN_VOXELS);
+    let grid = floor((pos - volume.min_bound) / bin_size);
+    let bin = vec3<u32>(clamp(grid, vec3(0.0), vec3<f32>(bins - 1u)));
+    let index = bin.x + bins.x * (bin.y + bins.y * bin.z);
+    // the indices follow the offsets of every bin and the end of the last one
+    let indices = bins.x * bins.y * bins.z + 1u;
+
+    var tunnel = volume.terrain.tunnel_reach;
+    for (var i = tunnel_bins[index]; i < tunnel_bins[index + 1u]; i++) {
+        let segment = tunnels[tunnel_bins[indices + i]];
+        tunnel = min(tunnel, tunnel_distance(pos, segment.start, segment.start_radius, segment.end, segment.end_radius));
+    }
+    return min(terrain_solid(pos, volume.terrain), tunnel);
+}
+
+// Field of the density source before the clipping, negative inside
+fn source_field(pos: vec3<f32>) -> f32 {
+    // simulations write their density into the sampled volume
+    let source = volume.density_source;
+    if (source == DENSITY_SAMPLED || source == DENSITY_FLUID || source == DENSITY_GRID_FLUID
+        || source == DENSITY_AUTOMATON) {
+        // volume is not loaded yet
+        if (any(volume.sampled_dims < vec3(2u))) {
+            return 1.0;
+        }
+        return volume.isovalue - sampled_density(pos);
+    }
+    if (volume.density_source == DENSITY_NOISE) {
+        return volume.isovalue - noise_density(pos, volume.noise);
+    }
+    if (volume.density_source == DENSITY_METABALLS) {
+        return volume.isovalue - metaball_density(pos);
+    }
+    if (volume.density_source == DENSITY_TERRAIN) {
+        return volume.isovalue - terrain_density(pos);
+    }
+    return scene_sdf(pos) - volume.isovalue;
+}
+
+// Signed field whose zero crossing is the extracted surface, negative inside. A capped clip
+// closes the surface at the cut, mirrors `VoxelClipping::clip`
+fn field(pos: vec3<f32>) -> f32 {
+    let density = source_field(pos);
+    if ((volume.clipping.flags & CLIP_CAPPED) != 0u) {
+        return max(density, clip_distance(pos, volume.clipping));
+    }
+    return density;
+}
+
+// Nearest material id of the sampled volume, mirrors `ScalarVolume::material`
+fn sampled_material(pos: vec3<f32>) -> u32 {
+    let dims = volume.sampled_dims;
+    if (any(dims < vec3(2u)) || arrayLength(&materials) != dims.x * dims.y * dims.z) {
+        return 0u;
+    }
+    let last = dims - 1u;
+    let extent = volume.sampled_max - volume.sampled_min;
+    let grid = clamp((pos - volume.sampled_min) / extent, vec3(0.0), vec3(1.0)) * vec3<f32>(last);
+    let nearest = min(vec3<u32>(floor(grid + 0.5)), last);
+    return materials[nearest.x + dims.x * (nearest.y + dims.y * nearest.z)];
+}
+
+// Material id at pos, mirrors `CpuDensity::material`
+fn material(pos: vec3<f32>) -> u32 {
+    if (volume.density_source == DENSITY_SAMPLED) {
+        return sampled_material(pos);
+    }
+    if (volume.density_source == DENSITY_TERRAIN) {
+        return terrain_material(pos, volume.terrain);
+    }
+    return 0u;
+}
+
+// Mirrors `material_weights`
+fn material_weights(material: u32) -> vec4<f32> {
+    let id = min(material, MAX_MATERIALS - 1u);
+    return select(vec4(0.0), vec4(1.0), vec4(id) == vec4(0u, 1u, 2u, 3u));
+}
+
+// Mirrors `hemisphere_direction` in `occlusion/mod.rs`
+fn hemisphere_direction(i: u32, count: u32) -> vec3<f32> {
+    let u = (f32(i) + 0.5) / f32(count);
+    let radius = sqrt(u);
+    let angle = f32(i) * GOLDEN_ANGLE;
+    return vec3(radius * cos(angle), radius * sin(angle), sqrt(1.0 - u));
+}
+
+// Outward normal from the central differences of the field, mirrors `gradient` in `cpu_mesher`
+fn field_normal(pos: vec3<f32>) -> vec3<f32> {
+    let step = volume.voxel_size * 0.5;
+    let gradient = vec3(
+        field(pos + vec3(step, 0.0, 0.0)) - field(pos - vec3(step, 0.0, 0.0)),
+        field(pos + vec3(0.0, step, 0.0)) - field(pos - vec3(0.0, step, 0.0)),
+        field(pos + vec3(0.0, 0.0, step)) - field(pos - vec3(0.0, 0.0, step)),
+    );
+    if (all(gradient == vec3(0.0))) {
+        return vec3(0.0);
+    }
+    return normalize(gradient);
+}
+
+// Mirrors `AmbientOcclusion::occlusion`
+fn ambient_occlusion(pos: vec3<f32>, normal: vec3<f32>) -> f32 {
+    let params = volume.ambient_occlusion;
+    if (params.directions == 0u || params.steps == 0u || all(normal == vec3(0.0))) {
+        return 1.0;
+    }
+
+    // orthonormal basis around the normal, same as glam's `any_orthonormal_pair`
+    let sign = select(-1.0, 1.0, normal.z >= 0.0);
+    let a = -1.0 / (sign + normal.z);
+    let b = normal.x * normal.y * a;
+    let tangent = vec3(1.0 + sign * normal.x * normal.x * a, sign * b, -sign * normal.x);
+    let bitangent = vec3(b, sign + normal.y * normal.y * a, -normal.y);
+
+    let ray_step = params.radius * volume.voxel_size / f32(params.steps);
+    var visibility = 0.0;
+    for (var i = 0u; i < params.directions; i++) {
+        let local = hemisphere_direction(i, params.directions);
+        let direction = tangent * local.x + bitangent * local.y + normal * local.z;
+
+        var ray_visibility = 1.0;
+        for (var k = 1u; k <= params.steps; k++) {
+            if (field(pos + direction * ray_step * f32(k)) < 0.0) {
+                ray_visibility = f32(k - 1u) / f32(params.steps);
+                break;
+            }
+        }
+        visibility += ray_visibility;
+    }
+
+    let occluded = 1.0 - visibility / f32(params.directions);
+    return clamp(1.0 - params.strength * occluded, 0.0, 1.0);
+}
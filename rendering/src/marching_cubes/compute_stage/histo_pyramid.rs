@@ -0,0 +1,759 @@
+use bevy_math::UVec3;
+use bevy_render::render_resource::binding_types::{
+    storage_buffer_sized, texture_3d, texture_storage_3d,
+};
+use bevy_render::render_resource::{
+    BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, Buffer, BufferDescriptor,
+    BufferUsages, CachedComputePipelineId, ComputePass, ComputePipeline, ComputePipelineDescriptor,
+    Extent3d, PipelineCache, ShaderDefVal, ShaderStages, StorageTextureAccess, TextureDescriptor,
+    TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureView,
+    TextureViewDescriptor,
+};
+use bevy_render::renderer::RenderDevice;
+
+use super::{BRICK_SIZE, COMPUTE_STAGE_SHADER_HANDLE};
+
+// must match the histo pyramid entry points in compute_stage.wgsl, indexed by the consts below
+const ENTRY_POINTS: [&str; 4] = [
+    "classify_pyramid_cells",
+    "reduce_pyramid",
+    "write_pyramid_args",
+    "traverse_pyramid",
+];
+const CLASSIFY_PYRAMID_CELLS: usize = 0;
+const REDUCE_PYRAMID: usize = 1;
+const WRITE_PYRAMID_ARGS: usize = 2;
+const TRAVERSE_PYRAMID: usize = 3;
+
+// workgroup size of `classify_pyramid_cells` and `reduce_pyramid` along every axis
+const LEVEL_WORKGROUP_SIZE: u32 = 4;
+
+/// Pipelines of [`SurfaceExtraction::HistoPyramid`](super::SurfaceExtraction::HistoPyramid),
+/// bound after the marching cubes bind group.
+pub struct HistoPyramidPipeline {
+    /// Writes a single level, reading the one below it.
+    level_layout: BindGroupLayout,
+    /// Reads every level.
+    traversal_layout: BindGroupLayout,
+    pub(crate) pipeline_ids: [CachedComputePipelineId; 4],
+}
+
+impl HistoPyramidPipeline {
+    pub(crate) fn new(
+        render_device: &RenderDevice,
+        pipeline_cache: &PipelineCache,
+        bind_group_layout: &BindGroupLayout,
+    ) -> Self {
+        let (level_layout, traversal_layout) = bind_group_layouts(render_device);
+        let pipeline_ids = core::array::from_fn(|index| {
+            let entry_point = ENTRY_POINTS[index];
+            let layout = match index {
+                CLASSIFY_PYRAMID_CELLS | REDUCE_PYRAMID => &level_layout,
+                _ => &traversal_layout,
+            };
+            pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                label: Some(format!("marching_cubes_{entry_point}_pipeline").into()),
+                layout: vec![bind_group_layout.clone(), layout.clone()],
+                push_constant_ranges: Vec::new(),
+                shader: COMPUTE_STAGE_SHADER_HANDLE,
+                shader_defs: vec![ShaderDefVal::UInt("DENSITY_GROUP".into(), 0)],
+                entry_point: entry_point.into(),
+                zero_initialize_workgroup_memory: false,
+            })
+        });
+
+        Self {
+            level_layout,
+            traversal_layout,
+            pipeline_ids,
+        }
+    }
+
+    /// Compiled pipelines of the histo pyramid entry points, in order.
+    ///
+    /// Expects them compiled, like the marching cubes node before it runs.
+    pub(crate) fn pipelines<'a>(
+        &self,
+        pipeline_cache: &'a PipelineCache,
+    ) -> [&'a ComputePipeline; 4] {
+        self.pipeline_ids
+            .map(|id| pipeline_cache.get_compute_pipeline(id).unwrap())
+    }
+}
+
+/// Layouts of the bind group writing a level and of the one reading every level.
+fn bind_group_layouts(render_device: &RenderDevice) -> (BindGroupLayout, BindGroupLayout) {
+    let level_layout = render_device.create_bind_group_layout(
+        "histo_pyramid_level_bind_group",
+        &BindGroupLayoutEntries::with_indices(
+            ShaderStages::COMPUTE,
+            (
+                (0, texture_3d(TextureSampleType::Uint)),
+                (
+                    1,
+                    texture_storage_3d(TextureFormat::R32Uint, StorageTextureAccess::WriteOnly),
+                ),
+            ),
+        ),
+    );
+    let traversal_layout = render_device.create_bind_group_layout(
+        "histo_pyramid_traversal_bind_group",
+        &BindGroupLayoutEntries::with_indices(
+            ShaderStages::COMPUTE,
+            (
+                (0, texture_3d(TextureSampleType::Uint)),
+                (2, storage_buffer_sized(false, None)),
+            ),
+        ),
+    );
+    (level_layout, traversal_layout)
+}
+
+/// Triangle counts of a box of cells in the levels of a 3D texture, every texel sums the up to
+/// eight below it up to the total in the single texel at the top.
+pub struct HistoPyramid {
+    size: UVec3,
+    /// Every level, read by the traversal.
+    view: TextureView,
+    /// One view per level, written by the reduction.
+    levels: Vec<TextureView>,
+    /// Dispatch args of `traverse_pyramid`, written by `write_pyramid_args`.
+    traversal_args: Buffer,
+}
+
+impl HistoPyramid {
+    pub fn new(render_device: &RenderDevice, size: UVec3) -> Self {
+        let level_count = level_count(size);
+        let texture = render_device.create_texture(&TextureDescriptor {
+            label: Some("histo_pyramid"),
+            size: Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: size.z,
+            },
+            mip_level_count: level_count,
+            sample_count: 1,
+            dimension: TextureDimension::D3,
+            format: TextureFormat::R32Uint,
+            usage: TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        let levels = (0..level_count)
+            .map(|level| {
+                texture.create_view(&TextureViewDescriptor {
+                    base_mip_level: level,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let traversal_args = render_device.create_buffer(&BufferDescriptor {
+            label: Some("histo_pyramid_traversal_args"),
+            // the workgroups and the padding of `TraversalArgs` up to its 16 byte alignment
+            size: 4 * size_of::<u32>() as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::INDIRECT,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            size,
+            view,
+            levels,
+            traversal_args,
+        }
+    }
+
+    /// Cells along every axis of the base.
+    #[inline]
+    pub fn size(&self) -> UVec3 {
+        self.size
+    }
+
+    pub fn bind(
+        &self,
+        render_device: &RenderDevice,
+        pipeline: &HistoPyramidPipeline,
+    ) -> HistoPyramidBindGroups {
+        // the classification does not read the level below, it binds the one above instead
+        let levels = (0..self.levels.len())
+            .map(|level| {
+                let below = if level == 0 { 1 } else { level - 1 };
+                render_device.create_bind_group(
+                    Some("histo_pyramid_level_bind_group"),
+                    &pipeline.level_layout,
+                    &BindGroupEntries::with_indices((
+                        (0, &self.levels[below]),
+                        (1, &self.levels[level]),
+                    )),
+                )
+            })
+            .collect();
+        let traversal = render_device.create_bind_group(
+            Some("histo_pyramid_traversal_bind_group"),
+            &pipeline.traversal_layout,
+            &BindGroupEntries::with_indices((
+                (0, &self.view),
+                (2, self.traversal_args.as_entire_binding()),
+            )),
+        );
+
+        HistoPyramidBindGroups {
+            size: self.size,
+            levels,
+            traversal,
+            traversal_args: self.traversal_args.clone(),
+        }
+    }
+}
+
+/// Extraction of [`HistoPyramid::bind`], recorded into a compute pass.
+pub struct HistoPyramidBindGroups {
+    size: UVec3,
+    levels: Vec<BindGroup>,
+    traversal: BindGroup,
+    traversal_args: Buffer,
+}
+
+impl HistoPyramidBindGroups {
+    /// Records the classification, the reduction and the traversal with the pipelines of
+    /// [`HistoPyramidPipeline::pipelines`].
+    ///
+    /// Expects the marching cubes bind group and its samples and bricks in place.
+    pub fn record(
+        &self,
+        pass: &mut ComputePass,
+        pipelines: &[&ComputePipeline],
+        brick_workgroups: UVec3,
+    ) {
+        // the base first, then every level up from the one below
+        for (level, bind_group) in self.levels.iter().enumerate() {
+            let entry_point = match level {
+                0 => CLASSIFY_PYRAMID_CELLS,
+                _ => REDUCE_PYRAMID,
+            };
+            let workgroups = (level_size(self.size, level as u32)
+                + UVec3::splat(LEVEL_WORKGROUP_SIZE - 1))
+                / LEVEL_WORKGROUP_SIZE;
+            pass.set_pipeline(pipelines[entry_point]);
+            pass.set_bind_group(1, bind_group, &[]);
+            pass.dispatch_workgroups(workgroups.x, workgroups.y, workgroups.z);
+        }
+
+        // the first invocation writes the traversal args, even without bricks
+        let brick_workgroups = brick_workgroups.max(UVec3::ONE);
+        pass.set_pipeline(pipelines[WRITE_PYRAMID_ARGS]);
+        pass.set_bind_group(1, &self.traversal, &[]);
+        pass.dispatch_workgroups(brick_workgroups.x, brick_workgroups.y, brick_workgroups.z);
+
+        pass.set_pipeline(pipelines[TRAVERSE_PYRAMID]);
+        pass.dispatch_workgroups_indirect(&self.traversal_args, 0);
+    }
+}
+
+/// Cells along every axis of the pyramid base, the powers of two covering all bricks.
+pub fn pyramid_size(brick_dims: UVec3) -> UVec3 {
+    UVec3::from_array(
+        (brick_dims * BRICK_SIZE)
+            .to_array()
+            .map(|cells| cells.next_power_of_two().max(BRICK_SIZE)),
+    )
+}
+
+/// Levels from the base to the single texel at the top.
+fn level_count(size: UVec3) -> u32 {
+    size.max_element().trailing_zeros() + 1
+}
+
+/// Texels of `level` along every axis, the short axes stop halving at one like the mips.
+fn level_size(size: UVec3, level: u32) -> UVec3 {
+    (size >> level).max(UVec3::ONE)
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_math::Vec3;
+    use bevy_math::bounding::Aabb3d;
+    use bevy_render::render_resource::encase::UniformBuffer;
+    use bevy_render::render_resource::{BufferInitDescriptor, WgpuLimits};
+    use naga_oil::compose::ShaderDefValue;
+
+    use super::*;
+    use crate::marching_cubes::compute_stage::node::record;
+    use crate::marching_cubes::compute_stage::pipeline::{
+        self, ExtractionBindGroups, MarchingCubesBindGroup, bind_group_layout,
+    };
+    use crate::marching_cubes::compute_stage::{SurfaceExtraction, VoxelVolumeUniform};
+    use crate::marching_cubes::cpu_mesher::tables::TRIANGLE_OFFSET_TABLE;
+    use crate::marching_cubes::cpu_mesher::{active_cells, polygonize};
+    use crate::marching_cubes::{MAX_VERTS_PER_VOXEL, Vertex, VoxelBrick, VoxelVolume};
+    use crate::scan::{GpuScan, ScanPipeline, exclusive_scan};
+    use crate::testing::{adapter_device, compose, compute_pipeline, read_buffer, test_adapter};
+
+    const BRICK_LEVEL: usize = BRICK_SIZE.trailing_zeros() as usize;
+
+    /// Texels of every level, x first, with the triangle counts of the cells in the base.
+    struct Pyramid {
+        size: UVec3,
+        levels: Vec<Vec<u32>>,
+    }
+
+    impl Pyramid {
+        /// `classify_pyramid_cells` and `reduce_pyramid` on the CPU.
+        fn new(size: UVec3, triangles: impl Fn(UVec3) -> u32) -> Self {
+            let mut pyramid = Self {
+                size,
+                levels: vec![texels(size).map(triangles).collect()],
+            };
+            for level in 1..level_count(size) as usize {
+                let texels = texels(level_size(size, level as u32))
+                    .map(|texel| {
+                        (0..8)
+                            .filter_map(|i| pyramid.texel(level - 1, texel * 2 + child(i)))
+                            .sum()
+                    })
+                    .collect();
+                pyramid.levels.push(texels);
+            }
+            pyramid
+        }
+
+        /// Count of the texel, none past the level like `in_level`.
+        fn texel(&self, level: usize, texel: UVec3) -> Option<u32> {
+            let size = level_size(self.size, level as u32);
+            texel
+                .cmplt(size)
+                .all()
+                .then(|| self.levels[level][index(size, texel)])
+        }
+
+        fn total(&self) -> u32 {
+            self.levels.last().unwrap()[0]
+        }
+
+        /// Cell of the triangle and its index in the cell, the walk of `traverse_pyramid`.
+        fn locate(&self, triangle: u32) -> (UVec3, u32) {
+            let mut remaining = triangle;
+            let mut cell = UVec3::ZERO;
+            for level in (1..self.levels.len()).rev() {
+                let first = cell * 2;
+                for i in 0..8 {
+                    let Some(count) = self.texel(level - 1, first + child(i)) else {
+                        continue;
+                    };
+                    cell = first + child(i);
+                    if remaining < count {
+                        break;
+                    }
+                    remaining -= count;
+                }
+            }
+            (cell, remaining)
+        }
+
+        /// First triangle and triangle count of the brick, what `write_pyramid_args` writes.
+        fn brick_range(&self, brick: UVec3) -> (u32, u32) {
+            let mut offset = 0;
+            for level in (BRICK_LEVEL + 1..self.levels.len()).rev() {
+                let node = brick >> (level - 1 - BRICK_LEVEL) as u32;
+                let first = (node >> 1u32) * 2;
+                let order = (node & UVec3::ONE).dot(UVec3::new(1, 2, 4));
+                offset += (0..order)
+                    .filter_map(|i| self.texel(level - 1, first + child(i)))
+                    .sum::<u32>();
+            }
+            (offset, self.texel(BRICK_LEVEL, brick).unwrap())
+        }
+    }
+
+    fn child(i: u32) -> UVec3 {
+        UVec3::new(i & 1, (i & 2) >> 1, (i & 4) >> 2)
+    }
+
+    fn index(size: UVec3, texel: UVec3) -> usize {
+        (texel.x + size.x * (texel.y + size.y * texel.z)) as usize
+    }
+
+    fn texels(size: UVec3) -> impl Iterator<Item = UVec3> {
+        (0..size.z).flat_map(move |z| {
+            (0..size.y).flat_map(move |y| (0..size.x).map(move |x| UVec3::new(x, y, z)))
+        })
+    }
+
+    /// Deterministic triangle counts with empty regions, like the cells around a surface.
+    fn triangles(cell: UVec3) -> u32 {
+        let mut state = 0x9e37_79b9 ^ index(UVec3::splat(64), cell) as u32;
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state ^= state >> 17;
+        state ^= state << 5;
+        if state.is_multiple_of(4) {
+            state % 6
+        } else {
+            0
+        }
+    }
+
+    /// Cubic and flat bases, the short axes run out of texels below the top.
+    const SIZES: [UVec3; 3] = [
+        UVec3::splat(32),
+        UVec3::new(32, 8, 16),
+        UVec3::new(8, 64, 8),
+    ];
+
+    /// Volume with the surface of `field` crossing partially filled bricks.
+    fn volume() -> (VoxelVolume, impl Fn(Vec3) -> f32) {
+        let volume = VoxelVolume {
+            aabb: Aabb3d::new(Vec3::ZERO, Vec3::new(1.0, 0.55, 0.8)),
+            ..Default::default()
+        };
+        let field = |pos: Vec3| pos.length() - 0.5 + 0.05 * (pos.x * 9.0).sin();
+        (volume, field)
+    }
+
+    #[test]
+    fn base_covers_every_brick() {
+        assert_eq!(pyramid_size(UVec3::ZERO), UVec3::splat(BRICK_SIZE));
+        assert_eq!(pyramid_size(UVec3::ONE), UVec3::splat(BRICK_SIZE));
+        assert_eq!(
+            pyramid_size(UVec3::new(3, 1, 2)),
+            UVec3::new(4, 1, 2) * BRICK_SIZE
+        );
+        assert_eq!(
+            pyramid_size(UVec3::new(4, 4, 4)),
+            UVec3::splat(4 * BRICK_SIZE)
+        );
+        assert_eq!(
+            level_count(UVec3::splat(BRICK_SIZE)),
+            BRICK_LEVEL as u32 + 1
+        );
+        assert_eq!(level_count(UVec3::new(64, 8, 16)), 7);
+        assert_eq!(level_size(UVec3::new(64, 8, 16), 4), UVec3::new(4, 1, 1));
+
+        // a long thin volume only pays for its long axis, within the default 3D texture limit
+        let size = pyramid_size(UVec3::new(256, 1, 2));
+        assert_eq!(size, UVec3::new(2048, 8, 16));
+        assert!(size.max_element() <= WgpuLimits::default().max_texture_dimension_3d);
+    }
+
+    #[test]
+    fn traversal_visits_every_triangle_once() {
+        for size in SIZES {
+            let pyramid = Pyramid::new(size, triangles);
+            let total: u32 = texels(size).map(triangles).sum();
+            assert_eq!(pyramid.total(), total, "{size}");
+
+            let mut visited = vec![0; texels(size).count()];
+            for triangle in 0..total {
+                let (cell, index) = pyramid.locate(triangle);
+                assert_eq!(
+                    index,
+                    visited[self::index(size, cell)],
+                    "triangle {triangle}"
+                );
+                visited[self::index(size, cell)] += 1;
+            }
+            assert!(texels(size).all(|cell| visited[index(size, cell)] == triangles(cell)));
+        }
+    }
+
+    #[test]
+    fn bricks_are_contiguous() {
+        for size in SIZES {
+            let pyramid = Pyramid::new(size, triangles);
+            let mut covered = 0;
+            for brick in texels(size / BRICK_SIZE) {
+                let (first, count) = pyramid.brick_range(brick);
+                for triangle in first..first + count {
+                    let (cell, _) = pyramid.locate(triangle);
+                    assert_eq!(cell / BRICK_SIZE, brick, "triangle {triangle} of {size}");
+                }
+                covered += count;
+            }
+            assert_eq!(covered, pyramid.total(), "{size}");
+        }
+    }
+
+    #[test]
+    fn matches_compute_vertices() {
+        let (volume, field) = volume();
+        let min_bound = Vec3::from(volume.aabb.min);
+        let size = pyramid_size(volume.brick_dims());
+        let mut vertex_counts = vec![0; texels(size).count()];
+        for (coord, mask) in active_cells(&volume, &field) {
+            let cell = ((coord - min_bound) / volume.voxel_size).round().as_uvec3();
+            let mask = mask as usize;
+            vertex_counts[index(size, cell)] =
+                (TRIANGLE_OFFSET_TABLE[mask] - TRIANGLE_OFFSET_TABLE[mask.max(1) - 1]) as u32;
+        }
+        let vertex_count = |cell: UVec3| vertex_counts[index(size, cell)];
+        let pyramid = Pyramid::new(size, |cell| vertex_count(cell) / 3);
+
+        // `classify_cells` and the scan lay the cells out brick by brick, `compute_vertices`
+        // writes every cell at its offset
+        let brick_dims = volume.brick_dims();
+        let cells_per_brick = BRICK_SIZE.pow(3) as usize;
+        let mut cell_counts = vec![0; brick_dims.element_product() as usize * cells_per_brick];
+        for brick in texels(size / BRICK_SIZE).filter(|brick| brick.cmplt(brick_dims).all()) {
+            let brick_index =
+                (brick.x + brick_dims.x * (brick.y + brick_dims.y * brick.z)) as usize;
+            for local in texels(UVec3::splat(BRICK_SIZE)) {
+                let slot = brick_index * cells_per_brick + index(UVec3::splat(BRICK_SIZE), local);
+                cell_counts[slot] = vertex_count(brick * BRICK_SIZE + local);
+            }
+        }
+        let cell_offsets = exclusive_scan(&cell_counts);
+        assert_eq!(
+            *cell_offsets.last().unwrap(),
+            3 * pyramid.total(),
+            "total vertices"
+        );
+        assert_eq!(
+            polygonize(&volume, &field).triangle_count() as u32,
+            pyramid.total()
+        );
+
+        // both draw every brick from the same number of vertices, the same triangles of the same
+        // cells, only their order inside the brick differs
+        for brick in texels(size / BRICK_SIZE).filter(|brick| brick.cmplt(brick_dims).all()) {
+            let brick_index =
+                (brick.x + brick_dims.x * (brick.y + brick_dims.y * brick.z)) as usize;
+            let first_cell = brick_index * cells_per_brick;
+            let scanned = cell_offsets[first_cell + cells_per_brick] - cell_offsets[first_cell];
+            let (first, count) = pyramid.brick_range(brick);
+            assert_eq!(3 * count, scanned, "vertices of brick {brick}");
+
+            let mut cells: Vec<_> = (first..first + count)
+                .map(|triangle| pyramid.locate(triangle))
+                .collect();
+            cells.sort_by_key(|&(cell, index)| (cell.to_array(), index));
+            let mut expected: Vec<_> = texels(UVec3::splat(BRICK_SIZE))
+                .map(|local| brick * BRICK_SIZE + local)
+                .flat_map(|cell| (0..vertex_count(cell) / 3).map(move |index| (cell, index)))
+                .collect();
+            expected.sort_by_key(|&(cell, index)| (cell.to_array(), index));
+            assert_eq!(cells, expected, "triangles of brick {brick}");
+        }
+    }
+
+    /// `compute_stage.wgsl` with its imports, as the pipeline cache composes it.
+    fn compute_stage_module() -> naga::Module {
+        compose(
+            &[
+                (include_str!("../noise/noise.wgsl"), "noise.wgsl"),
+                (include_str!("../clipping/clipping.wgsl"), "clipping.wgsl"),
+                (include_str!("../terrain/terrain.wgsl"), "terrain.wgsl"),
+                (include_str!("density.wgsl"), "density.wgsl"),
+            ],
+            (include_str!("compute_stage.wgsl"), "compute_stage.wgsl"),
+            &[("DENSITY_GROUP", ShaderDefValue::UInt(0))],
+        )
+    }
+
+    #[test]
+    fn shader_validates_with_the_pyramid_entry_points() {
+        let module = compute_stage_module();
+        naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::default(),
+        )
+        .validate(&module)
+        .unwrap_or_else(|err| panic!("{}", err.emit_to_string("compute_stage.wgsl")));
+
+        let entry_point = |name: &str| {
+            module
+                .entry_points
+                .iter()
+                .find(|entry_point| entry_point.name == name)
+                .unwrap_or_else(|| panic!("missing entry point {name}"))
+        };
+        for name in ENTRY_POINTS {
+            let expected = match name {
+                "traverse_pyramid" => [64, 1, 1],
+                _ => [LEVEL_WORKGROUP_SIZE; 3],
+            };
+            assert_eq!(entry_point(name).workgroup_size, expected, "{name}");
+        }
+    }
+
+    /// Vertices of every brick after the compute stage of `volume` ran on the device, a triangle
+    /// per three vertices.
+    fn gpu_brick_vertices(
+        render_device: &RenderDevice,
+        queue: &wgpu::Queue,
+        volume: &VoxelVolume,
+        extraction: SurfaceExtraction,
+    ) -> Vec<Vec<[u32; size_of::<Vertex>() / 4]>> {
+        let uniform = VoxelVolumeUniform {
+            min_bound: volume.aabb.min.into(),
+            max_bound: volume.aabb.max.into(),
+            voxel_size: volume.voxel_size,
+            isovalue: volume.isovalue,
+            density_source: volume.density.shader_index(),
+            user_params: volume.user_params,
+            ambient_occlusion: volume.ambient_occlusion.into(),
+            clipping: (&volume.clipping).into(),
+            ..Default::default()
+        };
+        let mut contents = UniformBuffer::new(Vec::new());
+        contents.write(&uniform).unwrap();
+        let uniform_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            label: None,
+            contents: contents.as_ref(),
+            usage: BufferUsages::UNIFORM,
+        });
+        let buffer = |size: usize, usage: BufferUsages| {
+            render_device.create_buffer(&BufferDescriptor {
+                label: None,
+                size: size.max(256) as u64,
+                usage: BufferUsages::STORAGE | usage,
+                mapped_at_creation: false,
+            })
+        };
+        let brick_count = volume.brick_dims().element_product() as usize;
+        let cell_count = brick_count * BRICK_SIZE.pow(3) as usize;
+        let vertices = buffer(
+            MAX_VERTS_PER_VOXEL * size_of::<Vertex>() * cell_count,
+            BufferUsages::COPY_SRC,
+        );
+        let bricks = buffer(
+            size_of::<VoxelBrick>() * brick_count,
+            BufferUsages::INDIRECT | BufferUsages::COPY_SRC,
+        );
+        let cell_counts = buffer(size_of::<u32>() * cell_count, BufferUsages::empty());
+        let cell_offsets = buffer(size_of::<u32>() * (cell_count + 1), BufferUsages::empty());
+        // the analytic field reads no densities, metaballs, tunnels or materials
+        let unused = buffer(0, BufferUsages::empty());
+        let samples = buffer(
+            size_of::<f32>() * (volume.count_dims() + UVec3::ONE).element_product() as usize,
+            BufferUsages::empty(),
+        );
+
+        let layout = bind_group_layout(render_device);
+        let bind_group = render_device.create_bind_group(
+            None,
+            &layout,
+            &BindGroupEntries::sequential((
+                vertices.as_entire_binding(),
+                uniform_buffer.as_entire_binding(),
+                unused.as_entire_binding(),
+                unused.as_entire_binding(),
+                unused.as_entire_binding(),
+                unused.as_entire_binding(),
+                unused.as_entire_binding(),
+                samples.as_entire_binding(),
+                bricks.as_entire_binding(),
+                cell_counts.as_entire_binding(),
+                cell_offsets.as_entire_binding(),
+            )),
+        );
+
+        let module = compute_stage_module();
+        let pipelines = pipeline::ENTRY_POINTS.map(|entry_point| {
+            compute_pipeline(render_device, &module, entry_point, Some(&[&layout]))
+        });
+        let (extraction, extraction_pipelines) = match extraction {
+            SurfaceExtraction::Scan => {
+                let (scan_pipeline, scan_pipelines) = ScanPipeline::compile(render_device);
+                let scan = GpuScan::new(render_device, cell_count as u32);
+                let bind_groups = scan.bind(
+                    render_device,
+                    &scan_pipeline,
+                    &cell_counts,
+                    &cell_offsets,
+                    None,
+                );
+                (ExtractionBindGroups::Scan(bind_groups), scan_pipelines)
+            }
+            SurfaceExtraction::HistoPyramid => {
+                let (level_layout, traversal_layout) = bind_group_layouts(render_device);
+                let pipelines = core::array::from_fn(|index| {
+                    let pipeline_layout = match index {
+                        CLASSIFY_PYRAMID_CELLS | REDUCE_PYRAMID => &level_layout,
+                        _ => &traversal_layout,
+                    };
+                    compute_pipeline(
+                        render_device,
+                        &module,
+                        ENTRY_POINTS[index],
+                        Some(&[&layout, pipeline_layout]),
+                    )
+                });
+                let pipeline = HistoPyramidPipeline {
+                    level_layout,
+                    traversal_layout,
+                    pipeline_ids: [CachedComputePipelineId::INVALID; 4],
+                };
+                let pyramid = HistoPyramid::new(render_device, pyramid_size(volume.brick_dims()));
+                let bind_groups = pyramid.bind(render_device, &pipeline);
+                (ExtractionBindGroups::HistoPyramid(bind_groups), pipelines)
+            }
+        };
+
+        let mut encoder = render_device.create_command_encoder(&Default::default());
+        record(
+            &mut encoder.begin_compute_pass(&Default::default()),
+            &uniform,
+            &MarchingCubesBindGroup {
+                bind_group,
+                extraction,
+            },
+            &pipelines.each_ref(),
+            &extraction_pipelines.each_ref(),
+        );
+        queue.submit([encoder.finish()]);
+
+        let vertices: Vec<[u32; size_of::<Vertex>() / 4]> =
+            read_buffer(render_device, queue, &vertices);
+        let bricks: Vec<VoxelBrick> = read_buffer(render_device, queue, &bricks);
+        bricks[..brick_count]
+            .iter()
+            .map(|brick| {
+                let first = brick.first_vertex as usize;
+                vertices[first..first + brick.vertex_count as usize].to_vec()
+            })
+            .collect()
+    }
+
+    #[test]
+    #[ignore = "needs a Vulkan, Metal or DX12 adapter, GL cannot write the pyramid levels"]
+    fn gpu_pyramid_matches_gpu_scan() {
+        let adapter = test_adapter();
+        assert_ne!(
+            adapter.get_info().backend,
+            wgpu::Backend::Gl,
+            "the GL backend cannot read and write levels of one texture at once"
+        );
+        let (render_device, queue) = adapter_device(&adapter);
+        // the torus of the analytic scene crosses the far bricks, which are partially filled
+        let volume = VoxelVolume {
+            aabb: Aabb3d::new(Vec3::ZERO, Vec3::new(0.75, 0.25, 0.6)),
+            ..Default::default()
+        };
+        let scanned = gpu_brick_vertices(&render_device, &queue, &volume, SurfaceExtraction::Scan);
+        let traversed = gpu_brick_vertices(
+            &render_device,
+            &queue,
+            &volume,
+            SurfaceExtraction::HistoPyramid,
+        );
+        assert!(scanned.iter().any(|vertices| !vertices.is_empty()));
+
+        // the same triangles in every brick, in the order of the cells or of the pyramid
+        let triangles = |vertices: &[[u32; size_of::<Vertex>() / 4]]| {
+            let mut triangles: Vec<_> = vertices.chunks_exact(3).map(<[_]>::to_vec).collect();
+            triangles.sort();
+            triangles
+        };
+        assert_eq!(scanned.len(), traversed.len());
+        for (brick, (scanned, traversed)) in scanned.iter().zip(&traversed).enumerate() {
+            assert_eq!(scanned.len() % 3, 0, "vertices of brick {brick}");
+            assert_eq!(
+                triangles(scanned),
+                triangles(traversed),
+                "triangles of brick {brick}"
+            );
+        }
+    }
+}
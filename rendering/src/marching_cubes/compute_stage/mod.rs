@@ -1,29 +1,73 @@
 use bevy_app::{App, Plugin};
-use bevy_asset::{Handle, load_internal_asset, weak_handle};
+use bevy_asset::{Assets, Handle, load_internal_asset, weak_handle};
 use bevy_ecs::resource::Resource;
 use bevy_ecs::schedule::IntoScheduleConfigs;
-use bevy_ecs::system::{Res, ResMut};
+use bevy_ecs::system::{Commands, Res, ResMut};
 use bevy_math::bounding::Aabb3d;
-use bevy_math::{UVec3, Vec3};
-use bevy_render::extract_resource::{ExtractResource, ExtractResourcePlugin};
+use bevy_math::{UVec3, Vec3, Vec4};
+use bevy_render::primitives::Aabb;
 use bevy_render::render_graph::{RenderGraph, RenderLabel};
 use bevy_render::render_resource::{Shader, ShaderType, UniformBuffer};
 use bevy_render::renderer::{RenderDevice, RenderQueue};
-use bevy_render::{Render, RenderApp, RenderSet};
+use bevy_render::{Extract, ExtractSchedule, Render, RenderApp, RenderSet};
+use bevy_time::{Real, Time, Virtual};
 
+use super::clipping::{ClippingUniform, VoxelClipping};
+use super::noise::{NoiseDensity, NoiseUniform};
+use super::occlusion::{AmbientOcclusion, AmbientOcclusionUniform};
+use super::terrain::{TerrainGenerator, TerrainUniform};
+use super::volume::ScalarVolume;
+
+pub mod histo_pyramid;
 pub mod node;
 pub mod pipeline;
 
-pub struct MarchingCubesComputePlugin;
+#[derive(Default)]
+pub struct MarchingCubesComputePlugin {
+    pub extraction: SurfaceExtraction,
+}
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
-struct MarchingCubesComputeLabel;
+pub(crate) struct MarchingCubesComputeLabel;
+
+/// Cells along every edge of a brick. The compute stage skips the bricks the surface does not
+/// pass through and the display stage draws the vertices of every brick on its own, culled by the
+/// view frustum.
+pub const BRICK_SIZE: u32 = 8;
+
+/// How the compute stage packs the vertices of the cells into the vertex buffer.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SurfaceExtraction {
+    /// Counts the vertices of every cell and writes them at the prefix sum of the counts.
+    #[default]
+    Scan,
+    /// Sums the triangle counts of the cells up a pyramid of 3D texture levels and walks down it
+    /// once per triangle, without a pass over the empty cells after the reduction.
+    ///
+    /// Volumes whose pyramid exceeds the 3D texture size limit of the device are scanned instead,
+    /// like every volume on the GL backend, which cannot read and write levels of one texture
+    /// at once.
+    ///
+    /// Its bricks are not packed in the order of the volume, on devices without multi draw
+    /// indirect every visible brick costs a draw in every pass and view.
+    HistoPyramid,
+}
 
 pub const COMPUTE_STAGE_SHADER_HANDLE: Handle<Shader> =
     weak_handle!("f2b936a3-3f56-4386-b58d-76eb65df3058");
+/// `marching_cubes::density`, the field of the [`VoxelVolume`] for any pipeline binding it at the
+/// `DENSITY_GROUP` shader def.
+pub const DENSITY_SHADER_HANDLE: Handle<Shader> =
+    weak_handle!("5c0e7b41-9a2d-4f63-8e1b-d7a4360c92f5");
 
 impl Plugin for MarchingCubesComputePlugin {
     fn build(&self, app: &mut App) {
+        load_internal_asset!(
+            app,
+            DENSITY_SHADER_HANDLE,
+            "density.wgsl",
+            Shader::from_wgsl
+        );
         load_internal_asset!(
             app,
             COMPUTE_STAGE_SHADER_HANDLE,
@@ -32,13 +76,16 @@ impl Plugin for MarchingCubesComputePlugin {
         );
 
         app.init_resource::<VoxelVolume>();
-        app.add_plugins(ExtractResourcePlugin::<VoxelVolumeUniform>::default());
 
         let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
             return;
         };
 
-        render_app.init_resource::<VoxelVolumeBuffer>();
+        render_app
+            .insert_resource(self.extraction)
+            .init_resource::<VoxelVolumeUniform>()
+            .init_resource::<VoxelVolumeBuffer>();
+        render_app.add_systems(ExtractSchedule, extract_voxel_volume);
         render_app.add_systems(
             Render,
             (
@@ -77,12 +124,56 @@ impl VoxelVolume {
     pub fn count_all(&self) -> u32 {
         self.count_dims().element_product()
     }
+
+    /// Bricks covering [`count_dims`](Self::count_dims), the last ones on every axis may be
+    /// partially filled.
+    #[inline]
+    pub fn brick_dims(&self) -> UVec3 {
+        brick_dims(self.count_dims())
+    }
 }
 
-#[derive(Resource, Clone, Copy, Debug)]
+#[inline]
+fn brick_dims(count_dims: UVec3) -> UVec3 {
+    (count_dims + UVec3::splat(BRICK_SIZE - 1)) / BRICK_SIZE
+}
+
+#[derive(Resource, Clone, Debug)]
 pub struct VoxelVolume {
     pub aabb: Aabb3d,
     pub voxel_size: f32,
+    /// Level of the extracted surface.
+    ///
+    /// For [`DensitySource::Analytic`] it offsets the signed distance, for
+    /// the other sources everything above it is considered inside.
+    pub isovalue: f32,
+    pub density: DensitySource,
+    /// Clock driving `time` in the compute stage.
+    pub time_source: TimeSource,
+    /// Free parameters readable as `volume.user_params` in `density.wgsl`.
+    ///
+    /// The analytic scene reads the first one as `(major, minor, pulse amplitude, pulse speed)`
+    /// offsets of the torus, so changing them animates the surface.
+    pub user_params: [Vec4; 4],
+    /// Occlusion baked into the vertices, darkens caves and crevices.
+    pub ambient_occlusion: AmbientOcclusion,
+    /// Clip planes and box cutting into the surface.
+    pub clipping: VoxelClipping,
+}
+
+impl VoxelVolume {
+    /// Covers the bounds of `volume`, one voxel per smallest sample spacing.
+    ///
+    /// Anisotropic spacings are handled by resampling, so voxels stay cubic.
+    pub fn sampled(handle: Handle<ScalarVolume>, volume: &ScalarVolume, isovalue: f32) -> Self {
+        Self {
+            aabb: volume.aabb(),
+            voxel_size: volume.spacing.min_element(),
+            isovalue,
+            density: DensitySource::Sampled(handle),
+            ..Default::default()
+        }
+    }
 }
 
 impl Default for VoxelVolume {
@@ -90,27 +181,174 @@ impl Default for VoxelVolume {
         Self {
             aabb: Aabb3d::new(Vec3::ZERO, Vec3::ONE),
             voxel_size: 0.0625,
+            isovalue: 0.0,
+            density: DensitySource::default(),
+            time_source: TimeSource::default(),
+            user_params: [Vec4::ZERO; 4],
+            ambient_occlusion: AmbientOcclusion::default(),
+            clipping: VoxelClipping::default(),
         }
     }
 }
 
-impl ExtractResource for VoxelVolumeUniform {
-    type Source = VoxelVolume;
+/// Which [`Time`] animated densities follow.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimeSource {
+    /// Pauses and scales with the game clock.
+    #[default]
+    Virtual,
+    /// Wall clock since startup.
+    Real,
+}
 
-    fn extract_resource(source: &Self::Source) -> Self {
-        Self {
-            min_bound: source.aabb.min.into(),
-            max_bound: source.aabb.max.into(),
-            voxel_size: source.voxel_size,
+impl TimeSource {
+    /// Seconds wrapped by [`Time::wrap_period`], an hour by default, so the `f32` keeps its
+    /// precision in long sessions.
+    pub fn elapsed_secs_wrapped(self, virtual_time: &Time<Virtual>, real_time: &Time<Real>) -> f32 {
+        match self {
+            TimeSource::Virtual => virtual_time.elapsed_secs_wrapped(),
+            TimeSource::Real => real_time.elapsed_secs_wrapped(),
         }
     }
 }
 
+/// Where the compute stage takes density values from.
+#[derive(Clone, Debug, Default)]
+pub enum DensitySource {
+    /// `scene_sdf` from `density.wgsl`.
+    #[default]
+    Analytic,
+    /// Trilinearly interpolated [`ScalarVolume`].
+    Sampled(Handle<ScalarVolume>),
+    /// Procedural noise, everything above the isovalue is considered inside.
+    Noise(NoiseDensity),
+    /// Sum of the [`Metaball`](super::metaball::Metaball) entities overlapping the volume.
+    Metaballs,
+    /// Particle density of the [`SphFluid`](super::fluid::SphFluid) resource, normalized by
+    /// its rest density.
+    Fluid,
+    /// Level set or smoke density of the first [`GridFluid`](super::grid_fluid::GridFluid)
+    /// entity, positive inside.
+    GridFluid,
+    /// Live cells of the [`CellularAutomaton`](super::automaton::CellularAutomaton) resource.
+    Automaton,
+    /// Heightfield with overhangs, caves and tunnels.
+    Terrain(TerrainGenerator),
+}
+
+impl DensitySource {
+    // must match the DENSITY_* constants in density.wgsl
+    fn shader_index(&self) -> u32 {
+        match self {
+            DensitySource::Analytic => 0,
+            DensitySource::Sampled(_) => 1,
+            DensitySource::Noise(_) => 2,
+            DensitySource::Metaballs => 3,
+            DensitySource::Fluid => 4,
+            DensitySource::GridFluid => 5,
+            DensitySource::Automaton => 6,
+            DensitySource::Terrain(_) => 7,
+        }
+    }
+}
+
+fn extract_voxel_volume(
+    mut commands: Commands,
+    voxel_volume: Extract<Res<VoxelVolume>>,
+    scalar_volumes: Extract<Res<Assets<ScalarVolume>>>,
+    virtual_time: Extract<Res<Time<Virtual>>>,
+    real_time: Extract<Res<Time<Real>>>,
+) {
+    let mut uniform = VoxelVolumeUniform {
+        min_bound: voxel_volume.aabb.min.into(),
+        max_bound: voxel_volume.aabb.max.into(),
+        voxel_size: voxel_volume.voxel_size,
+        isovalue: voxel_volume.isovalue,
+        density_source: voxel_volume.density.shader_index(),
+        time: voxel_volume
+            .time_source
+            .elapsed_secs_wrapped(&virtual_time, &real_time),
+        user_params: voxel_volume.user_params,
+        ambient_occlusion: voxel_volume.ambient_occlusion.into(),
+        clipping: (&voxel_volume.clipping).into(),
+        ..Default::default()
+    };
+
+    match &voxel_volume.density {
+        DensitySource::Sampled(handle) => {
+            // not loaded yet volumes are left with zero dims, which the shader treats as empty
+            if let Some(scalar_volume) = scalar_volumes.get(handle) {
+                let aabb = scalar_volume.aabb();
+                uniform.sampled_dims = scalar_volume.dims;
+                uniform.sampled_min = aabb.min.into();
+                uniform.sampled_max = aabb.max.into();
+            }
+        }
+        DensitySource::Noise(noise) => uniform.noise = noise.into(),
+        DensitySource::Terrain(terrain) => uniform.terrain = terrain.into(),
+        DensitySource::Fluid | DensitySource::GridFluid | DensitySource::Automaton => {
+            // simulated on the voxel corners
+            let dims = voxel_volume.count_dims();
+            let min = Vec3::from(voxel_volume.aabb.min);
+            uniform.sampled_dims = dims + UVec3::ONE;
+            uniform.sampled_min = min;
+            uniform.sampled_max = min + dims.as_vec3() * voxel_volume.voxel_size;
+        }
+        DensitySource::Analytic | DensitySource::Metaballs => {}
+    }
+
+    commands.insert_resource(uniform);
+}
+
 #[derive(Resource, ShaderType, Clone, Default)]
 pub struct VoxelVolumeUniform {
     min_bound: Vec3,
     max_bound: Vec3,
     voxel_size: f32,
+    isovalue: f32,
+    density_source: u32,
+    sampled_dims: UVec3,
+    sampled_min: Vec3,
+    sampled_max: Vec3,
+    time: f32,
+    // uniform address space requires nested structs to be 16 byte aligned
+    #[align(16)]
+    noise: NoiseUniform,
+    #[align(16)]
+    terrain: TerrainUniform,
+    user_params: [Vec4; 4],
+    #[align(16)]
+    ambient_occlusion: AmbientOcclusionUniform,
+    #[align(16)]
+    clipping: ClippingUniform,
+}
+
+impl VoxelVolumeUniform {
+    #[inline]
+    pub fn count_dims(&self) -> UVec3 {
+        ((self.max_bound - self.min_bound) / self.voxel_size).as_uvec3()
+    }
+
+    #[inline]
+    pub fn brick_dims(&self) -> UVec3 {
+        brick_dims(self.count_dims())
+    }
+
+    /// Center of the volume bounds.
+    #[inline]
+    pub fn center(&self) -> Vec3 {
+        (self.min_bound + self.max_bound) * 0.5
+    }
+
+    /// Bounds of the cells of the bricks from `first` up to `end`.
+    pub fn bricks_aabb(&self, first: UVec3, end: UVec3) -> Aabb {
+        let first = first * BRICK_SIZE;
+        let last = (end * BRICK_SIZE).min(self.count_dims());
+        Aabb::from_min_max(
+            self.min_bound + first.as_vec3() * self.voxel_size,
+            self.min_bound + last.as_vec3() * self.voxel_size,
+        )
+    }
 }
 
 #[derive(Resource, Default)]
@@ -1,13 +1,24 @@
 use bevy_ecs::world::World;
 use bevy_math::UVec3;
+use bevy_render::render_asset::RenderAssets;
 use bevy_render::render_graph;
-use bevy_render::render_resource::{CachedPipelineState, ComputePassDescriptor, PipelineCache};
+use bevy_render::render_resource::{
+    CachedPipelineState, ComputePass, ComputePassDescriptor, ComputePipeline, PipelineCache,
+};
 use bevy_render::renderer::RenderContext;
+use bevy_render::storage::GpuShaderStorageBuffer;
 
 use super::VoxelVolumeUniform;
-use super::pipeline::{MarchingCubesBindGroup, MarchingCubesPipeline};
+use super::pipeline::{
+    CLASSIFY_BRICKS, CLASSIFY_CELLS, COMPUTE_VERTICES, ExtractionBindGroups,
+    MarchingCubesBindGroup, MarchingCubesPipeline, SAMPLE_FIELD, WRITE_BRICK_ARGS,
+};
+use crate::marching_cubes::{BRICK_SIZE, MarchingCubesBuffers};
+use crate::scan::ScanPipeline;
 
 const WORKGROUP_SIZE: u32 = 2;
+// workgroup size of `sample_field` and `classify_bricks`
+const SAMPLE_WORKGROUP_SIZE: u32 = 4;
 
 #[derive(Default)]
 pub struct MarchingCubesNode {
@@ -19,17 +30,25 @@ impl render_graph::Node for MarchingCubesNode {
         let pipeline = world.resource::<MarchingCubesPipeline>();
         let pipeline_cache = world.resource::<PipelineCache>();
 
-        // if the corresponding pipeline has loaded, transition to the next stage
+        // if the corresponding pipelines have loaded, transition to the next stage
         if !self.pipeline_is_ready {
-            match pipeline_cache.get_compute_pipeline_state(pipeline.pipeline_id) {
-                CachedPipelineState::Ok(_) => {
-                    self.pipeline_is_ready = true;
+            let mut all_ready = true;
+            let histo_pyramid_ids = pipeline
+                .histo_pyramid
+                .iter()
+                .flat_map(|histo_pyramid| histo_pyramid.pipeline_ids);
+            for id in pipeline.pipeline_ids.into_iter().chain(histo_pyramid_ids) {
+                match pipeline_cache.get_compute_pipeline_state(id) {
+                    CachedPipelineState::Ok(_) => {}
+                    CachedPipelineState::Err(err) => {
+                        panic!("Initializing compute_stage.wgsl:\n{err}")
+                    }
+                    _ => all_ready = false,
                 }
-                CachedPipelineState::Err(err) => {
-                    panic!("Initializing compute_vertices.wgsl:\n{err}")
-                }
-                _ => {}
             }
+            // the histo pyramid falls back to the scan on volumes past the 3D texture limit
+            self.pipeline_is_ready =
+                all_ready && world.resource::<ScanPipeline>().is_ready(pipeline_cache);
         }
     }
 
@@ -44,14 +63,12 @@ impl render_graph::Node for MarchingCubesNode {
         }
 
         let marching_cubes_pipeline = world.resource::<MarchingCubesPipeline>();
+        let scan_pipeline = world.resource::<ScanPipeline>();
         let pipeline_cache = world.resource::<PipelineCache>();
         let bind_group = world.resource::<MarchingCubesBindGroup>();
         let voxel_volume = world.resource::<VoxelVolumeUniform>();
 
-        // TODO: Maybe add `count_dims` as for VoxelVolume
-        let voxel_count = ((voxel_volume.max_bound - voxel_volume.min_bound)
-            / voxel_volume.voxel_size)
-            .as_uvec3();
+        let voxel_count = voxel_volume.count_dims();
         let workgroup_size = (voxel_count + UVec3::splat(WORKGROUP_SIZE - 1)) / WORKGROUP_SIZE;
         if TELL_WORKGROUPS
             .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
@@ -60,20 +77,96 @@ impl render_graph::Node for MarchingCubesNode {
             tracing::info!("Workgroups: {}", workgroup_size);
         }
 
-        let mut pass = render_context
+        // the surface shrinks between runs, drop the vertices past its end for the readback
+        let vertices = world
+            .resource::<RenderAssets<GpuShaderStorageBuffer>>()
+            .get(world.resource::<MarchingCubesBuffers>().vertices.id())
+            .unwrap();
+        render_context
             .command_encoder()
-            .begin_compute_pass(&ComputePassDescriptor::default());
+            .clear_buffer(&vertices.buffer, 0, None);
 
-        let pipeline = pipeline_cache
-            .get_compute_pipeline(marching_cubes_pipeline.pipeline_id)
-            .unwrap();
-        pass.set_bind_group(0, &bind_group.0, &[]);
-        pass.set_pipeline(pipeline);
-        pass.dispatch_workgroups(workgroup_size.x, workgroup_size.y, workgroup_size.z);
+        let pipelines = marching_cubes_pipeline
+            .pipeline_ids
+            .map(|id| pipeline_cache.get_compute_pipeline(id).unwrap());
+        let extraction_pipelines = match &bind_group.extraction {
+            ExtractionBindGroups::Scan(_) => scan_pipeline.pipelines(pipeline_cache).unwrap(),
+            ExtractionBindGroups::HistoPyramid(_) => marching_cubes_pipeline
+                .histo_pyramid
+                .as_ref()
+                .unwrap()
+                .pipelines(pipeline_cache)
+                .to_vec(),
+        };
+        record(
+            &mut render_context
+                .command_encoder()
+                .begin_compute_pass(&ComputePassDescriptor::default()),
+            voxel_volume,
+            bind_group,
+            &pipelines,
+            &extraction_pipelines,
+        );
 
         Ok(())
     }
 }
 
+/// Records the compute stage of `voxel_volume` into `pass`.
+///
+/// `pipelines` are those of the compute stage entry points and `extraction_pipelines` those of
+/// the scan or the histo pyramid the bind group extracts the surface with, in the order of their
+/// entry points.
+pub(crate) fn record(
+    pass: &mut ComputePass,
+    voxel_volume: &VoxelVolumeUniform,
+    bind_group: &MarchingCubesBindGroup,
+    pipelines: &[&ComputePipeline],
+    extraction_pipelines: &[&ComputePipeline],
+) {
+    let voxel_count = voxel_volume.count_dims();
+    pass.set_bind_group(0, &bind_group.bind_group, &[]);
+
+    // one invocation per voxel corner, then per brick
+    let sample_workgroups =
+        (voxel_count + UVec3::splat(SAMPLE_WORKGROUP_SIZE)) / SAMPLE_WORKGROUP_SIZE;
+    pass.set_pipeline(pipelines[SAMPLE_FIELD]);
+    pass.dispatch_workgroups(
+        sample_workgroups.x,
+        sample_workgroups.y,
+        sample_workgroups.z,
+    );
+
+    let brick_workgroups = (voxel_volume.brick_dims() + UVec3::splat(SAMPLE_WORKGROUP_SIZE - 1))
+        / SAMPLE_WORKGROUP_SIZE;
+    pass.set_pipeline(pipelines[CLASSIFY_BRICKS]);
+    pass.dispatch_workgroups(brick_workgroups.x, brick_workgroups.y, brick_workgroups.z);
+
+    let cell_scan = match &bind_group.extraction {
+        ExtractionBindGroups::Scan(cell_scan) => cell_scan,
+        ExtractionBindGroups::HistoPyramid(histo_pyramid) => {
+            // the traversal writes the vertices, there is nothing left to dispatch
+            histo_pyramid.record(pass, extraction_pipelines, brick_workgroups);
+            return;
+        }
+    };
+
+    // every cell of the bricks, the padding past the volume counts no vertices
+    let cell_workgroups = voxel_volume.brick_dims() * (BRICK_SIZE / WORKGROUP_SIZE);
+    pass.set_pipeline(pipelines[CLASSIFY_CELLS]);
+    pass.dispatch_workgroups(cell_workgroups.x, cell_workgroups.y, cell_workgroups.z);
+
+    // packs the vertices of every brick next to each other
+    cell_scan.record(pass, extraction_pipelines);
+    pass.set_bind_group(0, &bind_group.bind_group, &[]);
+
+    pass.set_pipeline(pipelines[WRITE_BRICK_ARGS]);
+    pass.dispatch_workgroups(brick_workgroups.x, brick_workgroups.y, brick_workgroups.z);
+
+    let workgroup_size = (voxel_count + UVec3::splat(WORKGROUP_SIZE - 1)) / WORKGROUP_SIZE;
+    pass.set_pipeline(pipelines[COMPUTE_VERTICES]);
+    pass.dispatch_workgroups(workgroup_size.x, workgroup_size.y, workgroup_size.z);
+}
+
 use std::sync::atomic::{AtomicBool, Ordering};
 static TELL_WORKGROUPS: AtomicBool = AtomicBool::new(false);
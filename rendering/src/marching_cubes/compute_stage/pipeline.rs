@@ -1,33 +1,77 @@
 use bevy_ecs::resource::Resource;
-use bevy_ecs::system::{Commands, Res};
+use bevy_ecs::system::{Commands, Local, Res};
 use bevy_ecs::world::{FromWorld, World};
 use bevy_render::render_asset::RenderAssets;
-use bevy_render::render_resource::binding_types::{storage_buffer_sized, uniform_buffer_sized};
+use bevy_render::render_resource::binding_types::{
+    storage_buffer_read_only_sized, storage_buffer_sized, uniform_buffer_sized,
+};
 use bevy_render::render_resource::{
     BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, CachedComputePipelineId,
-    ComputePipelineDescriptor, PipelineCache, ShaderStages, ShaderType,
+    ComputePipelineDescriptor, PipelineCache, ShaderDefVal, ShaderStages, ShaderType,
 };
-use bevy_render::renderer::RenderDevice;
+use bevy_render::renderer::{RenderAdapterInfo, RenderDevice};
+use bevy_render::settings::Backends;
 use bevy_render::storage::GpuShaderStorageBuffer;
 
 use crate::marching_cubes::MarchingCubesBuffers;
+use crate::marching_cubes::metaball::MetaballBuffer;
+use crate::marching_cubes::terrain::TunnelBuffer;
+use crate::scan::{GpuScan, ScanBindGroups, ScanPipeline};
 
-use super::{COMPUTE_STAGE_SHADER_HANDLE, VoxelVolumeBuffer, VoxelVolumeUniform};
+use super::histo_pyramid::{
+    HistoPyramid, HistoPyramidBindGroups, HistoPyramidPipeline, pyramid_size,
+};
+use super::{
+    COMPUTE_STAGE_SHADER_HANDLE, SurfaceExtraction, VoxelVolumeBuffer, VoxelVolumeUniform,
+};
 
 #[derive(Resource)]
-pub struct MarchingCubesBindGroup(pub(crate) BindGroup);
+pub struct MarchingCubesBindGroup {
+    pub(crate) bind_group: BindGroup,
+    pub(crate) extraction: ExtractionBindGroups,
+}
+
+/// Bind groups of the [`SurfaceExtraction`] of the pipeline.
+pub(crate) enum ExtractionBindGroups {
+    /// Scan of the cell counts into the cell offsets.
+    Scan(ScanBindGroups),
+    HistoPyramid(HistoPyramidBindGroups),
+}
 
+#[allow(clippy::too_many_arguments)]
 pub fn prepare_bind_group(
     mut commands: Commands,
     pipeline: Res<MarchingCubesPipeline>,
     marching_cubes_buffers: Res<MarchingCubesBuffers>,
     gpu_buffers: Res<RenderAssets<GpuShaderStorageBuffer>>,
     settings_buffer: Res<VoxelVolumeBuffer>,
+    metaball_buffer: Res<MetaballBuffer>,
+    tunnel_buffer: Res<TunnelBuffer>,
+    scan_pipeline: Res<ScanPipeline>,
+    voxel_volume: Res<VoxelVolumeUniform>,
     render_device: Res<RenderDevice>,
+    mut cell_scan: Local<Option<GpuScan>>,
+    mut histo_pyramid: Local<Option<HistoPyramid>>,
 ) {
     let vertices = gpu_buffers
         .get(marching_cubes_buffers.vertices.id())
         .unwrap();
+    let densities = gpu_buffers
+        .get(marching_cubes_buffers.densities.id())
+        .unwrap();
+    let materials = gpu_buffers
+        .get(marching_cubes_buffers.materials.id())
+        .unwrap();
+    let samples = gpu_buffers
+        .get(marching_cubes_buffers.samples.id())
+        .unwrap();
+    let bricks = gpu_buffers.get(marching_cubes_buffers.bricks.id()).unwrap();
+    let cell_counts = gpu_buffers
+        .get(marching_cubes_buffers.cell_counts.id())
+        .unwrap();
+    let cell_offsets = gpu_buffers
+        .get(marching_cubes_buffers.cell_offsets.id())
+        .unwrap();
 
     let bind_group = render_device.create_bind_group(
         Some("marching_cubes_bind_group"),
@@ -35,44 +79,151 @@ pub fn prepare_bind_group(
         &BindGroupEntries::sequential((
             vertices.buffer.as_entire_buffer_binding(),
             &settings_buffer.buffer,
+            densities.buffer.as_entire_buffer_binding(),
+            metaball_buffer.buffer.binding().unwrap(),
+            tunnel_buffer.buffer.binding().unwrap(),
+            materials.buffer.as_entire_buffer_binding(),
+            tunnel_buffer.bins.binding().unwrap(),
+            samples.buffer.as_entire_buffer_binding(),
+            bricks.buffer.as_entire_buffer_binding(),
+            cell_counts.buffer.as_entire_buffer_binding(),
+            cell_offsets.buffer.as_entire_buffer_binding(),
         )),
     );
-    commands.insert_resource(MarchingCubesBindGroup(bind_group));
+
+    // the scratch buffers and the pyramid only depend on the cell count, pyramids past the 3D
+    // texture limit fall back to the scan
+    let size = pyramid_size(voxel_volume.brick_dims());
+    let max_size = render_device.limits().max_texture_dimension_3d;
+    let extraction = match &pipeline.histo_pyramid {
+        Some(histo_pyramid_pipeline) if size.max_element() <= max_size => {
+            if histo_pyramid
+                .as_ref()
+                .is_none_or(|pyramid| pyramid.size() != size)
+            {
+                *histo_pyramid = Some(HistoPyramid::new(&render_device, size));
+            }
+            ExtractionBindGroups::HistoPyramid(
+                histo_pyramid
+                    .as_ref()
+                    .unwrap()
+                    .bind(&render_device, histo_pyramid_pipeline),
+            )
+        }
+        _ => {
+            let cell_count = (cell_counts.buffer.size() / size_of::<u32>() as u64) as u32;
+            if cell_scan
+                .as_ref()
+                .is_none_or(|scan| scan.count() != cell_count)
+            {
+                if pipeline.histo_pyramid.is_some() {
+                    tracing::warn!(
+                        "Histo pyramid of {size} cells exceeds the 3D texture limit of {max_size}, \
+                         scanning instead"
+                    );
+                }
+                *cell_scan = Some(GpuScan::new(&render_device, cell_count));
+            }
+            // the pyramid of a larger volume is dropped with it
+            *histo_pyramid = None;
+            ExtractionBindGroups::Scan(cell_scan.as_ref().unwrap().bind(
+                &render_device,
+                &scan_pipeline,
+                &cell_counts.buffer,
+                &cell_offsets.buffer,
+                None,
+            ))
+        }
+    };
+    commands.insert_resource(MarchingCubesBindGroup {
+        bind_group,
+        extraction,
+    });
 }
 
+// must match the entry points in compute_stage.wgsl, indexed by the consts below
+pub(crate) const ENTRY_POINTS: [&str; 5] = [
+    "sample_field",
+    "classify_bricks",
+    "classify_cells",
+    "write_brick_args",
+    "compute_vertices",
+];
+pub(crate) const SAMPLE_FIELD: usize = 0;
+pub(crate) const CLASSIFY_BRICKS: usize = 1;
+pub(crate) const CLASSIFY_CELLS: usize = 2;
+pub(crate) const WRITE_BRICK_ARGS: usize = 3;
+pub(crate) const COMPUTE_VERTICES: usize = 4;
+
 #[derive(Resource)]
 pub struct MarchingCubesPipeline {
     pub(crate) bind_group_layout: BindGroupLayout,
-    pub(crate) pipeline_id: CachedComputePipelineId,
+    pub(crate) pipeline_ids: [CachedComputePipelineId; 5],
+    /// With [`SurfaceExtraction::HistoPyramid`].
+    pub(crate) histo_pyramid: Option<HistoPyramidPipeline>,
 }
 
 impl FromWorld for MarchingCubesPipeline {
     fn from_world(world: &mut World) -> Self {
         let render_device = world.resource::<RenderDevice>();
-        let bind_group_layout = render_device.create_bind_group_layout(
-            "marching_cubes_bind_croup",
-            &BindGroupLayoutEntries::sequential(
-                ShaderStages::COMPUTE,
-                (
-                    storage_buffer_sized(false, None),
-                    uniform_buffer_sized(false, Some(VoxelVolumeUniform::min_size())),
-                ),
-            ),
-        );
+        let bind_group_layout = bind_group_layout(render_device);
         let pipeline_cache = world.resource::<PipelineCache>();
-        let pipeline_id = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-            label: None,
-            layout: vec![bind_group_layout.clone()],
-            push_constant_ranges: Vec::new(),
-            shader: COMPUTE_STAGE_SHADER_HANDLE,
-            shader_defs: vec![],
-            entry_point: "compute_vertices".into(),
-            zero_initialize_workgroup_memory: false,
+        let pipeline_ids = ENTRY_POINTS.map(|entry_point| {
+            pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                label: Some(format!("marching_cubes_{entry_point}_pipeline").into()),
+                layout: vec![bind_group_layout.clone()],
+                push_constant_ranges: Vec::new(),
+                shader: COMPUTE_STAGE_SHADER_HANDLE,
+                shader_defs: vec![ShaderDefVal::UInt("DENSITY_GROUP".into(), 0)],
+                entry_point: entry_point.into(),
+                zero_initialize_workgroup_memory: false,
+            })
         });
 
+        // the GL backend emulates texture views by the levels of the whole texture, writing a
+        // level of the pyramid while reading the one below leaves them empty
+        let histo_pyramid = match world.resource::<SurfaceExtraction>() {
+            SurfaceExtraction::Scan => None,
+            SurfaceExtraction::HistoPyramid
+                if Backends::from(world.resource::<RenderAdapterInfo>().backend)
+                    == Backends::GL =>
+            {
+                tracing::warn!("Histo pyramid cannot write its levels on GL, scanning instead");
+                None
+            }
+            SurfaceExtraction::HistoPyramid => Some(HistoPyramidPipeline::new(
+                render_device,
+                pipeline_cache,
+                &bind_group_layout,
+            )),
+        };
+
         Self {
             bind_group_layout,
-            pipeline_id,
+            pipeline_ids,
+            histo_pyramid,
         }
     }
 }
+
+pub(crate) fn bind_group_layout(render_device: &RenderDevice) -> BindGroupLayout {
+    render_device.create_bind_group_layout(
+        "marching_cubes_bind_croup",
+        &BindGroupLayoutEntries::sequential(
+            ShaderStages::COMPUTE,
+            (
+                storage_buffer_sized(false, None),
+                uniform_buffer_sized(false, Some(VoxelVolumeUniform::min_size())),
+                storage_buffer_read_only_sized(false, None),
+                storage_buffer_read_only_sized(false, None),
+                storage_buffer_read_only_sized(false, None),
+                storage_buffer_read_only_sized(false, None),
+                storage_buffer_read_only_sized(false, None),
+                storage_buffer_sized(false, None),
+                storage_buffer_sized(false, None),
+                storage_buffer_sized(false, None),
+                storage_buffer_read_only_sized(false, None),
+            ),
+        ),
+    )
+}
@@ -0,0 +1,474 @@
+use bevy_asset::{Assets, RenderAssetUsages};
+use bevy_color::{Alpha, ColorToComponents};
+use bevy_ecs::system::{Query, Res, SystemParam};
+use bevy_math::{UVec3, Vec2, Vec3, Vec3Swizzles, Vec4};
+use bevy_render::mesh::{Indices, Mesh, PrimitiveTopology};
+use bevy_time::{Real, Time, Virtual};
+use bevy_transform::components::GlobalTransform;
+
+use super::compute_stage::{DensitySource, VoxelVolume};
+use super::material::{VoxelMaterials, material_weights};
+use super::metaball::{Metaball, MetaballInstance, gather_metaballs, metaball_density};
+use super::occlusion::AmbientOcclusion;
+use super::terrain::TunnelSegment;
+use super::volume::ScalarVolume;
+
+pub(crate) mod tables;
+
+use tables::{EDGE_VERTEX_IDS, TRIANGLE_OFFSET_TABLE, TRIANGLE_TABLE};
+
+const EPSILON: f32 = 0.00001;
+
+/// Triangle soup produced by marching cubes, every 3 positions form a triangle.
+#[derive(Clone, Debug, Default)]
+pub struct IsoSurface {
+    pub positions: Vec<Vec3>,
+    pub normals: Option<Vec<Vec3>>,
+    /// Linear RGBA per vertex.
+    pub colors: Option<Vec<[f32; 4]>>,
+    /// One weight per material and vertex, see [`VoxelMaterials`].
+    pub material_weights: Option<Vec<Vec4>>,
+    /// Ambient light reaching every vertex, see [`AmbientOcclusion`].
+    pub occlusion: Option<Vec<f32>>,
+}
+
+impl IsoSurface {
+    #[inline]
+    pub fn triangle_count(&self) -> usize {
+        self.positions.len() / 3
+    }
+
+    /// Builds a surface from the first `vertex_count` vertices of the GPU vertex buffer, the
+    /// rest of the buffer is left over from larger surfaces.
+    pub fn from_vertex_buffer(bytes: &[u8], vertex_count: usize) -> Self {
+        // `Vertex` is the position, occlusion, normal, section and material weights, readback
+        // bytes are not guaranteed to be aligned
+        let mut vertices: Vec<[f32; 12]> = bytemuck::pod_collect_to_vec(bytes);
+        vertices.truncate(vertex_count - vertex_count % 3);
+
+        Self {
+            positions: vertices.iter().map(|v| Vec3::from_slice(&v[..])).collect(),
+            occlusion: Some(vertices.iter().map(|v| v[3]).collect()),
+            normals: Some(vertices.iter().map(|v| Vec3::from_slice(&v[4..])).collect()),
+            material_weights: Some(vertices.iter().map(|v| Vec4::from_slice(&v[8..])).collect()),
+            ..Default::default()
+        }
+    }
+
+    /// Fills [`IsoSurface::colors`] by blending the material weights with `materials`,
+    /// darkened by the occlusion when there is one.
+    pub fn color_materials(&mut self, materials: &VoxelMaterials) {
+        self.colors = self.material_weights.as_ref().map(|weights| {
+            weights
+                .iter()
+                .enumerate()
+                .map(|(i, &weights)| {
+                    let occlusion = self
+                        .occlusion
+                        .as_ref()
+                        .map_or(1.0, |occlusion| occlusion[i]);
+                    let color = materials.blend(weights);
+                    (color * occlusion).with_alpha(color.alpha).to_f32_array()
+                })
+                .collect()
+        });
+    }
+
+    /// Fills [`IsoSurface::occlusion`] by marching `field` around every vertex.
+    pub fn compute_ambient_occlusion(
+        &mut self,
+        field: impl Fn(Vec3) -> f32,
+        settings: &AmbientOcclusion,
+        voxel_size: f32,
+    ) {
+        let occlusion = self
+            .positions
+            .iter()
+            .map(|&pos| settings.occlusion(&field, pos, voxel_size))
+            .collect();
+        self.occlusion = Some(occlusion);
+    }
+
+    /// Fills [`IsoSurface::normals`] with the normalized gradient of `field`.
+    pub fn compute_gradient_normals(&mut self, field: impl Fn(Vec3) -> f32, step: f32) {
+        let normals = self
+            .positions
+            .iter()
+            .map(|&pos| gradient(&field, pos, step).normalize_or_zero())
+            .collect();
+        self.normals = Some(normals);
+    }
+}
+
+impl From<IsoSurface> for Mesh {
+    fn from(surface: IsoSurface) -> Self {
+        let indices = (0..surface.positions.len() as u32).collect();
+        let mut mesh = Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::default(),
+        )
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, surface.positions)
+        .with_inserted_indices(Indices::U32(indices));
+
+        if let Some(normals) = surface.normals {
+            mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        }
+        if let Some(colors) = surface.colors {
+            mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+        }
+        mesh
+    }
+}
+
+/// CPU evaluation of the density used by the compute stage.
+///
+/// Has to be kept in sync with `field` in `density.wgsl`.
+pub struct CpuDensity<'a> {
+    volume: &'a VoxelVolume,
+    sampled: Option<&'a ScalarVolume>,
+    time: f32,
+    metaballs: Vec<MetaballInstance>,
+    fluid: Option<ScalarVolume>,
+    tunnels: Vec<TunnelSegment>,
+}
+
+impl<'a> CpuDensity<'a> {
+    pub fn new(volume: &'a VoxelVolume, scalar_volumes: &'a Assets<ScalarVolume>) -> Self {
+        let sampled = match &volume.density {
+            DensitySource::Sampled(handle) => scalar_volumes.get(handle),
+            DensitySource::Analytic
+            | DensitySource::Noise(_)
+            | DensitySource::Metaballs
+            | DensitySource::Fluid
+            | DensitySource::GridFluid
+            | DensitySource::Automaton
+            | DensitySource::Terrain(_) => None,
+        };
+        let tunnels = match &volume.density {
+            DensitySource::Terrain(terrain) => terrain.tunnels(&volume.aabb),
+            _ => Vec::new(),
+        };
+        Self {
+            volume,
+            sampled,
+            time: 0.0,
+            metaballs: Vec::new(),
+            fluid: None,
+            tunnels,
+        }
+    }
+
+    /// Evaluates animated densities at `time` seconds, see [`VoxelVolume::time_source`].
+    pub fn with_time(mut self, time: f32) -> Self {
+        self.time = time;
+        self
+    }
+
+    /// Metaballs for [`DensitySource::Metaballs`], see [`gather_metaballs`].
+    pub fn with_metaballs(mut self, metaballs: Vec<MetaballInstance>) -> Self {
+        self.metaballs = metaballs;
+        self
+    }
+
+    /// Simulated density for [`DensitySource::Fluid`], [`DensitySource::GridFluid`] or
+    /// [`DensitySource::Automaton`], see
+    /// [`FluidParticles::splat`](super::fluid::FluidParticles::splat),
+    /// [`GridFluidState::density`](super::grid_fluid::GridFluidState::density) and
+    /// [`AutomatonGrid::density`](super::automaton::AutomatonGrid::density).
+    ///
+    /// Running simulations only live on the GPU, without a density the volume is empty.
+    pub fn with_fluid(mut self, splat: ScalarVolume) -> Self {
+        self.fluid = Some(splat);
+        self
+    }
+
+    /// Material id at `pos`, see [`VoxelMaterials`].
+    pub fn material(&self, pos: Vec3) -> u32 {
+        match &self.volume.density {
+            DensitySource::Sampled(_) => self.sampled.map_or(0, |sampled| sampled.material(pos)),
+            DensitySource::Terrain(terrain) => terrain.material(pos),
+            _ => 0,
+        }
+    }
+
+    /// Signed field whose zero crossing is the extracted surface, negative inside.
+    pub fn field(&self, pos: Vec3) -> f32 {
+        self.volume.clipping.clip(self.source_field(pos), pos)
+    }
+
+    /// [`field`](Self::field) before the [`VoxelClipping`](super::VoxelClipping).
+    pub fn source_field(&self, pos: Vec3) -> f32 {
+        match &self.volume.density {
+            DensitySource::Analytic => {
+                scene_sdf(pos, self.time, self.volume.user_params[0]) - self.volume.isovalue
+            }
+            DensitySource::Sampled(_) => match self.sampled {
+                Some(sampled) => self.volume.isovalue - sampled.sample(pos),
+                // volume is not loaded yet
+                None => 1.0,
+            },
+            DensitySource::Noise(noise) => self.volume.isovalue - noise.density(pos),
+            DensitySource::Metaballs => {
+                self.volume.isovalue - metaball_density(&self.metaballs, pos)
+            }
+            DensitySource::Terrain(terrain) => {
+                self.volume.isovalue - terrain.density(pos, &self.tunnels)
+            }
+            DensitySource::Fluid | DensitySource::GridFluid | DensitySource::Automaton => {
+                match &self.fluid {
+                    Some(splat) => self.volume.isovalue - splat.sample(pos),
+                    None => 1.0,
+                }
+            }
+        }
+    }
+}
+
+/// Everything [`CpuDensity`] reads from the main world.
+#[derive(SystemParam)]
+pub struct CpuDensityParam<'w, 's> {
+    pub voxel_volume: Res<'w, VoxelVolume>,
+    scalar_volumes: Res<'w, Assets<ScalarVolume>>,
+    virtual_time: Res<'w, Time<Virtual>>,
+    real_time: Res<'w, Time<Real>>,
+    metaballs: Query<'w, 's, (&'static Metaball, &'static GlobalTransform)>,
+}
+
+impl CpuDensityParam<'_, '_> {
+    /// Density of the current [`VoxelVolume`], as the compute stage sees it this frame.
+    pub fn density(&self) -> CpuDensity<'_> {
+        let volume = &*self.voxel_volume;
+        let time = volume
+            .time_source
+            .elapsed_secs_wrapped(&self.virtual_time, &self.real_time);
+        CpuDensity::new(volume, &self.scalar_volumes)
+            .with_time(time)
+            .with_metaballs(gather_metaballs(volume, self.metaballs.iter()))
+    }
+}
+
+fn torus_sdf(pos: Vec3, radius: Vec2) -> f32 {
+    let q = Vec2::new(pos.xz().length() - radius.x, pos.y);
+    q.length() - radius.y
+}
+
+fn scene_sdf(pos: Vec3, time: f32, params: Vec4) -> f32 {
+    let pulse = params.z * (time * params.w).sin();
+    torus_sdf(
+        pos,
+        Vec2::new(0.5, 0.15) + Vec2::new(params.x, params.y + pulse),
+    )
+}
+
+fn gradient(field: &impl Fn(Vec3) -> f32, pos: Vec3, step: f32) -> Vec3 {
+    let dx = Vec3::X * step;
+    let dy = Vec3::Y * step;
+    let dz = Vec3::Z * step;
+    Vec3::new(
+        field(pos + dx) - field(pos - dx),
+        field(pos + dy) - field(pos - dy),
+        field(pos + dz) - field(pos - dz),
+    )
+}
+
+/// Extracts the zero crossing of `field` inside `volume`.
+///
+/// Produces the same triangles as `compute_vertices` in `compute_stage.wgsl`, with the cells in
+/// x, y, z order instead of brick by brick.
+pub fn polygonize(volume: &VoxelVolume, field: impl Fn(Vec3) -> f32) -> IsoSurface {
+    march(volume, field, None::<fn(Vec3) -> u32>)
+}
+
+/// [`polygonize`] with the material weights of the inside corner of every edge.
+pub fn polygonize_with_materials(
+    volume: &VoxelVolume,
+    field: impl Fn(Vec3) -> f32,
+    material: impl Fn(Vec3) -> u32,
+) -> IsoSurface {
+    march(volume, field, Some(material))
+}
+
+fn march(
+    volume: &VoxelVolume,
+    field: impl Fn(Vec3) -> f32,
+    material: Option<impl Fn(Vec3) -> u32>,
+) -> IsoSurface {
+    let count = volume.count_dims();
+    let min_bound = Vec3::from(volume.aabb.min);
+    let mut positions = Vec::new();
+    let mut weights = Vec::new();
+
+    for z in 0..count.z {
+        for y in 0..count.y {
+            for x in 0..count.x {
+                let coord = min_bound + UVec3::new(x, y, z).as_vec3() * volume.voxel_size;
+                if volume
+                    .clipping
+                    .cell_clipped(coord, coord + volume.voxel_size)
+                {
+                    continue;
+                }
+                let (data, mask) = cell_corners(&field, coord, volume.voxel_size);
+
+                let start = TRIANGLE_OFFSET_TABLE[mask.max(1) - 1];
+                let end = TRIANGLE_OFFSET_TABLE[mask];
+                for &edge_id in &TRIANGLE_TABLE[start..end] {
+                    let [first, second] = EDGE_VERTEX_IDS[edge_id];
+                    positions.push(interpolate(data[first], data[second]));
+                    if let Some(material) = &material {
+                        let inside = if data[first].1 < 0.0 { first } else { second };
+                        weights.push(material_weights(material(data[inside].0)));
+                    }
+                }
+            }
+        }
+    }
+
+    IsoSurface {
+        positions,
+        material_weights: material.map(|_| weights),
+        ..Default::default()
+    }
+}
+
+/// Corners of the cell at `coord` with their field values, and the case index of the cell.
+fn cell_corners(
+    field: &impl Fn(Vec3) -> f32,
+    coord: Vec3,
+    voxel_size: f32,
+) -> ([(Vec3, f32); 8], usize) {
+    let mut data = [(Vec3::ZERO, 0.0); 8];
+    let mut mask = 0;
+    for (i, corner) in data.iter_mut().enumerate() {
+        let offset = Vec3::new((i & 1) as f32, ((i & 2) >> 1) as f32, ((i & 4) >> 2) as f32);
+        let position = coord + offset * voxel_size;
+        let distance = field(position);
+        *corner = (position, distance);
+        if distance < 0.0 {
+            mask |= 1 << i;
+        }
+    }
+    (data, mask)
+}
+
+/// Minimum corner and case index of every cell the surface passes through.
+///
+/// Bit `i` of the case index is set when corner `i` is inside, the corners are ordered x first.
+pub fn active_cells(volume: &VoxelVolume, field: impl Fn(Vec3) -> f32) -> Vec<(Vec3, u8)> {
+    let count = volume.count_dims();
+    let min_bound = Vec3::from(volume.aabb.min);
+    let mut cells = Vec::new();
+
+    for z in 0..count.z {
+        for y in 0..count.y {
+            for x in 0..count.x {
+                let coord = min_bound + UVec3::new(x, y, z).as_vec3() * volume.voxel_size;
+                if volume
+                    .clipping
+                    .cell_clipped(coord, coord + volume.voxel_size)
+                {
+                    continue;
+                }
+                let (_, mask) = cell_corners(&field, coord, volume.voxel_size);
+                if mask != 0 && mask != 0xff {
+                    cells.push((coord, mask as u8));
+                }
+            }
+        }
+    }
+    cells
+}
+
+/// [`polygonize`] with normals from the field gradient.
+pub fn polygonize_with_normals(volume: &VoxelVolume, field: impl Fn(Vec3) -> f32) -> IsoSurface {
+    let mut surface = polygonize(volume, &field);
+    surface.compute_gradient_normals(&field, volume.voxel_size * 0.5);
+    surface
+}
+
+fn interpolate(first: (Vec3, f32), second: (Vec3, f32)) -> Vec3 {
+    if first.1.abs() < EPSILON {
+        first.0
+    } else if second.1.abs() < EPSILON {
+        second.0
+    } else if (first.1 - second.1).abs() < EPSILON {
+        first.0
+    } else {
+        let factor = -first.1 / (second.1 - first.1);
+        first.0.lerp(second.0, factor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    const RADIUS: f32 = 0.71;
+
+    fn sphere(pos: Vec3) -> f32 {
+        pos.length() - RADIUS
+    }
+
+    #[test]
+    fn sphere_vertices_lie_on_the_surface() {
+        let volume = VoxelVolume::default();
+        let surface = polygonize_with_normals(&volume, sphere);
+        assert!(surface.triangle_count() > 0);
+
+        for (pos, normal) in surface.positions.iter().zip(surface.normals.unwrap()) {
+            // linear interpolation along the edges is off by the curvature only
+            assert!(sphere(*pos).abs() < 0.05 * volume.voxel_size, "{pos}");
+            assert!(normal.dot(pos.normalize()) > 0.99, "{pos} {normal}");
+        }
+
+        let area: f32 = surface
+            .positions
+            .chunks_exact(3)
+            .map(|t| (t[1] - t[0]).cross(t[2] - t[0]).length() * 0.5)
+            .sum();
+        let expected = 4.0 * core::f32::consts::PI * RADIUS * RADIUS;
+        assert!((area / expected - 1.0).abs() < 0.02, "{area} {expected}");
+    }
+
+    #[test]
+    fn sphere_triangles_follow_the_active_cells() {
+        let volume = VoxelVolume::default();
+        let surface = polygonize(&volume, sphere);
+        let cells = active_cells(&volume, sphere);
+
+        let triangles: usize = cells
+            .iter()
+            .map(|&(_, mask)| {
+                let mask = mask as usize;
+                (TRIANGLE_OFFSET_TABLE[mask] - TRIANGLE_OFFSET_TABLE[mask - 1]) / 3
+            })
+            .sum();
+        assert_eq!(surface.triangle_count(), triangles);
+        for (coord, _) in cells {
+            // the cell diagonal is the farthest a crossing cell can be from the surface
+            let center = coord + volume.voxel_size * 0.5;
+            assert!(sphere(center).abs() < volume.voxel_size * 3f32.sqrt() * 0.5);
+        }
+    }
+
+    #[test]
+    fn sphere_surface_is_closed() {
+        let volume = VoxelVolume::default();
+        let surface = polygonize(&volume, sphere);
+
+        // neighboring cells compute shared vertices from their own corners
+        let key = |pos: Vec3| (pos * 1e4).round().as_ivec3().to_array();
+        let mut edges = HashMap::new();
+        for triangle in surface.positions.chunks_exact(3) {
+            for i in 0..3 {
+                let (a, b) = (key(triangle[i]), key(triangle[(i + 1) % 3]));
+                *edges
+                    .entry(if a < b { (a, b) } else { (b, a) })
+                    .or_insert(0) += 1;
+            }
+        }
+        assert!(edges.values().all(|&count| count == 2));
+    }
+}
@@ -0,0 +1,29 @@
+#define_import_path marching_cubes::bounds
+
+// Axis aligned box drawn by the raymarching display modes, 36 vertices without a vertex buffer
+
+const BOX_CORNERS: array<vec3<f32>, 8> = array<vec3<f32>, 8>(
+    vec3(0.0, 0.0, 0.0),
+    vec3(1.0, 0.0, 0.0),
+    vec3(0.0, 1.0, 0.0),
+    vec3(1.0, 1.0, 0.0),
+    vec3(0.0, 0.0, 1.0),
+    vec3(1.0, 0.0, 1.0),
+    vec3(0.0, 1.0, 1.0),
+    vec3(1.0, 1.0, 1.0),
+);
+
+// two counter clockwise triangles per face seen from outside
+const BOX_INDICES: array<u32, 36> = array<u32, 36>(
+    0u, 2u, 1u, 1u, 2u, 3u, // -z
+    4u, 5u, 6u, 5u, 7u, 6u, // +z
+    0u, 4u, 2u, 2u, 4u, 6u, // -x
+    1u, 3u, 5u, 3u, 7u, 5u, // +x
+    0u, 1u, 4u, 1u, 5u, 4u, // -y
+    2u, 6u, 3u, 3u, 6u, 7u, // +y
+);
+
+// Corner of the box for `vertex_index`, seen from outside the triangles are counter clockwise
+fn bounds_position(index: u32, min_bound: vec3<f32>, max_bound: vec3<f32>) -> vec3<f32> {
+    return mix(min_bound, max_bound, BOX_CORNERS[BOX_INDICES[index]]);
+}
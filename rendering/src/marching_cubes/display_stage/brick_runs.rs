@@ -0,0 +1,289 @@
+use bevy_asset::{Handle, weak_handle};
+use bevy_ecs::component::Component;
+use bevy_ecs::entity::Entity;
+use bevy_ecs::query::QueryState;
+use bevy_ecs::resource::Resource;
+use bevy_ecs::system::{Commands, Query, Res};
+use bevy_ecs::world::{FromWorld, World};
+use bevy_render::render_asset::RenderAssets;
+use bevy_render::render_graph::{self, RenderLabel};
+use bevy_render::render_resource::binding_types::{
+    storage_buffer_read_only_sized, storage_buffer_sized,
+};
+use bevy_render::render_resource::{
+    BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, Buffer, BufferDescriptor,
+    BufferInitDescriptor, BufferUsages, CachedComputePipelineId, CachedPipelineState,
+    ComputePassDescriptor, ComputePipelineDescriptor, PipelineCache, Shader, ShaderStages,
+    WgpuFeatures,
+};
+use bevy_render::renderer::{RenderContext, RenderDevice};
+use bevy_render::storage::GpuShaderStorageBuffer;
+
+use super::culling::VisibleVoxelBricks;
+use crate::marching_cubes::compute_stage::pipeline::{
+    ExtractionBindGroups, MarchingCubesBindGroup,
+};
+use crate::marching_cubes::{MarchingCubesBuffers, VoxelBrick};
+
+pub const BRICK_RUNS_SHADER_HANDLE: Handle<Shader> =
+    weak_handle!("d3a81f5e-6b27-4c90-a4e2-97f0c15b28d6");
+
+const WORKGROUP_SIZE: u32 = 64;
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+pub(super) struct VoxelBrickRunsLabel;
+
+/// One draw per run of [`VisibleVoxelBricks`], for devices without multi draw indirect.
+///
+/// The scan packs the vertices of consecutive bricks one after another, so a run of them is one
+/// range of vertices. [`DrawVoxeled`](super::DrawVoxeled) draws the bricks of the histo pyramid
+/// one by one instead.
+#[derive(Component)]
+pub(super) struct VoxelBrickRuns {
+    /// Draw indirect args of every run, written by [`VoxelBrickRunsNode`].
+    pub(super) args: Buffer,
+    pub(super) count: u32,
+    bind_group: BindGroup,
+}
+
+pub(super) fn prepare_voxel_brick_runs(
+    mut commands: Commands,
+    pipeline: Res<VoxelBrickRunsPipeline>,
+    marching_cubes_bind_group: Option<Res<MarchingCubesBindGroup>>,
+    marching_cubes_buffers: Res<MarchingCubesBuffers>,
+    gpu_buffers: Res<RenderAssets<GpuShaderStorageBuffer>>,
+    render_device: Res<RenderDevice>,
+    views: Query<(Entity, &VisibleVoxelBricks)>,
+) {
+    let scanned = marching_cubes_bind_group
+        .is_some_and(|bind_group| matches!(bind_group.extraction, ExtractionBindGroups::Scan(_)));
+    let multi_draw = render_device
+        .features()
+        .contains(WgpuFeatures::MULTI_DRAW_INDIRECT);
+    let bricks = gpu_buffers
+        .get(marching_cubes_buffers.bricks.id())
+        .filter(|_| scanned && !multi_draw);
+
+    for (view, visible_bricks) in &views {
+        let Some(bricks) = bricks else {
+            commands.entity(view).remove::<VoxelBrickRuns>();
+            continue;
+        };
+
+        // the bricks may lag behind the visible ones for a frame after the volume is resized
+        let brick_count = (bricks.buffer.size() / size_of::<VoxelBrick>() as u64) as u32;
+        let runs: Vec<[u32; 2]> = visible_bricks
+            .0
+            .iter()
+            .map(|range| [range.start, range.end.min(brick_count)])
+            .filter(|[first, end]| first < end)
+            .collect();
+        if runs.is_empty() {
+            commands.entity(view).remove::<VoxelBrickRuns>();
+            continue;
+        }
+
+        let runs_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            label: Some("voxel_brick_runs_buffer"),
+            contents: bytemuck::cast_slice(&runs),
+            usage: BufferUsages::STORAGE,
+        });
+        let args = render_device.create_buffer(&BufferDescriptor {
+            label: Some("voxel_brick_run_args_buffer"),
+            size: (size_of::<VoxelBrick>() * runs.len()) as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::INDIRECT,
+            mapped_at_creation: false,
+        });
+        let bind_group = render_device.create_bind_group(
+            Some("voxel_brick_runs_bind_group"),
+            &pipeline.bind_group_layout,
+            &BindGroupEntries::sequential((
+                bricks.buffer.as_entire_buffer_binding(),
+                runs_buffer.as_entire_buffer_binding(),
+                args.as_entire_buffer_binding(),
+            )),
+        );
+        commands.entity(view).insert(VoxelBrickRuns {
+            args,
+            count: runs.len() as u32,
+            bind_group,
+        });
+    }
+}
+
+#[derive(Resource)]
+pub(super) struct VoxelBrickRunsPipeline {
+    bind_group_layout: BindGroupLayout,
+    pipeline: CachedComputePipelineId,
+}
+
+impl FromWorld for VoxelBrickRunsPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let bind_group_layout = render_device.create_bind_group_layout(
+            "voxel_brick_runs_bind_group",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::COMPUTE,
+                (
+                    storage_buffer_read_only_sized(false, None),
+                    storage_buffer_read_only_sized(false, None),
+                    storage_buffer_sized(false, None),
+                ),
+            ),
+        );
+        let pipeline =
+            world
+                .resource::<PipelineCache>()
+                .queue_compute_pipeline(ComputePipelineDescriptor {
+                    label: Some("voxel_brick_runs_pipeline".into()),
+                    layout: vec![bind_group_layout.clone()],
+                    push_constant_ranges: Vec::new(),
+                    shader: BRICK_RUNS_SHADER_HANDLE,
+                    shader_defs: vec![],
+                    entry_point: "merge_brick_runs".into(),
+                    zero_initialize_workgroup_memory: false,
+                });
+
+        Self {
+            bind_group_layout,
+            pipeline,
+        }
+    }
+}
+
+/// Writes the [`VoxelBrickRuns`] of every view after the compute stage wrote the bricks.
+pub(super) struct VoxelBrickRunsNode {
+    views: QueryState<&'static VoxelBrickRuns>,
+    pipeline_is_ready: bool,
+}
+
+impl FromWorld for VoxelBrickRunsNode {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            views: QueryState::new(world),
+            pipeline_is_ready: false,
+        }
+    }
+}
+
+impl render_graph::Node for VoxelBrickRunsNode {
+    fn update(&mut self, world: &mut World) {
+        self.views.update_archetypes(world);
+
+        if !self.pipeline_is_ready {
+            let pipeline = world.resource::<VoxelBrickRunsPipeline>();
+            match world
+                .resource::<PipelineCache>()
+                .get_compute_pipeline_state(pipeline.pipeline)
+            {
+                CachedPipelineState::Ok(_) => self.pipeline_is_ready = true,
+                CachedPipelineState::Err(err) => panic!("Initializing brick_runs.wgsl:\n{err}"),
+                _ => {}
+            }
+        }
+    }
+
+    fn run<'w>(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext<'w>,
+        world: &'w World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        if !self.pipeline_is_ready || self.views.iter_manual(world).next().is_none() {
+            return Ok(());
+        }
+
+        let pipeline = world
+            .resource::<PipelineCache>()
+            .get_compute_pipeline(world.resource::<VoxelBrickRunsPipeline>().pipeline)
+            .unwrap();
+        let mut pass =
+            render_context
+                .command_encoder()
+                .begin_compute_pass(&ComputePassDescriptor {
+                    label: Some("voxel_brick_runs"),
+                    ..Default::default()
+                });
+        pass.set_pipeline(pipeline);
+        for runs in self.views.iter_manual(world) {
+            pass.set_bind_group(0, &runs.bind_group, &[]);
+            pass.dispatch_workgroups(runs.count.div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_render::render_resource::BindGroupEntry;
+
+    use super::*;
+    use crate::testing::{compose, compute_pipeline, read_buffer, test_device};
+
+    #[test]
+    fn runs_draw_the_vertices_of_their_bricks() {
+        let (render_device, queue) = test_device();
+        let module = compose(
+            &[],
+            (include_str!("brick_runs.wgsl"), "brick_runs.wgsl"),
+            &[],
+        );
+        let pipeline = compute_pipeline(&render_device, &module, "merge_brick_runs", None);
+
+        // bricks packed like the scan leaves them, empty ones at the first vertex of the next one
+        let vertex_counts = [3, 0, 6, 0, 0, 9, 3, 0];
+        let mut bricks = Vec::new();
+        let mut first_vertex = 0;
+        for vertex_count in vertex_counts {
+            bricks.push([vertex_count, 1, first_vertex, 0]);
+            first_vertex += vertex_count;
+        }
+        let runs = [[0, 2], [2, 3], [3, 5], [4, 8], [7, 8]];
+
+        let buffer = |contents: &[u8]| {
+            render_device.create_buffer_with_data(&BufferInitDescriptor {
+                label: None,
+                contents,
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+            })
+        };
+        let buffers = [
+            buffer(bytemuck::cast_slice(&bricks)),
+            buffer(bytemuck::cast_slice(&runs)),
+            buffer(&vec![0; size_of::<VoxelBrick>() * runs.len()]),
+        ];
+        let entries: Vec<_> = buffers
+            .iter()
+            .enumerate()
+            .map(|(binding, buffer)| BindGroupEntry {
+                binding: binding as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect();
+        let bind_group = render_device.create_bind_group(
+            None,
+            &pipeline.get_bind_group_layout(0).into(),
+            &entries,
+        );
+
+        let mut encoder = render_device.create_command_encoder(&Default::default());
+        {
+            let mut pass = encoder.begin_compute_pass(&Default::default());
+            pass.set_pipeline(&pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups((runs.len() as u32).div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+        queue.submit([encoder.finish()]);
+
+        let args: Vec<[u32; 4]> = read_buffer(&render_device, &queue, &buffers[2]);
+        let expected: Vec<[u32; 4]> = runs
+            .iter()
+            .map(|&[first, end]| {
+                let run = &bricks[first as usize..end as usize];
+                [run.iter().map(|brick| brick[0]).sum(), 1, run[0][2], 0]
+            })
+            .collect();
+        assert_eq!(args, expected);
+    }
+}
@@ -0,0 +1,30 @@
+// Merges the draw args of every run of visible bricks into one draw, the scan packs the
+// vertices of consecutive bricks one after another
+
+struct Brick {
+    vertex_count: u32,
+    instance_count: u32,
+    first_vertex: u32,
+    first_instance: u32,
+};
+
+@group(0) @binding(0) var<storage, read> bricks: array<Brick>;
+// first and end brick of every run
+@group(0) @binding(1) var<storage, read> runs: array<vec2<u32>>;
+@group(0) @binding(2) var<storage, read_write> run_args: array<Brick>;
+
+@compute @workgroup_size(64)
+fn merge_brick_runs(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let index = invocation_id.x;
+    if (index >= arrayLength(&runs)) {
+        return;
+    }
+    let run = runs[index];
+    // empty bricks keep the first vertex of the next one
+    let first = bricks[run.x];
+    let last = bricks[run.y - 1u];
+    run_args[index].vertex_count = last.first_vertex + last.vertex_count - first.first_vertex;
+    run_args[index].instance_count = 1u;
+    run_args[index].first_vertex = first.first_vertex;
+    run_args[index].first_instance = 0u;
+}
@@ -0,0 +1,125 @@
+use bevy_ecs::change_detection::DetectChanges;
+use bevy_ecs::query::ROQueryItem;
+use bevy_ecs::resource::Resource;
+use bevy_ecs::system::lifetimeless::SRes;
+use bevy_ecs::system::{Commands, Res, ResMut, SystemParamItem};
+use bevy_ecs::world::{FromWorld, World};
+use bevy_render::Extract;
+use bevy_render::render_phase::{PhaseItem, RenderCommand, RenderCommandResult, TrackedRenderPass};
+use bevy_render::render_resource::binding_types::uniform_buffer;
+use bevy_render::render_resource::{
+    BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, ShaderStages,
+    UniformBuffer,
+};
+use bevy_render::renderer::{RenderDevice, RenderQueue};
+
+use crate::marching_cubes::VoxelVolume;
+use crate::marching_cubes::clipping::{ClipCap, ClippingUniform};
+
+/// [`VoxelClipping`](crate::marching_cubes::VoxelClipping) of the [`VoxelVolume`] in the
+/// render world.
+#[derive(Resource, Clone, Copy)]
+pub(super) struct ExtractedVoxelClipping {
+    uniform: ClippingUniform,
+    /// Anything is cut away.
+    pub(super) active: bool,
+    /// An open cut, the surface beyond it is discarded per pixel.
+    pub(super) discard: bool,
+}
+
+pub(super) fn extract_voxel_clipping(
+    mut commands: Commands,
+    voxel_volume: Extract<Res<VoxelVolume>>,
+    clip_buffer: Res<VoxelClipBuffer>,
+) {
+    if !voxel_volume.is_changed() && clip_buffer.buffer.buffer().is_some() {
+        return;
+    }
+    let clipping = &voxel_volume.clipping;
+    commands.insert_resource(ExtractedVoxelClipping {
+        uniform: clipping.into(),
+        active: clipping.is_active(),
+        discard: clipping.is_active() && clipping.cap == ClipCap::Open,
+    });
+}
+
+#[derive(Resource, Default)]
+pub(super) struct VoxelClipBuffer {
+    pub(super) buffer: UniformBuffer<ClippingUniform>,
+}
+
+pub(super) fn prepare_voxel_clip_buffer(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut clip_buffer: ResMut<VoxelClipBuffer>,
+    clipping: Option<Res<ExtractedVoxelClipping>>,
+) {
+    let Some(clipping) = clipping.filter(DetectChanges::is_changed) else {
+        return;
+    };
+    clip_buffer.buffer.set(clipping.uniform);
+    clip_buffer
+        .buffer
+        .write_buffer(&render_device, &render_queue);
+}
+
+/// Layout of the clip planes for the prepass and shadow pipelines, the main pass binds them with
+/// the materials.
+#[derive(Resource)]
+pub(super) struct VoxelClipLayout(pub(super) BindGroupLayout);
+
+impl FromWorld for VoxelClipLayout {
+    fn from_world(world: &mut World) -> Self {
+        Self(world.resource::<RenderDevice>().create_bind_group_layout(
+            "voxel_clip_bind_group",
+            &BindGroupLayoutEntries::single(
+                ShaderStages::FRAGMENT,
+                uniform_buffer::<ClippingUniform>(false),
+            ),
+        ))
+    }
+}
+
+#[derive(Resource)]
+pub(super) struct VoxelClipBindGroup(BindGroup);
+
+pub(super) fn prepare_voxel_clip_bind_group(
+    mut commands: Commands,
+    layout: Res<VoxelClipLayout>,
+    clip_buffer: Res<VoxelClipBuffer>,
+    render_device: Res<RenderDevice>,
+) {
+    let Some(binding) = clip_buffer.buffer.binding() else {
+        return;
+    };
+
+    commands.insert_resource(VoxelClipBindGroup(render_device.create_bind_group(
+        Some("voxel_clip_bind_group"),
+        &layout.0,
+        &BindGroupEntries::single(binding),
+    )));
+}
+
+/// Binds the clip planes when the pipeline discards by them.
+pub(super) struct SetVoxelClipBindGroup<const I: usize>;
+
+impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetVoxelClipBindGroup<I> {
+    type Param = (SRes<ExtractedVoxelClipping>, SRes<VoxelClipBindGroup>);
+
+    type ViewQuery = ();
+
+    type ItemQuery = ();
+
+    fn render<'w>(
+        _item: &P,
+        _view: ROQueryItem<'w, Self::ViewQuery>,
+        _entity: Option<ROQueryItem<'w, Self::ItemQuery>>,
+        (clipping, bind_group): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        if clipping.discard {
+            pass.set_bind_group(I, &bind_group.into_inner().0, &[]);
+        }
+        RenderCommandResult::Success
+    }
+}
@@ -0,0 +1,182 @@
+use core::ops::Range;
+
+use bevy_ecs::component::Component;
+use bevy_ecs::entity::Entity;
+use bevy_ecs::query::With;
+use bevy_ecs::system::{Commands, Query, Res};
+use bevy_math::{Affine3A, UVec3};
+use bevy_pbr::LightEntity;
+use bevy_render::primitives::{Aabb, Frustum};
+use bevy_render::view::ExtractedView;
+
+use crate::marching_cubes::compute_stage::VoxelVolumeUniform;
+
+/// Bricks per axis of the clusters tested before their bricks.
+const CLUSTER_SIZE: u32 = 4;
+
+/// Runs of consecutive bricks of the volume inside the frustum of a view, in the order of the
+/// bricks buffer.
+///
+/// [`DrawVoxeled`](super::DrawVoxeled) draws all bricks in views without it.
+#[derive(Component, Default)]
+pub(super) struct VisibleVoxelBricks(pub(super) Vec<Range<u32>>);
+
+pub(super) fn prepare_visible_voxel_bricks(
+    mut commands: Commands,
+    voxel_volume: Option<Res<VoxelVolumeUniform>>,
+    views: Query<(Entity, &Frustum, Option<&LightEntity>), With<ExtractedView>>,
+) {
+    let Some(voxel_volume) = voxel_volume else {
+        return;
+    };
+
+    for (view, frustum, light_entity) in &views {
+        // same planes as bevy's visibility checks, shadow casters in front of a directional light
+        // cascade still throw shadows into it and cameras have no far plane
+        let (intersect_near, intersect_far) = match light_entity {
+            Some(LightEntity::Directional { .. }) => (false, true),
+            Some(_) => (true, true),
+            None => (true, false),
+        };
+        let visible = visible_brick_ranges(
+            voxel_volume.brick_dims(),
+            |first, end| voxel_volume.bricks_aabb(first, end),
+            frustum,
+            intersect_near,
+            intersect_far,
+        );
+        commands.entity(view).insert(VisibleVoxelBricks(visible));
+    }
+}
+
+/// Runs of the visible bricks in `brick_dims`, bounded by `bricks_aabb` from the first to the
+/// end brick.
+///
+/// The bricks are tested by [`CLUSTER_SIZE`] clusters first, the clusters outside the frustum
+/// are skipped and the ones inside it are kept whole, only the bricks of the clusters crossing
+/// its planes are tested one by one.
+fn visible_brick_ranges(
+    brick_dims: UVec3,
+    bricks_aabb: impl Fn(UVec3, UVec3) -> Aabb,
+    frustum: &Frustum,
+    intersect_near: bool,
+    intersect_far: bool,
+) -> Vec<Range<u32>> {
+    let intersects = |aabb: &Aabb| {
+        frustum.intersects_obb(aabb, &Affine3A::IDENTITY, intersect_near, intersect_far)
+    };
+    let contains = |aabb: &Aabb| {
+        frustum
+            .half_spaces
+            .iter()
+            .enumerate()
+            .filter(|&(index, _)| (index != 4 || intersect_near) && (index != 5 || intersect_far))
+            .all(|(_, half_space)| aabb.is_in_half_space(half_space, &Affine3A::IDENTITY))
+    };
+
+    let cluster_dims = (brick_dims + UVec3::splat(CLUSTER_SIZE - 1)) / CLUSTER_SIZE;
+    let mut ranges = Vec::new();
+    for cz in 0..cluster_dims.z {
+        for cy in 0..cluster_dims.y {
+            for cx in 0..cluster_dims.x {
+                let first = UVec3::new(cx, cy, cz) * CLUSTER_SIZE;
+                let end = (first + UVec3::splat(CLUSTER_SIZE)).min(brick_dims);
+                let aabb = bricks_aabb(first, end);
+                if !intersects(&aabb) {
+                    continue;
+                }
+                let inside = contains(&aabb);
+                for z in first.z..end.z {
+                    for y in first.y..end.y {
+                        let row = brick_dims.x * (y + brick_dims.y * z);
+                        if inside {
+                            ranges.push(row + first.x..row + end.x);
+                            continue;
+                        }
+                        for x in first.x..end.x {
+                            let brick = UVec3::new(x, y, z);
+                            if intersects(&bricks_aabb(brick, brick + UVec3::ONE)) {
+                                ranges.push(row + x..row + x + 1);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // the clusters leave the rows in pieces, neighboring pieces are merged into one draw
+    ranges.sort_unstable_by_key(|range| range.start);
+    let mut merged: Vec<Range<u32>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if last.end == range.start => last.end = range.end,
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_math::{Mat4, Vec3};
+
+    use super::*;
+
+    fn unit_bricks(first: UVec3, end: UVec3) -> Aabb {
+        Aabb::from_min_max(first.as_vec3(), end.as_vec3())
+    }
+
+    fn box_frustum(min: Vec3, max: Vec3) -> Frustum {
+        // looking down -z, the near plane at max.z and the far plane at min.z
+        let projection = Mat4::orthographic_rh(min.x, max.x, min.y, max.y, 0.0, max.z - min.z);
+        let view = Mat4::from_translation(Vec3::new(0.0, 0.0, -max.z));
+        Frustum::from_clip_from_world(&(projection * view))
+    }
+
+    fn brute_force(brick_dims: UVec3, frustum: &Frustum) -> Vec<u32> {
+        let mut visible = Vec::new();
+        let mut index = 0;
+        for z in 0..brick_dims.z {
+            for y in 0..brick_dims.y {
+                for x in 0..brick_dims.x {
+                    let brick = UVec3::new(x, y, z);
+                    let aabb = unit_bricks(brick, brick + UVec3::ONE);
+                    if frustum.intersects_obb(&aabb, &Affine3A::IDENTITY, true, true) {
+                        visible.push(index);
+                    }
+                    index += 1;
+                }
+            }
+        }
+        visible
+    }
+
+    #[test]
+    fn whole_volume_is_one_range() {
+        let brick_dims = UVec3::new(9, 6, 5);
+        let frustum = box_frustum(Vec3::splat(-1.0), Vec3::splat(20.0));
+        let ranges = visible_brick_ranges(brick_dims, unit_bricks, &frustum, true, true);
+        assert_eq!(ranges, vec![0..brick_dims.element_product()]);
+    }
+
+    #[test]
+    fn clusters_match_brick_by_brick_culling() {
+        let brick_dims = UVec3::new(11, 7, 9);
+        for (min, max) in [
+            (Vec3::new(2.5, 1.5, 3.5), Vec3::new(8.5, 5.5, 6.5)),
+            (Vec3::new(-3.0, -3.0, -3.0), Vec3::new(4.0, 13.0, 2.5)),
+            (Vec3::new(6.2, 0.1, 0.3), Vec3::new(30.0, 30.0, 30.0)),
+            (Vec3::new(20.0, 20.0, 20.0), Vec3::new(30.0, 30.0, 30.0)),
+        ] {
+            let frustum = box_frustum(min, max);
+            let ranges = visible_brick_ranges(brick_dims, unit_bricks, &frustum, true, true);
+            let bricks: Vec<u32> = ranges.iter().flat_map(Clone::clone).collect();
+            assert_eq!(bricks, brute_force(brick_dims, &frustum), "{min} {max}");
+            // runs are sorted and separated by at least one culled brick
+            for pair in ranges.windows(2) {
+                assert!(pair[0].end < pair[1].start);
+            }
+        }
+    }
+}
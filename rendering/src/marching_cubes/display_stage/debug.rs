@@ -0,0 +1,352 @@
+use core::f32::consts::FRAC_PI_2;
+
+use bevy_asset::{Assets, Handle, RenderAssetUsages};
+use bevy_color::Color;
+use bevy_ecs::change_detection::DetectChanges;
+use bevy_ecs::component::Component;
+use bevy_ecs::entity::Entity;
+use bevy_ecs::resource::Resource;
+use bevy_ecs::system::{Commands, Query, Res, ResMut};
+use bevy_gizmos::gizmos::Gizmos;
+use bevy_image::{Image, ImageSampler};
+use bevy_math::bounding::{Aabb3d, BoundingVolume};
+use bevy_math::primitives::Rectangle;
+use bevy_math::{Quat, UVec2, Vec3};
+use bevy_pbr::{MeshMaterial3d, NotShadowCaster, StandardMaterial};
+use bevy_render::Extract;
+use bevy_render::extract_component::ExtractComponent;
+use bevy_render::mesh::{Mesh, Mesh3d};
+use bevy_render::render_resource::{
+    Extent3d, ShaderType, TextureDimension, TextureFormat, UniformBuffer,
+};
+use bevy_render::renderer::{RenderDevice, RenderQueue};
+use bevy_transform::components::Transform;
+
+use crate::marching_cubes::VoxelVolume;
+use crate::marching_cubes::cpu_mesher::{CpuDensityParam, active_cells};
+
+const BOUNDS_COLOR: Color = Color::srgb(1.0, 1.0, 1.0);
+
+/// Debug views of a [`VoxeledRendered`](super::VoxeledRendered) surface, every field toggles
+/// one of them at runtime.
+///
+/// The overlays are drawn by the display stage, the other views evaluate the density on the CPU
+/// every frame, see [`CpuDensity`](crate::marching_cubes::cpu_mesher::CpuDensity). Gizmos need
+/// bevy's `GizmoPlugin` and the heatmap its `PbrPlugin`, both part of `DefaultPlugins`.
+#[derive(Component, Clone, Debug, Default, PartialEq, ExtractComponent)]
+pub struct VoxelDebug {
+    /// Triangle edges drawn over the surface.
+    pub wireframe: bool,
+    /// Gizmo around [`VoxelVolume::aabb`].
+    pub bounds: bool,
+    /// Where the surface crosses the faces of the voxel cells, drawn over the surface.
+    pub cell_grid: bool,
+    /// Gizmo outlines of the cells the surface passes through, colored by their case index.
+    pub cases: CaseHighlight,
+    /// Plane through the volume colored by the density.
+    pub heatmap: Option<DensitySlice>,
+}
+
+/// Which active cells [`VoxelDebug::cases`] outlines.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CaseHighlight {
+    #[default]
+    Off,
+    /// Every cell the surface passes through.
+    All,
+    /// Only the cells with this case index, bit `i` is set when corner `i` is inside.
+    Only(u8),
+}
+
+/// Axis aligned slice of the volume for [`VoxelDebug::heatmap`].
+///
+/// Inside is red, outside blue, fading to white at the surface which is traced in black.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DensitySlice {
+    /// Normal of the slice.
+    pub axis: SliceAxis,
+    /// Position along the axis, 0 at the minimum of the volume and 1 at its maximum.
+    pub position: f32,
+    /// Field value reaching the full color, in the units of the density.
+    pub range: f32,
+}
+
+impl Default for DensitySlice {
+    fn default() -> Self {
+        Self {
+            axis: SliceAxis::Y,
+            position: 0.5,
+            range: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SliceAxis {
+    X,
+    #[default]
+    Y,
+    Z,
+}
+
+impl DensitySlice {
+    /// Transform of a unit rectangle covering the slice of `aabb`.
+    fn transform(&self, aabb: &Aabb3d) -> Transform {
+        let min = Vec3::from(aabb.min);
+        let size = Vec3::from(aabb.max - aabb.min);
+        let mut translation = Vec3::from(aabb.center());
+        let offset = self.position.clamp(0.0, 1.0);
+        let (rotation, scale) = match self.axis {
+            SliceAxis::X => {
+                translation.x = min.x + offset * size.x;
+                (
+                    Quat::from_rotation_y(FRAC_PI_2),
+                    Vec3::new(size.z, size.y, 1.0),
+                )
+            }
+            SliceAxis::Y => {
+                translation.y = min.y + offset * size.y;
+                (
+                    Quat::from_rotation_x(-FRAC_PI_2),
+                    Vec3::new(size.x, size.z, 1.0),
+                )
+            }
+            SliceAxis::Z => {
+                translation.z = min.z + offset * size.z;
+                (Quat::IDENTITY, Vec3::new(size.x, size.y, 1.0))
+            }
+        };
+        Transform {
+            translation,
+            rotation,
+            scale,
+        }
+    }
+
+    /// One pixel per voxel of the slice.
+    fn resolution(&self, volume: &VoxelVolume) -> UVec2 {
+        let count = volume.count_dims();
+        let resolution = match self.axis {
+            SliceAxis::X => UVec2::new(count.z, count.y),
+            SliceAxis::Y => UVec2::new(count.x, count.z),
+            SliceAxis::Z => UVec2::new(count.x, count.y),
+        };
+        resolution.max(UVec2::ONE)
+    }
+}
+
+pub(super) fn draw_voxel_debug_gizmos(
+    mut gizmos: Gizmos,
+    debugs: Query<&VoxelDebug>,
+    density: CpuDensityParam,
+) {
+    let volume = &*density.voxel_volume;
+    for debug in &debugs {
+        if debug.bounds {
+            gizmos.cuboid(
+                Transform::from_translation(volume.aabb.center().into())
+                    .with_scale((volume.aabb.max - volume.aabb.min).into()),
+                BOUNDS_COLOR,
+            );
+        }
+
+        if debug.cases == CaseHighlight::Off {
+            continue;
+        }
+        let field = density.density();
+        let half_voxel = Vec3::splat(volume.voxel_size * 0.5);
+        for (coord, case) in active_cells(volume, |pos| field.field(pos)) {
+            if matches!(debug.cases, CaseHighlight::Only(only) if only != case) {
+                continue;
+            }
+            gizmos.cuboid(
+                Transform::from_translation(coord + half_voxel)
+                    .with_scale(Vec3::splat(volume.voxel_size)),
+                case_color(case),
+            );
+        }
+    }
+}
+
+/// Distinct hue for every case index.
+fn case_color(case: u8) -> Color {
+    Color::hsl(case as f32 / 256.0 * 360.0, 0.9, 0.55)
+}
+
+/// Slice plane drawn for the [`VoxelDebug::heatmap`] of `owner`.
+#[derive(Component)]
+pub(super) struct DensityHeatmap {
+    owner: Entity,
+    image: Handle<Image>,
+}
+
+pub(super) fn update_density_heatmaps(
+    mut commands: Commands,
+    debugs: Query<(Entity, &VoxelDebug)>,
+    mut heatmaps: Query<(Entity, &DensityHeatmap, &mut Transform)>,
+    density: CpuDensityParam,
+    mut images: ResMut<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (entity, heatmap, _) in &heatmaps {
+        let enabled = debugs
+            .get(heatmap.owner)
+            .is_ok_and(|(_, debug)| debug.heatmap.is_some());
+        if !enabled {
+            commands.entity(entity).despawn();
+        }
+    }
+
+    let volume = &*density.voxel_volume;
+    let field = density.density();
+    for (owner, debug) in &debugs {
+        let Some(slice) = debug.heatmap else {
+            continue;
+        };
+        let transform = slice.transform(&volume.aabb);
+        let image = heatmap_image(
+            |pos| field.field(pos),
+            &transform,
+            slice.resolution(volume),
+            slice.range,
+        );
+
+        match heatmaps
+            .iter_mut()
+            .find(|(_, heatmap, _)| heatmap.owner == owner)
+        {
+            Some((_, heatmap, mut heatmap_transform)) => {
+                *heatmap_transform = transform;
+                images.insert(&heatmap.image, image);
+            }
+            None => {
+                let image = images.add(image);
+                commands.spawn((
+                    DensityHeatmap {
+                        owner,
+                        image: image.clone(),
+                    },
+                    Mesh3d(meshes.add(Rectangle::new(1.0, 1.0))),
+                    MeshMaterial3d(materials.add(StandardMaterial {
+                        base_color_texture: Some(image),
+                        unlit: true,
+                        double_sided: true,
+                        cull_mode: None,
+                        ..Default::default()
+                    })),
+                    transform,
+                    NotShadowCaster,
+                ));
+            }
+        }
+    }
+}
+
+/// Samples `field` at the pixel centers of the unit rectangle placed by `transform`.
+fn heatmap_image(
+    field: impl Fn(Vec3) -> f32,
+    transform: &Transform,
+    resolution: UVec2,
+    range: f32,
+) -> Image {
+    let size = resolution.as_vec2();
+    let mut values = Vec::with_capacity(resolution.element_product() as usize);
+    for y in 0..resolution.y {
+        for x in 0..resolution.x {
+            // same orientation as the uvs of the rectangle mesh
+            let local = Vec3::new(
+                (x as f32 + 0.5) / size.x - 0.5,
+                0.5 - (y as f32 + 0.5) / size.y,
+                0.0,
+            );
+            values.push(field(transform.transform_point(local)));
+        }
+    }
+
+    let width = resolution.x as usize;
+    let mut data = Vec::with_capacity(values.len() * 4);
+    for (i, &value) in values.iter().enumerate() {
+        let right = values.get(i + 1).filter(|_| (i + 1) % width != 0);
+        let below = values.get(i + width);
+        let crossing = [right, below]
+            .into_iter()
+            .flatten()
+            .any(|&neighbor| (neighbor < 0.0) != (value < 0.0));
+        data.extend(if crossing {
+            [0, 0, 0, 255]
+        } else {
+            heat_color(value, range)
+        });
+    }
+
+    let mut image = Image::new(
+        Extent3d {
+            width: resolution.x,
+            height: resolution.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    // one texel per voxel, keep the cells visible
+    image.sampler = ImageSampler::nearest();
+    image
+}
+
+/// Diverging colormap, red inside and blue outside, `field` is negative inside.
+fn heat_color(field: f32, range: f32) -> [u8; 4] {
+    const INSIDE: Vec3 = Vec3::new(0.85, 0.15, 0.1);
+    const OUTSIDE: Vec3 = Vec3::new(0.1, 0.3, 0.85);
+    let t = (field / range.max(f32::EPSILON)).clamp(-1.0, 1.0);
+    let color = if t < 0.0 {
+        Vec3::ONE.lerp(INSIDE, -t)
+    } else {
+        Vec3::ONE.lerp(OUTSIDE, t)
+    };
+    let [r, g, b] = (color * 255.0).to_array().map(|c| c as u8);
+    [r, g, b, 255]
+}
+
+/// `VoxelGrid` in `display_stage.wgsl`, the cell lattice of the [`VoxelVolume`].
+#[derive(Resource, ShaderType, Clone, Default)]
+pub(super) struct VoxelGridUniform {
+    min: Vec3,
+    voxel_size: f32,
+}
+
+#[derive(Resource, Default)]
+pub(super) struct VoxelGridBuffer {
+    pub(super) buffer: UniformBuffer<VoxelGridUniform>,
+}
+
+pub(super) fn extract_voxel_grid(
+    mut commands: Commands,
+    voxel_volume: Extract<Res<VoxelVolume>>,
+    grid_buffer: Res<VoxelGridBuffer>,
+) {
+    if !voxel_volume.is_changed() && grid_buffer.buffer.buffer().is_some() {
+        return;
+    }
+    commands.insert_resource(VoxelGridUniform {
+        min: voxel_volume.aabb.min.into(),
+        voxel_size: voxel_volume.voxel_size,
+    });
+}
+
+pub(super) fn prepare_voxel_grid_buffer(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut grid_buffer: ResMut<VoxelGridBuffer>,
+    grid: Option<Res<VoxelGridUniform>>,
+) {
+    let Some(grid) = grid.filter(DetectChanges::is_changed) else {
+        return;
+    };
+    grid_buffer.buffer.set(grid.clone());
+    grid_buffer
+        .buffer
+        .write_buffer(&render_device, &render_queue);
+}
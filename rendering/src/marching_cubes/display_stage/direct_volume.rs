@@ -0,0 +1,525 @@
+use bevy_app::{App, Plugin, PostUpdate};
+use bevy_asset::{
+    Asset, AssetApp, AssetEvent, AssetId, Assets, Handle, RenderAssetUsages, load_internal_asset,
+    weak_handle,
+};
+use bevy_core_pipeline::core_3d::{CORE_3D_DEPTH_FORMAT, Transparent3d};
+use bevy_ecs::component::Component;
+use bevy_ecs::entity::{Entity, EntityHashMap};
+use bevy_ecs::event::EventReader;
+use bevy_ecs::query::{QueryItem, ROQueryItem};
+use bevy_ecs::resource::Resource;
+use bevy_ecs::schedule::IntoScheduleConfigs;
+use bevy_ecs::system::lifetimeless::SRes;
+use bevy_ecs::system::{Commands, Query, Res, ResMut, SystemParamItem};
+use bevy_ecs::world::{FromWorld, World};
+use bevy_image::{BevyDefault, Image};
+use bevy_math::{UVec3, Vec2, Vec3};
+use bevy_pbr::{MeshPipeline, MeshPipelineKey, MeshPipelineViewLayoutKey, SetMeshViewBindGroup};
+use bevy_render::extract_component::{ExtractComponent, ExtractComponentPlugin};
+use bevy_render::render_asset::RenderAssets;
+use bevy_render::render_phase::{
+    AddRenderCommand, DrawFunctions, PhaseItem, PhaseItemExtraIndex, RenderCommand,
+    RenderCommandResult, SetItemPipeline, TrackedRenderPass, ViewSortedRenderPhases,
+};
+use bevy_render::render_resource::binding_types::{
+    storage_buffer_read_only_sized, texture_3d, uniform_buffer,
+};
+use bevy_render::render_resource::{
+    BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, BlendState,
+    ColorTargetState, ColorWrites, CompareFunction, DepthStencilState, Extent3d, Face,
+    FragmentState, MultisampleState, PipelineCache, PrimitiveState, RenderPipelineDescriptor,
+    Shader, ShaderStages, ShaderType, SpecializedRenderPipeline, SpecializedRenderPipelines,
+    TextureDimension, TextureFormat, TextureSampleType, UniformBuffer, VertexState,
+};
+use bevy_render::renderer::{RenderDevice, RenderQueue};
+use bevy_render::storage::{GpuShaderStorageBuffer, ShaderStorageBuffer};
+use bevy_render::texture::GpuImage;
+use bevy_render::view::{
+    self, ExtractedView, Msaa, RenderVisibleEntities, ViewTarget, VisibilityClass,
+};
+use bevy_render::{Render, RenderApp, RenderSet};
+
+use super::BOUNDS_SHADER_HANDLE;
+use super::prepass::{ViewPrepasses, prepass_key};
+use super::raymarch::DrawBounds;
+use super::transfer_function::{TransferFunction, TransferFunctionLoader};
+use crate::marching_cubes::volume::ScalarVolume;
+
+pub const DIRECT_VOLUME_SHADER_HANDLE: Handle<Shader> =
+    weak_handle!("4e92c7b0-d815-4a3f-b6e7-0c5a29f1e83d");
+
+/// Direct volume rendering of [`VolumeRendered`] entities, a display mode next to the
+/// isosurface of [`VoxelRenderedPlugin`](super::VoxelRenderedPlugin).
+pub struct VolumeRenderedPlugin;
+
+/// Renders a [`ScalarVolume`] as a translucent medium instead of extracting a surface, for scan
+/// data where one isovalue hides most of the volume.
+///
+/// Rays through the bounds of the volume sample it at a fixed step and composite the
+/// [`TransferFunction`] colors front to back, stopping once they are nearly opaque. The volume
+/// is drawn in the transparent pass in world space, placed by its own origin and spacing, the
+/// entity transform is ignored. Views with a
+/// [`DepthPrepass`](bevy_core_pipeline::prepass::DepthPrepass) end the rays at the opaque
+/// geometry inside the volume, without one the volume is hidden once the camera enters it.
+#[derive(Component, Clone, Debug)]
+#[require(VisibilityClass)]
+#[component(on_add = view::add_visibility_class::<VolumeRendered>)]
+pub struct VolumeRendered {
+    pub volume: Handle<ScalarVolume>,
+    pub transfer_function: Handle<TransferFunction>,
+    /// Sample values mapped to the start and end of the transfer function, the value range of
+    /// the volume without one.
+    pub window: Option<Vec2>,
+    /// Distance between samples in voxels of the smallest spacing, the opacity is corrected for
+    /// it so the look does not change with the step.
+    pub step_size: f32,
+    /// Multiplies the transfer function opacity.
+    pub opacity_scale: f32,
+    /// Accumulated opacity at which a ray stops sampling.
+    pub early_termination: f32,
+    /// Offsets the first sample of every pixel by a per pixel noise that changes every frame,
+    /// trading the banding of the fixed step for noise that TAA can average.
+    pub jitter: bool,
+}
+
+impl Default for VolumeRendered {
+    fn default() -> Self {
+        Self {
+            volume: Handle::default(),
+            transfer_function: Handle::default(),
+            window: None,
+            step_size: 0.5,
+            opacity_scale: 1.0,
+            early_termination: 0.99,
+            jitter: true,
+        }
+    }
+}
+
+/// GPU copies of the assets of a [`VolumeRendered`], rebuilt when they change.
+#[derive(Component)]
+pub struct VolumeRenderData {
+    volume: AssetId<ScalarVolume>,
+    density: Handle<Image>,
+    dims: UVec3,
+    spacing: Vec3,
+    origin: Vec3,
+    value_range: Vec2,
+    transfer_function: AssetId<TransferFunction>,
+    lookup_table: Handle<ShaderStorageBuffer>,
+}
+
+/// `VolumeParams` in `direct_volume.wgsl`.
+#[derive(ShaderType, Clone, Copy, Default)]
+pub struct VolumeRenderUniform {
+    min_bound: Vec3,
+    step_size: f32,
+    max_bound: Vec3,
+    opacity_scale: f32,
+    spacing: Vec3,
+    early_termination: f32,
+    dims: UVec3,
+    jitter: u32,
+    window: Vec2,
+}
+
+/// [`VolumeRendered`] in the render world.
+#[derive(Component, Clone, Copy)]
+pub struct ExtractedVolumeRendered {
+    density: AssetId<Image>,
+    lookup_table: AssetId<ShaderStorageBuffer>,
+    uniform: VolumeRenderUniform,
+    center: Vec3,
+}
+
+impl Plugin for VolumeRenderedPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<TransferFunction>()
+            .init_asset_loader::<TransferFunctionLoader>()
+            .add_plugins(ExtractComponentPlugin::<VolumeRendered>::default())
+            .add_systems(PostUpdate, upload_volume_render_data);
+
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .init_resource::<VolumeRenderBindGroups>()
+            .add_render_command::<Transparent3d, DrawVolumeRenderedCommands>()
+            .add_systems(
+                Render,
+                (
+                    prepare_volume_render_bind_groups.in_set(RenderSet::PrepareBindGroups),
+                    queue_volume_rendered.in_set(RenderSet::Queue),
+                ),
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        load_internal_asset!(app, BOUNDS_SHADER_HANDLE, "bounds.wgsl", Shader::from_wgsl);
+        load_internal_asset!(
+            app,
+            DIRECT_VOLUME_SHADER_HANDLE,
+            "direct_volume.wgsl",
+            Shader::from_wgsl
+        );
+
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .init_resource::<VolumeRenderedPipeline>()
+            .init_resource::<SpecializedRenderPipelines<VolumeRenderedPipeline>>();
+    }
+}
+
+fn changed_assets<A: Asset>(events: &mut EventReader<AssetEvent<A>>) -> Vec<AssetId<A>> {
+    events
+        .read()
+        .filter_map(|event| match *event {
+            AssetEvent::Added { id }
+            | AssetEvent::Modified { id }
+            | AssetEvent::LoadedWithDependencies { id } => Some(id),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Single channel 3D texture of the samples, read with `textureLoad` so the values keep their
+/// full precision.
+fn density_image(volume: &ScalarVolume) -> Image {
+    Image::new(
+        Extent3d {
+            width: volume.dims.x,
+            height: volume.dims.y,
+            depth_or_array_layers: volume.dims.z,
+        },
+        TextureDimension::D3,
+        bytemuck::cast_slice(&volume.values).to_vec(),
+        TextureFormat::R32Float,
+        RenderAssetUsages::RENDER_WORLD,
+    )
+}
+
+fn value_range(volume: &ScalarVolume) -> Vec2 {
+    volume
+        .values
+        .iter()
+        .fold(Vec2::new(f32::MAX, f32::MIN), |range, &value| {
+            Vec2::new(range.x.min(value), range.y.max(value))
+        })
+}
+
+fn lookup_table_buffer(transfer_function: &TransferFunction) -> ShaderStorageBuffer {
+    ShaderStorageBuffer::new(
+        bytemuck::cast_slice(&transfer_function.lookup_table()),
+        RenderAssetUsages::RENDER_WORLD,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn upload_volume_render_data(
+    mut commands: Commands,
+    mut rendered: Query<(Entity, &VolumeRendered, Option<&mut VolumeRenderData>)>,
+    scalar_volumes: Res<Assets<ScalarVolume>>,
+    transfer_functions: Res<Assets<TransferFunction>>,
+    mut scalar_volume_events: EventReader<AssetEvent<ScalarVolume>>,
+    mut transfer_function_events: EventReader<AssetEvent<TransferFunction>>,
+    mut images: ResMut<Assets<Image>>,
+    mut storage_buffers: ResMut<Assets<ShaderStorageBuffer>>,
+) {
+    let changed_volumes = changed_assets(&mut scalar_volume_events);
+    let changed_transfer_functions = changed_assets(&mut transfer_function_events);
+
+    for (entity, rendered, data) in &mut rendered {
+        let (Some(volume), Some(transfer_function)) = (
+            scalar_volumes.get(&rendered.volume),
+            transfer_functions.get(&rendered.transfer_function),
+        ) else {
+            continue;
+        };
+
+        let Some(mut data) = data else {
+            commands.entity(entity).insert(VolumeRenderData {
+                volume: rendered.volume.id(),
+                density: images.add(density_image(volume)),
+                dims: volume.dims,
+                spacing: volume.spacing,
+                origin: volume.origin,
+                value_range: value_range(volume),
+                transfer_function: rendered.transfer_function.id(),
+                lookup_table: storage_buffers.add(lookup_table_buffer(transfer_function)),
+            });
+            continue;
+        };
+
+        if data.volume != rendered.volume.id() || changed_volumes.contains(&data.volume) {
+            images.insert(&data.density, density_image(volume));
+            data.volume = rendered.volume.id();
+            data.dims = volume.dims;
+            data.spacing = volume.spacing;
+            data.origin = volume.origin;
+            data.value_range = value_range(volume);
+        }
+        if data.transfer_function != rendered.transfer_function.id()
+            || changed_transfer_functions.contains(&data.transfer_function)
+        {
+            storage_buffers.insert(&data.lookup_table, lookup_table_buffer(transfer_function));
+            data.transfer_function = rendered.transfer_function.id();
+        }
+    }
+}
+
+impl ExtractComponent for VolumeRendered {
+    type QueryData = (&'static Self, &'static VolumeRenderData);
+    type QueryFilter = ();
+    type Out = ExtractedVolumeRendered;
+
+    fn extract_component((rendered, data): QueryItem<Self::QueryData>) -> Option<Self::Out> {
+        let min_bound = data.origin;
+        let max_bound = data.origin + data.dims.saturating_sub(UVec3::ONE).as_vec3() * data.spacing;
+        Some(ExtractedVolumeRendered {
+            density: data.density.id(),
+            lookup_table: data.lookup_table.id(),
+            uniform: VolumeRenderUniform {
+                min_bound,
+                step_size: rendered.step_size.max(0.01),
+                max_bound,
+                opacity_scale: rendered.opacity_scale,
+                spacing: data.spacing,
+                early_termination: rendered.early_termination,
+                dims: data.dims,
+                jitter: rendered.jitter as u32,
+                window: rendered.window.unwrap_or(data.value_range),
+            },
+            center: (min_bound + max_bound) * 0.5,
+        })
+    }
+}
+
+#[derive(Resource)]
+pub struct VolumeRenderedPipeline {
+    mesh_pipeline: MeshPipeline,
+    volume_layout: BindGroupLayout,
+}
+
+impl FromWorld for VolumeRenderedPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let volume_layout = render_device.create_bind_group_layout(
+            "volume_rendered_bind_group",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::VERTEX_FRAGMENT,
+                (
+                    uniform_buffer::<VolumeRenderUniform>(false),
+                    texture_3d(TextureSampleType::Float { filterable: false }),
+                    storage_buffer_read_only_sized(false, None),
+                ),
+            ),
+        );
+
+        Self {
+            mesh_pipeline: MeshPipeline::from_world(world),
+            volume_layout,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VolumeRenderedPipelineKey {
+    /// Only the msaa samples, hdr and the prepass bits are used.
+    pub mesh_key: MeshPipelineKey,
+}
+
+impl SpecializedRenderPipeline for VolumeRenderedPipeline {
+    type Key = VolumeRenderedPipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let mut shader_defs = vec![];
+        let depth_prepass = key.mesh_key.contains(MeshPipelineKey::DEPTH_PREPASS);
+        if depth_prepass {
+            shader_defs.push("DEPTH_PREPASS".into());
+        }
+        if key.mesh_key.msaa_samples() > 1 {
+            shader_defs.push("MULTISAMPLED".into());
+        }
+
+        RenderPipelineDescriptor {
+            label: Some("volume_rendered_pipeline".into()),
+            layout: vec![
+                self.mesh_pipeline
+                    .get_view_layout(MeshPipelineViewLayoutKey::from(key.mesh_key))
+                    .clone(),
+                self.volume_layout.clone(),
+            ],
+            push_constant_ranges: vec![],
+            vertex: VertexState {
+                shader: DIRECT_VOLUME_SHADER_HANDLE,
+                shader_defs: shader_defs.clone(),
+                entry_point: "vertex".into(),
+                buffers: vec![],
+            },
+            fragment: Some(FragmentState {
+                shader: DIRECT_VOLUME_SHADER_HANDLE,
+                shader_defs,
+                entry_point: "fragment".into(),
+                targets: vec![Some(ColorTargetState {
+                    format: if key.mesh_key.contains(MeshPipelineKey::HDR) {
+                        ViewTarget::TEXTURE_FORMAT_HDR
+                    } else {
+                        TextureFormat::bevy_default()
+                    },
+                    // the rays composite front to back into premultiplied colors
+                    blend: Some(BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState {
+                // with the prepass depth the back faces start the rays and the camera may be
+                // inside, otherwise the front faces are depth tested against the opaque geometry
+                cull_mode: Some(if depth_prepass {
+                    Face::Front
+                } else {
+                    Face::Back
+                }),
+                ..Default::default()
+            },
+            depth_stencil: Some(DepthStencilState {
+                format: CORE_3D_DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: if depth_prepass {
+                    CompareFunction::Always
+                } else {
+                    CompareFunction::GreaterEqual
+                },
+                stencil: Default::default(),
+                bias: Default::default(),
+            }),
+            multisample: MultisampleState {
+                count: key.mesh_key.msaa_samples(),
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            zero_initialize_workgroup_memory: false,
+        }
+    }
+}
+
+type DrawVolumeRenderedCommands = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetVolumeRenderBindGroup<1>,
+    DrawBounds,
+);
+
+/// Bind groups of the [`VolumeRendered`] entities whose assets are uploaded, by render entity.
+#[derive(Resource, Default)]
+struct VolumeRenderBindGroups(EntityHashMap<(BindGroup, UniformBuffer<VolumeRenderUniform>)>);
+
+fn prepare_volume_render_bind_groups(
+    pipeline: Res<VolumeRenderedPipeline>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    gpu_images: Res<RenderAssets<GpuImage>>,
+    gpu_buffers: Res<RenderAssets<GpuShaderStorageBuffer>>,
+    mut bind_groups: ResMut<VolumeRenderBindGroups>,
+    rendered: Query<(Entity, &ExtractedVolumeRendered)>,
+) {
+    bind_groups.0.clear();
+
+    for (entity, rendered) in &rendered {
+        let (Some(density), Some(lookup_table)) = (
+            gpu_images.get(rendered.density),
+            gpu_buffers.get(rendered.lookup_table),
+        ) else {
+            continue;
+        };
+
+        let mut buffer = UniformBuffer::from(rendered.uniform);
+        buffer.write_buffer(&render_device, &render_queue);
+
+        let bind_group = render_device.create_bind_group(
+            Some("volume_rendered_bind_group"),
+            &pipeline.volume_layout,
+            &BindGroupEntries::sequential((
+                buffer.binding().unwrap(),
+                &density.texture_view,
+                lookup_table.buffer.as_entire_buffer_binding(),
+            )),
+        );
+        bind_groups.0.insert(entity, (bind_group, buffer));
+    }
+}
+
+struct SetVolumeRenderBindGroup<const I: usize>;
+
+impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetVolumeRenderBindGroup<I> {
+    type Param = SRes<VolumeRenderBindGroups>;
+
+    type ViewQuery = ();
+
+    type ItemQuery = ();
+
+    fn render<'w>(
+        item: &P,
+        _view: ROQueryItem<'w, Self::ViewQuery>,
+        _entity: Option<ROQueryItem<'w, Self::ItemQuery>>,
+        bind_groups: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some((bind_group, _)) = bind_groups.into_inner().0.get(&item.entity()) else {
+            return RenderCommandResult::Skip;
+        };
+        pass.set_bind_group(I, bind_group, &[]);
+        RenderCommandResult::Success
+    }
+}
+
+fn queue_volume_rendered(
+    pipeline_cache: Res<PipelineCache>,
+    volume_pipeline: Res<VolumeRenderedPipeline>,
+    mut specialized_render_pipelines: ResMut<SpecializedRenderPipelines<VolumeRenderedPipeline>>,
+    transparent_draw_functions: Res<DrawFunctions<Transparent3d>>,
+    mut transparent_render_phases: ResMut<ViewSortedRenderPhases<Transparent3d>>,
+    rendered: Query<&ExtractedVolumeRendered>,
+    views: Query<(&ExtractedView, &RenderVisibleEntities, &Msaa, ViewPrepasses)>,
+) {
+    let draw_volume = transparent_draw_functions
+        .read()
+        .id::<DrawVolumeRenderedCommands>();
+
+    for (view, view_visible_entities, msaa, prepasses) in &views {
+        let Some(transparent_phase) = transparent_render_phases.get_mut(&view.retained_view_entity)
+        else {
+            continue;
+        };
+        let rangefinder = view.rangefinder3d();
+        let pipeline_id = specialized_render_pipelines.specialize(
+            &pipeline_cache,
+            &volume_pipeline,
+            VolumeRenderedPipelineKey {
+                // the view bind group of a view with prepass textures has a different layout
+                mesh_key: MeshPipelineKey::from_msaa_samples(msaa.samples())
+                    | MeshPipelineKey::from_hdr(view.hdr)
+                    | prepass_key(prepasses),
+            },
+        );
+
+        for &entity in view_visible_entities.get::<VolumeRendered>().iter() {
+            let Ok(volume) = rendered.get(entity.0) else {
+                continue;
+            };
+            transparent_phase.add(Transparent3d {
+                distance: rangefinder.distance_translation(&volume.center),
+                pipeline: pipeline_id,
+                entity,
+                draw_function: draw_volume,
+                batch_range: 0..1,
+                extra_index: PhaseItemExtraIndex::None,
+                indexed: false,
+            });
+        }
+    }
+}
@@ -0,0 +1,123 @@
+#import bevy_pbr::mesh_view_bindings::{view, globals}
+#import bevy_pbr::view_transformations::{frag_coord_to_ndc, position_ndc_to_world}
+#import marching_cubes::bounds::bounds_position
+
+#ifdef DEPTH_PREPASS
+#import bevy_pbr::prepass_utils::prepass_depth
+#endif
+
+// Direct volume rendering, composites the transfer function colors of the samples front to back
+
+// Must match `VolumeRenderUniform`
+struct VolumeParams {
+    min_bound: vec3<f32>,
+    // in voxels of the smallest spacing
+    step_size: f32,
+    max_bound: vec3<f32>,
+    opacity_scale: f32,
+    spacing: vec3<f32>,
+    early_termination: f32,
+    dims: vec3<u32>,
+    jitter: u32,
+    // sample values at the start and end of the transfer function
+    window: vec2<f32>,
+};
+
+@group(1) @binding(0) var<uniform> params: VolumeParams;
+@group(1) @binding(1) var density_texture: texture_3d<f32>;
+// `TransferFunction::lookup_table`
+@group(1) @binding(2) var<storage, read> transfer_function: array<vec4<f32>>;
+
+// bounds the loop for degenerate steps, a ray through a 512^3 volume at half voxel steps fits
+const MAX_SAMPLES: u32 = 4096u;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+};
+
+@vertex
+fn vertex(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let position = bounds_position(index, params.min_bound, params.max_bound);
+
+    var out: VertexOutput;
+    out.clip_position = view.clip_from_world * vec4(position, 1.0);
+    out.world_position = position;
+    return out;
+}
+
+fn density_value(coord: vec3<u32>) -> f32 {
+    return textureLoad(density_texture, min(coord, params.dims - 1u), 0).r;
+}
+
+// Trilinearly interpolated sample, mirrors `ScalarVolume::sample`
+fn sample_density(pos: vec3<f32>) -> f32 {
+    let last = vec3<f32>(params.dims - 1u);
+    let grid = clamp((pos - params.min_bound) / params.spacing, vec3(0.0), last);
+    let base = min(vec3<u32>(floor(grid)), max(params.dims, vec3(2u)) - 2u);
+    let t = grid - vec3<f32>(base);
+
+    let x0 = mix(density_value(base), density_value(base + vec3(1u, 0u, 0u)), t.x);
+    let x1 = mix(density_value(base + vec3(0u, 1u, 0u)), density_value(base + vec3(1u, 1u, 0u)), t.x);
+    let x2 = mix(density_value(base + vec3(0u, 0u, 1u)), density_value(base + vec3(1u, 0u, 1u)), t.x);
+    let x3 = mix(density_value(base + vec3(0u, 1u, 1u)), density_value(base + vec3(1u, 1u, 1u)), t.x);
+    return mix(mix(x0, x1, t.y), mix(x2, x3, t.y), t.z);
+}
+
+// Straight alpha color of a sample value, linear between the lookup table entries
+fn transfer(value: f32) -> vec4<f32> {
+    let last = arrayLength(&transfer_function) - 1u;
+    let t = saturate((value - params.window.x) / max(params.window.y - params.window.x, 1e-6));
+    let x = t * f32(last);
+    let i = min(u32(x), last - 1u);
+    return mix(transfer_function[i], transfer_function[i + 1u], x - f32(i));
+}
+
+// Interleaved gradient noise, shifted every frame
+fn jitter(frag_coord: vec2<f32>) -> f32 {
+    let pixel = frag_coord + 5.588238 * f32(globals.frame_count % 64u);
+    return fract(52.9829189 * fract(dot(pixel, vec2(0.06711056, 0.00583715))));
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let origin = view.world_position;
+    let direction = normalize(in.world_position - origin);
+
+    // slab intersection with the volume, the camera may be inside it
+    let inverse = 1.0 / direction;
+    let t0 = (params.min_bound - origin) * inverse;
+    let t1 = (params.max_bound - origin) * inverse;
+    let t_min = min(t0, t1);
+    let t_max = max(t0, t1);
+    let t_near = max(max(max(t_min.x, t_min.y), t_min.z), 0.0);
+    var t_far = min(min(t_max.x, t_max.y), t_max.z);
+
+#ifdef DEPTH_PREPASS
+    // stop at the opaque geometry, a depth of 0 is the far plane
+    let depth = prepass_depth(in.clip_position, 0u);
+    if (depth > 0.0) {
+        let scene = position_ndc_to_world(vec3(frag_coord_to_ndc(in.clip_position).xy, depth));
+        t_far = min(t_far, dot(scene - origin, direction));
+    }
+#endif
+
+    let step = params.step_size * min(min(params.spacing.x, params.spacing.y), params.spacing.z);
+    var t = t_near;
+    if (params.jitter != 0u) {
+        t += step * jitter(in.clip_position.xy);
+    }
+
+    var color = vec4(0.0);
+    for (var i = 0u; i < MAX_SAMPLES && t < t_far; i++) {
+        let sample = transfer(sample_density(origin + direction * t));
+        // the transfer function opacity is per voxel, correct it for the step
+        let alpha = 1.0 - pow(1.0 - saturate(sample.a * params.opacity_scale), params.step_size);
+        color += (1.0 - color.a) * vec4(sample.rgb * alpha, alpha);
+        if (color.a >= params.early_termination) {
+            break;
+        }
+        t += step;
+    }
+    return color;
+}
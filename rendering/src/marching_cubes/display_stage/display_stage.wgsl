@@ -1,27 +1,177 @@
 #import bevy_pbr::view_transformations::position_world_to_clip;
+#import marching_cubes::shading::{UNTEXTURED_ROUGHNESS, material_color, shade, shadow_visibility};
+
+#ifdef CLIPPING
+#import marching_cubes::clipping::{ClipParams, CLIP_CAPPED, CLIP_COLORMAP, clip_distance, clip_colormap};
+
+@group(1) @binding(2) var<uniform> clipping: ClipParams;
+#endif
+
+const WIREFRAME_COLOR: vec3<f32> = vec3(0.0);
+const CELL_GRID_COLOR: vec3<f32> = vec3(1.0, 0.8, 0.0);
+
+#ifdef DEBUG_CELL_GRID
+// Must match `VoxelGridUniform`
+struct VoxelGrid {
+    min: vec3<f32>,
+    voxel_size: f32,
+};
+
+@group(1) @binding(1) var<uniform> grid: VoxelGrid;
+#endif
+
+#ifdef TRIPLANAR
+// Must match `TriplanarUniform`
+struct TriplanarParams {
+    tiling: f32,
+    blend_sharpness: f32,
+    perceptual_roughness: f32,
+    flags: u32,
+};
+
+const TRIPLANAR_NORMAL_MAP: u32 = 1u;
+const TRIPLANAR_ROUGHNESS_MAP: u32 = 2u;
+
+@group(2) @binding(0) var<uniform> triplanar: TriplanarParams;
+@group(2) @binding(1) var albedo_texture: texture_2d<f32>;
+@group(2) @binding(2) var normal_texture: texture_2d<f32>;
+@group(2) @binding(3) var roughness_texture: texture_2d<f32>;
+@group(2) @binding(4) var triplanar_sampler: sampler;
+#endif
 
 struct Vertex {
     @builtin(vertex_index) index: u32,
     @location(0) position: vec3<f32>,
+    @location(1) material_weights: vec4<f32>,
+    @location(2) occlusion: f32,
+    @location(3) normal: vec3<f32>,
+    @location(4) section: f32,
 };
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
-    // @location(0) color: vec3<f32>,
+    @location(0) material_weights: vec4<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) occlusion: f32,
+    @location(3) normal: vec3<f32>,
+    @location(4) section: f32,
+#ifdef DEBUG_WIREFRAME
+    @location(5) barycentric: vec3<f32>,
+#endif
 };
 
 @vertex
 fn vertex(vertex: Vertex) -> VertexOutput {
     var out: VertexOutput;
     out.clip_position = position_world_to_clip(vertex.position);
-    // out.color = vec3<f32>(f32(vertex.index & 1), f32((vertex.index & 2) >> 1),
-    // f32((vertex.index & 4) >> 2));
+    out.material_weights = vertex.material_weights;
+    out.world_position = vertex.position;
+    out.occlusion = vertex.occlusion;
+    out.normal = vertex.normal;
+    out.section = vertex.section;
+#ifdef DEBUG_WIREFRAME
+    // the triangles are not indexed, every third vertex starts one
+    let corner = vertex.index % 3u;
+    out.barycentric = vec3(f32(corner == 0u), f32(corner == 1u), f32(corner == 2u));
+#endif
     return out;
 }
 
+#ifdef TRIPLANAR
+// Samples the three axis projections, the weights sum to one
+fn triplanar_sample(texture: texture_2d<f32>, pos: vec3<f32>, weights: vec3<f32>) -> vec4<f32> {
+    return textureSample(texture, triplanar_sampler, pos.zy) * weights.x
+        + textureSample(texture, triplanar_sampler, pos.xz) * weights.y
+        + textureSample(texture, triplanar_sampler, pos.xy) * weights.z;
+}
+
+// Whiteout blend of the tangent space normals of the three projections onto `normal`
+fn triplanar_normal(pos: vec3<f32>, normal: vec3<f32>, weights: vec3<f32>) -> vec3<f32> {
+    var x = textureSample(normal_texture, triplanar_sampler, pos.zy).xyz * 2.0 - 1.0;
+    var y = textureSample(normal_texture, triplanar_sampler, pos.xz).xyz * 2.0 - 1.0;
+    var z = textureSample(normal_texture, triplanar_sampler, pos.xy).xyz * 2.0 - 1.0;
+    x = vec3(x.xy + normal.zy, abs(x.z) * normal.x);
+    y = vec3(y.xy + normal.xz, abs(y.z) * normal.y);
+    z = vec3(z.xy + normal.xy, abs(z.z) * normal.z);
+    return normalize(x.zyx * weights.x + y.xzy * weights.y + z.xyz * weights.z);
+}
+
+#endif
+
+// Normal of the fragment, the face normal from the screen space derivatives unless smooth.
+// The derivatives always face the camera, the smooth normal is flipped on back faces
+fn surface_normal(mesh: VertexOutput, is_front: bool) -> vec3<f32> {
+#ifdef SHADING_SMOOTH
+    return select(-1.0, 1.0, is_front) * normalize(mesh.normal);
+#else
+    return normalize(cross(dpdy(mesh.world_position), dpdx(mesh.world_position)));
+#endif
+}
+
+// Pixel wide lines where `distance` reaches zero on any axis, 1 on the line
+fn debug_line(distance: vec3<f32>) -> f32 {
+    let pixels = distance / max(fwidth(distance), vec3(1e-6));
+    return 1.0 - saturate(min(min(pixels.x, pixels.y), pixels.z));
+}
+
+// Debug overlays of `VoxelDebug` over the shaded color
+fn debug_overlay(mesh: VertexOutput, color: vec3<f32>) -> vec3<f32> {
+    var result = color;
+#ifdef DEBUG_CELL_GRID
+    let cell = (mesh.world_position - grid.min) / grid.voxel_size;
+    result = mix(result, CELL_GRID_COLOR, debug_line(abs(fract(cell + 0.5) - 0.5)));
+#endif
+#ifdef DEBUG_WIREFRAME
+    result = mix(result, WIREFRAME_COLOR, debug_line(mesh.barycentric));
+#endif
+    return result;
+}
+
 @fragment
 fn fragment(
     mesh: VertexOutput,
+    @builtin(front_facing) is_front: bool,
 ) -> @location(0) vec4<f32> {
-    return vec4<f32>(1.0);
+#ifdef CLIPPING
+    let cut = clip_distance(mesh.world_position, clipping);
+    let cut_width = fwidth(cut);
+    // open cuts keep the surface beyond them, capped ones were closed by the compute stage
+    if ((clipping.flags & CLIP_CAPPED) == 0u && cut > 0.0) {
+        discard;
+    }
+#endif
+
+    var albedo = material_color(mesh.material_weights);
+    let geometric_normal = surface_normal(mesh, is_front);
+    var normal = geometric_normal;
+    var perceptual_roughness = UNTEXTURED_ROUGHNESS;
+
+#ifdef TRIPLANAR
+    let pos = mesh.world_position * triplanar.tiling;
+    var weights = pow(abs(normal), vec3(triplanar.blend_sharpness));
+    weights /= max(weights.x + weights.y + weights.z, 1e-6);
+
+    albedo *= triplanar_sample(albedo_texture, pos, weights);
+    if (triplanar.flags & TRIPLANAR_NORMAL_MAP) != 0u {
+        normal = triplanar_normal(pos, normal, weights);
+    }
+    perceptual_roughness = triplanar.perceptual_roughness;
+    if (triplanar.flags & TRIPLANAR_ROUGHNESS_MAP) != 0u {
+        perceptual_roughness *= triplanar_sample(roughness_texture, pos, weights).r;
+    }
+#endif
+
+#ifdef CLIPPING
+    // the cap lies on the clip planes, the surface only touches them along the rim
+    if ((clipping.flags & CLIP_COLORMAP) != 0u && cut >= -max(cut_width, 1e-5)) {
+        albedo = vec4(clip_colormap(mesh.section, clipping), albedo.a);
+    }
+#endif
+
+#ifdef SHADING_LIT
+    let color = shade(mesh.world_position, mesh.clip_position, geometric_normal, normal, albedo.rgb, clamp(perceptual_roughness, 0.089, 1.0), mesh.occlusion);
+#else
+    let color = albedo.rgb * mesh.occlusion * shadow_visibility(mesh.world_position, mesh.clip_position, geometric_normal);
+#endif
+    return vec4(debug_overlay(mesh, color), albedo.a);
 }
@@ -1,35 +1,114 @@
-use bevy_app::{App, Plugin};
+use core::mem::offset_of;
+use core::ops::Range;
+
+use bevy_app::{App, Plugin, Update};
 use bevy_asset::{AssetId, Handle, load_internal_asset, weak_handle};
+use bevy_color::ColorToComponents;
 use bevy_core_pipeline::core_3d::{
-    CORE_3D_DEPTH_FORMAT, Opaque3d, Opaque3dBatchSetKey, Opaque3dBinKey,
+    CORE_3D_DEPTH_FORMAT, Opaque3d, Opaque3dBatchSetKey, Opaque3dBinKey, Transparent3d,
 };
+use bevy_core_pipeline::prepass::Opaque3dPrepass;
+use bevy_ecs::change_detection::DetectChanges;
 use bevy_ecs::component::{Component, Tick};
-use bevy_ecs::query::ROQueryItem;
+use bevy_ecs::query::{ROQueryItem, With};
 use bevy_ecs::resource::Resource;
 use bevy_ecs::schedule::IntoScheduleConfigs;
-use bevy_ecs::system::lifetimeless::SRes;
-use bevy_ecs::system::{Local, Query, Res, ResMut, SystemParamItem};
-use bevy_ecs::world::FromWorld;
+use bevy_ecs::schedule::common_conditions::any_with_component;
+use bevy_ecs::system::lifetimeless::{Read, SRes};
+use bevy_ecs::system::{Commands, Local, Query, Res, ResMut, SystemParamItem};
+use bevy_ecs::world::{FromWorld, World};
 use bevy_image::BevyDefault;
-use bevy_pbr::{MeshPipeline, MeshPipelineKey, MeshPipelineViewLayoutKey, SetMeshViewBindGroup};
+use bevy_math::Vec4;
+use bevy_pbr::{
+    MeshPipeline, MeshPipelineKey, MeshPipelineViewLayoutKey, SetMeshViewBindGroup, Shadow,
+    ShadowFilteringMethod,
+};
 use bevy_render::extract_component::{ExtractComponent, ExtractComponentPlugin};
 use bevy_render::mesh::{Mesh, PrimitiveTopology, VertexBufferLayout, VertexFormat};
 use bevy_render::render_asset::RenderAssets;
+use bevy_render::render_graph::RenderGraph;
 use bevy_render::render_phase::{
     AddRenderCommand, BinnedRenderPhaseType, DrawFunctions, InputUniformIndex, PhaseItem,
-    RenderCommand, RenderCommandResult, SetItemPipeline, TrackedRenderPass, ViewBinnedRenderPhases,
+    PhaseItemExtraIndex, RenderCommand, RenderCommandResult, SetItemPipeline, TrackedRenderPass,
+    ViewBinnedRenderPhases, ViewSortedRenderPhases,
 };
+use bevy_render::render_resource::binding_types::uniform_buffer;
 use bevy_render::render_resource::{
-    ColorTargetState, ColorWrites, CompareFunction, DepthStencilState, Face, FragmentState,
-    MultisampleState, PipelineCache, PrimitiveState, RenderPipelineDescriptor, Shader,
-    SpecializedRenderPipeline, SpecializedRenderPipelines, TextureFormat, VertexAttribute,
-    VertexState, VertexStepMode,
+    BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, ColorTargetState,
+    ColorWrites, CompareFunction, DepthStencilState, Face, FragmentState, MultisampleState,
+    PipelineCache, PrimitiveState, RenderPipelineDescriptor, Sampler, Shader, ShaderDefVal,
+    ShaderStages, ShaderType, SpecializedRenderPipeline, SpecializedRenderPipelines, TextureFormat,
+    UniformBuffer, VertexAttribute, VertexState, VertexStepMode, WgpuFeatures,
 };
+use bevy_render::renderer::{RenderDevice, RenderQueue};
 use bevy_render::storage::GpuShaderStorageBuffer;
 use bevy_render::view::{ExtractedView, Msaa, RenderVisibleEntities, ViewTarget, VisibilityClass};
-use bevy_render::{Render, RenderApp, RenderSet, view};
+use bevy_render::{ExtractSchedule, Render, RenderApp, RenderSet, view};
 
-use super::{MarchingCubesBuffers, Vertex};
+use super::clipping::ClippingUniform;
+use super::compute_stage::pipeline::prepare_bind_group;
+use super::compute_stage::{MarchingCubesComputeLabel, VoxelVolumeUniform};
+use super::material::{MAX_MATERIALS, VoxelMaterials};
+use super::{MarchingCubesBuffers, Vertex, VoxelBrick};
+
+mod brick_runs;
+mod clipping;
+mod culling;
+mod debug;
+mod direct_volume;
+mod prepass;
+mod raymarch;
+mod shadow;
+mod transfer_function;
+mod transparency;
+mod triplanar;
+
+use brick_runs::{
+    BRICK_RUNS_SHADER_HANDLE, VoxelBrickRuns, VoxelBrickRunsLabel, VoxelBrickRunsNode,
+    VoxelBrickRunsPipeline, prepare_voxel_brick_runs,
+};
+use clipping::{
+    ExtractedVoxelClipping, VoxelClipBuffer, VoxelClipLayout, extract_voxel_clipping,
+    prepare_voxel_clip_bind_group, prepare_voxel_clip_buffer,
+};
+use culling::{VisibleVoxelBricks, prepare_visible_voxel_bricks};
+pub use debug::{CaseHighlight, DensitySlice, SliceAxis, VoxelDebug};
+use debug::{
+    VoxelGridBuffer, VoxelGridUniform, draw_voxel_debug_gizmos, extract_voxel_grid,
+    prepare_voxel_grid_buffer, update_density_heatmaps,
+};
+pub use direct_volume::{
+    DIRECT_VOLUME_SHADER_HANDLE, VolumeRendered, VolumeRenderedPipeline, VolumeRenderedPipelineKey,
+    VolumeRenderedPlugin,
+};
+use prepass::{
+    DrawVoxeledPrepassCommands, ViewPrepasses, prepare_voxel_prepass_view_bind_groups, prepass_key,
+    queue_voxel_prepass,
+};
+pub use prepass::{PREPASS_SHADER_HANDLE, VoxelPrepassPipeline, VoxelPrepassPipelineKey};
+use raymarch::{
+    DrawVoxelRaymarchCommands, DrawVoxelRaymarchPrepassCommands, RaymarchBindGroups,
+    prepare_raymarch_bind_groups, queue_voxel_raymarch,
+};
+pub use raymarch::{
+    RAYMARCH_SHADER_HANDLE, VoxelRaymarchPipeline, VoxelRaymarchPipelineKey, VoxelRaymarched,
+};
+use shadow::{
+    DrawVoxeledShadowCommands, VoxelShadowCasters, extract_voxel_shadow_casters,
+    prepare_voxel_shadow_view_bind_group, queue_voxel_shadows,
+};
+pub use shadow::{SHADOW_SHADER_HANDLE, VoxelShadowPipeline, VoxelShadowPipelineKey};
+pub use transfer_function::{
+    TRANSFER_FUNCTION_RESOLUTION, TransferFunction, TransferFunctionLoader,
+    TransferFunctionLoaderError, TransferFunctionManifest, TransferStop, TransferStopManifest,
+};
+use transparency::ExtractedVoxelTransparency;
+pub use transparency::{VoxelBlendMode, VoxelFaces, VoxelTransparency};
+pub use triplanar::TriplanarMaterial;
+use triplanar::{
+    SetTriplanarBindGroup, TriplanarBindGroups, prepare_triplanar_bind_groups, triplanar_layout,
+    triplanar_sampler,
+};
 
 pub struct VoxelRenderedPlugin;
 
@@ -39,45 +118,290 @@ pub struct VoxelRenderedPlugin;
 #[component(on_add = view::add_visibility_class::<VoxeledRendered>)]
 pub struct VoxeledRendered;
 
-#[derive(Resource, FromWorld)]
+/// Lighting of a [`VoxeledRendered`] surface.
+///
+/// Without it untextured surfaces are unlit and [`TriplanarMaterial`] surfaces flat shaded.
+/// Lit surfaces are shaded by the directional, point and spot lights like bevy's meshes. Every
+/// surface receives their shadows, unlit ones are darkened by the share of light they lose, and
+/// casts them unless it has [`NotShadowCaster`](bevy_pbr::NotShadowCaster).
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash, ExtractComponent)]
+pub enum VoxelShading {
+    /// Material colors darkened by the ambient occlusion.
+    Unlit,
+    /// Lit with per-face normals from the screen space derivatives, a faceted low-poly look.
+    Flat,
+    /// Lit with the field gradient from the compute stage interpolated across the triangles.
+    Smooth,
+}
+
+#[derive(Resource)]
 pub struct VoxelRenderedPipeline {
     mesh_pipeline: MeshPipeline,
+    materials_layout: BindGroupLayout,
+    triplanar_layout: BindGroupLayout,
+    triplanar_sampler: Sampler,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VoxelRenderedPipelineKey {
+    pub mesh_key: MeshPipelineKey,
+    /// Whether the surface has a [`TriplanarMaterial`] bound at group 2.
+    pub triplanar: bool,
+    pub shading: VoxelShading,
+    /// Blend mode of a [`VoxelTransparency`] surface, opaque without one.
+    pub blend: Option<VoxelBlendMode>,
+    pub cull_mode: Option<Face>,
+    /// [`VoxelDebug::wireframe`] overlay.
+    pub wireframe: bool,
+    /// [`VoxelDebug::cell_grid`] overlay.
+    pub cell_grid: bool,
+    /// Whether the [`VoxelClipping`](super::VoxelClipping) of the volume cuts anything away.
+    pub clipping: bool,
+}
+
+impl FromWorld for VoxelRenderedPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let materials_layout = render_device.create_bind_group_layout(
+            "voxel_materials_bind_group",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::FRAGMENT,
+                (
+                    uniform_buffer::<VoxelMaterialsUniform>(false),
+                    uniform_buffer::<VoxelGridUniform>(false),
+                    uniform_buffer::<ClippingUniform>(false),
+                ),
+            ),
+        );
+        let triplanar_layout = triplanar_layout(render_device);
+        let triplanar_sampler = triplanar_sampler(render_device);
+
+        Self {
+            mesh_pipeline: MeshPipeline::from_world(world),
+            materials_layout,
+            triplanar_layout,
+            triplanar_sampler,
+        }
+    }
 }
 
 struct DrawVoxeled;
 
-type DrawVoxeledCommands = (SetItemPipeline, SetMeshViewBindGroup<0>, DrawVoxeled);
+struct SetVoxelMaterialsBindGroup<const I: usize>;
+
+type DrawVoxeledCommands = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetVoxelMaterialsBindGroup<1>,
+    SetTriplanarBindGroup<2>,
+    DrawVoxeled,
+);
+
+/// `VoxelMaterials` in `shading.wgsl`.
+#[derive(ShaderType, Clone, Default)]
+struct VoxelMaterialsUniform {
+    colors: [Vec4; MAX_MATERIALS],
+}
+
+#[derive(Resource, Default)]
+struct VoxelMaterialsBuffer {
+    buffer: UniformBuffer<VoxelMaterialsUniform>,
+}
+
+#[derive(Resource)]
+struct VoxelMaterialsBindGroup(BindGroup);
 
 pub const DISPLAY_STAGE_SHADER_HANDLE: Handle<Shader> =
     weak_handle!("65b1d237-3e83-4d22-8097-2bb33a3462ae");
+/// `marching_cubes::bounds`, the box drawn by the raymarching display modes.
+pub const BOUNDS_SHADER_HANDLE: Handle<Shader> =
+    weak_handle!("0f6b3e85-c2d7-4a19-8e40-b59a1d72c6e3");
+/// `marching_cubes::shading`, the material colors and lighting of the display stage.
+pub const SHADING_SHADER_HANDLE: Handle<Shader> =
+    weak_handle!("a7c4e9d2-0b36-4f18-95e2-6d1f83b0c57e");
 
 impl Plugin for VoxelRenderedPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(ExtractComponentPlugin::<VoxeledRendered>::default());
+        app.add_plugins((
+            ExtractComponentPlugin::<VoxeledRendered>::default(),
+            ExtractComponentPlugin::<TriplanarMaterial>::default(),
+            ExtractComponentPlugin::<VoxelShading>::default(),
+            ExtractComponentPlugin::<VoxelTransparency>::default(),
+            ExtractComponentPlugin::<VoxelDebug>::default(),
+            ExtractComponentPlugin::<VoxelRaymarched>::default(),
+        ));
+        app.add_systems(
+            Update,
+            (draw_voxel_debug_gizmos, update_density_heatmaps)
+                .run_if(any_with_component::<VoxelDebug>),
+        );
 
         let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
             return;
         };
 
-        render_app.add_render_command::<Opaque3d, DrawVoxeledCommands>();
-        render_app.add_systems(Render, queue_voxel_rendered_phase.in_set(RenderSet::Queue));
+        render_app
+            .init_resource::<VoxelMaterialsBuffer>()
+            .init_resource::<VoxelGridBuffer>()
+            .init_resource::<VoxelClipBuffer>()
+            .init_resource::<TriplanarBindGroups>()
+            .init_resource::<RaymarchBindGroups>()
+            .init_resource::<VoxelShadowCasters>();
+        render_app
+            .add_render_command::<Opaque3d, DrawVoxeledCommands>()
+            .add_render_command::<Transparent3d, DrawVoxeledCommands>()
+            .add_render_command::<Opaque3dPrepass, DrawVoxeledPrepassCommands>()
+            .add_render_command::<Opaque3d, DrawVoxelRaymarchCommands>()
+            .add_render_command::<Opaque3dPrepass, DrawVoxelRaymarchPrepassCommands>()
+            .add_render_command::<Shadow, DrawVoxeledShadowCommands>();
+        render_app.add_systems(
+            ExtractSchedule,
+            (
+                extract_voxel_shadow_casters,
+                extract_voxel_grid,
+                extract_voxel_clipping,
+            ),
+        );
+        render_app.add_systems(
+            Render,
+            (
+                prepare_voxel_materials_buffer.in_set(RenderSet::PrepareResources),
+                prepare_voxel_grid_buffer.in_set(RenderSet::PrepareResources),
+                prepare_voxel_clip_buffer.in_set(RenderSet::PrepareResources),
+                prepare_visible_voxel_bricks.in_set(RenderSet::PrepareResources),
+                prepare_voxel_materials_bind_group.in_set(RenderSet::PrepareBindGroups),
+                prepare_voxel_clip_bind_group.in_set(RenderSet::PrepareBindGroups),
+                prepare_triplanar_bind_groups.in_set(RenderSet::PrepareBindGroups),
+                prepare_voxel_shadow_view_bind_group.in_set(RenderSet::PrepareBindGroups),
+                prepare_voxel_prepass_view_bind_groups.in_set(RenderSet::PrepareBindGroups),
+                prepare_raymarch_bind_groups.in_set(RenderSet::PrepareBindGroups),
+                prepare_voxel_brick_runs
+                    .after(prepare_bind_group)
+                    .in_set(RenderSet::PrepareBindGroups),
+                queue_voxel_rendered_phase.in_set(RenderSet::Queue),
+                queue_voxel_shadows.in_set(RenderSet::Queue),
+                queue_voxel_prepass.in_set(RenderSet::Queue),
+                queue_voxel_raymarch.in_set(RenderSet::Queue),
+            ),
+        );
+
+        let brick_runs_node = VoxelBrickRunsNode::from_world(render_app.world_mut());
+        let mut render_graph = render_app.world_mut().resource_mut::<RenderGraph>();
+        render_graph.add_node(VoxelBrickRunsLabel, brick_runs_node);
+        // the runs are merged from the bricks written by the compute stage
+        render_graph.add_node_edge(MarchingCubesComputeLabel, VoxelBrickRunsLabel);
+        render_graph.add_node_edge(VoxelBrickRunsLabel, bevy_render::graph::CameraDriverLabel);
     }
 
     fn finish(&self, app: &mut App) {
+        load_internal_asset!(app, BOUNDS_SHADER_HANDLE, "bounds.wgsl", Shader::from_wgsl);
+        load_internal_asset!(
+            app,
+            SHADING_SHADER_HANDLE,
+            "shading.wgsl",
+            Shader::from_wgsl
+        );
         load_internal_asset!(
             app,
             DISPLAY_STAGE_SHADER_HANDLE,
             "display_stage.wgsl",
             Shader::from_wgsl
         );
+        load_internal_asset!(app, SHADOW_SHADER_HANDLE, "shadow.wgsl", Shader::from_wgsl);
+        load_internal_asset!(
+            app,
+            PREPASS_SHADER_HANDLE,
+            "prepass.wgsl",
+            Shader::from_wgsl
+        );
+        load_internal_asset!(
+            app,
+            RAYMARCH_SHADER_HANDLE,
+            "raymarch.wgsl",
+            Shader::from_wgsl
+        );
+        load_internal_asset!(
+            app,
+            BRICK_RUNS_SHADER_HANDLE,
+            "brick_runs.wgsl",
+            Shader::from_wgsl
+        );
 
         let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
             return;
         };
 
         render_app
+            .init_resource::<VoxelClipLayout>()
             .init_resource::<VoxelRenderedPipeline>()
-            .init_resource::<SpecializedRenderPipelines<VoxelRenderedPipeline>>();
+            .init_resource::<SpecializedRenderPipelines<VoxelRenderedPipeline>>()
+            .init_resource::<VoxelShadowPipeline>()
+            .init_resource::<SpecializedRenderPipelines<VoxelShadowPipeline>>()
+            .init_resource::<VoxelPrepassPipeline>()
+            .init_resource::<SpecializedRenderPipelines<VoxelPrepassPipeline>>()
+            .init_resource::<VoxelRaymarchPipeline>()
+            .init_resource::<SpecializedRenderPipelines<VoxelRaymarchPipeline>>()
+            .init_resource::<VoxelBrickRunsPipeline>();
+    }
+}
+
+fn prepare_voxel_materials_buffer(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut voxel_materials_buffer: ResMut<VoxelMaterialsBuffer>,
+    voxel_materials: Res<VoxelMaterials>,
+) {
+    if !voxel_materials.is_changed() && voxel_materials_buffer.buffer.buffer().is_some() {
+        return;
+    }
+
+    voxel_materials_buffer.buffer.set(VoxelMaterialsUniform {
+        colors: voxel_materials.colors.map(|color| color.to_vec4()),
+    });
+    voxel_materials_buffer
+        .buffer
+        .write_buffer(&render_device, &render_queue);
+}
+
+fn prepare_voxel_materials_bind_group(
+    mut commands: Commands,
+    pipeline: Res<VoxelRenderedPipeline>,
+    voxel_materials_buffer: Res<VoxelMaterialsBuffer>,
+    grid_buffer: Res<VoxelGridBuffer>,
+    clip_buffer: Res<VoxelClipBuffer>,
+    render_device: Res<RenderDevice>,
+) {
+    let (Some(materials_binding), Some(grid_binding), Some(clip_binding)) = (
+        voxel_materials_buffer.buffer.binding(),
+        grid_buffer.buffer.binding(),
+        clip_buffer.buffer.binding(),
+    ) else {
+        return;
+    };
+
+    commands.insert_resource(VoxelMaterialsBindGroup(render_device.create_bind_group(
+        Some("voxel_materials_bind_group"),
+        &pipeline.materials_layout,
+        &BindGroupEntries::sequential((materials_binding, grid_binding, clip_binding)),
+    )));
+}
+
+impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetVoxelMaterialsBindGroup<I> {
+    type Param = SRes<VoxelMaterialsBindGroup>;
+
+    type ViewQuery = ();
+
+    type ItemQuery = ();
+
+    fn render<'w>(
+        _item: &P,
+        _view: ROQueryItem<'w, Self::ViewQuery>,
+        _entity: Option<ROQueryItem<'w, Self::ItemQuery>>,
+        bind_group: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        pass.set_bind_group(I, &bind_group.into_inner().0, &[]);
+        RenderCommandResult::Success
     }
 }
 
@@ -85,89 +409,179 @@ impl<P: PhaseItem> RenderCommand<P> for DrawVoxeled {
     type Param = (
         SRes<MarchingCubesBuffers>,
         SRes<RenderAssets<GpuShaderStorageBuffer>>,
+        SRes<RenderDevice>,
     );
 
-    type ViewQuery = ();
+    type ViewQuery = (
+        Option<Read<VisibleVoxelBricks>>,
+        Option<Read<VoxelBrickRuns>>,
+    );
 
     type ItemQuery = ();
 
     fn render<'w>(
         _item: &P,
-        _view: ROQueryItem<'w, Self::ViewQuery>,
+        (visible_bricks, brick_runs): ROQueryItem<'w, Self::ViewQuery>,
         _entity: Option<ROQueryItem<'w, Self::ItemQuery>>,
         param: SystemParamItem<'w, '_, Self::Param>,
         pass: &mut TrackedRenderPass<'w>,
     ) -> RenderCommandResult {
         let marching_cubes_buffers = param.0.into_inner();
         let gpu_storage_buffers = param.1.into_inner();
+        let multi_draw = param
+            .2
+            .features()
+            .contains(WgpuFeatures::MULTI_DRAW_INDIRECT);
 
         let vertices = gpu_storage_buffers
             .get(marching_cubes_buffers.vertices.id())
             .unwrap();
+        let bricks = gpu_storage_buffers
+            .get(marching_cubes_buffers.bricks.id())
+            .unwrap();
 
         pass.set_vertex_buffer(0, vertices.buffer.slice(..));
 
-        let num = vertices.buffer.size() / size_of::<Vertex>() as u64;
-        pass.draw(0..num as u32, 0..1);
+        let stride = size_of::<VoxelBrick>() as u64;
+        if let Some(brick_runs) = brick_runs {
+            for run in 0..brick_runs.count as u64 {
+                pass.draw_indirect(&brick_runs.args, run * stride);
+            }
+            return RenderCommandResult::Success;
+        }
+
+        // the compute stage leaves the vertex count of the empty bricks at zero, the bricks
+        // may lag behind the visible ones for a frame after the volume is resized
+        let brick_count = bricks.buffer.size() / stride;
+        let mut draw_bricks = |range: Range<u64>| {
+            let (first, end) = (range.start, range.end.min(brick_count));
+            if first >= end {
+                return;
+            }
+            if multi_draw {
+                pass.multi_draw_indirect(&bricks.buffer, first * stride, (end - first) as u32);
+            } else {
+                // one draw per brick, only the bricks of the histo pyramid get here
+                for brick in first..end {
+                    pass.draw_indirect(&bricks.buffer, brick * stride);
+                }
+            }
+        };
+        match visible_bricks {
+            Some(visible_bricks) => visible_bricks
+                .0
+                .iter()
+                .for_each(|range| draw_bricks(range.start as u64..range.end as u64)),
+            None => draw_bricks(0..brick_count),
+        }
 
         RenderCommandResult::Success
     }
 }
 
 impl SpecializedRenderPipeline for VoxelRenderedPipeline {
-    type Key = MeshPipelineKey;
+    type Key = VoxelRenderedPipelineKey;
 
     fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let mut layout = vec![
+            self.mesh_pipeline
+                .get_view_layout(MeshPipelineViewLayoutKey::from(key.mesh_key))
+                .clone(),
+            self.materials_layout.clone(),
+        ];
+        let mut shader_defs = vec![];
+        if key.triplanar {
+            layout.push(self.triplanar_layout.clone());
+            shader_defs.push("TRIPLANAR".into());
+        }
+        match key.shading {
+            VoxelShading::Unlit => {}
+            VoxelShading::Flat => shader_defs.push("SHADING_LIT".into()),
+            VoxelShading::Smooth => {
+                shader_defs.push("SHADING_LIT".into());
+                shader_defs.push("SHADING_SMOOTH".into());
+            }
+        }
+        if key.wireframe {
+            shader_defs.push("DEBUG_WIREFRAME".into());
+        }
+        if key.cell_grid {
+            shader_defs.push("DEBUG_CELL_GRID".into());
+        }
+        if key.clipping {
+            shader_defs.push("CLIPPING".into());
+        }
+        shadow_filter_defs(key.mesh_key, &mut shader_defs);
+
         RenderPipelineDescriptor {
             label: Some("voxel_rendered_pipeline".into()),
-            layout: vec![
-                self.mesh_pipeline
-                    .get_view_layout(MeshPipelineViewLayoutKey::from(key))
-                    .clone(),
-            ],
+            layout,
             push_constant_ranges: vec![],
             vertex: VertexState {
                 shader: DISPLAY_STAGE_SHADER_HANDLE,
-                shader_defs: vec![],
+                shader_defs: shader_defs.clone(),
                 entry_point: "vertex".into(),
                 buffers: vec![VertexBufferLayout {
                     array_stride: size_of::<Vertex>() as u64,
                     step_mode: VertexStepMode::Vertex,
-                    attributes: vec![VertexAttribute {
-                        format: VertexFormat::Float32x3,
-                        offset: 0,
-                        shader_location: 0,
-                    }],
+                    attributes: vec![
+                        VertexAttribute {
+                            format: VertexFormat::Float32x3,
+                            offset: 0,
+                            shader_location: 0,
+                        },
+                        VertexAttribute {
+                            format: VertexFormat::Float32x4,
+                            offset: offset_of!(Vertex, material_weights) as u64,
+                            shader_location: 1,
+                        },
+                        VertexAttribute {
+                            format: VertexFormat::Float32,
+                            offset: offset_of!(Vertex, occlusion) as u64,
+                            shader_location: 2,
+                        },
+                        VertexAttribute {
+                            format: VertexFormat::Float32x3,
+                            offset: offset_of!(Vertex, normal) as u64,
+                            shader_location: 3,
+                        },
+                        VertexAttribute {
+                            format: VertexFormat::Float32,
+                            offset: offset_of!(Vertex, section) as u64,
+                            shader_location: 4,
+                        },
+                    ],
                 }],
             },
             fragment: Some(FragmentState {
                 shader: DISPLAY_STAGE_SHADER_HANDLE,
-                shader_defs: vec![],
+                shader_defs,
                 entry_point: "fragment".into(),
                 targets: vec![Some(ColorTargetState {
-                    format: if key.contains(MeshPipelineKey::HDR) {
+                    format: if key.mesh_key.contains(MeshPipelineKey::HDR) {
                         ViewTarget::TEXTURE_FORMAT_HDR
                     } else {
                         TextureFormat::bevy_default()
                     },
-                    blend: None,
+                    blend: key.blend.map(VoxelBlendMode::blend_state),
                     write_mask: ColorWrites::ALL,
                 })],
             }),
             primitive: PrimitiveState {
-                topology: key.primitive_topology(),
-                cull_mode: Some(Face::Back),
+                topology: key.mesh_key.primitive_topology(),
+                cull_mode: key.cull_mode,
                 ..Default::default()
             },
             depth_stencil: Some(DepthStencilState {
                 format: CORE_3D_DEPTH_FORMAT,
-                depth_write_enabled: true,
+                // transparent surfaces are tested against the opaque depth without hiding each other
+                depth_write_enabled: key.blend.is_none(),
                 depth_compare: CompareFunction::GreaterEqual,
                 stencil: Default::default(),
                 bias: Default::default(),
             }),
             multisample: MultisampleState {
-                count: key.msaa_samples(),
+                count: key.mesh_key.msaa_samples(),
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
@@ -176,29 +590,98 @@ impl SpecializedRenderPipeline for VoxelRenderedPipeline {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn queue_voxel_rendered_phase(
     pipeline_cache: Res<PipelineCache>,
     voxel_rendered_pipeline: Res<VoxelRenderedPipeline>,
     mut opaque_render_phases: ResMut<ViewBinnedRenderPhases<Opaque3d>>,
     opaque_draw_functions: Res<DrawFunctions<Opaque3d>>,
+    mut transparent_render_phases: ResMut<ViewSortedRenderPhases<Transparent3d>>,
+    transparent_draw_functions: Res<DrawFunctions<Transparent3d>>,
     mut specialized_render_pipelines: ResMut<SpecializedRenderPipelines<VoxelRenderedPipeline>>,
-    views: Query<(&ExtractedView, &RenderVisibleEntities, &Msaa)>,
+    triplanar_bind_groups: Res<TriplanarBindGroups>,
+    shadings: Query<&VoxelShading>,
+    transparencies: Query<&ExtractedVoxelTransparency>,
+    voxel_volume: Option<Res<VoxelVolumeUniform>>,
+    debugs: Query<&VoxelDebug>,
+    raymarched: Query<(), With<VoxelRaymarched>>,
+    clipping: Option<Res<ExtractedVoxelClipping>>,
+    views: Query<(
+        &ExtractedView,
+        &RenderVisibleEntities,
+        &Msaa,
+        Option<&ShadowFilteringMethod>,
+        ViewPrepasses,
+    )>,
     mut next_tick: Local<Tick>,
 ) {
     let draw_voxel_rendered = opaque_draw_functions.read().id::<DrawVoxeledCommands>();
+    let draw_voxel_transparent = transparent_draw_functions
+        .read()
+        .id::<DrawVoxeledCommands>();
 
-    for (view, view_visible_entities, msaa) in views.iter() {
-        let Some(opaque_phase) = opaque_render_phases.get_mut(&view.retained_view_entity) else {
+    for (view, view_visible_entities, msaa, shadow_filter_method, prepasses) in views.iter() {
+        let (Some(opaque_phase), Some(transparent_phase)) = (
+            opaque_render_phases.get_mut(&view.retained_view_entity),
+            transparent_render_phases.get_mut(&view.retained_view_entity),
+        ) else {
             continue;
         };
+        let rangefinder = view.rangefinder3d();
+        let view_key = main_view_key(view, msaa, shadow_filter_method, prepasses);
 
         for &entity in view_visible_entities.get::<VoxeledRendered>().iter() {
+            if raymarched.contains(entity.0) {
+                continue;
+            }
+            let triplanar = triplanar_bind_groups.contains(entity.0);
+            let shading = match shadings.get(entity.0) {
+                Ok(&shading) => shading,
+                Err(_) if triplanar => VoxelShading::Flat,
+                Err(_) => VoxelShading::Unlit,
+            };
+            let key = VoxelRenderedPipelineKey {
+                mesh_key: view_key,
+                triplanar,
+                shading,
+                blend: None,
+                cull_mode: Some(Face::Back),
+                wireframe: debugs.get(entity.0).is_ok_and(|debug| debug.wireframe),
+                cell_grid: debugs.get(entity.0).is_ok_and(|debug| debug.cell_grid),
+                clipping: clipping.as_ref().is_some_and(|clipping| clipping.active),
+            };
+
+            if let Ok(transparent) = transparencies.get(entity.0) {
+                let distance =
+                    rangefinder.distance_translation(&transparent.center(voxel_volume.as_deref()));
+                // the sort is stable, the passes of one entity keep their order
+                for &cull_mode in transparent.transparency.faces.passes() {
+                    let pipeline_id = specialized_render_pipelines.specialize(
+                        &pipeline_cache,
+                        &voxel_rendered_pipeline,
+                        VoxelRenderedPipelineKey {
+                            blend: Some(transparent.transparency.blend),
+                            cull_mode,
+                            ..key
+                        },
+                    );
+                    transparent_phase.add(Transparent3d {
+                        distance,
+                        pipeline: pipeline_id,
+                        entity,
+                        draw_function: draw_voxel_transparent,
+                        batch_range: 0..1,
+                        extra_index: PhaseItemExtraIndex::None,
+                        indexed: false,
+                    });
+                }
+                continue;
+            }
+
             let pipeline_id = specialized_render_pipelines.specialize(
                 &pipeline_cache,
                 &voxel_rendered_pipeline,
-                MeshPipelineKey::from_msaa_samples(msaa.samples())
-                    | MeshPipelineKey::from_hdr(view.hdr)
-                    | MeshPipelineKey::from_primitive_topology(PrimitiveTopology::TriangleList),
+                key,
             );
 
             let this_tick = next_tick.get() + 1;
@@ -224,3 +707,38 @@ fn queue_voxel_rendered_phase(
         }
     }
 }
+
+/// Pipeline key bits of a view for the main pass.
+fn main_view_key(
+    view: &ExtractedView,
+    msaa: &Msaa,
+    shadow_filter_method: Option<&ShadowFilteringMethod>,
+    prepasses: (bool, bool, bool, bool),
+) -> MeshPipelineKey {
+    let view_key = MeshPipelineKey::from_msaa_samples(msaa.samples())
+        | MeshPipelineKey::from_hdr(view.hdr)
+        | MeshPipelineKey::from_primitive_topology(PrimitiveTopology::TriangleList)
+        // the view bind group of a view with prepass textures has a different layout
+        | prepass_key(prepasses);
+    view_key
+        | match shadow_filter_method.unwrap_or(&ShadowFilteringMethod::default()) {
+            ShadowFilteringMethod::Hardware2x2 => {
+                MeshPipelineKey::SHADOW_FILTER_METHOD_HARDWARE_2X2
+            }
+            ShadowFilteringMethod::Gaussian => MeshPipelineKey::SHADOW_FILTER_METHOD_GAUSSIAN,
+            ShadowFilteringMethod::Temporal => MeshPipelineKey::SHADOW_FILTER_METHOD_TEMPORAL,
+        }
+}
+
+/// Surfaces receive shadows filtered like bevy's meshes.
+fn shadow_filter_defs(mesh_key: MeshPipelineKey, shader_defs: &mut Vec<ShaderDefVal>) {
+    let shadow_filter_method =
+        mesh_key.intersection(MeshPipelineKey::SHADOW_FILTER_METHOD_RESERVED_BITS);
+    if shadow_filter_method == MeshPipelineKey::SHADOW_FILTER_METHOD_HARDWARE_2X2 {
+        shader_defs.push("SHADOW_FILTER_METHOD_HARDWARE_2X2".into());
+    } else if shadow_filter_method == MeshPipelineKey::SHADOW_FILTER_METHOD_GAUSSIAN {
+        shader_defs.push("SHADOW_FILTER_METHOD_GAUSSIAN".into());
+    } else if shadow_filter_method == MeshPipelineKey::SHADOW_FILTER_METHOD_TEMPORAL {
+        shader_defs.push("SHADOW_FILTER_METHOD_TEMPORAL".into());
+    }
+}
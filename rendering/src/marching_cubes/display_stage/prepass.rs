@@ -0,0 +1,364 @@
+use core::mem::offset_of;
+
+use bevy_asset::{AssetId, Handle, weak_handle};
+use bevy_core_pipeline::core_3d::CORE_3D_DEPTH_FORMAT;
+use bevy_core_pipeline::prepass::{
+    DeferredPrepass, DepthPrepass, MotionVectorPrepass, NormalPrepass, Opaque3dPrepass,
+    OpaqueNoLightmap3dBatchSetKey, OpaqueNoLightmap3dBinKey, PreviousViewData,
+    PreviousViewUniformOffset, PreviousViewUniforms, prepass_target_descriptors,
+};
+use bevy_ecs::component::Tick;
+use bevy_ecs::query::{Has, ROQueryItem, With};
+use bevy_ecs::resource::Resource;
+use bevy_ecs::system::lifetimeless::{Read, SRes};
+use bevy_ecs::system::{Commands, Local, Query, Res, ResMut, SystemParamItem};
+use bevy_ecs::world::{FromWorld, World};
+use bevy_pbr::MeshPipelineKey;
+use bevy_render::mesh::{Mesh, PrimitiveTopology, VertexBufferLayout, VertexFormat};
+use bevy_render::render_phase::{
+    BinnedRenderPhaseType, DrawFunctions, InputUniformIndex, PhaseItem, RenderCommand,
+    RenderCommandResult, SetItemPipeline, TrackedRenderPass, ViewBinnedRenderPhases,
+};
+use bevy_render::render_resource::binding_types::uniform_buffer;
+use bevy_render::render_resource::{
+    BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, CompareFunction,
+    DepthStencilState, Face, FragmentState, MultisampleState, PipelineCache, PrimitiveState,
+    RenderPipelineDescriptor, Shader, ShaderStages, SpecializedRenderPipeline,
+    SpecializedRenderPipelines, VertexAttribute, VertexState, VertexStepMode,
+};
+use bevy_render::renderer::RenderDevice;
+use bevy_render::view::{
+    ExtractedView, Msaa, RenderVisibleEntities, ViewUniform, ViewUniformOffset, ViewUniforms,
+};
+
+use super::clipping::{ExtractedVoxelClipping, SetVoxelClipBindGroup, VoxelClipLayout};
+use super::raymarch::VoxelRaymarched;
+use super::transparency::ExtractedVoxelTransparency;
+use super::{DrawVoxeled, VoxelShading, VoxeledRendered};
+use crate::marching_cubes::Vertex;
+
+pub const PREPASS_SHADER_HANDLE: Handle<Shader> =
+    weak_handle!("8a1f2d6e-94c3-4b7e-b5d0-3e6c71f9a248");
+
+/// Draws the voxel surfaces into the depth, normal and motion vector prepass textures, so
+/// screen space effects like edge detection, SSAO and TAA see them.
+///
+/// Views with a [`DeferredPrepass`] are skipped, the voxel surfaces are forward rendered only.
+#[derive(Resource)]
+pub struct VoxelPrepassPipeline {
+    pub(super) view_layout: BindGroupLayout,
+    pub(super) motion_vectors_view_layout: BindGroupLayout,
+    clip_layout: BindGroupLayout,
+}
+
+impl FromWorld for VoxelPrepassPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let view_layout = render_device.create_bind_group_layout(
+            "voxel_prepass_view_bind_group",
+            &BindGroupLayoutEntries::single(
+                ShaderStages::VERTEX_FRAGMENT,
+                uniform_buffer::<ViewUniform>(true),
+            ),
+        );
+        let motion_vectors_view_layout = render_device.create_bind_group_layout(
+            "voxel_prepass_motion_vectors_view_bind_group",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::VERTEX_FRAGMENT,
+                (
+                    uniform_buffer::<ViewUniform>(true),
+                    uniform_buffer::<PreviousViewData>(true),
+                ),
+            ),
+        );
+
+        Self {
+            view_layout,
+            motion_vectors_view_layout,
+            clip_layout: world.resource::<VoxelClipLayout>().0.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VoxelPrepassPipelineKey {
+    /// Only the msaa samples and the prepass bits are used.
+    pub mesh_key: MeshPipelineKey,
+    /// Whether the normals come from the field gradient instead of the faces.
+    pub smooth_normals: bool,
+    /// Whether an open [`VoxelClipping`](crate::marching_cubes::VoxelClipping) cut is
+    /// discarded, bound at group 1.
+    pub clip_discard: bool,
+}
+
+impl SpecializedRenderPipeline for VoxelPrepassPipeline {
+    type Key = VoxelPrepassPipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let normal_prepass = key.mesh_key.contains(MeshPipelineKey::NORMAL_PREPASS);
+        let motion_vector_prepass = key
+            .mesh_key
+            .contains(MeshPipelineKey::MOTION_VECTOR_PREPASS);
+
+        let mut shader_defs = vec![];
+        let mut layout = vec![self.view_layout.clone()];
+        if normal_prepass {
+            shader_defs.push("NORMAL_PREPASS".into());
+        }
+        if motion_vector_prepass {
+            shader_defs.push("MOTION_VECTOR_PREPASS".into());
+            layout = vec![self.motion_vectors_view_layout.clone()];
+        }
+        if key.smooth_normals {
+            shader_defs.push("SMOOTH_NORMALS".into());
+        }
+        if normal_prepass || motion_vector_prepass {
+            shader_defs.push("PREPASS_FRAGMENT".into());
+        }
+        if key.clip_discard {
+            layout.push(self.clip_layout.clone());
+            shader_defs.push("CLIP_DISCARD".into());
+        }
+        // a depth only prepass has no color targets and needs no fragment stage to write it
+        let fragment = normal_prepass || motion_vector_prepass || key.clip_discard;
+
+        RenderPipelineDescriptor {
+            label: Some("voxel_prepass_pipeline".into()),
+            layout,
+            push_constant_ranges: vec![],
+            vertex: VertexState {
+                shader: PREPASS_SHADER_HANDLE,
+                shader_defs: shader_defs.clone(),
+                entry_point: "vertex".into(),
+                buffers: vec![VertexBufferLayout {
+                    array_stride: size_of::<Vertex>() as u64,
+                    step_mode: VertexStepMode::Vertex,
+                    attributes: vec![
+                        VertexAttribute {
+                            format: VertexFormat::Float32x3,
+                            offset: 0,
+                            shader_location: 0,
+                        },
+                        VertexAttribute {
+                            format: VertexFormat::Float32x3,
+                            offset: offset_of!(Vertex, normal) as u64,
+                            shader_location: 1,
+                        },
+                    ],
+                }],
+            },
+            fragment: fragment.then(|| FragmentState {
+                shader: PREPASS_SHADER_HANDLE,
+                shader_defs,
+                entry_point: "fragment".into(),
+                targets: prepass_target_descriptors(normal_prepass, motion_vector_prepass, false),
+            }),
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                cull_mode: Some(Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(DepthStencilState {
+                format: CORE_3D_DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::GreaterEqual,
+                stencil: Default::default(),
+                bias: Default::default(),
+            }),
+            multisample: MultisampleState {
+                count: key.mesh_key.msaa_samples(),
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            zero_initialize_workgroup_memory: false,
+        }
+    }
+}
+
+/// Prepass components of a view, they change the layout of its mesh view bind group.
+pub(super) type ViewPrepasses = (
+    Has<DepthPrepass>,
+    Has<NormalPrepass>,
+    Has<MotionVectorPrepass>,
+    Has<DeferredPrepass>,
+);
+
+pub(super) fn prepass_key(
+    (depth, normal, motion_vector, deferred): (bool, bool, bool, bool),
+) -> MeshPipelineKey {
+    let mut key = MeshPipelineKey::NONE;
+    if depth {
+        key |= MeshPipelineKey::DEPTH_PREPASS;
+    }
+    if normal {
+        key |= MeshPipelineKey::NORMAL_PREPASS;
+    }
+    if motion_vector {
+        key |= MeshPipelineKey::MOTION_VECTOR_PREPASS;
+    }
+    if deferred {
+        key |= MeshPipelineKey::DEFERRED_PREPASS;
+    }
+    key
+}
+
+pub(super) type DrawVoxeledPrepassCommands = (
+    SetItemPipeline,
+    SetVoxelPrepassViewBindGroup<0>,
+    SetVoxelClipBindGroup<1>,
+    DrawVoxeled,
+);
+
+#[derive(Resource)]
+pub(super) struct VoxelPrepassViewBindGroups {
+    view: BindGroup,
+    motion_vectors_view: Option<BindGroup>,
+}
+
+pub(super) fn prepare_voxel_prepass_view_bind_groups(
+    mut commands: Commands,
+    pipeline: Res<VoxelPrepassPipeline>,
+    view_uniforms: Res<ViewUniforms>,
+    previous_view_uniforms: Res<PreviousViewUniforms>,
+    render_device: Res<RenderDevice>,
+) {
+    let Some(view_binding) = view_uniforms.uniforms.binding() else {
+        return;
+    };
+
+    let view = render_device.create_bind_group(
+        Some("voxel_prepass_view_bind_group"),
+        &pipeline.view_layout,
+        &BindGroupEntries::single(view_binding.clone()),
+    );
+    let motion_vectors_view =
+        previous_view_uniforms
+            .uniforms
+            .binding()
+            .map(|previous_view_binding| {
+                render_device.create_bind_group(
+                    Some("voxel_prepass_motion_vectors_view_bind_group"),
+                    &pipeline.motion_vectors_view_layout,
+                    &BindGroupEntries::sequential((view_binding, previous_view_binding)),
+                )
+            });
+
+    commands.insert_resource(VoxelPrepassViewBindGroups {
+        view,
+        motion_vectors_view,
+    });
+}
+
+pub(super) struct SetVoxelPrepassViewBindGroup<const I: usize>;
+
+impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetVoxelPrepassViewBindGroup<I> {
+    type Param = SRes<VoxelPrepassViewBindGroups>;
+
+    type ViewQuery = (
+        Read<ViewUniformOffset>,
+        Has<MotionVectorPrepass>,
+        Option<Read<PreviousViewUniformOffset>>,
+    );
+
+    type ItemQuery = ();
+
+    fn render<'w>(
+        _item: &P,
+        (view_uniform_offset, motion_vector_prepass, previous_view_uniform_offset): ROQueryItem<
+            'w,
+            Self::ViewQuery,
+        >,
+        _entity: Option<ROQueryItem<'w, Self::ItemQuery>>,
+        bind_groups: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let bind_groups = bind_groups.into_inner();
+        if !motion_vector_prepass {
+            pass.set_bind_group(I, &bind_groups.view, &[view_uniform_offset.offset]);
+            return RenderCommandResult::Success;
+        }
+
+        let (Some(bind_group), Some(previous_view_uniform_offset)) = (
+            &bind_groups.motion_vectors_view,
+            previous_view_uniform_offset,
+        ) else {
+            return RenderCommandResult::Skip;
+        };
+        pass.set_bind_group(
+            I,
+            bind_group,
+            &[
+                view_uniform_offset.offset,
+                previous_view_uniform_offset.offset,
+            ],
+        );
+        RenderCommandResult::Success
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(super) fn queue_voxel_prepass(
+    pipeline_cache: Res<PipelineCache>,
+    prepass_pipeline: Res<VoxelPrepassPipeline>,
+    mut specialized_render_pipelines: ResMut<SpecializedRenderPipelines<VoxelPrepassPipeline>>,
+    prepass_draw_functions: Res<DrawFunctions<Opaque3dPrepass>>,
+    mut prepass_render_phases: ResMut<ViewBinnedRenderPhases<Opaque3dPrepass>>,
+    shadings: Query<&VoxelShading>,
+    transparent: Query<(), With<ExtractedVoxelTransparency>>,
+    raymarched: Query<(), With<VoxelRaymarched>>,
+    clipping: Option<Res<ExtractedVoxelClipping>>,
+    views: Query<(&ExtractedView, &RenderVisibleEntities, &Msaa, ViewPrepasses)>,
+    mut next_tick: Local<Tick>,
+) {
+    let draw_voxel_prepass = prepass_draw_functions
+        .read()
+        .id::<DrawVoxeledPrepassCommands>();
+
+    for (view, view_visible_entities, msaa, prepasses) in &views {
+        let view_key = MeshPipelineKey::from_msaa_samples(msaa.samples()) | prepass_key(prepasses);
+        // the prepass node does not run for deferred views
+        if view_key.contains(MeshPipelineKey::DEFERRED_PREPASS) {
+            continue;
+        }
+        let Some(prepass_phase) = prepass_render_phases.get_mut(&view.retained_view_entity) else {
+            continue;
+        };
+
+        for &entity in view_visible_entities.get::<VoxeledRendered>().iter() {
+            // transparent surfaces must not hide what is behind them from the screen space effects,
+            // raymarched surfaces have their own prepass pipeline
+            if transparent.contains(entity.0) || raymarched.contains(entity.0) {
+                continue;
+            }
+            let pipeline_id = specialized_render_pipelines.specialize(
+                &pipeline_cache,
+                &prepass_pipeline,
+                VoxelPrepassPipelineKey {
+                    mesh_key: view_key,
+                    smooth_normals: shadings.get(entity.0) == Ok(&VoxelShading::Smooth),
+                    clip_discard: clipping.as_ref().is_some_and(|clipping| clipping.discard),
+                },
+            );
+
+            let this_tick = next_tick.get() + 1;
+            next_tick.set(this_tick);
+
+            prepass_phase.add(
+                OpaqueNoLightmap3dBatchSetKey {
+                    pipeline: pipeline_id,
+                    draw_function: draw_voxel_prepass,
+                    material_bind_group_index: None,
+                    vertex_slab: Default::default(),
+                    index_slab: None,
+                },
+                OpaqueNoLightmap3dBinKey {
+                    asset_id: AssetId::<Mesh>::invalid().untyped(),
+                },
+                entity,
+                InputUniformIndex::default(),
+                BinnedRenderPhaseType::NonMesh,
+                *next_tick,
+            );
+        }
+    }
+}
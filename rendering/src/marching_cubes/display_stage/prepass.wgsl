@@ -0,0 +1,97 @@
+#import bevy_render::view::View
+
+// Prepass of the voxel surfaces, writes the depth and the normals and motion vectors the view asks for
+
+@group(0) @binding(0) var<uniform> view: View;
+
+#ifdef MOTION_VECTOR_PREPASS
+// Must match `PreviousViewData`
+struct PreviousView {
+    view_from_world: mat4x4<f32>,
+    clip_from_world: mat4x4<f32>,
+    clip_from_view: mat4x4<f32>,
+};
+
+@group(0) @binding(1) var<uniform> previous_view: PreviousView;
+#endif
+
+#ifdef CLIP_DISCARD
+#import marching_cubes::clipping::{ClipParams, clip_distance}
+
+@group(1) @binding(0) var<uniform> clipping: ClipParams;
+#endif
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+};
+
+#ifdef PREPASS_FRAGMENT
+struct FragmentOutput {
+#ifdef NORMAL_PREPASS
+    @location(0) normal: vec4<f32>,
+#endif
+#ifdef MOTION_VECTOR_PREPASS
+    @location(1) motion_vector: vec2<f32>,
+#endif
+};
+#endif
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = view.clip_from_world * vec4(vertex.position, 1.0);
+    out.world_position = vertex.position;
+    out.normal = vertex.normal;
+    return out;
+}
+
+#ifdef PREPASS_FRAGMENT
+@fragment
+fn fragment(mesh: VertexOutput) -> FragmentOutput {
+    var out: FragmentOutput;
+
+#ifdef CLIP_DISCARD
+    if (clip_distance(mesh.world_position, clipping) > 0.0) {
+        discard;
+    }
+#endif
+
+#ifdef NORMAL_PREPASS
+    // same normal as the lit display stage, encoded like bevy's prepass
+#ifdef SMOOTH_NORMALS
+    let normal = normalize(mesh.normal);
+#else
+    let normal = normalize(cross(dpdy(mesh.world_position), dpdx(mesh.world_position)));
+#endif
+    out.normal = vec4(normal * 0.5 + 0.5, 1.0);
+#endif
+
+#ifdef MOTION_VECTOR_PREPASS
+    // the surface is rebuilt every frame without vertex history, only the camera motion counts
+    let position = vec4(mesh.world_position, 1.0);
+    let clip_position = view.unjittered_clip_from_world * position;
+    let previous_clip_position = previous_view.clip_from_world * position;
+    let ndc = clip_position.xy / clip_position.w;
+    let previous_ndc = previous_clip_position.xy / previous_clip_position.w;
+    // ndc is y up, the motion vectors are in uv space
+    out.motion_vector = (ndc - previous_ndc) * vec2(0.5, -0.5);
+#endif
+
+    return out;
+}
+#else ifdef CLIP_DISCARD
+// depth only, the fragment stage only cuts the surface open
+@fragment
+fn fragment(mesh: VertexOutput) {
+    if (clip_distance(mesh.world_position, clipping) > 0.0) {
+        discard;
+    }
+}
+#endif
@@ -0,0 +1,482 @@
+use bevy_asset::{AssetId, Handle, weak_handle};
+use bevy_core_pipeline::core_3d::{
+    CORE_3D_DEPTH_FORMAT, Opaque3d, Opaque3dBatchSetKey, Opaque3dBinKey,
+};
+use bevy_core_pipeline::prepass::{
+    Opaque3dPrepass, OpaqueNoLightmap3dBatchSetKey, OpaqueNoLightmap3dBinKey,
+    prepass_target_descriptors,
+};
+use bevy_ecs::component::{Component, Tick};
+use bevy_ecs::entity::{Entity, EntityHashMap};
+use bevy_ecs::query::{ROQueryItem, With};
+use bevy_ecs::resource::Resource;
+use bevy_ecs::system::lifetimeless::SRes;
+use bevy_ecs::system::{Local, Query, Res, ResMut, SystemParamItem};
+use bevy_ecs::world::{FromWorld, World};
+use bevy_image::BevyDefault;
+use bevy_pbr::{
+    MeshPipeline, MeshPipelineKey, MeshPipelineViewLayoutKey, SetMeshViewBindGroup,
+    ShadowFilteringMethod,
+};
+use bevy_render::extract_component::ExtractComponent;
+use bevy_render::mesh::Mesh;
+use bevy_render::render_asset::RenderAssets;
+use bevy_render::render_phase::{
+    BinnedRenderPhaseType, DrawFunctions, InputUniformIndex, PhaseItem, RenderCommand,
+    RenderCommandResult, SetItemPipeline, TrackedRenderPass, ViewBinnedRenderPhases,
+};
+use bevy_render::render_resource::binding_types::{storage_buffer_read_only_sized, uniform_buffer};
+use bevy_render::render_resource::{
+    BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, ColorTargetState,
+    ColorWrites, CompareFunction, DepthStencilState, Face, FragmentState, MultisampleState,
+    PipelineCache, PrimitiveState, RenderPipelineDescriptor, Shader, ShaderDefVal, ShaderStages,
+    ShaderType, SpecializedRenderPipeline, SpecializedRenderPipelines, TextureFormat,
+    UniformBuffer, VertexState,
+};
+use bevy_render::renderer::{RenderDevice, RenderQueue};
+use bevy_render::storage::GpuShaderStorageBuffer;
+use bevy_render::view::{ExtractedView, Msaa, RenderVisibleEntities, ViewTarget};
+
+use super::prepass::{
+    SetVoxelPrepassViewBindGroup, ViewPrepasses, VoxelPrepassPipeline, prepass_key,
+};
+use super::{
+    SetVoxelMaterialsBindGroup, VoxelRenderedPipeline, VoxelShading, VoxeledRendered,
+    main_view_key, shadow_filter_defs,
+};
+use crate::marching_cubes::MarchingCubesBuffers;
+use crate::marching_cubes::compute_stage::{VoxelVolumeBuffer, VoxelVolumeUniform};
+use crate::marching_cubes::metaball::MetaballBuffer;
+use crate::marching_cubes::terrain::TunnelBuffer;
+
+pub const RAYMARCH_SHADER_HANDLE: Handle<Shader> =
+    weak_handle!("d3b6f0a2-71c8-4e95-9a4f-2e8c15b7d604");
+
+/// Draws a [`VoxeledRendered`] surface by sphere tracing the density field inside the
+/// [`VoxelVolume`](crate::marching_cubes::VoxelVolume) bounds instead of rasterizing the
+/// marching cubes mesh, so the surface has no facets at any voxel size.
+///
+/// Every pixel of the bounding box marches the same `field` the compute stage samples, and writes
+/// the depth of the hit so the surface intersects meshes and shows in the prepass textures. The
+/// normal is the field gradient and the [`AmbientOcclusion`](crate::marching_cubes::AmbientOcclusion)
+/// is evaluated per pixel. [`VoxelShading::Flat`] and [`VoxelShading::Smooth`] both light the
+/// surface, [`TriplanarMaterial`](super::TriplanarMaterial), [`VoxelTransparency`](super::VoxelTransparency)
+/// and the [`VoxelDebug`](super::VoxelDebug) overlays are ignored. The compute stage still meshes
+/// the volume, the mesh casts the shadows and is what export reads.
+#[derive(Component, Clone, Copy, Debug, PartialEq, ExtractComponent)]
+pub struct VoxelRaymarched {
+    /// Steps before a ray gives up, a miss leaves the pixel empty.
+    pub max_steps: u32,
+    /// Multiplies the field value to get the step length, below 1 for fields that are not
+    /// distances.
+    pub step_scale: f32,
+    /// Longest step in voxels, thin features narrower than it can be missed.
+    pub max_step: f32,
+    /// Bisection steps refining the hit once a step ends inside.
+    pub refine_steps: u32,
+}
+
+impl Default for VoxelRaymarched {
+    fn default() -> Self {
+        Self {
+            max_steps: 256,
+            step_scale: 1.0,
+            max_step: 1.0,
+            refine_steps: 8,
+        }
+    }
+}
+
+/// `RaymarchParams` in `raymarch.wgsl`.
+#[derive(ShaderType, Clone, Default)]
+struct RaymarchUniform {
+    max_steps: u32,
+    refine_steps: u32,
+    step_scale: f32,
+    max_step: f32,
+}
+
+/// Group of the raymarch bindings and the density bindings of `density.wgsl`.
+const MAIN_DENSITY_GROUP: u32 = 2;
+const PREPASS_DENSITY_GROUP: u32 = 1;
+
+#[derive(Resource)]
+pub struct VoxelRaymarchPipeline {
+    mesh_pipeline: MeshPipeline,
+    materials_layout: BindGroupLayout,
+    prepass_view_layout: BindGroupLayout,
+    prepass_motion_vectors_view_layout: BindGroupLayout,
+    raymarch_layout: BindGroupLayout,
+}
+
+impl FromWorld for VoxelRaymarchPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let raymarch_layout = render_device.create_bind_group_layout(
+            "voxel_raymarch_bind_group",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::VERTEX_FRAGMENT,
+                (
+                    uniform_buffer::<RaymarchUniform>(false),
+                    uniform_buffer::<VoxelVolumeUniform>(false),
+                    storage_buffer_read_only_sized(false, None),
+                    storage_buffer_read_only_sized(false, None),
+                    storage_buffer_read_only_sized(false, None),
+                    storage_buffer_read_only_sized(false, None),
+                    storage_buffer_read_only_sized(false, None),
+                ),
+            ),
+        );
+        let rendered_pipeline = world.resource::<VoxelRenderedPipeline>();
+        let prepass_pipeline = world.resource::<VoxelPrepassPipeline>();
+
+        Self {
+            mesh_pipeline: rendered_pipeline.mesh_pipeline.clone(),
+            materials_layout: rendered_pipeline.materials_layout.clone(),
+            prepass_view_layout: prepass_pipeline.view_layout.clone(),
+            prepass_motion_vectors_view_layout: prepass_pipeline.motion_vectors_view_layout.clone(),
+            raymarch_layout,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VoxelRaymarchPipelineKey {
+    pub mesh_key: MeshPipelineKey,
+    /// Whether the surface is lit, unlit surfaces show the material colors and the occlusion.
+    pub lit: bool,
+    /// The depth, normal and motion vector prepass variant.
+    pub prepass: bool,
+}
+
+impl SpecializedRenderPipeline for VoxelRaymarchPipeline {
+    type Key = VoxelRaymarchPipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let mut shader_defs = vec![];
+        let (layout, targets) = if key.prepass {
+            let normal_prepass = key.mesh_key.contains(MeshPipelineKey::NORMAL_PREPASS);
+            let motion_vector_prepass = key
+                .mesh_key
+                .contains(MeshPipelineKey::MOTION_VECTOR_PREPASS);
+            shader_defs.push("PREPASS_PIPELINE".into());
+            shader_defs.push(ShaderDefVal::UInt(
+                "DENSITY_GROUP".into(),
+                PREPASS_DENSITY_GROUP,
+            ));
+            if normal_prepass {
+                shader_defs.push("NORMAL_PREPASS".into());
+            }
+            let view_layout = if motion_vector_prepass {
+                shader_defs.push("MOTION_VECTOR_PREPASS".into());
+                &self.prepass_motion_vectors_view_layout
+            } else {
+                &self.prepass_view_layout
+            };
+            (
+                vec![view_layout.clone(), self.raymarch_layout.clone()],
+                prepass_target_descriptors(normal_prepass, motion_vector_prepass, false),
+            )
+        } else {
+            shader_defs.push(ShaderDefVal::UInt(
+                "DENSITY_GROUP".into(),
+                MAIN_DENSITY_GROUP,
+            ));
+            if key.lit {
+                shader_defs.push("SHADING_LIT".into());
+            }
+            shadow_filter_defs(key.mesh_key, &mut shader_defs);
+            (
+                vec![
+                    self.mesh_pipeline
+                        .get_view_layout(MeshPipelineViewLayoutKey::from(key.mesh_key))
+                        .clone(),
+                    self.materials_layout.clone(),
+                    self.raymarch_layout.clone(),
+                ],
+                vec![Some(ColorTargetState {
+                    format: if key.mesh_key.contains(MeshPipelineKey::HDR) {
+                        ViewTarget::TEXTURE_FORMAT_HDR
+                    } else {
+                        TextureFormat::bevy_default()
+                    },
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            )
+        };
+
+        RenderPipelineDescriptor {
+            label: Some("voxel_raymarch_pipeline".into()),
+            layout,
+            push_constant_ranges: vec![],
+            vertex: VertexState {
+                shader: RAYMARCH_SHADER_HANDLE,
+                shader_defs: shader_defs.clone(),
+                entry_point: "vertex".into(),
+                buffers: vec![],
+            },
+            // even a depth only prepass needs the fragment stage to write the depth of the hit
+            fragment: Some(FragmentState {
+                shader: RAYMARCH_SHADER_HANDLE,
+                shader_defs,
+                entry_point: "fragment".into(),
+                targets,
+            }),
+            primitive: PrimitiveState {
+                // the back faces of the bounds stay on screen with the camera inside the volume
+                cull_mode: Some(Face::Front),
+                ..Default::default()
+            },
+            depth_stencil: Some(DepthStencilState {
+                format: CORE_3D_DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::GreaterEqual,
+                stencil: Default::default(),
+                bias: Default::default(),
+            }),
+            multisample: MultisampleState {
+                count: key.mesh_key.msaa_samples(),
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            zero_initialize_workgroup_memory: false,
+        }
+    }
+}
+
+pub(super) type DrawVoxelRaymarchCommands = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetVoxelMaterialsBindGroup<1>,
+    SetRaymarchBindGroup<2>,
+    DrawBounds,
+);
+
+pub(super) type DrawVoxelRaymarchPrepassCommands = (
+    SetItemPipeline,
+    SetVoxelPrepassViewBindGroup<0>,
+    SetRaymarchBindGroup<1>,
+    DrawBounds,
+);
+
+/// Bind groups of the [`VoxelRaymarched`] entities, by render entity.
+#[derive(Resource, Default)]
+pub(super) struct RaymarchBindGroups(EntityHashMap<(BindGroup, UniformBuffer<RaymarchUniform>)>);
+
+#[allow(clippy::too_many_arguments)]
+pub(super) fn prepare_raymarch_bind_groups(
+    pipeline: Res<VoxelRaymarchPipeline>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    marching_cubes_buffers: Res<MarchingCubesBuffers>,
+    gpu_buffers: Res<RenderAssets<GpuShaderStorageBuffer>>,
+    volume_buffer: Res<VoxelVolumeBuffer>,
+    metaball_buffer: Res<MetaballBuffer>,
+    tunnel_buffer: Res<TunnelBuffer>,
+    mut raymarch_bind_groups: ResMut<RaymarchBindGroups>,
+    raymarched: Query<(Entity, &VoxelRaymarched)>,
+) {
+    raymarch_bind_groups.0.clear();
+
+    let (
+        Some(densities),
+        Some(materials),
+        Some(volume_binding),
+        Some(metaball_binding),
+        Some(tunnel_binding),
+        Some(tunnel_bins_binding),
+    ) = (
+        gpu_buffers.get(marching_cubes_buffers.densities.id()),
+        gpu_buffers.get(marching_cubes_buffers.materials.id()),
+        volume_buffer.buffer.binding(),
+        metaball_buffer.buffer.binding(),
+        tunnel_buffer.buffer.binding(),
+        tunnel_buffer.bins.binding(),
+    )
+    else {
+        return;
+    };
+
+    for (entity, raymarched) in &raymarched {
+        let mut buffer = UniformBuffer::from(RaymarchUniform {
+            max_steps: raymarched.max_steps,
+            refine_steps: raymarched.refine_steps,
+            step_scale: raymarched.step_scale,
+            max_step: raymarched.max_step,
+        });
+        buffer.write_buffer(&render_device, &render_queue);
+
+        let bind_group = render_device.create_bind_group(
+            Some("voxel_raymarch_bind_group"),
+            &pipeline.raymarch_layout,
+            &BindGroupEntries::sequential((
+                buffer.binding().unwrap(),
+                volume_binding.clone(),
+                densities.buffer.as_entire_buffer_binding(),
+                metaball_binding.clone(),
+                tunnel_binding.clone(),
+                materials.buffer.as_entire_buffer_binding(),
+                tunnel_bins_binding.clone(),
+            )),
+        );
+        raymarch_bind_groups.0.insert(entity, (bind_group, buffer));
+    }
+}
+
+pub(super) struct SetRaymarchBindGroup<const I: usize>;
+
+impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetRaymarchBindGroup<I> {
+    type Param = SRes<RaymarchBindGroups>;
+
+    type ViewQuery = ();
+
+    type ItemQuery = ();
+
+    fn render<'w>(
+        item: &P,
+        _view: ROQueryItem<'w, Self::ViewQuery>,
+        _entity: Option<ROQueryItem<'w, Self::ItemQuery>>,
+        bind_groups: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some((bind_group, _)) = bind_groups.into_inner().0.get(&item.entity()) else {
+            return RenderCommandResult::Skip;
+        };
+        pass.set_bind_group(I, bind_group, &[]);
+        RenderCommandResult::Success
+    }
+}
+
+/// Draws the box of `bounds.wgsl`, the vertices come from the vertex index.
+pub(super) struct DrawBounds;
+
+impl<P: PhaseItem> RenderCommand<P> for DrawBounds {
+    type Param = ();
+
+    type ViewQuery = ();
+
+    type ItemQuery = ();
+
+    fn render<'w>(
+        _item: &P,
+        _view: ROQueryItem<'w, Self::ViewQuery>,
+        _entity: Option<ROQueryItem<'w, Self::ItemQuery>>,
+        _param: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        pass.draw(0..36, 0..1);
+        RenderCommandResult::Success
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(super) fn queue_voxel_raymarch(
+    pipeline_cache: Res<PipelineCache>,
+    raymarch_pipeline: Res<VoxelRaymarchPipeline>,
+    mut specialized_render_pipelines: ResMut<SpecializedRenderPipelines<VoxelRaymarchPipeline>>,
+    opaque_draw_functions: Res<DrawFunctions<Opaque3d>>,
+    mut opaque_render_phases: ResMut<ViewBinnedRenderPhases<Opaque3d>>,
+    prepass_draw_functions: Res<DrawFunctions<Opaque3dPrepass>>,
+    mut prepass_render_phases: ResMut<ViewBinnedRenderPhases<Opaque3dPrepass>>,
+    raymarched: Query<(), With<VoxelRaymarched>>,
+    shadings: Query<&VoxelShading>,
+    views: Query<(
+        &ExtractedView,
+        &RenderVisibleEntities,
+        &Msaa,
+        Option<&ShadowFilteringMethod>,
+        ViewPrepasses,
+    )>,
+    mut next_tick: Local<Tick>,
+) {
+    let draw_raymarch = opaque_draw_functions
+        .read()
+        .id::<DrawVoxelRaymarchCommands>();
+    let draw_raymarch_prepass = prepass_draw_functions
+        .read()
+        .id::<DrawVoxelRaymarchPrepassCommands>();
+
+    for (view, view_visible_entities, msaa, shadow_filter_method, prepasses) in &views {
+        let Some(opaque_phase) = opaque_render_phases.get_mut(&view.retained_view_entity) else {
+            continue;
+        };
+        let view_key = main_view_key(view, msaa, shadow_filter_method, prepasses);
+        let prepass_view_key =
+            MeshPipelineKey::from_msaa_samples(msaa.samples()) | prepass_key(prepasses);
+        // the prepass node does not run for deferred views
+        let mut prepass_phase = prepass_render_phases
+            .get_mut(&view.retained_view_entity)
+            .filter(|_| !prepass_view_key.contains(MeshPipelineKey::DEFERRED_PREPASS));
+
+        for &entity in view_visible_entities.get::<VoxeledRendered>().iter() {
+            if !raymarched.contains(entity.0) {
+                continue;
+            }
+            let lit = shadings
+                .get(entity.0)
+                .is_ok_and(|&shading| shading != VoxelShading::Unlit);
+
+            let pipeline_id = specialized_render_pipelines.specialize(
+                &pipeline_cache,
+                &raymarch_pipeline,
+                VoxelRaymarchPipelineKey {
+                    mesh_key: view_key,
+                    lit,
+                    prepass: false,
+                },
+            );
+
+            let this_tick = next_tick.get() + 1;
+            next_tick.set(this_tick);
+
+            opaque_phase.add(
+                Opaque3dBatchSetKey {
+                    draw_function: draw_raymarch,
+                    pipeline: pipeline_id,
+                    material_bind_group_index: None,
+                    vertex_slab: Default::default(),
+                    index_slab: None,
+                    lightmap_slab: None,
+                },
+                Opaque3dBinKey {
+                    asset_id: AssetId::<Mesh>::invalid().untyped(),
+                },
+                entity,
+                InputUniformIndex::default(),
+                BinnedRenderPhaseType::NonMesh,
+                *next_tick,
+            );
+
+            let Some(prepass_phase) = prepass_phase.as_mut() else {
+                continue;
+            };
+            let pipeline_id = specialized_render_pipelines.specialize(
+                &pipeline_cache,
+                &raymarch_pipeline,
+                VoxelRaymarchPipelineKey {
+                    mesh_key: prepass_view_key,
+                    // the prepass writes no color
+                    lit: false,
+                    prepass: true,
+                },
+            );
+            prepass_phase.add(
+                OpaqueNoLightmap3dBatchSetKey {
+                    pipeline: pipeline_id,
+                    draw_function: draw_raymarch_prepass,
+                    material_bind_group_index: None,
+                    vertex_slab: Default::default(),
+                    index_slab: None,
+                },
+                OpaqueNoLightmap3dBinKey {
+                    asset_id: AssetId::<Mesh>::invalid().untyped(),
+                },
+                entity,
+                InputUniformIndex::default(),
+                BinnedRenderPhaseType::NonMesh,
+                *next_tick,
+            );
+        }
+    }
+}
@@ -0,0 +1,180 @@
+#import marching_cubes::bounds::bounds_position
+#import marching_cubes::density::{volume, field, source_field, field_normal, ambient_occlusion, material, material_weights}
+#import marching_cubes::clipping::{CLIP_CAPPED, CLIP_COLORMAP, clip_distance, clip_colormap}
+
+#ifdef PREPASS_PIPELINE
+#import bevy_render::view::View
+
+@group(0) @binding(0) var<uniform> view: View;
+
+#ifdef MOTION_VECTOR_PREPASS
+// Must match `PreviousViewData`
+struct PreviousView {
+    view_from_world: mat4x4<f32>,
+    clip_from_world: mat4x4<f32>,
+    clip_from_view: mat4x4<f32>,
+};
+
+@group(0) @binding(1) var<uniform> previous_view: PreviousView;
+#endif
+#else
+#import bevy_pbr::mesh_view_bindings::view
+#import marching_cubes::shading::{UNTEXTURED_ROUGHNESS, material_color, shade, shadow_visibility}
+#endif
+
+// Sphere traces the density field of the volume from the back faces of its bounding box, the
+// density bindings are at `DENSITY_GROUP`
+
+// Must match `RaymarchUniform`
+struct RaymarchParams {
+    max_steps: u32,
+    refine_steps: u32,
+    step_scale: f32,
+    // in voxels
+    max_step: f32,
+};
+
+@group(#{DENSITY_GROUP}) @binding(0) var<uniform> raymarch: RaymarchParams;
+
+// shortest step in voxels, keeps the march moving where the field is flat
+const MIN_STEP: f32 = 0.01;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+};
+
+struct FragmentOutput {
+#ifdef PREPASS_PIPELINE
+#ifdef NORMAL_PREPASS
+    @location(0) normal: vec4<f32>,
+#endif
+#ifdef MOTION_VECTOR_PREPASS
+    @location(1) motion_vector: vec2<f32>,
+#endif
+#else
+    @location(0) color: vec4<f32>,
+#endif
+    @builtin(frag_depth) depth: f32,
+};
+
+@vertex
+fn vertex(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let position = bounds_position(index, volume.min_bound, volume.max_bound);
+
+    var out: VertexOutput;
+    out.clip_position = view.clip_from_world * vec4(position, 1.0);
+    out.world_position = position;
+    return out;
+}
+
+// Distance along the ray to the first point inside the surface, negative on a miss
+fn trace(origin: vec3<f32>, direction: vec3<f32>, t_near: f32, t_far: f32) -> f32 {
+    var previous_t = t_near;
+    var t = t_near;
+    var distance = field(origin + direction * t);
+    // the ray starts inside, the surface is cut by the box or the near plane
+    if (distance < 0.0) {
+        return t;
+    }
+
+    for (var i = 0u; i < raymarch.max_steps; i++) {
+        // the field is no exact distance for most sources, clamp the step so thin features
+        // are not jumped over
+        let step = clamp(
+            distance * raymarch.step_scale,
+            MIN_STEP * volume.voxel_size,
+            raymarch.max_step * volume.voxel_size,
+        );
+        previous_t = t;
+        t += step;
+        if (t > t_far) {
+            return -1.0;
+        }
+        distance = field(origin + direction * t);
+        if (distance < 0.0) {
+            break;
+        }
+    }
+    if (distance >= 0.0) {
+        return -1.0;
+    }
+
+    // bisect the sign change, `t` stays inside
+    var outside = previous_t;
+    for (var i = 0u; i < raymarch.refine_steps; i++) {
+        let middle = (outside + t) * 0.5;
+        if (field(origin + direction * middle) < 0.0) {
+            t = middle;
+        } else {
+            outside = middle;
+        }
+    }
+    return t;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> FragmentOutput {
+    let origin = view.world_position;
+    let direction = normalize(in.world_position - origin);
+
+    // slab intersection with the volume, the camera may be inside it
+    let inverse = 1.0 / direction;
+    let t0 = (volume.min_bound - origin) * inverse;
+    let t1 = (volume.max_bound - origin) * inverse;
+    let t_min = min(t0, t1);
+    let t_max = max(t0, t1);
+    let t_near = max(max(max(t_min.x, t_min.y), t_min.z), 0.0);
+    let t_far = min(min(t_max.x, t_max.y), t_max.z);
+
+    let t = trace(origin, direction, t_near, t_far);
+    if (t < 0.0) {
+        discard;
+    }
+    let hit = origin + direction * t;
+    let cut = clip_distance(hit, volume.clipping);
+    // an open cut hides the hit instead of looking inside, a capped one is part of the field
+    if ((volume.clipping.flags & CLIP_CAPPED) == 0u && cut > 0.0) {
+        discard;
+    }
+    var normal = field_normal(hit);
+    if (all(normal == vec3(0.0))) {
+        normal = -direction;
+    }
+
+    var out: FragmentOutput;
+    let clip_position = view.clip_from_world * vec4(hit, 1.0);
+    out.depth = clip_position.z / clip_position.w;
+
+#ifdef PREPASS_PIPELINE
+#ifdef NORMAL_PREPASS
+    out.normal = vec4(normal * 0.5 + 0.5, 1.0);
+#endif
+#ifdef MOTION_VECTOR_PREPASS
+    // the field has no history, only the camera motion counts
+    let unjittered_clip_position = view.unjittered_clip_from_world * vec4(hit, 1.0);
+    let previous_clip_position = previous_view.clip_from_world * vec4(hit, 1.0);
+    let ndc = unjittered_clip_position.xy / unjittered_clip_position.w;
+    let previous_ndc = previous_clip_position.xy / previous_clip_position.w;
+    out.motion_vector = (ndc - previous_ndc) * vec2(0.5, -0.5);
+#endif
+#else
+    var albedo = material_color(material_weights(material(hit)));
+    // the cap is where the cut dominates the field
+    if ((volume.clipping.flags & CLIP_COLORMAP) != 0u) {
+        let density = source_field(hit);
+        if (cut >= density) {
+            albedo = vec4(clip_colormap(density, volume.clipping), albedo.a);
+        }
+    }
+    let occlusion = ambient_occlusion(hit, normal);
+#ifdef SHADING_LIT
+    let color = shade(hit, in.clip_position, normal, normal, albedo.rgb, UNTEXTURED_ROUGHNESS, occlusion);
+#else
+    let color = albedo.rgb * occlusion * shadow_visibility(hit, in.clip_position, normal);
+#endif
+    out.color = vec4(color, albedo.a);
+#endif
+
+    return out;
+}
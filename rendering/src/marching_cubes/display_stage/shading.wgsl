@@ -0,0 +1,66 @@
+#define_import_path marching_cubes::shading
+
+#import bevy_pbr::mesh_types::MESH_FLAGS_SHADOW_RECEIVER_BIT;
+#import bevy_pbr::mesh_view_bindings::view;
+#import bevy_pbr::pbr_functions::{apply_pbr_lighting, calculate_view};
+#import bevy_pbr::pbr_types::{PbrInput, pbr_input_new};
+
+// Material colors and lighting shared by the meshed and the raymarched display stage
+
+// untextured surfaces have no roughness of their own
+const UNTEXTURED_ROUGHNESS: f32 = 0.8;
+
+// Must match `VoxelMaterialsUniform`
+struct VoxelMaterials {
+    colors: array<vec4<f32>, 4>,
+};
+
+@group(1) @binding(0) var<uniform> materials: VoxelMaterials;
+
+// Blends the material colors across the triangle, mirrors `VoxelMaterials::blend`
+fn material_color(weights: vec4<f32>) -> vec4<f32> {
+    let total = max(weights.x + weights.y + weights.z + weights.w, 1e-6);
+    var color = vec4(0.0);
+    for (var i = 0; i < 4; i++) {
+        color += weights[i] * materials.colors[i];
+    }
+    return color / total;
+}
+
+// Surface at `pos` as bevy's PBR lighting sees it, a dielectric receiving shadows
+fn pbr_input(pos: vec3<f32>, frag_coord: vec4<f32>, geometric_normal: vec3<f32>, normal: vec3<f32>, albedo: vec3<f32>, perceptual_roughness: f32, occlusion: f32) -> PbrInput {
+    var input = pbr_input_new();
+    input.material.base_color = vec4(albedo, 1.0);
+    input.material.perceptual_roughness = perceptual_roughness;
+    input.diffuse_occlusion = vec3(occlusion);
+    input.frag_coord = frag_coord;
+    input.world_position = vec4(pos, 1.0);
+    // the normal bias of the shadows follows the geometry, not the normal map
+    input.world_normal = geometric_normal;
+    input.N = normal;
+    input.is_orthographic = view.clip_from_view[3].w == 1.0;
+    input.V = calculate_view(input.world_position, input.is_orthographic);
+    input.flags = MESH_FLAGS_SHADOW_RECEIVER_BIT;
+    return input;
+}
+
+// Ambient, directional, point and spot lights with their shadows through bevy's PBR lighting,
+// the ambient light is darkened by the occlusion. `frag_coord` picks the light cluster
+fn shade(pos: vec3<f32>, frag_coord: vec4<f32>, geometric_normal: vec3<f32>, normal: vec3<f32>, albedo: vec3<f32>, perceptual_roughness: f32, occlusion: f32) -> vec3<f32> {
+    return apply_pbr_lighting(pbr_input(pos, frag_coord, geometric_normal, normal, albedo, perceptual_roughness, occlusion)).rgb;
+}
+
+// Share of the light on a white diffuse surface left after the shadows, 1 without shadows.
+// Unlit surfaces are darkened by it, so they still sit on the ground
+fn shadow_visibility(pos: vec3<f32>, frag_coord: vec4<f32>, normal: vec3<f32>) -> f32 {
+    var input = pbr_input(pos, frag_coord, normal, normal, vec3(1.0), 1.0, 1.0);
+    input.material.reflectance = vec3(0.0);
+    let shadowed = luminance(apply_pbr_lighting(input).rgb);
+    input.flags = 0u;
+    let lit = luminance(apply_pbr_lighting(input).rgb);
+    return select(1.0, shadowed / lit, lit > 0.0);
+}
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
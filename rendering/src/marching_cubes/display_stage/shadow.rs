@@ -0,0 +1,272 @@
+use bevy_asset::{AssetId, Handle, weak_handle};
+use bevy_core_pipeline::core_3d::CORE_3D_DEPTH_FORMAT;
+use bevy_ecs::component::Tick;
+use bevy_ecs::entity::Entity;
+use bevy_ecs::query::{ROQueryItem, With, Without};
+use bevy_ecs::resource::Resource;
+use bevy_ecs::system::lifetimeless::{Read, SRes};
+use bevy_ecs::system::{Commands, Local, Query, Res, ResMut, SystemParamItem};
+use bevy_ecs::world::{FromWorld, World};
+use bevy_pbr::{
+    LightEntity, NotShadowCaster, Shadow, ShadowBatchSetKey, ShadowBinKey, ViewLightEntities,
+};
+use bevy_render::Extract;
+use bevy_render::mesh::{Mesh, PrimitiveTopology, VertexBufferLayout, VertexFormat};
+use bevy_render::render_phase::{
+    BinnedRenderPhaseType, DrawFunctions, InputUniformIndex, PhaseItem, RenderCommand,
+    RenderCommandResult, SetItemPipeline, TrackedRenderPass, ViewBinnedRenderPhases,
+};
+use bevy_render::render_resource::binding_types::uniform_buffer;
+use bevy_render::render_resource::{
+    BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, CompareFunction,
+    DepthStencilState, FragmentState, MultisampleState, PipelineCache, PrimitiveState,
+    RenderPipelineDescriptor, Shader, ShaderStages, SpecializedRenderPipeline,
+    SpecializedRenderPipelines, VertexAttribute, VertexState, VertexStepMode,
+};
+use bevy_render::renderer::RenderDevice;
+use bevy_render::settings::WgpuFeatures;
+use bevy_render::sync_world::{MainEntity, RenderEntity};
+use bevy_render::view::{
+    ExtractedView, InheritedVisibility, ViewUniform, ViewUniformOffset, ViewUniforms,
+};
+
+use super::clipping::{ExtractedVoxelClipping, SetVoxelClipBindGroup, VoxelClipLayout};
+use super::{DrawVoxeled, VoxeledRendered};
+use crate::marching_cubes::Vertex;
+
+pub const SHADOW_SHADER_HANDLE: Handle<Shader> =
+    weak_handle!("c3e81f4a-5b27-4d69-9a0e-7f62d18b4c95");
+
+/// Depth only pipeline drawing the voxel surfaces into the shadow maps.
+#[derive(Resource)]
+pub struct VoxelShadowPipeline {
+    view_layout: BindGroupLayout,
+    clip_layout: BindGroupLayout,
+    depth_clip_control_supported: bool,
+}
+
+impl FromWorld for VoxelShadowPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let view_layout = render_device.create_bind_group_layout(
+            "voxel_shadow_view_bind_group",
+            &BindGroupLayoutEntries::single(
+                ShaderStages::VERTEX,
+                uniform_buffer::<ViewUniform>(true),
+            ),
+        );
+
+        Self {
+            view_layout,
+            clip_layout: world.resource::<VoxelClipLayout>().0.clone(),
+            depth_clip_control_supported: render_device
+                .features()
+                .contains(WgpuFeatures::DEPTH_CLIP_CONTROL),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VoxelShadowPipelineKey {
+    /// Directional light cascades, whose near plane must not clip the casters in front of it.
+    pub unclipped_depth_ortho: bool,
+    /// Whether an open [`VoxelClipping`](crate::marching_cubes::VoxelClipping) cut is
+    /// discarded, bound at group 1.
+    pub clip_discard: bool,
+}
+
+impl SpecializedRenderPipeline for VoxelShadowPipeline {
+    type Key = VoxelShadowPipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        // same as bevy's prepass, clamp the depth in the shader without the native feature
+        let emulate_unclipped_depth =
+            key.unclipped_depth_ortho && !self.depth_clip_control_supported;
+        let mut shader_defs = vec![];
+        let mut layout = vec![self.view_layout.clone()];
+        if emulate_unclipped_depth {
+            shader_defs.push("UNCLIPPED_DEPTH_ORTHO_EMULATION".into());
+        }
+        if key.clip_discard {
+            layout.push(self.clip_layout.clone());
+            shader_defs.push("CLIP_DISCARD".into());
+        }
+
+        RenderPipelineDescriptor {
+            label: Some("voxel_shadow_pipeline".into()),
+            layout,
+            push_constant_ranges: vec![],
+            vertex: VertexState {
+                shader: SHADOW_SHADER_HANDLE,
+                shader_defs: shader_defs.clone(),
+                entry_point: "vertex".into(),
+                buffers: vec![VertexBufferLayout {
+                    array_stride: size_of::<Vertex>() as u64,
+                    step_mode: VertexStepMode::Vertex,
+                    attributes: vec![VertexAttribute {
+                        format: VertexFormat::Float32x3,
+                        offset: 0,
+                        shader_location: 0,
+                    }],
+                }],
+            },
+            fragment: (emulate_unclipped_depth || key.clip_discard).then(|| FragmentState {
+                shader: SHADOW_SHADER_HANDLE,
+                shader_defs,
+                entry_point: "fragment".into(),
+                targets: vec![],
+            }),
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                cull_mode: None,
+                unclipped_depth: key.unclipped_depth_ortho && self.depth_clip_control_supported,
+                ..Default::default()
+            },
+            depth_stencil: Some(DepthStencilState {
+                format: CORE_3D_DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::GreaterEqual,
+                stencil: Default::default(),
+                bias: Default::default(),
+            }),
+            multisample: MultisampleState::default(),
+            zero_initialize_workgroup_memory: false,
+        }
+    }
+}
+
+pub(super) type DrawVoxeledShadowCommands = (
+    SetItemPipeline,
+    SetVoxelShadowViewBindGroup<0>,
+    SetVoxelClipBindGroup<1>,
+    DrawVoxeled,
+);
+
+/// Visible [`VoxeledRendered`] entities without [`NotShadowCaster`], extracted every frame.
+#[derive(Resource, Default)]
+pub(super) struct VoxelShadowCasters(Vec<(Entity, MainEntity)>);
+
+type ShadowCasterFilter = (With<VoxeledRendered>, Without<NotShadowCaster>);
+
+pub(super) fn extract_voxel_shadow_casters(
+    mut casters: ResMut<VoxelShadowCasters>,
+    entities: Extract<Query<(Entity, &RenderEntity, &InheritedVisibility), ShadowCasterFilter>>,
+) {
+    casters.0.clear();
+    casters.0.extend(
+        entities
+            .iter()
+            .filter(|(_, _, visibility)| visibility.get())
+            .map(|(entity, render_entity, _)| (render_entity.id(), MainEntity::from(entity))),
+    );
+}
+
+#[derive(Resource)]
+pub(super) struct VoxelShadowViewBindGroup(BindGroup);
+
+pub(super) fn prepare_voxel_shadow_view_bind_group(
+    mut commands: Commands,
+    pipeline: Res<VoxelShadowPipeline>,
+    view_uniforms: Res<ViewUniforms>,
+    render_device: Res<RenderDevice>,
+) {
+    let Some(binding) = view_uniforms.uniforms.binding() else {
+        return;
+    };
+
+    commands.insert_resource(VoxelShadowViewBindGroup(render_device.create_bind_group(
+        Some("voxel_shadow_view_bind_group"),
+        &pipeline.view_layout,
+        &BindGroupEntries::single(binding),
+    )));
+}
+
+pub(super) struct SetVoxelShadowViewBindGroup<const I: usize>;
+
+impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetVoxelShadowViewBindGroup<I> {
+    type Param = SRes<VoxelShadowViewBindGroup>;
+
+    type ViewQuery = Read<ViewUniformOffset>;
+
+    type ItemQuery = ();
+
+    fn render<'w>(
+        _item: &P,
+        view_uniform_offset: ROQueryItem<'w, Self::ViewQuery>,
+        _entity: Option<ROQueryItem<'w, Self::ItemQuery>>,
+        bind_group: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        pass.set_bind_group(I, &bind_group.into_inner().0, &[view_uniform_offset.offset]);
+        RenderCommandResult::Success
+    }
+}
+
+/// Adds every shadow caster to the shadow phase of every light view, the lights only compute
+/// the visibility of meshes.
+#[allow(clippy::too_many_arguments)]
+pub(super) fn queue_voxel_shadows(
+    pipeline_cache: Res<PipelineCache>,
+    shadow_pipeline: Res<VoxelShadowPipeline>,
+    mut specialized_render_pipelines: ResMut<SpecializedRenderPipelines<VoxelShadowPipeline>>,
+    shadow_draw_functions: Res<DrawFunctions<Shadow>>,
+    mut shadow_render_phases: ResMut<ViewBinnedRenderPhases<Shadow>>,
+    casters: Res<VoxelShadowCasters>,
+    clipping: Option<Res<ExtractedVoxelClipping>>,
+    view_lights: Query<&ViewLightEntities, With<ExtractedView>>,
+    view_light_entities: Query<(&LightEntity, &ExtractedView)>,
+    mut next_tick: Local<Tick>,
+) {
+    if casters.0.is_empty() {
+        return;
+    }
+    let draw_voxel_shadow = shadow_draw_functions
+        .read()
+        .id::<DrawVoxeledShadowCommands>();
+
+    for view_lights in &view_lights {
+        for &view_light_entity in &view_lights.lights {
+            let Ok((light_entity, extracted_view_light)) =
+                view_light_entities.get(view_light_entity)
+            else {
+                continue;
+            };
+            let Some(shadow_phase) =
+                shadow_render_phases.get_mut(&extracted_view_light.retained_view_entity)
+            else {
+                continue;
+            };
+
+            let pipeline_id = specialized_render_pipelines.specialize(
+                &pipeline_cache,
+                &shadow_pipeline,
+                VoxelShadowPipelineKey {
+                    unclipped_depth_ortho: matches!(light_entity, LightEntity::Directional { .. }),
+                    clip_discard: clipping.as_ref().is_some_and(|clipping| clipping.discard),
+                },
+            );
+
+            for &caster in &casters.0 {
+                let this_tick = next_tick.get() + 1;
+                next_tick.set(this_tick);
+
+                shadow_phase.add(
+                    ShadowBatchSetKey {
+                        pipeline: pipeline_id,
+                        draw_function: draw_voxel_shadow,
+                        material_bind_group_index: None,
+                        vertex_slab: Default::default(),
+                        index_slab: None,
+                    },
+                    ShadowBinKey {
+                        asset_id: AssetId::<Mesh>::invalid().untyped(),
+                    },
+                    caster,
+                    InputUniformIndex::default(),
+                    BinnedRenderPhaseType::NonMesh,
+                    *next_tick,
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,60 @@
+#import bevy_render::view::View
+
+// Depth only pass of the voxel surfaces into the shadow maps, `view` is the light view
+
+@group(0) @binding(0) var<uniform> view: View;
+
+#ifdef CLIP_DISCARD
+#import marching_cubes::clipping::{ClipParams, clip_distance}
+
+@group(1) @binding(0) var<uniform> clipping: ClipParams;
+#endif
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+#ifdef UNCLIPPED_DEPTH_ORTHO_EMULATION
+    @location(0) unclipped_depth: f32,
+#endif
+#ifdef CLIP_DISCARD
+    @location(1) world_position: vec3<f32>,
+#endif
+};
+
+@vertex
+fn vertex(@location(0) position: vec3<f32>) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = view.clip_from_world * vec4(position, 1.0);
+#ifdef UNCLIPPED_DEPTH_ORTHO_EMULATION
+    // casters in front of the near plane would be clipped, clamp them and keep the real depth
+    out.unclipped_depth = out.clip_position.z;
+    out.clip_position.z = min(out.clip_position.z, 1.0);
+#endif
+#ifdef CLIP_DISCARD
+    out.world_position = position;
+#endif
+    return out;
+}
+
+// an open clip cuts the casters too, so the cut away surface throws no shadow
+#ifdef CLIP_DISCARD
+fn clip_discard(in: VertexOutput) {
+    if (clip_distance(in.world_position, clipping) > 0.0) {
+        discard;
+    }
+}
+#endif
+
+#ifdef UNCLIPPED_DEPTH_ORTHO_EMULATION
+@fragment
+fn fragment(in: VertexOutput) -> @builtin(frag_depth) f32 {
+#ifdef CLIP_DISCARD
+    clip_discard(in);
+#endif
+    return in.unclipped_depth;
+}
+#else ifdef CLIP_DISCARD
+@fragment
+fn fragment(in: VertexOutput) {
+    clip_discard(in);
+}
+#endif
@@ -0,0 +1,155 @@
+use bevy_asset::io::Reader;
+use bevy_asset::{Asset, AssetLoader, LoadContext, ron};
+use bevy_color::{ColorToComponents, LinearRgba, Mix};
+use bevy_math::Vec4;
+use bevy_reflect::TypePath;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Entries of the lookup table a [`TransferFunction`] is baked into for the GPU.
+pub const TRANSFER_FUNCTION_RESOLUTION: usize = 256;
+
+/// Color and opacity of the samples of a [`VolumeRendered`](super::VolumeRendered) volume.
+///
+/// The gradient is linear between the stops and clamped outside them. Sample values are
+/// normalized by [`VolumeRendered::window`](super::VolumeRendered::window) before the lookup, so
+/// the stops are between 0 and 1. The alpha is the opacity of one voxel.
+#[derive(Asset, TypePath, Clone, Debug)]
+pub struct TransferFunction {
+    stops: Vec<TransferStop>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TransferStop {
+    pub value: f32,
+    pub color: LinearRgba,
+}
+
+impl TransferFunction {
+    /// Sorts the stops by value.
+    pub fn new(mut stops: Vec<TransferStop>) -> Self {
+        assert!(
+            !stops.is_empty(),
+            "TransferFunction needs at least one stop"
+        );
+        stops.sort_by(|a, b| a.value.total_cmp(&b.value));
+        Self { stops }
+    }
+
+    /// From transparent black at 0 to opaque white at 1.
+    pub fn grayscale() -> Self {
+        Self::new(vec![
+            TransferStop {
+                value: 0.0,
+                color: LinearRgba::NONE,
+            },
+            TransferStop {
+                value: 1.0,
+                color: LinearRgba::WHITE,
+            },
+        ])
+    }
+
+    pub fn stops(&self) -> &[TransferStop] {
+        &self.stops
+    }
+
+    /// Color of the normalized sample `value`.
+    pub fn sample(&self, value: f32) -> LinearRgba {
+        let next = self.stops.partition_point(|stop| stop.value <= value);
+        if next == 0 {
+            return self.stops[0].color;
+        }
+        let Some(after) = self.stops.get(next) else {
+            return self.stops[next - 1].color;
+        };
+        let before = &self.stops[next - 1];
+        let t = (value - before.value) / (after.value - before.value).max(f32::EPSILON);
+        before.color.mix(&after.color, t)
+    }
+
+    /// The gradient sampled at [`TRANSFER_FUNCTION_RESOLUTION`] evenly spaced values from 0 to 1,
+    /// `transfer_function` in `direct_volume.wgsl`.
+    pub fn lookup_table(&self) -> Vec<Vec4> {
+        let last = (TRANSFER_FUNCTION_RESOLUTION - 1) as f32;
+        (0..TRANSFER_FUNCTION_RESOLUTION)
+            .map(|i| self.sample(i as f32 / last).to_vec4())
+            .collect()
+    }
+}
+
+impl Default for TransferFunction {
+    fn default() -> Self {
+        Self::grayscale()
+    }
+}
+
+/// Loads `*.transfer.ron` files, see [`TransferFunctionManifest`].
+#[derive(Default)]
+pub struct TransferFunctionLoader;
+
+/// ```ron
+/// (
+///     stops: [
+///         (value: 0.2, color: (0.0, 0.0, 0.0, 0.0)),
+///         (value: 0.5, color: (0.9, 0.4, 0.3, 0.05)),
+///         (value: 0.8, color: (1.0, 1.0, 0.9, 0.8)),
+///     ],
+/// )
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TransferFunctionManifest {
+    pub stops: Vec<TransferStopManifest>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct TransferStopManifest {
+    pub value: f32,
+    /// Linear RGBA.
+    pub color: [f32; 4],
+}
+
+#[derive(Debug, Error)]
+pub enum TransferFunctionLoaderError {
+    #[error("could not read transfer function: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid transfer function: {0}")]
+    Manifest(#[from] ron::de::SpannedError),
+    #[error("transfer function has no stops")]
+    NoStops,
+}
+
+impl AssetLoader for TransferFunctionLoader {
+    type Asset = TransferFunction;
+    type Settings = ();
+    type Error = TransferFunctionLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &Self::Settings,
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let manifest: TransferFunctionManifest = ron::de::from_bytes(&bytes)?;
+        if manifest.stops.is_empty() {
+            return Err(TransferFunctionLoaderError::NoStops);
+        }
+
+        Ok(TransferFunction::new(
+            manifest
+                .stops
+                .into_iter()
+                .map(|stop| TransferStop {
+                    value: stop.value,
+                    color: LinearRgba::from_f32_array(stop.color),
+                })
+                .collect(),
+        ))
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["transfer.ron"]
+    }
+}
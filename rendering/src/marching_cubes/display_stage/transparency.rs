@@ -0,0 +1,118 @@
+use bevy_ecs::component::Component;
+use bevy_ecs::query::QueryItem;
+use bevy_math::Vec3;
+use bevy_render::extract_component::ExtractComponent;
+use bevy_render::primitives::Aabb;
+use bevy_render::render_resource::{BlendComponent, BlendFactor, BlendOperation, BlendState, Face};
+use bevy_transform::components::GlobalTransform;
+
+use crate::marching_cubes::compute_stage::VoxelVolumeUniform;
+
+/// Alpha blended [`VoxeledRendered`](super::VoxeledRendered) surface, for water and glass.
+///
+/// The surface is drawn in the transparent pass, sorted back to front with the other transparent
+/// entities by the center of its [`Aabb`], or of the voxel volume without one. The alpha comes from the material colors and the
+/// [`TriplanarMaterial`](super::TriplanarMaterial) albedo. Transparent surfaces still cast
+/// shadows but are left out of the depth and normal prepass.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct VoxelTransparency {
+    pub blend: VoxelBlendMode,
+    pub faces: VoxelFaces,
+}
+
+/// How a transparent surface is blended over what is behind it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum VoxelBlendMode {
+    /// Standard alpha blending of straight alpha colors.
+    #[default]
+    Alpha,
+    /// The colors are already multiplied by their alpha.
+    Premultiplied,
+    /// Adds the color weighted by its alpha, for glowing surfaces.
+    Additive,
+}
+
+/// Which faces of a transparent surface are drawn.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum VoxelFaces {
+    /// Only the faces towards the camera, the inside of a closed surface is invisible.
+    #[default]
+    Front,
+    /// Both sides in one draw, the overlapping faces of the surface blend in mesh order.
+    Both,
+    /// The back faces first then the front faces, so a closed surface blends its far side under
+    /// its near side. Draws the surface twice.
+    BackThenFront,
+}
+
+impl VoxelBlendMode {
+    pub fn blend_state(self) -> BlendState {
+        match self {
+            VoxelBlendMode::Alpha => BlendState::ALPHA_BLENDING,
+            VoxelBlendMode::Premultiplied => BlendState::PREMULTIPLIED_ALPHA_BLENDING,
+            VoxelBlendMode::Additive => BlendState {
+                color: BlendComponent {
+                    src_factor: BlendFactor::SrcAlpha,
+                    dst_factor: BlendFactor::One,
+                    operation: BlendOperation::Add,
+                },
+                alpha: BlendComponent {
+                    src_factor: BlendFactor::Zero,
+                    dst_factor: BlendFactor::One,
+                    operation: BlendOperation::Add,
+                },
+            },
+        }
+    }
+}
+
+impl VoxelFaces {
+    /// Culled face of every draw, in draw order.
+    pub fn passes(self) -> &'static [Option<Face>] {
+        match self {
+            VoxelFaces::Front => &[Some(Face::Back)],
+            VoxelFaces::Both => &[None],
+            VoxelFaces::BackThenFront => &[Some(Face::Front), Some(Face::Back)],
+        }
+    }
+}
+
+/// [`VoxelTransparency`] in the render world with what its sort point is computed from.
+#[derive(Component, Clone, Copy)]
+pub struct ExtractedVoxelTransparency {
+    pub transparency: VoxelTransparency,
+    pub transform: GlobalTransform,
+    /// Local center of the [`Aabb`], if the entity has one.
+    pub aabb_center: Option<Vec3>,
+}
+
+impl ExtractedVoxelTransparency {
+    /// World space point the surface is sorted by, the [`Aabb`] center or the volume center.
+    pub fn center(&self, volume: Option<&VoxelVolumeUniform>) -> Vec3 {
+        let center = self
+            .aabb_center
+            .or_else(|| volume.map(VoxelVolumeUniform::center))
+            .unwrap_or(Vec3::ZERO);
+        self.transform.transform_point(center)
+    }
+}
+
+impl ExtractComponent for VoxelTransparency {
+    type QueryData = (
+        &'static Self,
+        &'static GlobalTransform,
+        Option<&'static Aabb>,
+    );
+    type QueryFilter = ();
+    type Out = ExtractedVoxelTransparency;
+
+    fn extract_component(
+        (transparency, transform, aabb): QueryItem<Self::QueryData>,
+    ) -> Option<Self::Out> {
+        Some(ExtractedVoxelTransparency {
+            transparency: *transparency,
+            transform: *transform,
+            aabb_center: aabb.map(|aabb| aabb.center.into()),
+        })
+    }
+}
@@ -0,0 +1,186 @@
+use bevy_asset::Handle;
+use bevy_ecs::component::Component;
+use bevy_ecs::entity::{Entity, EntityHashMap};
+use bevy_ecs::query::ROQueryItem;
+use bevy_ecs::resource::Resource;
+use bevy_ecs::system::lifetimeless::SRes;
+use bevy_ecs::system::{Query, Res, ResMut, SystemParamItem};
+use bevy_image::Image;
+use bevy_render::extract_component::ExtractComponent;
+use bevy_render::render_asset::RenderAssets;
+use bevy_render::render_phase::{PhaseItem, RenderCommand, RenderCommandResult, TrackedRenderPass};
+use bevy_render::render_resource::binding_types::{sampler, texture_2d, uniform_buffer};
+use bevy_render::render_resource::{
+    AddressMode, BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, FilterMode,
+    Sampler, SamplerBindingType, SamplerDescriptor, ShaderStages, ShaderType, TextureSampleType,
+    UniformBuffer,
+};
+use bevy_render::renderer::{RenderDevice, RenderQueue};
+use bevy_render::texture::{FallbackImage, GpuImage};
+
+use super::VoxelRenderedPipeline;
+
+/// Textures projected along the three axes of a [`VoxeledRendered`](super::VoxeledRendered)
+/// surface, marching cubes output has no UVs to map them with.
+///
+/// The albedo is tinted by the blended [`VoxelMaterials`](super::super::VoxelMaterials) color.
+/// Normal and roughness maps must be loaded as linear images, roughness is read from the red
+/// channel. All textures are sampled with a repeating sampler, the image samplers are ignored.
+#[derive(Component, Clone, Debug, ExtractComponent)]
+pub struct TriplanarMaterial {
+    pub albedo: Handle<Image>,
+    pub normal: Option<Handle<Image>>,
+    pub roughness: Option<Handle<Image>>,
+    /// Multiplies the roughness map, or the roughness without one.
+    pub perceptual_roughness: f32,
+    /// Texture repeats per world unit.
+    pub tiling: f32,
+    /// Exponent on the normal before it weights the projections, higher gives sharper seams.
+    pub blend_sharpness: f32,
+}
+
+impl Default for TriplanarMaterial {
+    fn default() -> Self {
+        Self {
+            albedo: Handle::default(),
+            normal: None,
+            roughness: None,
+            perceptual_roughness: 0.8,
+            tiling: 1.0,
+            blend_sharpness: 4.0,
+        }
+    }
+}
+
+const TRIPLANAR_NORMAL_MAP: u32 = 1;
+const TRIPLANAR_ROUGHNESS_MAP: u32 = 2;
+
+/// `TriplanarParams` in `display_stage.wgsl`.
+#[derive(ShaderType, Clone, Default)]
+struct TriplanarUniform {
+    tiling: f32,
+    blend_sharpness: f32,
+    perceptual_roughness: f32,
+    flags: u32,
+}
+
+pub(super) fn triplanar_layout(render_device: &RenderDevice) -> BindGroupLayout {
+    render_device.create_bind_group_layout(
+        "triplanar_bind_group",
+        &BindGroupLayoutEntries::sequential(
+            ShaderStages::FRAGMENT,
+            (
+                uniform_buffer::<TriplanarUniform>(false),
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                sampler(SamplerBindingType::Filtering),
+            ),
+        ),
+    )
+}
+
+pub(super) fn triplanar_sampler(render_device: &RenderDevice) -> Sampler {
+    render_device.create_sampler(&SamplerDescriptor {
+        label: Some("triplanar_sampler"),
+        address_mode_u: AddressMode::Repeat,
+        address_mode_v: AddressMode::Repeat,
+        address_mode_w: AddressMode::Repeat,
+        mag_filter: FilterMode::Linear,
+        min_filter: FilterMode::Linear,
+        mipmap_filter: FilterMode::Linear,
+        ..Default::default()
+    })
+}
+
+/// Bind groups of the [`TriplanarMaterial`] entities whose textures are loaded, by render entity.
+#[derive(Resource, Default)]
+pub(super) struct TriplanarBindGroups(EntityHashMap<(BindGroup, UniformBuffer<TriplanarUniform>)>);
+
+impl TriplanarBindGroups {
+    pub(super) fn contains(&self, entity: Entity) -> bool {
+        self.0.contains_key(&entity)
+    }
+}
+
+pub(super) fn prepare_triplanar_bind_groups(
+    pipeline: Res<VoxelRenderedPipeline>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    gpu_images: Res<RenderAssets<GpuImage>>,
+    fallback_image: Res<FallbackImage>,
+    mut triplanar_bind_groups: ResMut<TriplanarBindGroups>,
+    materials: Query<(Entity, &TriplanarMaterial)>,
+) {
+    triplanar_bind_groups.0.clear();
+
+    for (entity, material) in &materials {
+        let Some(albedo) = gpu_images.get(&material.albedo) else {
+            continue;
+        };
+        // a missing map falls back, one still loading waits for it
+        let (normal, roughness) = match (&material.normal, &material.roughness) {
+            (Some(normal), _) if gpu_images.get(normal).is_none() => continue,
+            (_, Some(roughness)) if gpu_images.get(roughness).is_none() => continue,
+            (normal, roughness) => (
+                normal.as_ref().and_then(|normal| gpu_images.get(normal)),
+                roughness
+                    .as_ref()
+                    .and_then(|roughness| gpu_images.get(roughness)),
+            ),
+        };
+
+        let mut flags = 0;
+        if normal.is_some() {
+            flags |= TRIPLANAR_NORMAL_MAP;
+        }
+        if roughness.is_some() {
+            flags |= TRIPLANAR_ROUGHNESS_MAP;
+        }
+
+        let mut buffer = UniformBuffer::from(TriplanarUniform {
+            tiling: material.tiling,
+            blend_sharpness: material.blend_sharpness,
+            perceptual_roughness: material.perceptual_roughness,
+            flags,
+        });
+        buffer.write_buffer(&render_device, &render_queue);
+
+        let bind_group = render_device.create_bind_group(
+            Some("triplanar_bind_group"),
+            &pipeline.triplanar_layout,
+            &BindGroupEntries::sequential((
+                buffer.binding().unwrap(),
+                &albedo.texture_view,
+                &normal.unwrap_or(&fallback_image.d2).texture_view,
+                &roughness.unwrap_or(&fallback_image.d2).texture_view,
+                &pipeline.triplanar_sampler,
+            )),
+        );
+        triplanar_bind_groups.0.insert(entity, (bind_group, buffer));
+    }
+}
+
+pub(super) struct SetTriplanarBindGroup<const I: usize>;
+
+impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetTriplanarBindGroup<I> {
+    type Param = SRes<TriplanarBindGroups>;
+
+    type ViewQuery = ();
+
+    type ItemQuery = ();
+
+    fn render<'w>(
+        item: &P,
+        _view: ROQueryItem<'w, Self::ViewQuery>,
+        _entity: Option<ROQueryItem<'w, Self::ItemQuery>>,
+        bind_groups: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        // untextured surfaces are specialized without the group
+        if let Some((bind_group, _)) = bind_groups.into_inner().0.get(&item.entity()) {
+            pass.set_bind_group(I, bind_group, &[]);
+        }
+        RenderCommandResult::Success
+    }
+}
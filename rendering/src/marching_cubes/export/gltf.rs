@@ -0,0 +1,229 @@
+use std::io::{self, Write};
+
+use bevy_math::Vec3;
+
+use crate::marching_cubes::cpu_mesher::IsoSurface;
+
+const FLOAT: u32 = 5126;
+const ARRAY_BUFFER: u32 = 34962;
+const TRIANGLES: u32 = 4;
+
+/// Writes a glTF 2.0 with the buffer embedded as a base64 data uri.
+pub fn write_gltf(surface: &IsoSurface, writer: &mut impl Write) -> io::Result<()> {
+    let buffer = buffer_data(surface);
+    let uri = format!(
+        "data:application/octet-stream;base64,{}",
+        base64_encode(&buffer)
+    );
+    let json = document(surface, Some(&uri));
+    writer.write_all(json.as_bytes())
+}
+
+/// Writes a binary glTF 2.0 (`.glb`).
+pub fn write_glb(surface: &IsoSurface, writer: &mut impl Write) -> io::Result<()> {
+    let mut buffer = buffer_data(surface);
+    let mut json = document(surface, None).into_bytes();
+
+    // chunks are 4 byte aligned, JSON is padded with spaces and BIN with zeros
+    json.resize(json.len().next_multiple_of(4), b' ');
+    buffer.resize(buffer.len().next_multiple_of(4), 0);
+
+    // an empty surface has no buffer and leaves out the optional BIN chunk
+    let bin_length = if buffer.is_empty() {
+        0
+    } else {
+        8 + buffer.len()
+    };
+    let total_length = 12 + 8 + json.len() + bin_length;
+    writer.write_all(b"glTF")?;
+    writer.write_all(&2u32.to_le_bytes())?;
+    writer.write_all(&(total_length as u32).to_le_bytes())?;
+
+    writer.write_all(&(json.len() as u32).to_le_bytes())?;
+    writer.write_all(b"JSON")?;
+    writer.write_all(&json)?;
+    if buffer.is_empty() {
+        return Ok(());
+    }
+
+    writer.write_all(&(buffer.len() as u32).to_le_bytes())?;
+    writer.write_all(b"BIN\0")?;
+    writer.write_all(&buffer)
+}
+
+/// Non-interleaved positions, normals and colors, in that order.
+fn buffer_data(surface: &IsoSurface) -> Vec<u8> {
+    let mut buffer: Vec<u8> = bytemuck::cast_slice(&surface.positions).to_vec();
+    if let Some(normals) = &surface.normals {
+        buffer.extend_from_slice(bytemuck::cast_slice(normals));
+    }
+    if let Some(colors) = &surface.colors {
+        buffer.extend_from_slice(bytemuck::cast_slice(colors));
+    }
+    buffer
+}
+
+fn document(surface: &IsoSurface, uri: Option<&str>) -> String {
+    const ASSET: &str =
+        "\"asset\": {\"version\": \"2.0\", \"generator\": \"rendering marching cubes\"}";
+
+    let count = surface.positions.len();
+    // accessors and buffers can't be empty, an empty surface is a node without a mesh
+    if count == 0 {
+        return format!(
+            "{{{ASSET}, \"scene\": 0, \"scenes\": [{{\"nodes\": [0]}}], \"nodes\": [{{\"name\": \"isosurface\"}}]}}"
+        );
+    }
+    let (min, max) = surface.positions.iter().fold(
+        (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+        |(min, max), &p| (min.min(p), max.max(p)),
+    );
+
+    let mut attributes = vec!["\"POSITION\": 0".to_string()];
+    let mut accessors = vec![format!(
+        "{{\"bufferView\": 0, \"componentType\": {FLOAT}, \"count\": {count}, \"type\": \"VEC3\", \
+         \"min\": [{}, {}, {}], \"max\": [{}, {}, {}]}}",
+        min.x, min.y, min.z, max.x, max.y, max.z
+    )];
+    let mut views = vec![(0, count * 12)];
+
+    let mut offset = count * 12;
+    if surface.normals.is_some() {
+        attributes.push(format!("\"NORMAL\": {}", accessors.len()));
+        accessors.push(format!(
+            "{{\"bufferView\": {}, \"componentType\": {FLOAT}, \"count\": {count}, \"type\": \"VEC3\"}}",
+            views.len()
+        ));
+        views.push((offset, count * 12));
+        offset += count * 12;
+    }
+    if surface.colors.is_some() {
+        attributes.push(format!("\"COLOR_0\": {}", accessors.len()));
+        accessors.push(format!(
+            "{{\"bufferView\": {}, \"componentType\": {FLOAT}, \"count\": {count}, \"type\": \"VEC4\"}}",
+            views.len()
+        ));
+        views.push((offset, count * 16));
+        offset += count * 16;
+    }
+
+    let views = views
+        .iter()
+        .map(|(offset, length)| {
+            format!(
+                "{{\"buffer\": 0, \"byteOffset\": {offset}, \"byteLength\": {length}, \"target\": {ARRAY_BUFFER}}}"
+            )
+        })
+        .collect::<Vec<_>>();
+    let buffer = match uri {
+        Some(uri) => format!("{{\"byteLength\": {offset}, \"uri\": \"{uri}\"}}"),
+        None => format!("{{\"byteLength\": {offset}}}"),
+    };
+
+    format!(
+        "{{{ASSET}, \"scene\": 0, \"scenes\": [{{\"nodes\": [0]}}], \"nodes\": [{{\"mesh\": 0, \"name\": \"isosurface\"}}], \
+         \"meshes\": [{{\"primitives\": [{{\"attributes\": {{{}}}, \"mode\": {TRIANGLES}}}]}}], \
+         \"accessors\": [{}], \"bufferViews\": [{}], \"buffers\": [{buffer}]}}",
+        attributes.join(", "),
+        accessors.join(", "),
+        views.join(", "),
+    )
+}
+
+fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marching_cubes::export::tests::surface;
+
+    #[test]
+    fn accessors_and_buffer() {
+        let surface = surface();
+        let mut bytes = Vec::new();
+        write_gltf(&surface, &mut bytes).unwrap();
+        let json = String::from_utf8(bytes).unwrap();
+
+        assert!(
+            json.contains(
+                "\"count\": 6, \"type\": \"VEC3\", \"min\": [-1, 0, 0], \"max\": [1, 2, 3]"
+            )
+        );
+        assert!(json.contains("\"POSITION\": 0, \"NORMAL\": 1, \"COLOR_0\": 2"));
+        // positions and normals are 12 bytes, colors 16 bytes per vertex
+        let length: usize = 6 * (12 + 12 + 16);
+        assert!(json.contains(&format!("\"byteLength\": {length}, \"uri\"")));
+        assert!(json.contains("\"byteOffset\": 144, \"byteLength\": 96"));
+
+        let uri = json.split("base64,").nth(1).unwrap();
+        let encoded = &uri[..uri.find('"').unwrap()];
+        assert_eq!(encoded, base64_encode(&buffer_data(&surface)));
+        assert_eq!(encoded.len(), length.div_ceil(3) * 4);
+    }
+
+    #[test]
+    fn glb_chunks_are_aligned() {
+        let mut bytes = Vec::new();
+        write_glb(&surface(), &mut bytes).unwrap();
+
+        let word = |offset: usize| u32::from_le_bytes(bytes[offset..][..4].try_into().unwrap());
+        assert_eq!(&bytes[..4], b"glTF");
+        assert_eq!(word(4), 2);
+        assert_eq!(word(8) as usize, bytes.len());
+
+        let json_length = word(12) as usize;
+        assert_eq!(json_length % 4, 0);
+        assert_eq!(&bytes[16..20], b"JSON");
+        let bin = 20 + json_length;
+        assert_eq!(word(bin), 6 * 40);
+        assert_eq!(&bytes[bin + 4..bin + 8], b"BIN\0");
+    }
+
+    #[test]
+    fn empty_surface_has_no_mesh() {
+        let surface = IsoSurface::default();
+        let mut bytes = Vec::new();
+        write_gltf(&surface, &mut bytes).unwrap();
+        let json = String::from_utf8(bytes).unwrap();
+        assert!(json.contains("\"nodes\": [{\"name\": \"isosurface\"}]"));
+        for key in ["meshes", "accessors", "bufferViews", "buffers", "count"] {
+            assert!(!json.contains(key), "{key} in {json}");
+        }
+
+        let mut bytes = Vec::new();
+        write_glb(&surface, &mut bytes).unwrap();
+        let json_length = u32::from_le_bytes(bytes[12..16].try_into().unwrap()) as usize;
+        // header and JSON chunk only
+        assert_eq!(bytes.len(), 20 + json_length);
+        assert_eq!(
+            u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize,
+            bytes.len()
+        );
+    }
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+    }
+}
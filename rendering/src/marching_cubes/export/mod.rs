@@ -0,0 +1,293 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use bevy_app::{App, Plugin, Update};
+use bevy_color::{ColorToComponents, LinearRgba, Srgba};
+use bevy_ecs::component::Component;
+use bevy_ecs::entity::Entity;
+use bevy_ecs::event::{Event, EventReader};
+use bevy_ecs::observer::Trigger;
+use bevy_ecs::system::{Commands, Query, Res};
+use bevy_render::gpu_readback::{Readback, ReadbackComplete};
+use thiserror::Error;
+
+use super::cpu_mesher::{CpuDensityParam, IsoSurface, polygonize_with_materials};
+use super::{MarchingCubesBuffers, VoxelBrick, VoxelMaterials};
+
+pub mod gltf;
+pub mod obj;
+pub mod ply;
+
+/// Handles [`ExportIsoSurface`] requests.
+pub struct IsoSurfaceExportPlugin;
+
+impl Plugin for IsoSurfaceExportPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ExportIsoSurface>()
+            .add_systems(Update, handle_export_requests);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Obj,
+    Ply,
+    Gltf,
+    Glb,
+}
+
+impl ExportFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "obj" => Some(Self::Obj),
+            "ply" => Some(Self::Ply),
+            "gltf" => Some(Self::Gltf),
+            "glb" => Some(Self::Glb),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ExportError {
+    #[error("could not write isosurface: {0}")]
+    Io(#[from] io::Error),
+    #[error("unknown export format for `{}`, expected obj, ply, gltf or glb", .0.display())]
+    UnknownFormat(PathBuf),
+}
+
+impl IsoSurface {
+    pub fn write(&self, writer: &mut impl Write, format: ExportFormat) -> io::Result<()> {
+        match format {
+            ExportFormat::Obj => obj::write_obj(self, writer),
+            ExportFormat::Ply => ply::write_ply(self, writer),
+            ExportFormat::Gltf => gltf::write_gltf(self, writer),
+            ExportFormat::Glb => gltf::write_glb(self, writer),
+        }
+    }
+
+    /// Writes the surface to `path`, the format is picked from the extension.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ExportError> {
+        let path = path.as_ref();
+        let format =
+            ExportFormat::from_path(path).ok_or_else(|| ExportError::UnknownFormat(path.into()))?;
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer, format)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Where [`ExportIsoSurface`] takes the surface from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExportSource {
+    /// Reads back the output of the compute stage, positions and material colors.
+    #[default]
+    Gpu,
+    /// Runs the CPU mesher on the current [`VoxelVolume`], with gradient normals and material
+    /// colors.
+    Cpu,
+}
+
+/// Writes the current isosurface to `path`, the format is picked from the extension.
+///
+/// GPU exports complete a few frames later, once the readback is done.
+#[derive(Event, Clone, Debug)]
+pub struct ExportIsoSurface {
+    pub path: PathBuf,
+    pub source: ExportSource,
+}
+
+impl ExportIsoSurface {
+    pub fn new(path: impl Into<PathBuf>, source: ExportSource) -> Self {
+        Self {
+            path: path.into(),
+            source,
+        }
+    }
+}
+
+/// GPU export waiting for the vertex and brick readbacks of the same frame.
+#[derive(Component)]
+struct PendingExport {
+    path: PathBuf,
+    vertices: Option<Vec<u8>>,
+    bricks: Option<Vec<u8>>,
+}
+
+/// Readback of one of the buffers of the [`PendingExport`] entity.
+#[derive(Component, Clone, Copy)]
+enum ExportReadback {
+    Vertices(Entity),
+    Bricks(Entity),
+}
+
+fn handle_export_requests(
+    mut commands: Commands,
+    mut requests: EventReader<ExportIsoSurface>,
+    density_param: CpuDensityParam,
+    marching_cubes_buffers: Res<MarchingCubesBuffers>,
+    materials: Res<VoxelMaterials>,
+) {
+    for request in requests.read() {
+        match request.source {
+            ExportSource::Gpu => {
+                let export = commands
+                    .spawn(PendingExport {
+                        path: request.path.clone(),
+                        vertices: None,
+                        bricks: None,
+                    })
+                    .id();
+                // the bricks hold the vertex count, the rest of the vertex buffer is stale
+                commands
+                    .spawn((
+                        Readback::buffer(marching_cubes_buffers.vertices.clone()),
+                        ExportReadback::Vertices(export),
+                    ))
+                    .observe(write_gpu_readback);
+                commands
+                    .spawn((
+                        Readback::buffer(marching_cubes_buffers.bricks.clone()),
+                        ExportReadback::Bricks(export),
+                    ))
+                    .observe(write_gpu_readback);
+            }
+            ExportSource::Cpu => {
+                let voxel_volume = &density_param.voxel_volume;
+                let density = density_param.density();
+                let mut surface = polygonize_with_materials(
+                    voxel_volume,
+                    |pos| density.field(pos),
+                    |pos| density.material(pos),
+                );
+                surface.compute_gradient_normals(
+                    |pos| density.field(pos),
+                    voxel_volume.voxel_size * 0.5,
+                );
+                surface.compute_ambient_occlusion(
+                    |pos| density.field(pos),
+                    &voxel_volume.ambient_occlusion,
+                    voxel_volume.voxel_size,
+                );
+                surface.color_materials(&materials);
+                save_and_log(&surface, &request.path);
+            }
+        }
+    }
+}
+
+fn write_gpu_readback(
+    trigger: Trigger<ReadbackComplete>,
+    mut commands: Commands,
+    readbacks: Query<&ExportReadback>,
+    mut exports: Query<&mut PendingExport>,
+    materials: Res<VoxelMaterials>,
+) {
+    let entity = trigger.target();
+    let Ok(&readback) = readbacks.get(entity) else {
+        return;
+    };
+    // readbacks repeat every frame until removed
+    commands.entity(entity).despawn();
+
+    let (ExportReadback::Vertices(export) | ExportReadback::Bricks(export)) = readback;
+    let Ok(mut pending) = exports.get_mut(export) else {
+        return;
+    };
+    let bytes = Some(trigger.event().0.clone());
+    match readback {
+        ExportReadback::Vertices(_) => pending.vertices = bytes,
+        ExportReadback::Bricks(_) => pending.bricks = bytes,
+    }
+    let (Some(vertices), Some(bricks)) = (&pending.vertices, &pending.bricks) else {
+        return;
+    };
+
+    // vertices are packed from the start of the buffer, brick by brick
+    let bricks: Vec<VoxelBrick> = bytemuck::pod_collect_to_vec(bricks);
+    let vertex_count = bricks
+        .iter()
+        .map(|brick| brick.first_vertex + brick.vertex_count)
+        .max()
+        .unwrap_or(0);
+
+    let mut surface = IsoSurface::from_vertex_buffer(vertices, vertex_count as usize);
+    surface.color_materials(&materials);
+    save_and_log(&surface, &pending.path);
+    commands.entity(export).despawn();
+}
+
+fn save_and_log(surface: &IsoSurface, path: &Path) {
+    match surface.save(path) {
+        Ok(()) => tracing::info!(
+            "Exported {} triangles to {}",
+            surface.triangle_count(),
+            path.display()
+        ),
+        Err(err) => tracing::error!("Exporting isosurface to {}: {err}", path.display()),
+    }
+}
+
+fn to_srgb(color: [f32; 4]) -> [f32; 4] {
+    Srgba::from(LinearRgba::from_f32_array(color)).to_f32_array()
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_math::Vec3;
+
+    use super::*;
+
+    /// Two triangles with normals and colors, the second one degenerate.
+    pub(super) fn surface() -> IsoSurface {
+        let positions = vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 2.0, 0.0),
+            Vec3::new(-1.0, 0.0, 3.0),
+            Vec3::new(-1.0, 0.0, 3.0),
+            Vec3::new(-1.0, 0.0, 3.0),
+        ];
+        IsoSurface {
+            normals: Some(vec![Vec3::Z; positions.len()]),
+            colors: Some(vec![[1.0, 0.0, 0.0, 1.0]; positions.len()]),
+            positions,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn vertex_buffer_is_trimmed_by_the_vertex_count() {
+        let vertices: Vec<[f32; 12]> = (0..9)
+            .map(|i| {
+                // the degenerate triangle at 3..6 is kept
+                let x = if (3..6).contains(&i) { 1.0 } else { i as f32 };
+                [x, 0.0, x * x, 0.5, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0]
+            })
+            .collect();
+        let surface = IsoSurface::from_vertex_buffer(bytemuck::cast_slice(&vertices), 6);
+
+        assert_eq!(surface.triangle_count(), 2);
+        assert_eq!(surface.positions[4], Vec3::new(1.0, 0.0, 1.0));
+        assert_eq!(surface.occlusion.as_deref(), Some(&[0.5; 6][..]));
+        assert!(surface.normals.unwrap().iter().all(|&n| n == Vec3::Y));
+    }
+
+    #[test]
+    fn format_from_extension() {
+        assert_eq!(
+            ExportFormat::from_path(Path::new("mesh.PLY")),
+            Some(ExportFormat::Ply)
+        );
+        assert_eq!(
+            ExportFormat::from_path(Path::new("a/mesh.glb")),
+            Some(ExportFormat::Glb)
+        );
+        assert_eq!(ExportFormat::from_path(Path::new("mesh.stl")), None);
+        assert_eq!(ExportFormat::from_path(Path::new("mesh")), None);
+    }
+}
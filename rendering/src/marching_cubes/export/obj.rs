@@ -0,0 +1,89 @@
+use std::io::{self, Write};
+
+use super::to_srgb;
+use crate::marching_cubes::cpu_mesher::IsoSurface;
+
+/// Writes a Wavefront OBJ, vertex colors use the common `v x y z r g b` extension.
+pub fn write_obj(surface: &IsoSurface, writer: &mut impl Write) -> io::Result<()> {
+    writeln!(writer, "# marching cubes isosurface")?;
+    writeln!(writer, "o isosurface")?;
+
+    for (i, position) in surface.positions.iter().enumerate() {
+        write!(writer, "v {} {} {}", position.x, position.y, position.z)?;
+        if let Some(colors) = &surface.colors {
+            let [r, g, b, _] = to_srgb(colors[i]);
+            write!(writer, " {r} {g} {b}")?;
+        }
+        writeln!(writer)?;
+    }
+
+    if let Some(normals) = &surface.normals {
+        for normal in normals {
+            writeln!(writer, "vn {} {} {}", normal.x, normal.y, normal.z)?;
+        }
+    }
+
+    // OBJ indices are 1-based
+    for triangle in 0..surface.triangle_count() {
+        let [a, b, c] = [1, 2, 3].map(|i| 3 * triangle + i);
+        if surface.normals.is_some() {
+            writeln!(writer, "f {a}//{a} {b}//{b} {c}//{c}")?;
+        } else {
+            writeln!(writer, "f {a} {b} {c}")?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marching_cubes::export::tests::surface;
+
+    fn lines(surface: &IsoSurface) -> Vec<String> {
+        let mut bytes = Vec::new();
+        write_obj(surface, &mut bytes).unwrap();
+        String::from_utf8(bytes)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    fn count(lines: &[String], prefix: &str) -> usize {
+        lines.iter().filter(|line| line.starts_with(prefix)).count()
+    }
+
+    #[test]
+    fn counts_and_indices() {
+        let lines = lines(&surface());
+        assert_eq!(count(&lines, "v "), 6);
+        assert_eq!(count(&lines, "vn "), 6);
+        assert_eq!(count(&lines, "f "), 2);
+        // colors are written in sRGB after the position
+        let vertex: Vec<f32> = lines[3][2..]
+            .split(' ')
+            .map(|value| value.parse().unwrap())
+            .collect();
+        let expected = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+        assert!(
+            vertex
+                .iter()
+                .zip(expected)
+                .all(|(v, e)| (v - e).abs() < 1e-5)
+        );
+        assert_eq!(lines.last().unwrap(), "f 4//4 5//5 6//6");
+    }
+
+    #[test]
+    fn faces_without_normals() {
+        let mut surface = surface();
+        surface.normals = None;
+        surface.colors = None;
+        let lines = lines(&surface);
+        assert_eq!(count(&lines, "vn "), 0);
+        assert!(lines.contains(&"v 0 2 0".to_string()));
+        assert_eq!(lines.last().unwrap(), "f 4 5 6");
+    }
+}
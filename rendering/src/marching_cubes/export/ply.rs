@@ -0,0 +1,127 @@
+use std::io::{self, Write};
+
+use super::to_srgb;
+use crate::marching_cubes::cpu_mesher::IsoSurface;
+
+/// Writes a binary little endian PLY.
+pub fn write_ply(surface: &IsoSurface, writer: &mut impl Write) -> io::Result<()> {
+    writeln!(writer, "ply")?;
+    writeln!(writer, "format binary_little_endian 1.0")?;
+    writeln!(writer, "comment marching cubes isosurface")?;
+    writeln!(writer, "element vertex {}", surface.positions.len())?;
+    for axis in ["x", "y", "z"] {
+        writeln!(writer, "property float {axis}")?;
+    }
+    if surface.normals.is_some() {
+        for axis in ["nx", "ny", "nz"] {
+            writeln!(writer, "property float {axis}")?;
+        }
+    }
+    if surface.colors.is_some() {
+        for channel in ["red", "green", "blue", "alpha"] {
+            writeln!(writer, "property uchar {channel}")?;
+        }
+    }
+    writeln!(writer, "element face {}", surface.triangle_count())?;
+    writeln!(writer, "property list uchar uint vertex_indices")?;
+    writeln!(writer, "end_header")?;
+
+    for (i, position) in surface.positions.iter().enumerate() {
+        for value in position.to_array() {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        if let Some(normals) = &surface.normals {
+            for value in normals[i].to_array() {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+        if let Some(colors) = &surface.colors {
+            let color = to_srgb(colors[i]).map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+            writer.write_all(&color)?;
+        }
+    }
+
+    for triangle in 0..surface.triangle_count() as u32 {
+        writer.write_all(&[3])?;
+        for i in 0..3 {
+            writer.write_all(&(3 * triangle + i).to_le_bytes())?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marching_cubes::export::tests::surface;
+
+    const END_HEADER: &[u8] = b"end_header\n";
+
+    fn split(bytes: &[u8]) -> (&str, &[u8]) {
+        let end = bytes
+            .windows(END_HEADER.len())
+            .position(|window| window == END_HEADER)
+            .unwrap()
+            + END_HEADER.len();
+        (str::from_utf8(&bytes[..end]).unwrap(), &bytes[end..])
+    }
+
+    #[test]
+    fn header_and_body() {
+        let mut bytes = Vec::new();
+        write_ply(&surface(), &mut bytes).unwrap();
+        let (header, body) = split(&bytes);
+
+        assert_eq!(
+            header.lines().collect::<Vec<_>>(),
+            [
+                "ply",
+                "format binary_little_endian 1.0",
+                "comment marching cubes isosurface",
+                "element vertex 6",
+                "property float x",
+                "property float y",
+                "property float z",
+                "property float nx",
+                "property float ny",
+                "property float nz",
+                "property uchar red",
+                "property uchar green",
+                "property uchar blue",
+                "property uchar alpha",
+                "element face 2",
+                "property list uchar uint vertex_indices",
+                "end_header",
+            ]
+        );
+
+        // 6 floats and 4 color bytes per vertex, a count and 3 indices per face
+        let vertex_size = 6 * 4 + 4;
+        assert_eq!(body.len(), 6 * vertex_size + 2 * 13);
+        let y: [u8; 4] = body[2 * vertex_size + 4..][..4].try_into().unwrap();
+        assert_eq!(f32::from_le_bytes(y), 2.0);
+        assert_eq!(&body[24..28], &[255, 0, 0, 255]);
+
+        let last_face = &body[body.len() - 13..];
+        assert_eq!(last_face[0], 3);
+        let indices: Vec<u32> = last_face[1..]
+            .chunks_exact(4)
+            .map(|index| u32::from_le_bytes(index.try_into().unwrap()))
+            .collect();
+        assert_eq!(indices, [3, 4, 5]);
+    }
+
+    #[test]
+    fn positions_only() {
+        let mut surface = surface();
+        surface.normals = None;
+        surface.colors = None;
+        let mut bytes = Vec::new();
+        write_ply(&surface, &mut bytes).unwrap();
+        let (header, body) = split(&bytes);
+
+        assert!(!header.contains("nx") && !header.contains("red"));
+        assert_eq!(body.len(), 6 * 12 + 2 * 13);
+    }
+}
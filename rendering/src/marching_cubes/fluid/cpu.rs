@@ -0,0 +1,355 @@
+use core::f32::consts::PI;
+
+use bevy_math::bounding::Aabb3d;
+use bevy_math::{IVec3, UVec3, Vec3};
+
+use super::{MAX_PER_CELL, SphSettings, grid_dims};
+use crate::marching_cubes::VoxelVolume;
+use crate::marching_cubes::volume::ScalarVolume;
+
+/// Particle state of a fluid, also a CPU reference of the `sph.wgsl` simulation.
+///
+/// Meant for small particle counts and headless tests, neighbors are visited in the same
+/// order as on the GPU so both stay close, not bit identical.
+#[derive(Clone, Debug, Default)]
+pub struct FluidParticles {
+    pub positions: Vec<Vec3>,
+    pub velocities: Vec<Vec3>,
+}
+
+impl FluidParticles {
+    /// Particles at rest on a regular grid filling `region`.
+    pub fn block(region: Aabb3d, spacing: f32) -> Self {
+        let min = Vec3::from(region.min);
+        let counts = (Vec3::from(region.max - region.min) / spacing)
+            .floor()
+            .as_uvec3()
+            + UVec3::ONE;
+
+        let mut positions = Vec::with_capacity(counts.element_product() as usize);
+        for z in 0..counts.z {
+            for y in 0..counts.y {
+                for x in 0..counts.x {
+                    positions.push(min + UVec3::new(x, y, z).as_vec3() * spacing);
+                }
+            }
+        }
+
+        Self {
+            velocities: vec![Vec3::ZERO; positions.len()],
+            positions,
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Advances the simulation by one [`SphSettings::time_step`] inside `bounds`.
+    pub fn step(&mut self, settings: &SphSettings, bounds: &Aabb3d) {
+        let sph = Sph::new(settings, bounds, &self.positions);
+
+        let densities: Vec<f32> = self
+            .positions
+            .iter()
+            .map(|&pos| sph.density_at(&self.positions, pos))
+            .collect();
+
+        let accelerations: Vec<Vec3> = (0..self.len())
+            .map(|i| sph.acceleration(i, &self.positions, &self.velocities, &densities))
+            .collect();
+
+        let (min, max) = (Vec3::from(bounds.min), Vec3::from(bounds.max));
+        let time_step = settings.time_step;
+        for ((pos, vel), acceleration) in self
+            .positions
+            .iter_mut()
+            .zip(&mut self.velocities)
+            .zip(accelerations)
+        {
+            *vel += acceleration * time_step;
+            *pos += *vel * time_step;
+
+            // reflect off the volume bounds
+            let outside = pos.cmplt(min) | pos.cmpgt(max);
+            *vel = Vec3::select(outside, -*vel * settings.restitution, *vel);
+            *pos = pos.clamp(min, max);
+        }
+    }
+
+    /// Fluid density on the corners of the `volume` voxels, normalized by the rest density.
+    ///
+    /// Same values as the GPU splat, so marching `volume.isovalue` gives the same surface.
+    pub fn splat(&self, settings: &SphSettings, volume: &VoxelVolume) -> ScalarVolume {
+        let sph = Sph::new(settings, &volume.aabb, &self.positions);
+        let dims = volume.count_dims() + UVec3::ONE;
+        let origin = Vec3::from(volume.aabb.min);
+
+        let mut values = Vec::with_capacity(dims.element_product() as usize);
+        for z in 0..dims.z {
+            for y in 0..dims.y {
+                for x in 0..dims.x {
+                    let pos = origin + UVec3::new(x, y, z).as_vec3() * volume.voxel_size;
+                    values.push(sph.density_at(&self.positions, pos) / settings.rest_density);
+                }
+            }
+        }
+
+        let mut splat = ScalarVolume::new(dims, values);
+        splat.spacing = Vec3::splat(volume.voxel_size);
+        splat.origin = origin;
+        splat
+    }
+}
+
+/// Kernels and neighbor grid of one step.
+struct Sph<'a> {
+    settings: &'a SphSettings,
+    min: Vec3,
+    grid_dims: UVec3,
+    /// Lowest [`MAX_PER_CELL`] particle indices per cell, ascending like `insert_particles`.
+    cells: Vec<Vec<u32>>,
+}
+
+impl<'a> Sph<'a> {
+    fn new(settings: &'a SphSettings, bounds: &Aabb3d, positions: &[Vec3]) -> Self {
+        let grid_dims = grid_dims(bounds, settings.smoothing_radius);
+        let mut sph = Self {
+            settings,
+            min: bounds.min.into(),
+            grid_dims,
+            cells: vec![Vec::new(); grid_dims.element_product() as usize],
+        };
+
+        for (i, &pos) in positions.iter().enumerate() {
+            let cell = sph.cell_index(sph.cell_coord(pos));
+            if sph.cells[cell].len() < MAX_PER_CELL {
+                sph.cells[cell].push(i as u32);
+            }
+        }
+        sph
+    }
+
+    fn cell_coord(&self, pos: Vec3) -> IVec3 {
+        ((pos - self.min) / self.settings.smoothing_radius)
+            .floor()
+            .as_ivec3()
+            .clamp(IVec3::ZERO, self.grid_dims.as_ivec3() - 1)
+    }
+
+    fn cell_index(&self, coord: IVec3) -> usize {
+        let c = coord.as_uvec3();
+        (c.x + self.grid_dims.x * (c.y + self.grid_dims.y * c.z)) as usize
+    }
+
+    /// Neighbor particles in z, y, x cell order then by index, as in `sph.wgsl`.
+    fn neighbors(&self, pos: Vec3) -> impl Iterator<Item = usize> + '_ {
+        let center = self.cell_coord(pos);
+        let dims = self.grid_dims.as_ivec3();
+        (-1..=1)
+            .flat_map(|z| (-1..=1).flat_map(move |y| (-1..=1).map(move |x| IVec3::new(x, y, z))))
+            .map(move |offset| center + offset)
+            .filter(move |coord| coord.cmpge(IVec3::ZERO).all() && coord.cmplt(dims).all())
+            .flat_map(|coord| self.cells[self.cell_index(coord)].iter())
+            .map(|&j| j as usize)
+    }
+
+    fn poly6(&self, r2: f32) -> f32 {
+        let h = self.settings.smoothing_radius;
+        let h2 = h * h;
+        if r2 >= h2 {
+            return 0.0;
+        }
+        let d = h2 - r2;
+        315.0 / (64.0 * PI * h.powi(9)) * d * d * d
+    }
+
+    fn spiky_gradient(&self, offset: Vec3, r: f32) -> Vec3 {
+        let h = self.settings.smoothing_radius;
+        if r >= h || r <= 0.0 {
+            return Vec3::ZERO;
+        }
+        let d = h - r;
+        -45.0 / (PI * h.powi(6)) * d * d * (offset / r)
+    }
+
+    fn viscosity_laplacian(&self, r: f32) -> f32 {
+        let h = self.settings.smoothing_radius;
+        if r >= h {
+            return 0.0;
+        }
+        45.0 / (PI * h.powi(6)) * (h - r)
+    }
+
+    fn pressure(&self, density: f32) -> f32 {
+        self.settings.stiffness * (density - self.settings.rest_density).max(0.0)
+    }
+
+    fn density_at(&self, positions: &[Vec3], pos: Vec3) -> f32 {
+        self.neighbors(pos)
+            .map(|j| self.settings.particle_mass * self.poly6(pos.distance_squared(positions[j])))
+            .sum()
+    }
+
+    fn acceleration(
+        &self,
+        i: usize,
+        positions: &[Vec3],
+        velocities: &[Vec3],
+        densities: &[f32],
+    ) -> Vec3 {
+        let (pos, vel, density) = (positions[i], velocities[i], densities[i]);
+        let pressure_i = self.pressure(density);
+        let mass = self.settings.particle_mass;
+
+        let mut pressure_force = Vec3::ZERO;
+        let mut viscosity_force = Vec3::ZERO;
+        for j in self.neighbors(pos).filter(|&j| j != i) {
+            let offset = pos - positions[j];
+            let r = offset.length();
+            let shared_pressure = (pressure_i + self.pressure(densities[j])) / (2.0 * densities[j]);
+            pressure_force -= mass * shared_pressure * self.spiky_gradient(offset, r);
+            viscosity_force +=
+                mass * (velocities[j] - vel) / densities[j] * self.viscosity_laplacian(r);
+        }
+
+        (pressure_force + self.settings.viscosity * viscosity_force) / density
+            + self.settings.gravity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bounds() -> Aabb3d {
+        Aabb3d::new(Vec3::ZERO, Vec3::splat(0.5))
+    }
+
+    /// A block of fluid hanging in the middle of the bounds.
+    fn dropped_block(settings: &SphSettings) -> FluidParticles {
+        let region = Aabb3d::new(Vec3::new(0.0, 0.2, 0.0), Vec3::splat(0.15));
+        FluidParticles::block(region, settings.particle_spacing())
+    }
+
+    fn volume() -> VoxelVolume {
+        VoxelVolume {
+            aabb: bounds(),
+            voxel_size: 0.025,
+            ..Default::default()
+        }
+    }
+
+    /// Mass of the splatted density, the kernel integrates to one.
+    fn splat_mass(splat: &ScalarVolume, settings: &SphSettings) -> f32 {
+        let voxel = splat.spacing.element_product();
+        splat.values.iter().sum::<f32>() * settings.rest_density * voxel
+    }
+
+    #[test]
+    fn dropped_block_stays_inside_the_bounds() {
+        let settings = SphSettings::default();
+        let mut particles = dropped_block(&settings);
+        let count = particles.len();
+        let (min, max) = (Vec3::from(bounds().min), Vec3::from(bounds().max));
+
+        // long enough to hit the floor and splash
+        for _ in 0..250 {
+            particles.step(&settings, &bounds());
+        }
+
+        assert_eq!(particles.len(), count);
+        assert_eq!(particles.velocities.len(), count);
+        for pos in &particles.positions {
+            assert!(pos.is_finite());
+            assert!(
+                pos.cmpge(min).all() && pos.cmple(max).all(),
+                "{pos} escaped"
+            );
+        }
+        let lowest = particles
+            .positions
+            .iter()
+            .map(|pos| pos.y)
+            .fold(f32::MAX, f32::min);
+        assert!(
+            lowest < min.y + 0.05,
+            "the block did not fall, lowest {lowest}"
+        );
+    }
+
+    #[test]
+    fn mass_is_conserved() {
+        let settings = SphSettings::default();
+        let mut particles = dropped_block(&settings);
+        let mass = particles.len() as f32 * settings.particle_mass;
+
+        let before = splat_mass(&particles.splat(&settings, &volume()), &settings);
+        assert!((before - mass).abs() < 0.02 * mass, "{before} vs {mass}");
+
+        // still falling freely, the kernels do not reach the bounds yet
+        for _ in 0..20 {
+            particles.step(&settings, &bounds());
+        }
+        let after = splat_mass(&particles.splat(&settings, &volume()), &settings);
+        assert!((after - before).abs() < 0.02 * mass, "{after} vs {before}");
+    }
+
+    #[test]
+    fn splat_covers_the_particles() {
+        let settings = SphSettings::default();
+        let particles = dropped_block(&settings);
+        let volume = volume();
+        let splat = particles.splat(&settings, &volume);
+        assert_eq!(splat.dims, volume.count_dims() + UVec3::ONE);
+
+        let h = settings.smoothing_radius;
+        for z in 0..splat.dims.z {
+            for y in 0..splat.dims.y {
+                for x in 0..splat.dims.x {
+                    let coord = UVec3::new(x, y, z);
+                    let pos = splat.origin + coord.as_vec3() * splat.spacing;
+                    let nearest = particles
+                        .positions
+                        .iter()
+                        .map(|particle| particle.distance(pos))
+                        .fold(f32::MAX, f32::min);
+                    // poly6 is positive exactly inside the smoothing radius
+                    let value = splat.get(coord);
+                    assert_eq!(
+                        value > 0.0,
+                        nearest < h,
+                        "{value} at {pos}, nearest {nearest}"
+                    );
+                }
+            }
+        }
+
+        // the inside of the block is about the rest density
+        let center = splat.sample(Vec3::new(0.0, 0.2, 0.0));
+        assert!((0.8..1.2).contains(&center), "{center}");
+    }
+
+    #[test]
+    fn full_cells_keep_the_lowest_indices() {
+        let settings = SphSettings::default();
+        // twice the capacity in the same cell, listed from the highest index down
+        let positions = vec![Vec3::splat(0.01); 2 * MAX_PER_CELL];
+        let sph = Sph::new(&settings, &bounds(), &positions);
+
+        let cell = sph.cell_index(sph.cell_coord(positions[0]));
+        let expected: Vec<u32> = (0..MAX_PER_CELL as u32).collect();
+        assert_eq!(sph.cells[cell], expected);
+        assert_eq!(
+            sph.neighbors(positions[0]).count(),
+            MAX_PER_CELL,
+            "extra particles are not neighbors"
+        );
+    }
+}
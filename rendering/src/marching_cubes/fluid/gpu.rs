@@ -0,0 +1,361 @@
+use bevy_app::{App, Plugin};
+use bevy_asset::{AssetId, Handle, load_internal_asset, weak_handle};
+use bevy_ecs::resource::Resource;
+use bevy_ecs::schedule::IntoScheduleConfigs;
+use bevy_ecs::system::{Commands, Res, ResMut};
+use bevy_ecs::world::{FromWorld, World};
+use bevy_math::{UVec3, Vec3};
+use bevy_render::render_asset::RenderAssets;
+use bevy_render::render_graph::{self, RenderGraph, RenderLabel};
+use bevy_render::render_resource::binding_types::{storage_buffer_sized, uniform_buffer};
+use bevy_render::render_resource::{
+    BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, CachedComputePipelineId,
+    CachedPipelineState, ComputePassDescriptor, ComputePipelineDescriptor, PipelineCache, Shader,
+    ShaderStages, ShaderType, UniformBuffer,
+};
+use bevy_render::renderer::{RenderContext, RenderDevice, RenderQueue};
+use bevy_render::storage::{GpuShaderStorageBuffer, ShaderStorageBuffer};
+use bevy_render::{Extract, ExtractSchedule, Render, RenderApp, RenderSet};
+
+use super::{FluidBuffers, SphFluid};
+use crate::marching_cubes::compute_stage::MarchingCubesComputeLabel;
+use crate::marching_cubes::{MarchingCubesBuffers, VoxelVolume};
+
+pub const SPH_SHADER_HANDLE: Handle<Shader> = weak_handle!("c4e1f7a2-93b6-4d58-8e0a-5b7d2f6c1a94");
+
+const WORKGROUP_SIZE: u32 = 64;
+
+// dispatched in this order every step, `splat_density` once per frame after the steps
+const STEP_ENTRY_POINTS: [&str; 5] = [
+    "clear_grid",
+    "insert_particles",
+    "compute_density",
+    "compute_forces",
+    "integrate",
+];
+const SPLAT_ENTRY_POINT: &str = "splat_density";
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+struct SphFluidLabel;
+
+pub struct GpuFluidPlugin;
+
+impl Plugin for GpuFluidPlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(app, SPH_SHADER_HANDLE, "sph.wgsl", Shader::from_wgsl);
+
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app.init_resource::<FluidUniformBuffer>();
+        render_app.add_systems(ExtractSchedule, extract_fluid);
+        render_app.add_systems(
+            Render,
+            (
+                prepare_fluid_uniform_buffer.in_set(RenderSet::PrepareResources),
+                prepare_fluid_bind_group.in_set(RenderSet::PrepareBindGroups),
+            ),
+        );
+
+        let mut render_graph = render_app.world_mut().resource_mut::<RenderGraph>();
+        render_graph.add_node(SphFluidLabel, SphFluidNode::default());
+        // the compute stage reads the splatted density
+        render_graph.add_node_edge(SphFluidLabel, MarchingCubesComputeLabel);
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app.init_resource::<SphFluidPipeline>();
+    }
+}
+
+/// `FluidParams` in `sph.wgsl`.
+#[derive(ShaderType, Clone, Default)]
+struct FluidUniform {
+    bounds_min: Vec3,
+    smoothing_radius: f32,
+    bounds_max: Vec3,
+    particle_mass: f32,
+    grid_dims: UVec3,
+    particle_count: u32,
+    gravity: Vec3,
+    rest_density: f32,
+    splat_dims: UVec3,
+    stiffness: f32,
+    viscosity: f32,
+    time_step: f32,
+    restitution: f32,
+    voxel_size: f32,
+}
+
+#[derive(Resource)]
+struct ExtractedFluid {
+    uniform: FluidUniform,
+    buffers: [AssetId<ShaderStorageBuffer>; 7],
+    steps: u32,
+}
+
+fn extract_fluid(
+    mut commands: Commands,
+    fluid: Extract<Option<Res<SphFluid>>>,
+    fluid_buffers: Extract<Option<Res<FluidBuffers>>>,
+    voxel_volume: Extract<Res<VoxelVolume>>,
+    marching_cubes_buffers: Extract<Res<MarchingCubesBuffers>>,
+) {
+    let (Some(fluid), Some(fluid_buffers)) = (&*fluid, &*fluid_buffers) else {
+        commands.remove_resource::<ExtractedFluid>();
+        return;
+    };
+
+    let settings = &fluid.settings;
+    let uniform = FluidUniform {
+        bounds_min: voxel_volume.aabb.min.into(),
+        smoothing_radius: settings.smoothing_radius,
+        bounds_max: voxel_volume.aabb.max.into(),
+        particle_mass: settings.particle_mass,
+        grid_dims: fluid_buffers.grid_dims,
+        particle_count: fluid_buffers.particle_count,
+        gravity: settings.gravity,
+        rest_density: settings.rest_density,
+        splat_dims: voxel_volume.count_dims() + UVec3::ONE,
+        stiffness: settings.stiffness,
+        viscosity: settings.viscosity,
+        time_step: settings.time_step,
+        restitution: settings.restitution,
+        voxel_size: voxel_volume.voxel_size,
+    };
+
+    commands.insert_resource(ExtractedFluid {
+        uniform,
+        buffers: [
+            fluid_buffers.positions.id(),
+            fluid_buffers.velocities.id(),
+            fluid_buffers.accelerations.id(),
+            fluid_buffers.particle_densities.id(),
+            fluid_buffers.cell_counts.id(),
+            fluid_buffers.cell_entries.id(),
+            marching_cubes_buffers.densities.id(),
+        ],
+        steps: fluid_buffers.steps,
+    });
+}
+
+#[derive(Resource, Default)]
+struct FluidUniformBuffer {
+    buffer: UniformBuffer<FluidUniform>,
+}
+
+fn prepare_fluid_uniform_buffer(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut fluid_uniform_buffer: ResMut<FluidUniformBuffer>,
+    fluid: Option<Res<ExtractedFluid>>,
+) {
+    let Some(fluid) = fluid else {
+        return;
+    };
+
+    fluid_uniform_buffer.buffer.set(fluid.uniform.clone());
+    fluid_uniform_buffer
+        .buffer
+        .write_buffer(&render_device, &render_queue);
+}
+
+/// Bind group and work of this frame.
+#[derive(Resource)]
+struct SphFluidBindGroup {
+    bind_group: BindGroup,
+    particle_count: u32,
+    cell_count: u32,
+    splat_count: u32,
+    steps: u32,
+}
+
+fn prepare_fluid_bind_group(
+    mut commands: Commands,
+    pipeline: Res<SphFluidPipeline>,
+    fluid: Option<Res<ExtractedFluid>>,
+    fluid_uniform_buffer: Res<FluidUniformBuffer>,
+    gpu_buffers: Res<RenderAssets<GpuShaderStorageBuffer>>,
+    render_device: Res<RenderDevice>,
+) {
+    commands.remove_resource::<SphFluidBindGroup>();
+
+    let Some(fluid) = fluid else {
+        return;
+    };
+    let (Some(uniform), Some(buffers)) = (
+        fluid_uniform_buffer.buffer.binding(),
+        fluid
+            .buffers
+            .iter()
+            .map(|id| gpu_buffers.get(*id))
+            .collect::<Option<Vec<_>>>(),
+    ) else {
+        return;
+    };
+
+    let bind_group = render_device.create_bind_group(
+        Some("sph_fluid_bind_group"),
+        &pipeline.bind_group_layout,
+        &BindGroupEntries::sequential((
+            uniform,
+            buffers[0].buffer.as_entire_buffer_binding(),
+            buffers[1].buffer.as_entire_buffer_binding(),
+            buffers[2].buffer.as_entire_buffer_binding(),
+            buffers[3].buffer.as_entire_buffer_binding(),
+            buffers[4].buffer.as_entire_buffer_binding(),
+            buffers[5].buffer.as_entire_buffer_binding(),
+            buffers[6].buffer.as_entire_buffer_binding(),
+        )),
+    );
+
+    let uniform = &fluid.uniform;
+    commands.insert_resource(SphFluidBindGroup {
+        bind_group,
+        particle_count: uniform.particle_count,
+        cell_count: uniform.grid_dims.element_product(),
+        splat_count: uniform.splat_dims.element_product(),
+        steps: fluid.steps,
+    });
+}
+
+#[derive(Resource)]
+struct SphFluidPipeline {
+    bind_group_layout: BindGroupLayout,
+    step_pipeline_ids: [CachedComputePipelineId; 5],
+    splat_pipeline_id: CachedComputePipelineId,
+}
+
+impl FromWorld for SphFluidPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let bind_group_layout = render_device.create_bind_group_layout(
+            "sph_fluid_bind_group",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::COMPUTE,
+                (
+                    uniform_buffer::<FluidUniform>(false),
+                    storage_buffer_sized(false, None),
+                    storage_buffer_sized(false, None),
+                    storage_buffer_sized(false, None),
+                    storage_buffer_sized(false, None),
+                    storage_buffer_sized(false, None),
+                    storage_buffer_sized(false, None),
+                    storage_buffer_sized(false, None),
+                ),
+            ),
+        );
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let queue = |entry_point: &'static str| {
+            pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                label: Some(format!("sph_fluid_{entry_point}_pipeline").into()),
+                layout: vec![bind_group_layout.clone()],
+                push_constant_ranges: Vec::new(),
+                shader: SPH_SHADER_HANDLE,
+                shader_defs: vec![],
+                entry_point: entry_point.into(),
+                zero_initialize_workgroup_memory: false,
+            })
+        };
+
+        Self {
+            step_pipeline_ids: STEP_ENTRY_POINTS.map(queue),
+            splat_pipeline_id: queue(SPLAT_ENTRY_POINT),
+            bind_group_layout,
+        }
+    }
+}
+
+#[derive(Default)]
+struct SphFluidNode {
+    pipeline_is_ready: bool,
+}
+
+impl render_graph::Node for SphFluidNode {
+    fn update(&mut self, world: &mut World) {
+        let pipeline = world.resource::<SphFluidPipeline>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+
+        if !self.pipeline_is_ready {
+            let mut all_ready = true;
+            for id in pipeline
+                .step_pipeline_ids
+                .iter()
+                .chain([&pipeline.splat_pipeline_id])
+            {
+                match pipeline_cache.get_compute_pipeline_state(*id) {
+                    CachedPipelineState::Ok(_) => {}
+                    CachedPipelineState::Err(err) => {
+                        panic!("Initializing sph.wgsl:\n{err}")
+                    }
+                    _ => all_ready = false,
+                }
+            }
+            self.pipeline_is_ready = all_ready;
+        }
+    }
+
+    fn run<'w>(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext<'w>,
+        world: &'w World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let Some(fluid) = world.get_resource::<SphFluidBindGroup>() else {
+            return Ok(());
+        };
+        if !self.pipeline_is_ready {
+            return Ok(());
+        }
+
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let pipeline = world.resource::<SphFluidPipeline>();
+        let step_pipelines = pipeline
+            .step_pipeline_ids
+            .map(|id| pipeline_cache.get_compute_pipeline(id).unwrap());
+        let splat_pipeline = pipeline_cache
+            .get_compute_pipeline(pipeline.splat_pipeline_id)
+            .unwrap();
+
+        let workgroups = |count: u32| count.div_ceil(WORKGROUP_SIZE);
+        // the grid clear runs per cell, the others per particle
+        let step_workgroups = [
+            workgroups(fluid.cell_count),
+            workgroups(fluid.particle_count),
+            workgroups(fluid.particle_count),
+            workgroups(fluid.particle_count),
+            workgroups(fluid.particle_count),
+        ];
+
+        let mut pass =
+            render_context
+                .command_encoder()
+                .begin_compute_pass(&ComputePassDescriptor {
+                    label: Some("sph_fluid"),
+                    ..Default::default()
+                });
+        pass.set_bind_group(0, &fluid.bind_group, &[]);
+
+        for _ in 0..fluid.steps {
+            for (pipeline, workgroups) in step_pipelines.iter().zip(step_workgroups) {
+                pass.set_pipeline(pipeline);
+                pass.dispatch_workgroups(workgroups, 1, 1);
+            }
+        }
+
+        // the density needs the grid of the final positions
+        for (pipeline, workgroups) in step_pipelines[..2].iter().zip(step_workgroups) {
+            pass.set_pipeline(pipeline);
+            pass.dispatch_workgroups(workgroups, 1, 1);
+        }
+        pass.set_pipeline(splat_pipeline);
+        pass.dispatch_workgroups(workgroups(fluid.splat_count), 1, 1);
+
+        Ok(())
+    }
+}
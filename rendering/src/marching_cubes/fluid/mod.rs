@@ -0,0 +1,235 @@
+use bevy_app::{App, Plugin, PostUpdate};
+use bevy_asset::{Assets, Handle, RenderAssetUsages};
+use bevy_ecs::change_detection::DetectChanges;
+use bevy_ecs::resource::Resource;
+use bevy_ecs::schedule::IntoScheduleConfigs;
+use bevy_ecs::system::{Commands, Res, ResMut};
+use bevy_math::bounding::Aabb3d;
+use bevy_math::{UVec3, Vec3, Vec4};
+use bevy_render::RenderApp;
+use bevy_render::storage::ShaderStorageBuffer;
+use bevy_time::{Time, Virtual};
+
+use super::{DensitySource, MarchingCubesBuffers, VoxelVolume, density_buffer};
+
+mod cpu;
+pub mod gpu;
+
+pub use cpu::FluidParticles;
+
+/// Particles a neighbor grid cell holds, the ones with the highest indices past it are not
+/// seen by their neighbors.
+///
+/// Must match `MAX_PER_CELL` in `sph.wgsl`.
+pub const MAX_PER_CELL: usize = 64;
+
+/// Simulates [`SphFluid`] on the GPU for [`DensitySource::Fluid`].
+pub struct SphFluidPlugin;
+
+impl Plugin for SphFluidPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PostUpdate, (rebuild_fluid_buffers, advance_fluid).chain());
+
+        if app.get_sub_app(RenderApp).is_some() {
+            app.add_plugins(gpu::GpuFluidPlugin);
+        }
+    }
+}
+
+/// Smoothed particle hydrodynamics parameters (Müller et al. 2003).
+#[derive(Clone, Copy, Debug)]
+pub struct SphSettings {
+    /// Kernel support, also the size of a neighbor grid cell.
+    pub smoothing_radius: f32,
+    pub particle_mass: f32,
+    pub rest_density: f32,
+    /// Pressure per unit of density above the rest density.
+    pub stiffness: f32,
+    pub viscosity: f32,
+    pub gravity: Vec3,
+    /// Fixed simulation step in seconds.
+    pub time_step: f32,
+    /// Fraction of the velocity kept when bouncing off the volume bounds.
+    pub restitution: f32,
+}
+
+impl Default for SphSettings {
+    fn default() -> Self {
+        let smoothing_radius = 0.1;
+        let rest_density = 1000.0;
+        Self {
+            smoothing_radius,
+            // a particle every half smoothing radius is at rest density
+            particle_mass: rest_density * (smoothing_radius * 0.5).powi(3),
+            rest_density,
+            stiffness: 20.0,
+            viscosity: 0.01,
+            gravity: Vec3::new(0.0, -9.81, 0.0),
+            time_step: 0.002,
+            restitution: 0.3,
+        }
+    }
+}
+
+impl SphSettings {
+    /// Spacing at which particles start at about the rest density.
+    pub fn particle_spacing(&self) -> f32 {
+        (self.particle_mass / self.rest_density).cbrt()
+    }
+}
+
+/// How many fixed steps run each frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FluidStepping {
+    /// Follows [`Time<Virtual>`], pausing it pauses the fluid.
+    RealTime { max_steps_per_frame: u32 },
+    /// Always the same number of steps, independent of the frame time.
+    PerFrame(u32),
+}
+
+impl FluidStepping {
+    /// Steps to run for a frame of `delta` seconds, `accumulator` carries the remainder.
+    pub fn steps(self, time_step: f32, delta: f32, accumulator: &mut f32) -> u32 {
+        match self {
+            FluidStepping::PerFrame(steps) => steps,
+            FluidStepping::RealTime {
+                max_steps_per_frame,
+            } => {
+                *accumulator += delta;
+                let steps = ((*accumulator / time_step) as u32).min(max_steps_per_frame);
+                *accumulator -= steps as f32 * time_step;
+                // drop the backlog instead of spiraling when frames are too slow
+                *accumulator = accumulator.min(time_step);
+                steps
+            }
+        }
+    }
+}
+
+impl Default for FluidStepping {
+    fn default() -> Self {
+        Self::RealTime {
+            max_steps_per_frame: 8,
+        }
+    }
+}
+
+/// Fluid simulated inside the [`VoxelVolume`] bounds when its density is
+/// [`DensitySource::Fluid`].
+///
+/// Changing this resource, or the volume, restarts the simulation from `initial`.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct SphFluid {
+    pub settings: SphSettings,
+    pub initial: FluidParticles,
+    pub stepping: FluidStepping,
+}
+
+impl SphFluid {
+    /// Fluid starting as a block of particles filling `region`.
+    pub fn block(settings: SphSettings, region: Aabb3d) -> Self {
+        Self {
+            initial: FluidParticles::block(region, settings.particle_spacing()),
+            settings,
+            stepping: FluidStepping::default(),
+        }
+    }
+}
+
+/// GPU state of the running simulation.
+#[derive(Resource, Clone)]
+pub(crate) struct FluidBuffers {
+    positions: Handle<ShaderStorageBuffer>,
+    velocities: Handle<ShaderStorageBuffer>,
+    accelerations: Handle<ShaderStorageBuffer>,
+    particle_densities: Handle<ShaderStorageBuffer>,
+    cell_counts: Handle<ShaderStorageBuffer>,
+    cell_entries: Handle<ShaderStorageBuffer>,
+    particle_count: u32,
+    grid_dims: UVec3,
+    /// Steps to run this frame.
+    steps: u32,
+    accumulator: f32,
+}
+
+/// Neighbor grid covering `bounds` with cells of `cell_size`.
+pub(crate) fn grid_dims(bounds: &Aabb3d, cell_size: f32) -> UVec3 {
+    let extent = Vec3::from(bounds.max - bounds.min);
+    (extent / cell_size).ceil().as_uvec3().max(UVec3::ONE)
+}
+
+fn rebuild_fluid_buffers(
+    mut commands: Commands,
+    fluid: Option<Res<SphFluid>>,
+    voxel_volume: Res<VoxelVolume>,
+    fluid_buffers: Option<Res<FluidBuffers>>,
+    marching_cubes_buffers: Res<MarchingCubesBuffers>,
+    mut storage_buffers: ResMut<Assets<ShaderStorageBuffer>>,
+) {
+    let Some(fluid) = fluid.filter(|_| matches!(voxel_volume.density, DensitySource::Fluid)) else {
+        if fluid_buffers.is_some() {
+            commands.remove_resource::<FluidBuffers>();
+        }
+        return;
+    };
+    if fluid_buffers.is_some() && !fluid.is_changed() && !voxel_volume.is_changed() {
+        return;
+    }
+
+    let particles = &fluid.initial;
+    let particle_count = particles.len();
+    let grid_dims = grid_dims(&voxel_volume.aabb, fluid.settings.smoothing_radius);
+    let cell_count = grid_dims.element_product() as usize;
+
+    let vec4_buffer = |values: &[Vec3]| {
+        // bindings can not be empty
+        let mut values: Vec<Vec4> = values.iter().map(|v| v.extend(0.0)).collect();
+        if values.is_empty() {
+            values.push(Vec4::ZERO);
+        }
+        ShaderStorageBuffer::new(
+            bytemuck::cast_slice(&values),
+            RenderAssetUsages::RENDER_WORLD,
+        )
+    };
+    let zeroed = |count: usize, stride: usize| {
+        ShaderStorageBuffer::with_size(count.max(1) * stride, RenderAssetUsages::RENDER_WORLD)
+    };
+
+    // fluid density is splatted into the buffer the compute stage reads sampled volumes from
+    let splat_count = (voxel_volume.count_dims() + UVec3::ONE).element_product() as usize;
+    storage_buffers.insert(
+        &marching_cubes_buffers.densities,
+        density_buffer(&vec![0.0; splat_count]),
+    );
+
+    commands.insert_resource(FluidBuffers {
+        positions: storage_buffers.add(vec4_buffer(&particles.positions)),
+        velocities: storage_buffers.add(vec4_buffer(&particles.velocities)),
+        accelerations: storage_buffers.add(zeroed(particle_count, size_of::<Vec4>())),
+        particle_densities: storage_buffers.add(zeroed(particle_count, size_of::<f32>())),
+        cell_counts: storage_buffers.add(zeroed(cell_count, size_of::<u32>())),
+        cell_entries: storage_buffers.add(zeroed(cell_count * MAX_PER_CELL, size_of::<u32>())),
+        particle_count: particle_count as u32,
+        grid_dims,
+        steps: 0,
+        accumulator: 0.0,
+    });
+}
+
+fn advance_fluid(
+    fluid: Option<Res<SphFluid>>,
+    fluid_buffers: Option<ResMut<FluidBuffers>>,
+    time: Res<Time<Virtual>>,
+) {
+    let (Some(fluid), Some(mut fluid_buffers)) = (fluid, fluid_buffers) else {
+        return;
+    };
+
+    let time_step = fluid.settings.time_step;
+    let fluid_buffers = &mut *fluid_buffers;
+    fluid_buffers.steps =
+        fluid
+            .stepping
+            .steps(time_step, time.delta_secs(), &mut fluid_buffers.accumulator);
+}
@@ -0,0 +1,223 @@
+// Smoothed particle hydrodynamics (Müller et al. 2003), mirrored by `FluidParticles::step`
+
+struct FluidParams {
+    bounds_min: vec3<f32>,
+    smoothing_radius: f32,
+    bounds_max: vec3<f32>,
+    particle_mass: f32,
+    grid_dims: vec3<u32>,
+    particle_count: u32,
+    gravity: vec3<f32>,
+    rest_density: f32,
+    splat_dims: vec3<u32>,
+    stiffness: f32,
+    viscosity: f32,
+    time_step: f32,
+    restitution: f32,
+    voxel_size: f32,
+};
+
+@group(0) @binding(0) var<uniform> params: FluidParams;
+// xyz used, w is padding
+@group(0) @binding(1) var<storage, read_write> positions: array<vec4<f32>>;
+@group(0) @binding(2) var<storage, read_write> velocities: array<vec4<f32>>;
+@group(0) @binding(3) var<storage, read_write> accelerations: array<vec4<f32>>;
+@group(0) @binding(4) var<storage, read_write> particle_densities: array<f32>;
+@group(0) @binding(5) var<storage, read_write> cell_counts: array<atomic<u32>>;
+// lowest MAX_PER_CELL particle indices per cell in ascending order, EMPTY past the count
+@group(0) @binding(6) var<storage, read_write> cell_entries: array<atomic<u32>>;
+// density on the voxel grid corners, read by the compute stage
+@group(0) @binding(7) var<storage, read_write> splat: array<f32>;
+
+// Must match `MAX_PER_CELL` in `fluid/mod.rs`
+const MAX_PER_CELL: u32 = 64;
+const EMPTY: u32 = 0xffffffffu;
+const PI: f32 = 3.14159265358979;
+
+fn cell_count() -> u32 {
+    return params.grid_dims.x * params.grid_dims.y * params.grid_dims.z;
+}
+
+fn cell_coord(pos: vec3<f32>) -> vec3<i32> {
+    let coord = vec3<i32>(floor((pos - params.bounds_min) / params.smoothing_radius));
+    return clamp(coord, vec3(0), vec3<i32>(params.grid_dims) - 1);
+}
+
+fn cell_index(coord: vec3<i32>) -> u32 {
+    let c = vec3<u32>(coord);
+    return c.x + params.grid_dims.x * (c.y + params.grid_dims.y * c.z);
+}
+
+fn poly6(r2: f32) -> f32 {
+    let h = params.smoothing_radius;
+    let h2 = h * h;
+    if (r2 >= h2) {
+        return 0.0;
+    }
+    let d = h2 - r2;
+    return 315.0 / (64.0 * PI * pow(h, 9.0)) * d * d * d;
+}
+
+fn spiky_gradient(offset: vec3<f32>, r: f32) -> vec3<f32> {
+    let h = params.smoothing_radius;
+    if (r >= h || r <= 0.0) {
+        return vec3(0.0);
+    }
+    let d = h - r;
+    return -45.0 / (PI * pow(h, 6.0)) * d * d * (offset / r);
+}
+
+fn viscosity_laplacian(r: f32) -> f32 {
+    let h = params.smoothing_radius;
+    if (r >= h) {
+        return 0.0;
+    }
+    return 45.0 / (PI * pow(h, 6.0)) * (h - r);
+}
+
+fn pressure(density: f32) -> f32 {
+    return params.stiffness * max(density - params.rest_density, 0.0);
+}
+
+@compute @workgroup_size(64)
+fn clear_grid(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= cell_count()) {
+        return;
+    }
+    atomicStore(&cell_counts[id.x], 0u);
+    for (var k: u32 = 0; k < MAX_PER_CELL; k++) {
+        atomicStore(&cell_entries[id.x * MAX_PER_CELL + k], EMPTY);
+    }
+}
+
+// Insertion sort with atomicMin, every slot keeps the smaller index and the larger one moves
+// on. Slots stay sorted whatever the scheduling, and a full cell keeps its lowest indices like
+// `FluidParticles::step`
+@compute @workgroup_size(64)
+fn insert_particles(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.particle_count) {
+        return;
+    }
+    let cell = cell_index(cell_coord(positions[id.x].xyz));
+    atomicAdd(&cell_counts[cell], 1u);
+
+    var index = id.x;
+    for (var k: u32 = 0; k < MAX_PER_CELL && index != EMPTY; k++) {
+        index = max(index, atomicMin(&cell_entries[cell * MAX_PER_CELL + k], index));
+    }
+}
+
+fn neighbor_cell(center: vec3<i32>, offset: vec3<i32>) -> i32 {
+    let coord = center + offset;
+    if (any(coord < vec3(0)) || any(coord >= vec3<i32>(params.grid_dims))) {
+        return -1;
+    }
+    return i32(cell_index(coord));
+}
+
+fn cell_len(cell: u32) -> u32 {
+    return min(atomicLoad(&cell_counts[cell]), MAX_PER_CELL);
+}
+
+// Unnormalized density at `pos`, neighbors are visited in z, y, x cell order then by index
+fn density_at(pos: vec3<f32>) -> f32 {
+    let center = cell_coord(pos);
+    var density = 0.0;
+    for (var z = -1; z <= 1; z++) {
+        for (var y = -1; y <= 1; y++) {
+            for (var x = -1; x <= 1; x++) {
+                let cell = neighbor_cell(center, vec3(x, y, z));
+                if (cell < 0) {
+                    continue;
+                }
+                let start = u32(cell) * MAX_PER_CELL;
+                for (var k: u32 = 0; k < cell_len(u32(cell)); k++) {
+                    let offset = pos - positions[atomicLoad(&cell_entries[start + k])].xyz;
+                    density += params.particle_mass * poly6(dot(offset, offset));
+                }
+            }
+        }
+    }
+    return density;
+}
+
+@compute @workgroup_size(64)
+fn compute_density(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.particle_count) {
+        return;
+    }
+    particle_densities[id.x] = density_at(positions[id.x].xyz);
+}
+
+@compute @workgroup_size(64)
+fn compute_forces(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if (i >= params.particle_count) {
+        return;
+    }
+    let pos = positions[i].xyz;
+    let vel = velocities[i].xyz;
+    let density = particle_densities[i];
+    let pressure_i = pressure(density);
+
+    let center = cell_coord(pos);
+    var pressure_force = vec3(0.0);
+    var viscosity_force = vec3(0.0);
+    for (var z = -1; z <= 1; z++) {
+        for (var y = -1; y <= 1; y++) {
+            for (var x = -1; x <= 1; x++) {
+                let cell = neighbor_cell(center, vec3(x, y, z));
+                if (cell < 0) {
+                    continue;
+                }
+                let start = u32(cell) * MAX_PER_CELL;
+                for (var k: u32 = 0; k < cell_len(u32(cell)); k++) {
+                    let j = atomicLoad(&cell_entries[start + k]);
+                    if (j == i) {
+                        continue;
+                    }
+                    let offset = pos - positions[j].xyz;
+                    let r = length(offset);
+                    let density_j = particle_densities[j];
+                    let shared_pressure = (pressure_i + pressure(density_j)) / (2.0 * density_j);
+                    pressure_force -= params.particle_mass * shared_pressure * spiky_gradient(offset, r);
+                    viscosity_force += params.particle_mass * (velocities[j].xyz - vel) / density_j * viscosity_laplacian(r);
+                }
+            }
+        }
+    }
+
+    let acceleration = (pressure_force + params.viscosity * viscosity_force) / density + params.gravity;
+    accelerations[i] = vec4(acceleration, 0.0);
+}
+
+@compute @workgroup_size(64)
+fn integrate(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if (i >= params.particle_count) {
+        return;
+    }
+    var vel = velocities[i].xyz + accelerations[i].xyz * params.time_step;
+    var pos = positions[i].xyz + vel * params.time_step;
+
+    // reflect off the volume bounds
+    let below = pos < params.bounds_min;
+    let above = pos > params.bounds_max;
+    vel = select(vel, -vel * params.restitution, below | above);
+    pos = clamp(pos, params.bounds_min, params.bounds_max);
+
+    positions[i] = vec4(pos, 0.0);
+    velocities[i] = vec4(vel, 0.0);
+}
+
+// Fluid density on the voxel grid, normalized by the rest density
+@compute @workgroup_size(64)
+fn splat_density(@builtin(global_invocation_id) id: vec3<u32>) {
+    let dims = params.splat_dims;
+    if (id.x >= dims.x * dims.y * dims.z) {
+        return;
+    }
+    let coord = vec3(id.x % dims.x, (id.x / dims.x) % dims.y, id.x / (dims.x * dims.y));
+    let pos = params.bounds_min + vec3<f32>(coord) * params.voxel_size;
+    splat[id.x] = density_at(pos) / params.rest_density;
+}
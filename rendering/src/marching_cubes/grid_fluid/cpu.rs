@@ -0,0 +1,446 @@
+use bevy_math::bounding::{Aabb3d, BoundingVolume};
+use bevy_math::{IVec3, UVec3, Vec3, Vec3A, Vec4};
+
+use super::{GridFluid, GridFluidMode, GridFluidSettings, LEVEL_SET_BAND};
+use crate::marching_cubes::VoxelVolume;
+use crate::marching_cubes::volume::ScalarVolume;
+
+/// Grid fluid state on the corners of the [`VoxelVolume`] voxels, also a CPU reference of
+/// the `grid_fluid.wgsl` simulation.
+#[derive(Clone, Debug)]
+pub struct GridFluidState {
+    pub dims: UVec3,
+    pub spacing: f32,
+    pub origin: Vec3,
+    /// `xyz` velocity on the `+x`, `+y` and `+z` faces of the cell, `w` level set for liquids,
+    /// density for smoke.
+    pub cells: Vec<Vec4>,
+    pub pressure: Vec<f32>,
+}
+
+impl GridFluidState {
+    /// Fluid at rest filling `fluid.regions`.
+    pub fn new(fluid: &GridFluid, volume: &VoxelVolume) -> Self {
+        let dims = volume.count_dims() + UVec3::ONE;
+        let spacing = volume.voxel_size;
+        let origin = Vec3::from(volume.aabb.min);
+        let band = LEVEL_SET_BAND * spacing;
+
+        // regions reaching the bounds continue past them, so walls are not part of the surface
+        let (min, max) = (volume.aabb.min, volume.aabb.max);
+        let regions: Vec<Aabb3d> = fluid
+            .regions
+            .iter()
+            .map(|region| Aabb3d {
+                min: Vec3A::select(region.min.cmple(min), min - band, region.min),
+                max: Vec3A::select(region.max.cmpge(max), max + band, region.max),
+            })
+            .collect();
+
+        let mut cells = Vec::with_capacity(dims.element_product() as usize);
+        for z in 0..dims.z {
+            for y in 0..dims.y {
+                for x in 0..dims.x {
+                    let pos = origin + UVec3::new(x, y, z).as_vec3() * spacing;
+                    let scalar = match fluid.settings.mode {
+                        GridFluidMode::Liquid => regions
+                            .iter()
+                            .map(|region| box_sdf(region, pos))
+                            .fold(band, f32::min)
+                            .clamp(-band, band),
+                        GridFluidMode::Smoke => {
+                            let inside = fluid.regions.iter().any(|region| {
+                                pos.cmpge(region.min.into()).all()
+                                    && pos.cmple(region.max.into()).all()
+                            });
+                            if inside { 1.0 } else { 0.0 }
+                        }
+                    };
+                    cells.push(Vec3::ZERO.extend(scalar));
+                }
+            }
+        }
+
+        Self {
+            pressure: vec![0.0; cells.len()],
+            dims,
+            spacing,
+            origin,
+            cells,
+        }
+    }
+
+    /// Advances the simulation by one [`GridFluidSettings::time_step`].
+    pub fn step(&mut self, settings: &GridFluidSettings) {
+        let grid = Grid {
+            settings,
+            dims: self.dims,
+            spacing: self.spacing,
+        };
+
+        let advected: Vec<Vec4> = grid.coords().map(|c| grid.advect(&self.cells, c)).collect();
+
+        let divergence: Vec<f32> = grid
+            .coords()
+            .map(|c| grid.divergence(&advected, c))
+            .collect();
+
+        let mut scratch = vec![0.0; self.pressure.len()];
+        for _ in 0..settings.jacobi_pairs() {
+            for (i, c) in grid.coords().enumerate() {
+                scratch[i] = grid.jacobi(&advected, &divergence, &self.pressure, c);
+            }
+            for (i, c) in grid.coords().enumerate() {
+                self.pressure[i] = grid.jacobi(&advected, &divergence, &scratch, c);
+            }
+        }
+
+        for (i, c) in grid.coords().enumerate() {
+            self.cells[i] = grid.project(&advected, &self.pressure, c);
+        }
+    }
+
+    /// Density as written for the compute stage, positive inside.
+    ///
+    /// Marching `volume.isovalue` of zero gives the liquid surface.
+    pub fn density(&self, settings: &GridFluidSettings) -> ScalarVolume {
+        let values = self
+            .cells
+            .iter()
+            .map(|cell| match settings.mode {
+                GridFluidMode::Liquid => -cell.w,
+                GridFluidMode::Smoke => cell.w,
+            })
+            .collect();
+
+        let mut density = ScalarVolume::new(self.dims, values);
+        density.spacing = Vec3::splat(self.spacing);
+        density.origin = self.origin;
+        density
+    }
+}
+
+fn box_sdf(region: &Aabb3d, pos: Vec3) -> f32 {
+    let q = (pos - Vec3::from(region.center())).abs() - Vec3::from(region.half_size());
+    q.max(Vec3::ZERO).length() + q.max_element().min(0.0)
+}
+
+fn axis_offset(axis: usize) -> IVec3 {
+    let mut offset = IVec3::ZERO;
+    offset[axis] = 1;
+    offset
+}
+
+/// Passes of one step, mirror the entry points of `grid_fluid.wgsl`.
+struct Grid<'a> {
+    settings: &'a GridFluidSettings,
+    dims: UVec3,
+    spacing: f32,
+}
+
+impl Grid<'_> {
+    fn coords(&self) -> impl Iterator<Item = IVec3> {
+        let dims = self.dims.as_ivec3();
+        (0..dims.z)
+            .flat_map(move |z| (0..dims.y).flat_map(move |y| (0..dims.x).map(move |x| (x, y, z))))
+            .map(|(x, y, z)| IVec3::new(x, y, z))
+    }
+
+    fn in_grid(&self, c: IVec3) -> bool {
+        c.cmpge(IVec3::ZERO).all() && c.cmplt(self.dims.as_ivec3()).all()
+    }
+
+    fn cell(&self, c: IVec3) -> usize {
+        let u = c.as_uvec3();
+        (u.x + self.dims.x * (u.y + self.dims.y * u.z)) as usize
+    }
+
+    fn is_last(&self, c: IVec3, axis: usize) -> bool {
+        c[axis] + 1 == self.dims[axis] as i32
+    }
+
+    fn in_fluid(&self, scalar: f32) -> bool {
+        self.settings.mode == GridFluidMode::Smoke || scalar < 0.0
+    }
+
+    fn state_at(&self, cells: &[Vec4], c: IVec3) -> Vec4 {
+        cells[self.cell(c.clamp(IVec3::ZERO, self.dims.as_ivec3() - 1))]
+    }
+
+    fn sample_channel(&self, cells: &[Vec4], channel: usize, grid: Vec3) -> f32 {
+        let at = |c: IVec3| self.state_at(cells, c)[channel];
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+
+        let g = grid.clamp(Vec3::ZERO, (self.dims - 1).as_vec3());
+        let base = g.floor().as_ivec3();
+        let t = g - base.as_vec3();
+
+        let x0 = lerp(at(base), at(base + IVec3::new(1, 0, 0)), t.x);
+        let x1 = lerp(
+            at(base + IVec3::new(0, 1, 0)),
+            at(base + IVec3::new(1, 1, 0)),
+            t.x,
+        );
+        let x2 = lerp(
+            at(base + IVec3::new(0, 0, 1)),
+            at(base + IVec3::new(1, 0, 1)),
+            t.x,
+        );
+        let x3 = lerp(
+            at(base + IVec3::new(0, 1, 1)),
+            at(base + IVec3::new(1, 1, 1)),
+            t.x,
+        );
+        lerp(lerp(x0, x1, t.y), lerp(x2, x3, t.y), t.z)
+    }
+
+    fn velocity_at(&self, cells: &[Vec4], pos: Vec3) -> Vec3 {
+        Vec3::new(
+            self.sample_channel(cells, 0, pos - Vec3::new(0.5, 0.0, 0.0)),
+            self.sample_channel(cells, 1, pos - Vec3::new(0.0, 0.5, 0.0)),
+            self.sample_channel(cells, 2, pos - Vec3::new(0.0, 0.0, 0.5)),
+        )
+    }
+
+    fn advect(&self, cells: &[Vec4], c: IVec3) -> Vec4 {
+        let settings = self.settings;
+        let dt = settings.time_step;
+        let pos = c.as_vec3();
+        let scalar = cells[self.cell(c)].w;
+
+        let mut next = Vec4::ZERO;
+        for axis in 0..3 {
+            let half = 0.5 * axis_offset(axis).as_vec3();
+            let face = pos + half;
+            let back = face - self.velocity_at(cells, face) * dt / self.spacing;
+            let mut velocity = self.sample_channel(cells, axis, back - half);
+
+            let neighbor = self.state_at(cells, c + axis_offset(axis)).w;
+            match settings.mode {
+                GridFluidMode::Liquid => {
+                    if scalar.min(neighbor) < 0.0 {
+                        velocity += settings.gravity[axis] * dt;
+                    }
+                }
+                GridFluidMode::Smoke => {
+                    velocity -=
+                        settings.gravity[axis] * settings.buoyancy * 0.5 * (scalar + neighbor) * dt;
+                }
+            }
+            if self.is_last(c, axis) {
+                velocity = 0.0;
+            }
+            next[axis] = velocity;
+        }
+
+        let back = pos - self.velocity_at(cells, pos) * dt / self.spacing;
+        next.w = self.sample_channel(cells, 3, back);
+        match settings.mode {
+            GridFluidMode::Liquid => {
+                let band = LEVEL_SET_BAND * self.spacing;
+                next.w = next.w.clamp(-band, band);
+            }
+            GridFluidMode::Smoke => next.w *= (1.0 - settings.dissipation * dt).max(0.0),
+        }
+        next
+    }
+
+    fn divergence(&self, advected: &[Vec4], c: IVec3) -> f32 {
+        let velocity = advected[self.cell(c)];
+        let mut sum = 0.0;
+        for axis in 0..3 {
+            sum += velocity[axis];
+            if c[axis] > 0 {
+                sum -= advected[self.cell(c - axis_offset(axis))][axis];
+            }
+        }
+        sum / self.spacing
+    }
+
+    fn jacobi(&self, advected: &[Vec4], divergence: &[f32], pressure: &[f32], c: IVec3) -> f32 {
+        let i = self.cell(c);
+        if !self.in_fluid(advected[i].w) {
+            return 0.0;
+        }
+        let mut sum = 0.0;
+        let mut count = 0.0;
+        for k in 0..6 {
+            let offset = axis_offset(k / 2);
+            let n = if k % 2 == 1 { c + offset } else { c - offset };
+            if !self.in_grid(n) {
+                continue;
+            }
+            count += 1.0;
+            let j = self.cell(n);
+            if !self.in_fluid(advected[j].w) {
+                continue;
+            }
+            sum += pressure[j];
+        }
+        (sum - self.spacing * self.spacing * divergence[i]) / count
+    }
+
+    /// Projected velocity of the `axis` face of `c`, if it touches the fluid.
+    fn projected_face(
+        &self,
+        advected: &[Vec4],
+        pressure: &[f32],
+        c: IVec3,
+        axis: usize,
+    ) -> Option<f32> {
+        let n = c + axis_offset(axis);
+        if !self.in_grid(c) || !self.in_grid(n) {
+            return None;
+        }
+        let (i, j) = (self.cell(c), self.cell(n));
+        if !self.in_fluid(advected[i].w) && !self.in_fluid(advected[j].w) {
+            return None;
+        }
+        Some(advected[i][axis] - (pressure[j] - pressure[i]) / self.spacing)
+    }
+
+    fn project(&self, advected: &[Vec4], pressure: &[f32], c: IVec3) -> Vec4 {
+        let mut next = advected[self.cell(c)];
+        for axis in 0..3 {
+            if self.is_last(c, axis) {
+                next[axis] = 0.0;
+                continue;
+            }
+            if let Some(velocity) = self.projected_face(advected, pressure, c, axis) {
+                next[axis] = velocity;
+                continue;
+            }
+
+            let (mut sum, mut count) = (0.0, 0.0);
+            for k in 0..6 {
+                let offset = axis_offset(k / 2);
+                let n = if k % 2 == 1 { c + offset } else { c - offset };
+                if let Some(velocity) = self.projected_face(advected, pressure, n, axis) {
+                    sum += velocity;
+                    count += 1.0;
+                }
+            }
+            next[axis] = if count > 0.0 { sum / count } else { 0.0 };
+        }
+        next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn volume() -> VoxelVolume {
+        VoxelVolume {
+            aabb: Aabb3d::new(Vec3::ZERO, Vec3::ONE),
+            voxel_size: 0.125,
+            ..Default::default()
+        }
+    }
+
+    fn grid<'a>(state: &GridFluidState, settings: &'a GridFluidSettings) -> Grid<'a> {
+        Grid {
+            settings,
+            dims: state.dims,
+            spacing: state.spacing,
+        }
+    }
+
+    /// Root mean square of the velocity divergence over the grid.
+    fn divergence(state: &GridFluidState, settings: &GridFluidSettings) -> f32 {
+        let grid = grid(state, settings);
+        let sum: f32 = grid
+            .coords()
+            .map(|c| grid.divergence(&state.cells, c).powi(2))
+            .sum();
+        (sum / state.cells.len() as f32).sqrt()
+    }
+
+    /// Smoke filling the whole volume, flowing out of its center.
+    fn diverging_smoke(settings: GridFluidSettings) -> GridFluidState {
+        let volume = volume();
+        let fluid = GridFluid::new(settings, [volume.aabb]);
+        let mut state = GridFluidState::new(&fluid, &volume);
+        let center = (state.dims - 1).as_vec3() * 0.5;
+        let grid = grid(&state, &settings);
+        for (i, c) in grid.coords().enumerate() {
+            for axis in 0..3 {
+                // faces on the far walls are closed
+                if !grid.is_last(c, axis) {
+                    let face = c.as_vec3()[axis] + 0.5;
+                    state.cells[i][axis] = (face - center[axis]) * 0.1;
+                }
+            }
+        }
+        state
+    }
+
+    #[test]
+    fn projection_reduces_divergence() {
+        let mut previous = f32::MAX;
+        for iterations in [10, 40, 160] {
+            // without a time step the step is only the pressure projection
+            let settings = GridFluidSettings {
+                mode: GridFluidMode::Smoke,
+                time_step: 0.0,
+                jacobi_iterations: iterations,
+                ..Default::default()
+            };
+            let mut state = diverging_smoke(settings);
+            let before = divergence(&state, &settings);
+            state.step(&settings);
+            let after = divergence(&state, &settings);
+
+            assert!(
+                after < before * 0.8,
+                "{iterations} iterations: {before} -> {after}"
+            );
+            assert!(
+                after < previous,
+                "{iterations} iterations: {after} vs {previous}"
+            );
+            previous = after;
+            if iterations >= 160 {
+                assert!(after < before * 0.05, "{before} -> {after}");
+            }
+        }
+    }
+
+    #[test]
+    fn liquid_at_rest_keeps_its_level() {
+        let settings = GridFluidSettings::default();
+        let volume = volume();
+        let region = Aabb3d {
+            min: volume.aabb.min,
+            max: Vec3A::new(1.0, -0.25, 1.0),
+        };
+        let fluid = GridFluid::new(settings, [region]);
+        let mut state = GridFluidState::new(&fluid, &volume);
+        let initial = state.cells.clone();
+
+        // a second of gravity pulling on the liquid
+        for _ in 0..120 {
+            state.step(&settings);
+        }
+
+        let spacing = state.spacing;
+        for (cell, start) in state.cells.iter().zip(&initial) {
+            assert!(
+                (cell.w - start.w).abs() < 0.05 * spacing,
+                "level set moved from {} to {}",
+                start.w,
+                cell.w
+            );
+            assert!(
+                cell.truncate().length() < 0.01,
+                "velocity {}",
+                cell.truncate()
+            );
+        }
+
+        // the surface is still between the same samples
+        let density = state.density(&settings);
+        assert!(density.sample(Vec3::new(0.3, -0.3, -0.2)) > 0.0);
+        assert!(density.sample(Vec3::new(0.3, -0.2, -0.2)) < 0.0);
+    }
+}
@@ -0,0 +1,340 @@
+use bevy_app::{App, Plugin};
+use bevy_asset::{AssetId, Handle, load_internal_asset, weak_handle};
+use bevy_ecs::resource::Resource;
+use bevy_ecs::schedule::IntoScheduleConfigs;
+use bevy_ecs::system::{Commands, Query, Res, ResMut};
+use bevy_ecs::world::{FromWorld, World};
+use bevy_math::{UVec3, Vec3};
+use bevy_render::render_asset::RenderAssets;
+use bevy_render::render_graph::{self, RenderGraph, RenderLabel};
+use bevy_render::render_resource::binding_types::{storage_buffer_sized, uniform_buffer};
+use bevy_render::render_resource::{
+    BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, CachedComputePipelineId,
+    CachedPipelineState, ComputePassDescriptor, ComputePipelineDescriptor, PipelineCache, Shader,
+    ShaderStages, ShaderType, UniformBuffer,
+};
+use bevy_render::renderer::{RenderContext, RenderDevice, RenderQueue};
+use bevy_render::storage::{GpuShaderStorageBuffer, ShaderStorageBuffer};
+use bevy_render::{Extract, ExtractSchedule, Render, RenderApp, RenderSet};
+
+use super::{GridFluid, GridFluidBuffers, LEVEL_SET_BAND};
+use crate::marching_cubes::compute_stage::MarchingCubesComputeLabel;
+use crate::marching_cubes::{MarchingCubesBuffers, VoxelVolume};
+
+pub const GRID_FLUID_SHADER_HANDLE: Handle<Shader> =
+    weak_handle!("5e2a8d17-b4c9-4f03-a6e1-9d8c3b7f2e65");
+
+const WORKGROUP_SIZE: u32 = 4;
+
+// must match the entry points in grid_fluid.wgsl, indexed by the consts below
+const ENTRY_POINTS: [&str; 6] = [
+    "advect",
+    "compute_divergence",
+    "jacobi_even",
+    "jacobi_odd",
+    "project",
+    "write_density",
+];
+const ADVECT: usize = 0;
+const DIVERGENCE: usize = 1;
+const JACOBI_EVEN: usize = 2;
+const JACOBI_ODD: usize = 3;
+const PROJECT: usize = 4;
+const WRITE_DENSITY: usize = 5;
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+struct GridFluidLabel;
+
+pub struct GpuGridFluidPlugin;
+
+impl Plugin for GpuGridFluidPlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(
+            app,
+            GRID_FLUID_SHADER_HANDLE,
+            "grid_fluid.wgsl",
+            Shader::from_wgsl
+        );
+
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app.init_resource::<GridFluidUniformBuffer>();
+        render_app.add_systems(ExtractSchedule, extract_grid_fluid);
+        render_app.add_systems(
+            Render,
+            (
+                prepare_grid_fluid_uniform_buffer.in_set(RenderSet::PrepareResources),
+                prepare_grid_fluid_bind_group.in_set(RenderSet::PrepareBindGroups),
+            ),
+        );
+
+        let mut render_graph = render_app.world_mut().resource_mut::<RenderGraph>();
+        render_graph.add_node(GridFluidLabel, GridFluidNode::default());
+        // the compute stage reads the written density
+        render_graph.add_node_edge(GridFluidLabel, MarchingCubesComputeLabel);
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app.init_resource::<GridFluidPipeline>();
+    }
+}
+
+/// `GridParams` in `grid_fluid.wgsl`.
+#[derive(ShaderType, Clone, Default)]
+struct GridFluidUniform {
+    origin: Vec3,
+    spacing: f32,
+    dims: UVec3,
+    mode: u32,
+    gravity: Vec3,
+    time_step: f32,
+    buoyancy: f32,
+    dissipation: f32,
+    band: f32,
+}
+
+#[derive(Resource)]
+struct ExtractedGridFluid {
+    uniform: GridFluidUniform,
+    buffers: [AssetId<ShaderStorageBuffer>; 6],
+    steps: u32,
+    jacobi_pairs: u32,
+}
+
+fn extract_grid_fluid(
+    mut commands: Commands,
+    fluids: Extract<Query<(&GridFluid, &GridFluidBuffers)>>,
+    voxel_volume: Extract<Res<VoxelVolume>>,
+    marching_cubes_buffers: Extract<Res<MarchingCubesBuffers>>,
+) {
+    let Some((fluid, fluid_buffers)) = fluids.iter().next() else {
+        commands.remove_resource::<ExtractedGridFluid>();
+        return;
+    };
+
+    let settings = &fluid.settings;
+    let uniform = GridFluidUniform {
+        origin: voxel_volume.aabb.min.into(),
+        spacing: voxel_volume.voxel_size,
+        dims: fluid_buffers.dims,
+        mode: settings.mode.shader_index(),
+        gravity: settings.gravity,
+        time_step: settings.time_step,
+        buoyancy: settings.buoyancy,
+        dissipation: settings.dissipation,
+        band: LEVEL_SET_BAND * voxel_volume.voxel_size,
+    };
+
+    commands.insert_resource(ExtractedGridFluid {
+        uniform,
+        buffers: [
+            fluid_buffers.state.id(),
+            fluid_buffers.advected.id(),
+            fluid_buffers.pressure.id(),
+            fluid_buffers.pressure_scratch.id(),
+            fluid_buffers.divergence.id(),
+            marching_cubes_buffers.densities.id(),
+        ],
+        steps: fluid_buffers.steps,
+        jacobi_pairs: settings.jacobi_pairs(),
+    });
+}
+
+#[derive(Resource, Default)]
+struct GridFluidUniformBuffer {
+    buffer: UniformBuffer<GridFluidUniform>,
+}
+
+fn prepare_grid_fluid_uniform_buffer(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut grid_fluid_uniform_buffer: ResMut<GridFluidUniformBuffer>,
+    fluid: Option<Res<ExtractedGridFluid>>,
+) {
+    let Some(fluid) = fluid else {
+        return;
+    };
+
+    grid_fluid_uniform_buffer.buffer.set(fluid.uniform.clone());
+    grid_fluid_uniform_buffer
+        .buffer
+        .write_buffer(&render_device, &render_queue);
+}
+
+/// Bind group and work of this frame.
+#[derive(Resource)]
+struct GridFluidBindGroup {
+    bind_group: BindGroup,
+    dims: UVec3,
+    steps: u32,
+    jacobi_pairs: u32,
+}
+
+fn prepare_grid_fluid_bind_group(
+    mut commands: Commands,
+    pipeline: Res<GridFluidPipeline>,
+    fluid: Option<Res<ExtractedGridFluid>>,
+    grid_fluid_uniform_buffer: Res<GridFluidUniformBuffer>,
+    gpu_buffers: Res<RenderAssets<GpuShaderStorageBuffer>>,
+    render_device: Res<RenderDevice>,
+) {
+    commands.remove_resource::<GridFluidBindGroup>();
+
+    let Some(fluid) = fluid else {
+        return;
+    };
+    let (Some(uniform), Some(buffers)) = (
+        grid_fluid_uniform_buffer.buffer.binding(),
+        fluid
+            .buffers
+            .iter()
+            .map(|id| gpu_buffers.get(*id))
+            .collect::<Option<Vec<_>>>(),
+    ) else {
+        return;
+    };
+
+    let bind_group = render_device.create_bind_group(
+        Some("grid_fluid_bind_group"),
+        &pipeline.bind_group_layout,
+        &BindGroupEntries::sequential((
+            uniform,
+            buffers[0].buffer.as_entire_buffer_binding(),
+            buffers[1].buffer.as_entire_buffer_binding(),
+            buffers[2].buffer.as_entire_buffer_binding(),
+            buffers[3].buffer.as_entire_buffer_binding(),
+            buffers[4].buffer.as_entire_buffer_binding(),
+            buffers[5].buffer.as_entire_buffer_binding(),
+        )),
+    );
+
+    commands.insert_resource(GridFluidBindGroup {
+        bind_group,
+        dims: fluid.uniform.dims,
+        steps: fluid.steps,
+        jacobi_pairs: fluid.jacobi_pairs,
+    });
+}
+
+#[derive(Resource)]
+struct GridFluidPipeline {
+    bind_group_layout: BindGroupLayout,
+    pipeline_ids: [CachedComputePipelineId; 6],
+}
+
+impl FromWorld for GridFluidPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let bind_group_layout = render_device.create_bind_group_layout(
+            "grid_fluid_bind_group",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::COMPUTE,
+                (
+                    uniform_buffer::<GridFluidUniform>(false),
+                    storage_buffer_sized(false, None),
+                    storage_buffer_sized(false, None),
+                    storage_buffer_sized(false, None),
+                    storage_buffer_sized(false, None),
+                    storage_buffer_sized(false, None),
+                    storage_buffer_sized(false, None),
+                ),
+            ),
+        );
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let pipeline_ids = ENTRY_POINTS.map(|entry_point| {
+            pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                label: Some(format!("grid_fluid_{entry_point}_pipeline").into()),
+                layout: vec![bind_group_layout.clone()],
+                push_constant_ranges: Vec::new(),
+                shader: GRID_FLUID_SHADER_HANDLE,
+                shader_defs: vec![],
+                entry_point: entry_point.into(),
+                zero_initialize_workgroup_memory: false,
+            })
+        });
+
+        Self {
+            bind_group_layout,
+            pipeline_ids,
+        }
+    }
+}
+
+#[derive(Default)]
+struct GridFluidNode {
+    pipeline_is_ready: bool,
+}
+
+impl render_graph::Node for GridFluidNode {
+    fn update(&mut self, world: &mut World) {
+        let pipeline = world.resource::<GridFluidPipeline>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+
+        if !self.pipeline_is_ready {
+            let mut all_ready = true;
+            for id in pipeline.pipeline_ids {
+                match pipeline_cache.get_compute_pipeline_state(id) {
+                    CachedPipelineState::Ok(_) => {}
+                    CachedPipelineState::Err(err) => {
+                        panic!("Initializing grid_fluid.wgsl:\n{err}")
+                    }
+                    _ => all_ready = false,
+                }
+            }
+            self.pipeline_is_ready = all_ready;
+        }
+    }
+
+    fn run<'w>(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext<'w>,
+        world: &'w World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let Some(fluid) = world.get_resource::<GridFluidBindGroup>() else {
+            return Ok(());
+        };
+        if !self.pipeline_is_ready {
+            return Ok(());
+        }
+
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let pipelines = world
+            .resource::<GridFluidPipeline>()
+            .pipeline_ids
+            .map(|id| pipeline_cache.get_compute_pipeline(id).unwrap());
+
+        let workgroups = (fluid.dims + UVec3::splat(WORKGROUP_SIZE - 1)) / WORKGROUP_SIZE;
+        let mut pass =
+            render_context
+                .command_encoder()
+                .begin_compute_pass(&ComputePassDescriptor {
+                    label: Some("grid_fluid"),
+                    ..Default::default()
+                });
+        pass.set_bind_group(0, &fluid.bind_group, &[]);
+        let mut dispatch = |entry_point: usize| {
+            pass.set_pipeline(pipelines[entry_point]);
+            pass.dispatch_workgroups(workgroups.x, workgroups.y, workgroups.z);
+        };
+
+        for _ in 0..fluid.steps {
+            dispatch(ADVECT);
+            dispatch(DIVERGENCE);
+            for _ in 0..fluid.jacobi_pairs {
+                dispatch(JACOBI_EVEN);
+                dispatch(JACOBI_ODD);
+            }
+            dispatch(PROJECT);
+        }
+        dispatch(WRITE_DENSITY);
+
+        Ok(())
+    }
+}
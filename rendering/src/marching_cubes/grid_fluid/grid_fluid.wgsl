@@ -0,0 +1,236 @@
+// Stable fluids (Stam 1999) on a staggered grid whose cells are the voxel corners,
+// mirrored by `GridFluidState::step`
+
+struct GridParams {
+    origin: vec3<f32>,
+    spacing: f32,
+    dims: vec3<u32>,
+    mode: u32,
+    gravity: vec3<f32>,
+    time_step: f32,
+    buoyancy: f32,
+    dissipation: f32,
+    band: f32,
+};
+
+@group(0) @binding(0) var<uniform> params: GridParams;
+// xyz velocity on the +x, +y and +z faces of the cell, w level set (liquid) or smoke density
+@group(0) @binding(1) var<storage, read_write> state: array<vec4<f32>>;
+// advected state, projected back into `state`
+@group(0) @binding(2) var<storage, read_write> advected: array<vec4<f32>>;
+@group(0) @binding(3) var<storage, read_write> pressure: array<f32>;
+@group(0) @binding(4) var<storage, read_write> pressure_scratch: array<f32>;
+@group(0) @binding(5) var<storage, read_write> divergence: array<f32>;
+// positive inside, read by the compute stage
+@group(0) @binding(6) var<storage, read_write> density: array<f32>;
+
+// Must match `GridFluidMode::shader_index`
+const MODE_LIQUID: u32 = 0;
+const MODE_SMOKE: u32 = 1;
+
+fn in_grid(c: vec3<i32>) -> bool {
+    return all(c >= vec3(0)) && all(c < vec3<i32>(params.dims));
+}
+
+fn cell(c: vec3<i32>) -> u32 {
+    let u = vec3<u32>(c);
+    return u.x + params.dims.x * (u.y + params.dims.y * u.z);
+}
+
+fn axis_offset(axis: u32) -> vec3<i32> {
+    var offset = vec3(0);
+    offset[axis] = 1;
+    return offset;
+}
+
+// Cells taking part in the projection, air is left out for liquids
+fn in_fluid(scalar: f32) -> bool {
+    return params.mode == MODE_SMOKE || scalar < 0.0;
+}
+
+fn state_at(c: vec3<i32>) -> vec4<f32> {
+    return state[cell(clamp(c, vec3(0), vec3<i32>(params.dims) - 1))];
+}
+
+// Trilinear interpolation of one state channel in grid coordinates of its samples
+fn sample_channel(channel: u32, grid: vec3<f32>) -> f32 {
+    let g = clamp(grid, vec3(0.0), vec3<f32>(params.dims - 1u));
+    let base = vec3<i32>(floor(g));
+    let t = g - vec3<f32>(base);
+
+    let x0 = mix(state_at(base)[channel], state_at(base + vec3(1, 0, 0))[channel], t.x);
+    let x1 = mix(state_at(base + vec3(0, 1, 0))[channel], state_at(base + vec3(1, 1, 0))[channel], t.x);
+    let x2 = mix(state_at(base + vec3(0, 0, 1))[channel], state_at(base + vec3(1, 0, 1))[channel], t.x);
+    let x3 = mix(state_at(base + vec3(0, 1, 1))[channel], state_at(base + vec3(1, 1, 1))[channel], t.x);
+    return mix(mix(x0, x1, t.y), mix(x2, x3, t.y), t.z);
+}
+
+// Velocity at a position in cell coordinates, each component lives half a cell further
+fn velocity_at(pos: vec3<f32>) -> vec3<f32> {
+    return vec3(
+        sample_channel(0u, pos - vec3(0.5, 0.0, 0.0)),
+        sample_channel(1u, pos - vec3(0.0, 0.5, 0.0)),
+        sample_channel(2u, pos - vec3(0.0, 0.0, 0.5)),
+    );
+}
+
+// Semi-Lagrangian advection of the whole state, then body forces
+@compute @workgroup_size(4, 4, 4)
+fn advect(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (any(id >= params.dims)) {
+        return;
+    }
+    let c = vec3<i32>(id);
+    let pos = vec3<f32>(id);
+    let dt = params.time_step;
+    let scalar = state[cell(c)].w;
+
+    var next: vec4<f32>;
+    for (var axis = 0u; axis < 3u; axis++) {
+        let face = pos + 0.5 * vec3<f32>(axis_offset(axis));
+        let back = face - velocity_at(face) * dt / params.spacing;
+        var velocity = sample_channel(axis, back - 0.5 * vec3<f32>(axis_offset(axis)));
+
+        let neighbor = state_at(c + axis_offset(axis)).w;
+        if (params.mode == MODE_LIQUID) {
+            // air velocities are extrapolated from the liquid after the projection
+            if (min(scalar, neighbor) < 0.0) {
+                velocity += params.gravity[axis] * dt;
+            }
+        } else {
+            velocity -= params.gravity[axis] * params.buoyancy * 0.5 * (scalar + neighbor) * dt;
+        }
+        // no flow through the volume bounds
+        if (id[axis] + 1u == params.dims[axis]) {
+            velocity = 0.0;
+        }
+        next[axis] = velocity;
+    }
+
+    next.w = sample_channel(3u, pos - velocity_at(pos) * dt / params.spacing);
+    if (params.mode == MODE_LIQUID) {
+        next.w = clamp(next.w, -params.band, params.band);
+    } else {
+        next.w *= max(1.0 - params.dissipation * dt, 0.0);
+    }
+    advected[cell(c)] = next;
+}
+
+@compute @workgroup_size(4, 4, 4)
+fn compute_divergence(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (any(id >= params.dims)) {
+        return;
+    }
+    let c = vec3<i32>(id);
+    let velocity = advected[cell(c)];
+    var sum = 0.0;
+    for (var axis = 0u; axis < 3u; axis++) {
+        sum += velocity[axis];
+        // the face on the lower bound is a wall
+        if (id[axis] > 0u) {
+            sum -= advected[cell(c - axis_offset(axis))][axis];
+        }
+    }
+    divergence[cell(c)] = sum / params.spacing;
+}
+
+// Jacobi iteration of the pressure Poisson equation, `odd` reads `pressure_scratch`
+fn jacobi(c: vec3<i32>, odd: bool) -> f32 {
+    let i = cell(c);
+    if (!in_fluid(advected[i].w)) {
+        return 0.0;
+    }
+    var sum = 0.0;
+    var count = 0.0;
+    for (var k = 0u; k < 6u; k++) {
+        let offset = axis_offset(k / 2u);
+        let n = select(c - offset, c + offset, k % 2u == 1u);
+        // solid walls, no pressure gradient across them
+        if (!in_grid(n)) {
+            continue;
+        }
+        count += 1.0;
+        let j = cell(n);
+        // air is at zero pressure
+        if (!in_fluid(advected[j].w)) {
+            continue;
+        }
+        sum += select(pressure[j], pressure_scratch[j], odd);
+    }
+    return (sum - params.spacing * params.spacing * divergence[i]) / count;
+}
+
+@compute @workgroup_size(4, 4, 4)
+fn jacobi_even(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (any(id >= params.dims)) {
+        return;
+    }
+    let c = vec3<i32>(id);
+    pressure_scratch[cell(c)] = jacobi(c, false);
+}
+
+@compute @workgroup_size(4, 4, 4)
+fn jacobi_odd(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (any(id >= params.dims)) {
+        return;
+    }
+    let c = vec3<i32>(id);
+    pressure[cell(c)] = jacobi(c, true);
+}
+
+// Projected velocity on the `axis` face of `c` in x, 1 in y when the face touches the fluid
+fn projected_face(c: vec3<i32>, axis: u32) -> vec2<f32> {
+    let n = c + axis_offset(axis);
+    if (!in_grid(c) || !in_grid(n)) {
+        return vec2(0.0);
+    }
+    let i = cell(c);
+    let j = cell(n);
+    if (!in_fluid(advected[i].w) && !in_fluid(advected[j].w)) {
+        return vec2(0.0);
+    }
+    return vec2(advected[i][axis] - (pressure[j] - pressure[i]) / params.spacing, 1.0);
+}
+
+// Removes the divergent part of the advected velocity
+@compute @workgroup_size(4, 4, 4)
+fn project(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (any(id >= params.dims)) {
+        return;
+    }
+    let c = vec3<i32>(id);
+    let current = advected[cell(c)];
+
+    var next = current;
+    for (var axis = 0u; axis < 3u; axis++) {
+        if (id[axis] + 1u == params.dims[axis]) {
+            next[axis] = 0.0;
+            continue;
+        }
+        let face = projected_face(c, axis);
+        if (face.y > 0.0) {
+            next[axis] = face.x;
+            continue;
+        }
+        // extrapolated one face into the air so the surface moves with the liquid,
+        // the rest of the air is at rest
+        var sum = vec2(0.0);
+        for (var k = 0u; k < 6u; k++) {
+            let offset = axis_offset(k / 2u);
+            sum += projected_face(select(c - offset, c + offset, k % 2u == 1u), axis);
+        }
+        next[axis] = select(0.0, sum.x / sum.y, sum.y > 0.0);
+    }
+    state[cell(c)] = next;
+}
+
+@compute @workgroup_size(4, 4, 4)
+fn write_density(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (any(id >= params.dims)) {
+        return;
+    }
+    let i = cell(vec3<i32>(id));
+    let scalar = state[i].w;
+    // the level set is negative inside
+    density[i] = select(scalar, -scalar, params.mode == MODE_LIQUID);
+}
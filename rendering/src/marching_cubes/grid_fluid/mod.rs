@@ -0,0 +1,197 @@
+use bevy_app::{App, Plugin, PostUpdate};
+use bevy_asset::{Assets, Handle, RenderAssetUsages};
+use bevy_ecs::change_detection::DetectChanges;
+use bevy_ecs::component::Component;
+use bevy_ecs::entity::Entity;
+use bevy_ecs::schedule::IntoScheduleConfigs;
+use bevy_ecs::system::{Commands, Query, Res, ResMut};
+use bevy_ecs::world::Ref;
+use bevy_math::bounding::Aabb3d;
+use bevy_math::{UVec3, Vec3};
+use bevy_render::RenderApp;
+use bevy_render::storage::ShaderStorageBuffer;
+use bevy_time::{Time, Virtual};
+
+use super::fluid::FluidStepping;
+use super::{DensitySource, MarchingCubesBuffers, VoxelVolume, density_buffer};
+
+mod cpu;
+pub mod gpu;
+
+pub use cpu::GridFluidState;
+
+/// Width of the level set around the surface in voxels, values further away are clamped.
+const LEVEL_SET_BAND: f32 = 4.0;
+
+/// Simulates a [`GridFluid`] on the GPU for [`DensitySource::GridFluid`].
+pub struct GridFluidPlugin;
+
+impl Plugin for GridFluidPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PostUpdate,
+            (rebuild_grid_fluid_buffers, advance_grid_fluid).chain(),
+        );
+
+        if app.get_sub_app(RenderApp).is_some() {
+            app.add_plugins(gpu::GpuGridFluidPlugin);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GridFluidMode {
+    /// Level set transported by the flow, falling with gravity.
+    #[default]
+    Liquid,
+    /// Density carried by the flow, rising with [`GridFluidSettings::buoyancy`].
+    Smoke,
+}
+
+impl GridFluidMode {
+    // must match the MODE_* constants in grid_fluid.wgsl
+    fn shader_index(self) -> u32 {
+        match self {
+            GridFluidMode::Liquid => 0,
+            GridFluidMode::Smoke => 1,
+        }
+    }
+}
+
+/// Stable fluids parameters, the grid itself is the one of the [`VoxelVolume`].
+#[derive(Clone, Copy, Debug)]
+pub struct GridFluidSettings {
+    pub mode: GridFluidMode,
+    pub gravity: Vec3,
+    /// Fixed simulation step in seconds.
+    pub time_step: f32,
+    /// Rounded up to an even count.
+    pub jacobi_iterations: u32,
+    /// Smoke acceleration against gravity per unit of density.
+    pub buoyancy: f32,
+    /// Fraction of the smoke density lost per second.
+    pub dissipation: f32,
+}
+
+impl Default for GridFluidSettings {
+    fn default() -> Self {
+        Self {
+            mode: GridFluidMode::default(),
+            gravity: Vec3::new(0.0, -9.81, 0.0),
+            time_step: 1.0 / 120.0,
+            jacobi_iterations: 40,
+            buoyancy: 0.2,
+            dissipation: 0.1,
+        }
+    }
+}
+
+impl GridFluidSettings {
+    /// Jacobi iterations run in pairs, so the pressure always ends in the same buffer.
+    pub fn jacobi_pairs(&self) -> u32 {
+        self.jacobi_iterations.div_ceil(2).max(1)
+    }
+}
+
+/// Fluid simulated on the [`VoxelVolume`] grid when its density is
+/// [`DensitySource::GridFluid`], only the first entity is simulated.
+///
+/// Changing the component, or the volume, restarts the simulation from `regions`.
+#[derive(Component, Clone, Debug, Default)]
+pub struct GridFluid {
+    pub settings: GridFluidSettings,
+    /// Filled with liquid, or smoke of density 1, at the start.
+    pub regions: Vec<Aabb3d>,
+    pub stepping: FluidStepping,
+}
+
+impl GridFluid {
+    pub fn new(settings: GridFluidSettings, regions: impl IntoIterator<Item = Aabb3d>) -> Self {
+        Self {
+            settings,
+            regions: regions.into_iter().collect(),
+            stepping: FluidStepping::default(),
+        }
+    }
+}
+
+/// GPU state of the running simulation, on the [`GridFluid`] entity.
+#[derive(Component, Clone)]
+pub(crate) struct GridFluidBuffers {
+    state: Handle<ShaderStorageBuffer>,
+    advected: Handle<ShaderStorageBuffer>,
+    pressure: Handle<ShaderStorageBuffer>,
+    pressure_scratch: Handle<ShaderStorageBuffer>,
+    divergence: Handle<ShaderStorageBuffer>,
+    dims: UVec3,
+    /// Steps to run this frame.
+    steps: u32,
+    accumulator: f32,
+}
+
+fn rebuild_grid_fluid_buffers(
+    mut commands: Commands,
+    fluids: Query<(Entity, Ref<GridFluid>, Option<&GridFluidBuffers>)>,
+    voxel_volume: Res<VoxelVolume>,
+    marching_cubes_buffers: Res<MarchingCubesBuffers>,
+    mut storage_buffers: ResMut<Assets<ShaderStorageBuffer>>,
+) {
+    let active = matches!(voxel_volume.density, DensitySource::GridFluid);
+    for (index, (entity, fluid, fluid_buffers)) in fluids.iter().enumerate() {
+        if !active || index > 0 {
+            if fluid_buffers.is_some() {
+                commands.entity(entity).remove::<GridFluidBuffers>();
+            }
+            continue;
+        }
+        if fluid_buffers.is_some() && !fluid.is_changed() && !voxel_volume.is_changed() {
+            continue;
+        }
+
+        let state = GridFluidState::new(&fluid, &voxel_volume);
+        let cell_count = state.cells.len();
+        let zeroed = || {
+            ShaderStorageBuffer::with_size(
+                cell_count * size_of::<f32>(),
+                RenderAssetUsages::RENDER_WORLD,
+            )
+        };
+
+        // the density is written into the buffer the compute stage reads sampled volumes from
+        storage_buffers.insert(
+            &marching_cubes_buffers.densities,
+            density_buffer(&vec![0.0; cell_count]),
+        );
+
+        commands.entity(entity).insert(GridFluidBuffers {
+            state: storage_buffers.add(ShaderStorageBuffer::new(
+                bytemuck::cast_slice(&state.cells),
+                RenderAssetUsages::RENDER_WORLD,
+            )),
+            advected: storage_buffers.add(ShaderStorageBuffer::with_size(
+                cell_count * size_of::<[f32; 4]>(),
+                RenderAssetUsages::RENDER_WORLD,
+            )),
+            pressure: storage_buffers.add(zeroed()),
+            pressure_scratch: storage_buffers.add(zeroed()),
+            divergence: storage_buffers.add(zeroed()),
+            dims: state.dims,
+            steps: 0,
+            accumulator: 0.0,
+        });
+    }
+}
+
+fn advance_grid_fluid(
+    mut fluids: Query<(&GridFluid, &mut GridFluidBuffers)>,
+    time: Res<Time<Virtual>>,
+) {
+    for (fluid, mut fluid_buffers) in &mut fluids {
+        let fluid_buffers = &mut *fluid_buffers;
+        fluid_buffers.steps = fluid.stepping.steps(
+            fluid.settings.time_step,
+            time.delta_secs(),
+            &mut fluid_buffers.accumulator,
+        );
+    }
+}
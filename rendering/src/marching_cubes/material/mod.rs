@@ -0,0 +1,63 @@
+use bevy_app::{App, Plugin};
+use bevy_color::{ColorToComponents, LinearRgba};
+use bevy_ecs::resource::Resource;
+use bevy_math::Vec4;
+use bevy_render::extract_resource::{ExtractResource, ExtractResourcePlugin};
+
+/// Materials a surface can blend, ids past the last one use the last material.
+pub const MAX_MATERIALS: usize = 4;
+
+/// Extracts the [`VoxelMaterials`] palette for the display stage.
+pub struct VoxelMaterialPlugin;
+
+impl Plugin for VoxelMaterialPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<VoxelMaterials>();
+        app.add_plugins(ExtractResourcePlugin::<VoxelMaterials>::default());
+    }
+}
+
+/// Colors of the surface materials, indexed by material id.
+///
+/// Material ids come from [`ScalarVolume::materials`](super::ScalarVolume::materials) for
+/// sampled volumes and from the depth layers of
+/// [`TerrainGenerator::material`](super::TerrainGenerator::material), everything else is
+/// material 0.
+#[derive(Resource, Clone, Debug, ExtractResource)]
+pub struct VoxelMaterials {
+    pub colors: [LinearRgba; MAX_MATERIALS],
+}
+
+impl Default for VoxelMaterials {
+    fn default() -> Self {
+        Self {
+            colors: [
+                // grass, sand, rock, deep rock
+                LinearRgba::rgb(0.12, 0.3, 0.05),
+                LinearRgba::rgb(0.55, 0.45, 0.25),
+                LinearRgba::rgb(0.2, 0.19, 0.18),
+                LinearRgba::rgb(0.05, 0.05, 0.06),
+            ],
+        }
+    }
+}
+
+impl VoxelMaterials {
+    /// Color of blended `weights`, mirrors `fragment` in `display_stage.wgsl`.
+    pub fn blend(&self, weights: Vec4) -> LinearRgba {
+        let total = weights.element_sum().max(1e-6);
+        let mut color = Vec4::ZERO;
+        for (weight, material) in weights.to_array().into_iter().zip(self.colors) {
+            color += weight * material.to_vec4();
+        }
+        LinearRgba::from_vec4(color / total)
+    }
+}
+
+/// Weights of a vertex showing a single material.
+#[inline]
+pub fn material_weights(material: u32) -> Vec4 {
+    let mut weights = [0.0; MAX_MATERIALS];
+    weights[(material as usize).min(MAX_MATERIALS - 1)] = 1.0;
+    Vec4::from_array(weights)
+}
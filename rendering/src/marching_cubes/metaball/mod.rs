@@ -0,0 +1,187 @@
+use bevy_app::{App, Plugin};
+use bevy_ecs::component::Component;
+use bevy_ecs::resource::Resource;
+use bevy_ecs::schedule::IntoScheduleConfigs;
+use bevy_ecs::system::{Commands, Query, Res, ResMut};
+use bevy_math::Vec3;
+use bevy_math::bounding::{BoundingSphere, IntersectsVolume};
+use bevy_render::render_resource::{ShaderType, StorageBuffer};
+use bevy_render::renderer::{RenderDevice, RenderQueue};
+use bevy_render::{Extract, ExtractSchedule, Render, RenderApp, RenderSet};
+use bevy_transform::components::{GlobalTransform, Transform};
+
+use super::VoxelVolume;
+
+/// Gathers [`Metaball`]s for [`DensitySource::Metaballs`](super::DensitySource::Metaballs).
+pub struct MetaballPlugin;
+
+impl Plugin for MetaballPlugin {
+    fn build(&self, app: &mut App) {
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .init_resource::<ExtractedMetaballs>()
+            .init_resource::<MetaballBuffer>();
+        render_app.add_systems(ExtractSchedule, extract_metaballs);
+        render_app.add_systems(
+            Render,
+            prepare_metaball_buffer.in_set(RenderSet::PrepareResources),
+        );
+    }
+}
+
+/// Blob whose field is summed with the other metaballs, placed by its [`Transform`].
+///
+/// The radius is scaled by the largest scale axis of the entity.
+#[derive(Component, Clone, Copy, Debug)]
+#[require(Transform)]
+pub struct Metaball {
+    /// Distance at which the field drops to zero.
+    pub radius: f32,
+    /// Field value at the center, negative values carve into other blobs.
+    pub strength: f32,
+    pub kernel: MetaballKernel,
+}
+
+impl Default for Metaball {
+    fn default() -> Self {
+        Self {
+            radius: 0.5,
+            strength: 1.0,
+            kernel: MetaballKernel::default(),
+        }
+    }
+}
+
+/// Falloff from the center to [`Metaball::radius`], `t` is `distance / radius`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MetaballKernel {
+    /// `(1 - t²)³`, smooth at the center and the border.
+    #[default]
+    Wyvill,
+    /// `(1 - t²)²`, softer blobs.
+    Quadratic,
+    /// `1 - t`, cone shaped.
+    Linear,
+}
+
+impl MetaballKernel {
+    // must match the KERNEL_* constants in density.wgsl
+    fn shader_index(self) -> u32 {
+        match self {
+            MetaballKernel::Wyvill => 0,
+            MetaballKernel::Quadratic => 1,
+            MetaballKernel::Linear => 2,
+        }
+    }
+
+    pub fn falloff(self, t: f32) -> f32 {
+        if t >= 1.0 {
+            return 0.0;
+        }
+        let s = 1.0 - t * t;
+        match self {
+            MetaballKernel::Wyvill => s * s * s,
+            MetaballKernel::Quadratic => s * s,
+            MetaballKernel::Linear => 1.0 - t,
+        }
+    }
+}
+
+/// World space metaball, `Metaball` in `density.wgsl`.
+#[derive(ShaderType, Clone, Copy, Debug, Default)]
+pub struct MetaballInstance {
+    pub center: Vec3,
+    pub radius: f32,
+    pub strength: f32,
+    kernel: u32,
+}
+
+impl MetaballInstance {
+    pub fn new(metaball: &Metaball, transform: &GlobalTransform) -> Self {
+        let (scale, _, translation) = transform.to_scale_rotation_translation();
+        Self {
+            center: translation,
+            radius: metaball.radius * scale.abs().max_element(),
+            strength: metaball.strength,
+            kernel: metaball.kernel.shader_index(),
+        }
+    }
+
+    pub fn kernel(&self) -> MetaballKernel {
+        match self.kernel {
+            1 => MetaballKernel::Quadratic,
+            2 => MetaballKernel::Linear,
+            _ => MetaballKernel::Wyvill,
+        }
+    }
+
+    #[inline]
+    pub fn density(&self, pos: Vec3) -> f32 {
+        if self.radius <= 0.0 {
+            return 0.0;
+        }
+        self.strength
+            * self
+                .kernel()
+                .falloff(pos.distance(self.center) / self.radius)
+    }
+}
+
+/// Metaballs reaching into `volume`.
+pub fn gather_metaballs<'a>(
+    volume: &VoxelVolume,
+    metaballs: impl IntoIterator<Item = (&'a Metaball, &'a GlobalTransform)>,
+) -> Vec<MetaballInstance> {
+    metaballs
+        .into_iter()
+        .map(|(metaball, transform)| MetaballInstance::new(metaball, transform))
+        .filter(|instance| {
+            BoundingSphere::new(instance.center, instance.radius).intersects(&volume.aabb)
+        })
+        .collect()
+}
+
+/// Summed field of `metaballs`, mirrors `metaball_density` in `density.wgsl`.
+pub fn metaball_density(metaballs: &[MetaballInstance], pos: Vec3) -> f32 {
+    metaballs.iter().map(|metaball| metaball.density(pos)).sum()
+}
+
+#[derive(Resource, Default)]
+struct ExtractedMetaballs(Vec<MetaballInstance>);
+
+fn extract_metaballs(
+    mut commands: Commands,
+    voxel_volume: Extract<Res<VoxelVolume>>,
+    metaballs: Extract<Query<(&Metaball, &GlobalTransform)>>,
+) {
+    commands.insert_resource(ExtractedMetaballs(gather_metaballs(
+        &voxel_volume,
+        metaballs.iter(),
+    )));
+}
+
+#[derive(Resource, Default)]
+pub struct MetaballBuffer {
+    pub buffer: StorageBuffer<Vec<MetaballInstance>>,
+}
+
+fn prepare_metaball_buffer(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut metaball_buffer: ResMut<MetaballBuffer>,
+    metaballs: Res<ExtractedMetaballs>,
+) {
+    let buffer = metaball_buffer.buffer.get_mut();
+    buffer.clone_from(&metaballs.0);
+    // empty bindings are not allowed, a zero strength ball adds nothing
+    if buffer.is_empty() {
+        buffer.push(MetaballInstance::default());
+    }
+
+    metaball_buffer
+        .buffer
+        .write_buffer(&render_device, &render_queue);
+}
@@ -1,28 +1,92 @@
-use bevy_app::{App, Plugin};
-use bevy_asset::{Assets, Handle, RenderAssetUsages};
+use bevy_app::{App, Plugin, PostUpdate};
+use bevy_asset::{AssetEvent, AssetId, Assets, Handle, RenderAssetUsages};
+use bevy_ecs::event::EventReader;
 use bevy_ecs::resource::Resource;
+use bevy_ecs::schedule::IntoScheduleConfigs;
+use bevy_ecs::schedule::common_conditions::resource_changed;
+use bevy_ecs::system::{Local, Res, ResMut};
 use bevy_ecs::world::FromWorld;
-use bevy_math::Vec3;
+use bevy_math::{UVec3, Vec3, Vec4};
 use bevy_render::extract_resource::{ExtractResource, ExtractResourcePlugin};
 use bevy_render::render_resource::BufferUsages;
 use bevy_render::storage::ShaderStorageBuffer;
 
+pub use automaton::{AutomatonRule, CellularAutomaton, StepAutomaton};
 use bytemuck::{Pod, Zeroable};
-pub use compute_stage::VoxelVolume;
+pub use clipping::{ClipCap, ClipPlane, VoxelClipping};
+pub use compute_stage::{BRICK_SIZE, DensitySource, SurfaceExtraction, TimeSource, VoxelVolume};
+pub use cpu_mesher::IsoSurface;
+pub use fluid::{SphFluid, SphSettings};
+pub use grid_fluid::{GridFluid, GridFluidMode, GridFluidSettings};
+pub use material::VoxelMaterials;
+pub use metaball::{Metaball, MetaballKernel};
+pub use noise::NoiseDensity;
+pub use occlusion::AmbientOcclusion;
+pub use terrain::{TerrainGenerator, TunnelSettings};
+pub use volume::ScalarVolume;
+pub use voxelize::{CsgOp, VoxelizeMesh};
 
+use crate::scan::ScanPlugin;
+
+pub mod automaton;
+pub mod clipping;
 pub mod compute_stage;
+pub mod cpu_mesher;
 pub mod display_stage;
+pub mod export;
+pub mod fluid;
+pub mod grid_fluid;
+pub mod material;
+pub mod metaball;
+pub mod noise;
+pub mod occlusion;
+pub mod terrain;
+pub mod volume;
+pub mod voxelize;
 
-pub struct MarchingCubesPlugin;
+#[derive(Default)]
+pub struct MarchingCubesPlugin {
+    pub extraction: SurfaceExtraction,
+}
 
 impl Plugin for MarchingCubesPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(compute_stage::MarchingCubesComputePlugin);
+        app.add_plugins((
+            volume::ScalarVolumePlugin,
+            noise::NoisePlugin,
+            terrain::TerrainPlugin,
+            clipping::ClippingPlugin,
+            metaball::MetaballPlugin,
+            compute_stage::MarchingCubesComputePlugin {
+                extraction: self.extraction,
+            },
+            material::VoxelMaterialPlugin,
+            export::IsoSurfaceExportPlugin,
+            voxelize::MeshVoxelizePlugin,
+            // after the compute stage, the simulation nodes run before it
+            fluid::SphFluidPlugin,
+            grid_fluid::GridFluidPlugin,
+            automaton::CellularAutomatonPlugin,
+        ));
+        // shared with any other plugin packing its output by a scan
+        if !app.is_plugin_added::<ScanPlugin>() {
+            app.add_plugins(ScanPlugin);
+        }
 
         app.init_resource::<MarchingCubesBuffers>();
         app.add_plugins(ExtractResourcePlugin::<MarchingCubesBuffers>::default());
+        app.add_systems(
+            PostUpdate,
+            (
+                resize_marching_cubes_buffers.run_if(resource_changed::<VoxelVolume>),
+                upload_sampled_density,
+            ),
+        );
 
-        app.add_plugins(display_stage::VoxelRenderedPlugin);
+        app.add_plugins((
+            display_stage::VoxelRenderedPlugin,
+            display_stage::VolumeRenderedPlugin,
+        ));
     }
 }
 
@@ -32,28 +96,185 @@ const MAX_VERTS_PER_VOXEL: usize = 12;
 #[repr(C)]
 struct Vertex {
     position: Vec3,
-    _pad0: f32,
+    /// Ambient light reaching the vertex, see [`AmbientOcclusion`].
+    occlusion: f32,
+    /// Normalized field gradient, the smooth shading normal.
+    normal: Vec3,
+    /// Field before the clipping, the density behind a [`ClipCap::Colormap`] cut.
+    section: f32,
+    /// One weight per material, see [`material::material_weights`].
+    material_weights: Vec4,
+}
+
+/// Draw indirect args of the vertices of a [`BRICK_SIZE`] cube of cells.
+///
+/// The args of the bricks are packed without padding, a run of neighboring bricks is drawn with
+/// one multi draw. Devices without multi draw indirect draw a run of scanned bricks from one
+/// merged arg and the bricks of the histo pyramid one by one.
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C)]
+struct VoxelBrick {
+    vertex_count: u32,
+    instance_count: u32,
+    first_vertex: u32,
+    first_instance: u32,
 }
 
 #[derive(Resource, Clone, ExtractResource)]
 pub struct MarchingCubesBuffers {
+    /// Vertices of every cell packed by their scanned offsets, grouped by brick.
     vertices: Handle<ShaderStorageBuffer>,
+    /// Samples of [`DensitySource::Sampled`] volume, a single zero otherwise.
+    densities: Handle<ShaderStorageBuffer>,
+    /// Material ids of the sampled volume, a single zero without materials.
+    materials: Handle<ShaderStorageBuffer>,
+    /// Field on every voxel corner.
+    samples: Handle<ShaderStorageBuffer>,
+    /// One [`VoxelBrick`] per brick.
+    bricks: Handle<ShaderStorageBuffer>,
+    /// Vertex count of every cell, grouped by brick.
+    cell_counts: Handle<ShaderStorageBuffer>,
+    /// Exclusive scan of the cell counts followed by the total, the first vertex of every cell.
+    cell_offsets: Handle<ShaderStorageBuffer>,
+    dims: UVec3,
 }
 
 impl FromWorld for MarchingCubesBuffers {
     fn from_world(world: &mut bevy_ecs::world::World) -> Self {
         let voxel_volume = world.resource::<VoxelVolume>();
-        let buffer_size = voxel_volume.count_all() as usize;
-        tracing::info!("Voxels Count: {}", buffer_size);
+        let dims = voxel_volume.count_dims();
+        let brick_count = voxel_volume.brick_dims().element_product();
 
         let mut storage_buffers = world.resource_mut::<Assets<ShaderStorageBuffer>>();
-        let mut vertex_buffer = ShaderStorageBuffer::with_size(
-            MAX_VERTS_PER_VOXEL * size_of::<Vertex>() * buffer_size,
-            RenderAssetUsages::RENDER_WORLD,
-        );
-        vertex_buffer.buffer_description.usage |= BufferUsages::VERTEX;
-        let vertices = storage_buffers.add(vertex_buffer);
+        let vertices = storage_buffers.add(vertex_buffer(brick_count));
+        let densities = storage_buffers.add(density_buffer(&[0.0]));
+        let materials = storage_buffers.add(material_buffer(None));
+        let samples = storage_buffers.add(sample_buffer(dims));
+        let bricks = storage_buffers.add(brick_buffer(brick_count));
+        let cell_counts = storage_buffers.add(cell_buffer(brick_count * BRICK_SIZE.pow(3)));
+        let cell_offsets = storage_buffers.add(cell_buffer(brick_count * BRICK_SIZE.pow(3) + 1));
+
+        Self {
+            vertices,
+            densities,
+            materials,
+            samples,
+            bricks,
+            cell_counts,
+            cell_offsets,
+            dims,
+        }
+    }
+}
+
+fn vertex_buffer(brick_count: u32) -> ShaderStorageBuffer {
+    // bricks on the far faces of the volume are partially filled
+    let voxel_count = brick_count * BRICK_SIZE.pow(3);
+    tracing::info!("Voxels Count: {}", voxel_count);
+
+    let mut vertex_buffer = ShaderStorageBuffer::with_size(
+        MAX_VERTS_PER_VOXEL * size_of::<Vertex>() * voxel_count as usize,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    // COPY_DST for clearing between runs, COPY_SRC for readback
+    vertex_buffer.buffer_description.usage |=
+        BufferUsages::VERTEX | BufferUsages::COPY_DST | BufferUsages::COPY_SRC;
+    vertex_buffer
+}
+
+fn sample_buffer(dims: UVec3) -> ShaderStorageBuffer {
+    ShaderStorageBuffer::with_size(
+        size_of::<f32>() * (dims + UVec3::ONE).element_product() as usize,
+        RenderAssetUsages::RENDER_WORLD,
+    )
+}
+
+fn brick_buffer(brick_count: u32) -> ShaderStorageBuffer {
+    let mut brick_buffer = ShaderStorageBuffer::with_size(
+        size_of::<VoxelBrick>() * brick_count.max(1) as usize,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    // the display stage draws every brick from its args
+    brick_buffer.buffer_description.usage |= BufferUsages::INDIRECT;
+    brick_buffer
+}
+
+fn cell_buffer(count: u32) -> ShaderStorageBuffer {
+    ShaderStorageBuffer::with_size(
+        size_of::<u32>() * count.max(1) as usize,
+        RenderAssetUsages::RENDER_WORLD,
+    )
+}
+
+fn density_buffer(values: &[f32]) -> ShaderStorageBuffer {
+    ShaderStorageBuffer::new(
+        bytemuck::cast_slice(values),
+        RenderAssetUsages::RENDER_WORLD,
+    )
+}
+
+fn material_buffer(materials: Option<&[u8]>) -> ShaderStorageBuffer {
+    let ids: Vec<u32> = match materials {
+        Some(materials) => materials.iter().map(|&id| id as u32).collect(),
+        None => vec![0],
+    };
+    ShaderStorageBuffer::new(bytemuck::cast_slice(&ids), RenderAssetUsages::RENDER_WORLD)
+}
+
+fn resize_marching_cubes_buffers(
+    voxel_volume: Res<VoxelVolume>,
+    mut marching_cubes_buffers: ResMut<MarchingCubesBuffers>,
+    mut storage_buffers: ResMut<Assets<ShaderStorageBuffer>>,
+) {
+    let dims = voxel_volume.count_dims();
+    if dims == marching_cubes_buffers.dims {
+        return;
+    }
 
-        Self { vertices }
+    let brick_count = voxel_volume.brick_dims().element_product();
+    marching_cubes_buffers.dims = dims;
+    storage_buffers.insert(&marching_cubes_buffers.vertices, vertex_buffer(brick_count));
+    storage_buffers.insert(&marching_cubes_buffers.samples, sample_buffer(dims));
+    storage_buffers.insert(&marching_cubes_buffers.bricks, brick_buffer(brick_count));
+    let cell_count = brick_count * BRICK_SIZE.pow(3);
+    storage_buffers.insert(&marching_cubes_buffers.cell_counts, cell_buffer(cell_count));
+    storage_buffers.insert(
+        &marching_cubes_buffers.cell_offsets,
+        cell_buffer(cell_count + 1),
+    );
+}
+
+fn upload_sampled_density(
+    voxel_volume: Res<VoxelVolume>,
+    marching_cubes_buffers: Res<MarchingCubesBuffers>,
+    scalar_volumes: Res<Assets<ScalarVolume>>,
+    mut scalar_volume_events: EventReader<AssetEvent<ScalarVolume>>,
+    mut storage_buffers: ResMut<Assets<ShaderStorageBuffer>>,
+    mut uploaded: Local<Option<AssetId<ScalarVolume>>>,
+) {
+    let DensitySource::Sampled(handle) = &voxel_volume.density else {
+        scalar_volume_events.clear();
+        *uploaded = None;
+        return;
+    };
+
+    let mut asset_changed = false;
+    for event in scalar_volume_events.read() {
+        asset_changed |= event.is_loaded_with_dependencies(handle) || event.is_modified(handle);
+    }
+    if *uploaded == Some(handle.id()) && !asset_changed {
+        return;
+    }
+
+    if let Some(scalar_volume) = scalar_volumes.get(handle) {
+        storage_buffers.insert(
+            &marching_cubes_buffers.densities,
+            density_buffer(&scalar_volume.values),
+        );
+        storage_buffers.insert(
+            &marching_cubes_buffers.materials,
+            material_buffer(scalar_volume.materials.as_deref()),
+        );
+        *uploaded = Some(handle.id());
     }
 }
@@ -0,0 +1,418 @@
+use bevy_app::{App, Plugin};
+use bevy_asset::{Handle, load_internal_asset, weak_handle};
+use bevy_math::{IVec3, UVec3, Vec3, Vec3Swizzles};
+use bevy_render::render_resource::{Shader, ShaderType};
+
+pub const NOISE_SHADER_HANDLE: Handle<Shader> =
+    weak_handle!("0b9a6c3e-58d2-4f7b-a1e4-6d2f8c9b7a30");
+
+/// Registers `noise.wgsl` as the `marching_cubes::noise` shader import.
+pub struct NoisePlugin;
+
+impl Plugin for NoisePlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(app, NOISE_SHADER_HANDLE, "noise.wgsl", Shader::from_wgsl);
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NoiseKind {
+    /// Perlin style gradient noise.
+    #[default]
+    Gradient,
+    Simplex,
+    /// Cellular noise, positive around the feature points.
+    Worley,
+}
+
+impl NoiseKind {
+    // must match the NOISE_* constants in noise.wgsl
+    fn shader_index(self) -> u32 {
+        match self {
+            NoiseKind::Gradient => 0,
+            NoiseKind::Simplex => 1,
+            NoiseKind::Worley => 2,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FractalKind {
+    /// A single octave.
+    None,
+    #[default]
+    Fbm,
+    /// Ridged multifractal, sharp crests where the base noise crosses zero.
+    Ridged,
+}
+
+impl FractalKind {
+    // must match the FRACTAL_* constants in noise.wgsl
+    fn shader_index(self) -> u32 {
+        match self {
+            FractalKind::None => 0,
+            FractalKind::Fbm => 1,
+            FractalKind::Ridged => 2,
+        }
+    }
+}
+
+/// Procedural density, `noise(pos) * amplitude - pos.y * height_gradient`, positive inside.
+///
+/// With a `height_gradient` of zero the noise fills the whole volume (caves, clouds),
+/// larger values turn it into terrain around `y = 0`. Evaluated on the GPU by
+/// `noise_density` in `noise.wgsl` and on the CPU by [`NoiseDensity::density`].
+#[derive(Clone, Copy, Debug)]
+pub struct NoiseDensity {
+    pub kind: NoiseKind,
+    pub fractal: FractalKind,
+    pub seed: u32,
+    /// Frequency of the first octave.
+    pub frequency: f32,
+    pub octaves: u32,
+    /// Frequency multiplier between octaves.
+    pub lacunarity: f32,
+    /// Amplitude multiplier between octaves.
+    pub gain: f32,
+    pub amplitude: f32,
+    /// Domain warp offset in noise space, 0 disables warping.
+    pub warp: f32,
+    pub warp_frequency: f32,
+    pub height_gradient: f32,
+}
+
+impl Default for NoiseDensity {
+    fn default() -> Self {
+        Self {
+            kind: NoiseKind::default(),
+            fractal: FractalKind::default(),
+            seed: 0,
+            frequency: 2.0,
+            octaves: 5,
+            lacunarity: 2.0,
+            gain: 0.5,
+            amplitude: 0.5,
+            warp: 0.0,
+            warp_frequency: 1.0,
+            height_gradient: 1.0,
+        }
+    }
+}
+
+/// `NoiseParams` in `noise.wgsl`.
+#[derive(ShaderType, Clone, Copy, Default, Debug, PartialEq)]
+pub struct NoiseUniform {
+    kind: u32,
+    fractal: u32,
+    seed: u32,
+    octaves: u32,
+    frequency: f32,
+    lacunarity: f32,
+    gain: f32,
+    amplitude: f32,
+    warp: f32,
+    warp_frequency: f32,
+    height_gradient: f32,
+    padding: u32,
+}
+
+impl From<&NoiseDensity> for NoiseUniform {
+    fn from(noise: &NoiseDensity) -> Self {
+        Self {
+            kind: noise.kind.shader_index(),
+            fractal: noise.fractal.shader_index(),
+            seed: noise.seed,
+            octaves: noise.octaves,
+            frequency: noise.frequency,
+            lacunarity: noise.lacunarity,
+            gain: noise.gain,
+            amplitude: noise.amplitude,
+            warp: noise.warp,
+            warp_frequency: noise.warp_frequency,
+            height_gradient: noise.height_gradient,
+            padding: 0,
+        }
+    }
+}
+
+impl NoiseDensity {
+    pub fn density(&self, pos: Vec3) -> f32 {
+        let mut p = pos * self.frequency;
+        if self.warp != 0.0 {
+            let q = pos * self.warp_frequency;
+            let warp = Vec3::new(
+                gradient_noise(q, self.seed ^ 1),
+                gradient_noise(q, self.seed ^ 2),
+                gradient_noise(q, self.seed ^ 3),
+            );
+            p += self.warp * warp;
+        }
+
+        let value = match self.fractal {
+            FractalKind::None => self.base_noise(p, self.seed),
+            FractalKind::Fbm => self.fbm(p),
+            FractalKind::Ridged => self.ridged(p),
+        };
+        value * self.amplitude - pos.y * self.height_gradient
+    }
+
+    fn base_noise(&self, p: Vec3, seed: u32) -> f32 {
+        match self.kind {
+            NoiseKind::Gradient => gradient_noise(p, seed),
+            NoiseKind::Simplex => simplex_noise(p, seed),
+            NoiseKind::Worley => worley_noise(p, seed),
+        }
+    }
+
+    /// Fractal Brownian motion, every octave uses its own seed.
+    pub fn fbm(&self, p: Vec3) -> f32 {
+        let mut sum = 0.0;
+        let mut amplitude = 0.5;
+        let mut frequency = 1.0;
+        for i in 0..self.octaves {
+            sum += amplitude * self.base_noise(p * frequency, self.seed.wrapping_add(i));
+            frequency *= self.lacunarity;
+            amplitude *= self.gain;
+        }
+        sum
+    }
+
+    /// Ridged multifractal (Musgrave).
+    pub fn ridged(&self, p: Vec3) -> f32 {
+        let mut sum = 0.0;
+        let mut amplitude = 0.5;
+        let mut frequency = 1.0;
+        let mut weight = 1.0;
+        for i in 0..self.octaves {
+            let ridge = 1.0
+                - self
+                    .base_noise(p * frequency, self.seed.wrapping_add(i))
+                    .abs();
+            let signal = ridge * ridge * weight;
+            weight = (signal * 2.0).clamp(0.0, 1.0);
+            sum += amplitude * signal;
+            frequency *= self.lacunarity;
+            amplitude *= self.gain;
+        }
+        sum
+    }
+}
+
+/// Hash Functions for GPU Rendering, Jarzynski and Olano 2020.
+fn pcg3d(input: UVec3) -> UVec3 {
+    let mut v = input
+        .wrapping_mul(UVec3::splat(1664525))
+        .wrapping_add(UVec3::splat(1013904223));
+    v.x = v.x.wrapping_add(v.y.wrapping_mul(v.z));
+    v.y = v.y.wrapping_add(v.z.wrapping_mul(v.x));
+    v.z = v.z.wrapping_add(v.x.wrapping_mul(v.y));
+    v = v ^ (v >> 16u32);
+    v.x = v.x.wrapping_add(v.y.wrapping_mul(v.z));
+    v.y = v.y.wrapping_add(v.z.wrapping_mul(v.x));
+    v.z = v.z.wrapping_add(v.x.wrapping_mul(v.y));
+    v
+}
+
+pub(crate) fn hash_cell(cell: IVec3, seed: u32) -> UVec3 {
+    let seed = UVec3::new(0x9e3779b9, 0x85ebca6b, 0xc2b2ae35).wrapping_mul(UVec3::splat(seed));
+    pcg3d(cell.as_uvec3().wrapping_add(seed))
+}
+
+// [0, 1) with 24 bits, exact on CPU and GPU
+pub(crate) fn to_unit(h: UVec3) -> Vec3 {
+    (h >> 8u32).as_vec3() / 16777216.0
+}
+
+fn cell_gradient(cell: IVec3, seed: u32) -> Vec3 {
+    to_unit(hash_cell(cell, seed)) * 2.0 - 1.0
+}
+
+/// Perlin style gradient noise, roughly `[-1, 1]`.
+pub fn gradient_noise(p: Vec3, seed: u32) -> f32 {
+    let floored = p.floor();
+    let cell = floored.as_ivec3();
+    let f = p - floored;
+    let u = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+
+    let corner = |x: i32, y: i32, z: i32| {
+        let offset = IVec3::new(x, y, z);
+        cell_gradient(cell + offset, seed).dot(f - offset.as_vec3())
+    };
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+
+    let x0 = lerp(corner(0, 0, 0), corner(1, 0, 0), u.x);
+    let x1 = lerp(corner(0, 1, 0), corner(1, 1, 0), u.x);
+    let x2 = lerp(corner(0, 0, 1), corner(1, 0, 1), u.x);
+    let x3 = lerp(corner(0, 1, 1), corner(1, 1, 1), u.x);
+    lerp(lerp(x0, x1, u.y), lerp(x2, x3, u.y), u.z)
+}
+
+/// 3D simplex noise (Gustavson), roughly `[-1, 1]`.
+pub fn simplex_noise(p: Vec3, seed: u32) -> f32 {
+    let skew = (p.x + p.y + p.z) / 3.0;
+    let floored = (p + skew).floor();
+    let cell = floored.as_ivec3();
+    let unskew = (floored.x + floored.y + floored.z) / 6.0;
+    let x0 = p - floored + unskew;
+
+    let g = Vec3::select(x0.cmpge(x0.yzx()), Vec3::ONE, Vec3::ZERO);
+    let l = 1.0 - g;
+    let i1 = g.min(l.zxy());
+    let i2 = g.max(l.zxy());
+
+    let x1 = x0 - i1 + 1.0 / 6.0;
+    let x2 = x0 - i2 + 2.0 / 6.0;
+    let x3 = x0 - 1.0 + 3.0 / 6.0;
+
+    let corner = |cell: IVec3, x: Vec3| {
+        let t = (0.6 - x.dot(x)).max(0.0);
+        let t2 = t * t;
+        t2 * t2 * cell_gradient(cell, seed).dot(x)
+    };
+
+    let n = corner(cell, x0)
+        + corner(cell + i1.as_ivec3(), x1)
+        + corner(cell + i2.as_ivec3(), x2)
+        + corner(cell + IVec3::ONE, x3);
+    32.0 * n
+}
+
+/// Cellular noise, `1 - 2 * F1` so feature points are the positive blobs.
+pub fn worley_noise(p: Vec3, seed: u32) -> f32 {
+    let floored = p.floor();
+    let cell = floored.as_ivec3();
+    let f = p - floored;
+
+    let mut min_distance_sq: f32 = 8.0;
+    for z in -1..=1 {
+        for y in -1..=1 {
+            for x in -1..=1 {
+                let offset = IVec3::new(x, y, z);
+                let point = offset.as_vec3() + to_unit(hash_cell(cell + offset, seed));
+                min_distance_sq = min_distance_sq.min(point.distance_squared(f));
+            }
+        }
+    }
+    1.0 - 2.0 * min_distance_sq.sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic points spread over a few cells on both sides of the origin.
+    fn points() -> impl Iterator<Item = Vec3> {
+        (0..4096).map(|i| {
+            let unit = to_unit(pcg3d(UVec3::splat(i)));
+            (unit - 0.5) * 16.0
+        })
+    }
+
+    #[test]
+    fn hash_matches_reference_values() {
+        assert_eq!(
+            pcg3d(UVec3::ZERO),
+            UVec3::new(0x9bafd7c6, 0xa8e88a6b, 0x3f15482c)
+        );
+        assert_eq!(
+            pcg3d(UVec3::new(1, 2, 3)),
+            UVec3::new(0xfa9f79a6, 0x48f2f44c, 0x596f5ab1)
+        );
+        // negative cells wrap like the `bitcast` in noise.wgsl
+        assert_eq!(
+            hash_cell(IVec3::new(-1, 2, -3), 7),
+            UVec3::new(0xafc9c6bc, 0xd0573c7e, 0x0bb92b35)
+        );
+        assert_eq!(hash_cell(IVec3::ZERO, 0), pcg3d(UVec3::ZERO));
+    }
+
+    #[test]
+    fn to_unit_is_half_open() {
+        assert_eq!(to_unit(UVec3::ZERO), Vec3::ZERO);
+        assert!(to_unit(UVec3::MAX).cmplt(Vec3::ONE).all());
+    }
+
+    #[test]
+    fn base_noise_ranges() {
+        for p in points() {
+            for seed in [0, 3, u32::MAX] {
+                let gradient = gradient_noise(p, seed);
+                assert!(
+                    (-1.0..=1.0).contains(&gradient),
+                    "gradient {gradient} at {p}"
+                );
+                let simplex = simplex_noise(p, seed);
+                assert!((-1.0..=1.0).contains(&simplex), "simplex {simplex} at {p}");
+                // the nearest point is at most a cell diagonal away
+                let worley = worley_noise(p, seed);
+                assert!(
+                    (1.0 - 2.0 * 3f32.sqrt()..=1.0).contains(&worley),
+                    "worley {worley} at {p}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn gradient_noise_is_zero_on_the_lattice() {
+        for cell in [IVec3::ZERO, IVec3::new(-3, 5, 7)] {
+            assert_eq!(gradient_noise(cell.as_vec3(), 11), 0.0);
+        }
+    }
+
+    #[test]
+    fn fractal_ranges() {
+        let noise = NoiseDensity::default();
+        // octave amplitudes are 0.5, 0.25, ... so the sums stay below 1
+        for p in points() {
+            let fbm = noise.fbm(p);
+            assert!((-1.0..1.0).contains(&fbm), "fbm {fbm} at {p}");
+            let ridged = noise.ridged(p);
+            assert!((0.0..1.0).contains(&ridged), "ridged {ridged} at {p}");
+        }
+    }
+
+    #[test]
+    fn deterministic_per_seed() {
+        let kinds = [NoiseKind::Gradient, NoiseKind::Simplex, NoiseKind::Worley];
+        let fractals = [FractalKind::None, FractalKind::Fbm, FractalKind::Ridged];
+        for kind in kinds {
+            for fractal in fractals {
+                for warp in [0.0, 0.4] {
+                    let noise = NoiseDensity {
+                        kind,
+                        fractal,
+                        warp,
+                        seed: 5,
+                        ..Default::default()
+                    };
+                    let reseeded = NoiseDensity { seed: 6, ..noise };
+
+                    let values: Vec<f32> = points().map(|p| noise.density(p)).collect();
+                    let again: Vec<f32> = points().map(|p| noise.density(p)).collect();
+                    assert_eq!(values, again, "{kind:?} {fractal:?} warp {warp}");
+                    assert!(values.iter().all(|value| value.is_finite()));
+
+                    let changed = points()
+                        .zip(&values)
+                        .filter(|&(p, &value)| reseeded.density(p) != value)
+                        .count();
+                    assert!(
+                        changed > values.len() / 2,
+                        "{kind:?} {fractal:?} warp {warp} ignores the seed"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn warp_moves_the_samples() {
+        let noise = NoiseDensity::default();
+        let warped = NoiseDensity { warp: 0.5, ..noise };
+        let changed = points()
+            .filter(|&p| noise.density(p) != warped.density(p))
+            .count();
+        assert!(changed > 4000);
+    }
+}
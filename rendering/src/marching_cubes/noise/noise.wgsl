@@ -0,0 +1,205 @@
+#define_import_path marching_cubes::noise
+
+// Seedable 3D noise, every function is mirrored in `noise/mod.rs`
+
+struct NoiseParams {
+    kind: u32,
+    fractal: u32,
+    seed: u32,
+    octaves: u32,
+    frequency: f32,
+    lacunarity: f32,
+    gain: f32,
+    amplitude: f32,
+    warp: f32,
+    warp_frequency: f32,
+    height_gradient: f32,
+    // rounds the size up to 16 bytes, the structs holding it keep their layout without the
+    // `@align` attributes that the headers of imported modules drop
+    padding: u32,
+};
+
+// Must match `NoiseKind::shader_index`
+const NOISE_GRADIENT: u32 = 0;
+const NOISE_SIMPLEX: u32 = 1;
+const NOISE_WORLEY: u32 = 2;
+
+// Must match `FractalKind::shader_index`
+const FRACTAL_NONE: u32 = 0;
+const FRACTAL_FBM: u32 = 1;
+const FRACTAL_RIDGED: u32 = 2;
+
+// Hash Functions for GPU Rendering, Jarzynski and Olano 2020
+fn pcg3d(input: vec3<u32>) -> vec3<u32> {
+    var v = input * 1664525u + 1013904223u;
+    v.x += v.y * v.z;
+    v.y += v.z * v.x;
+    v.z += v.x * v.y;
+    v ^= v >> vec3(16u);
+    v.x += v.y * v.z;
+    v.y += v.z * v.x;
+    v.z += v.x * v.y;
+    return v;
+}
+
+fn hash_cell(cell: vec3<i32>, seed: u32) -> vec3<u32> {
+    return pcg3d(bitcast<vec3<u32>>(cell) + seed * vec3(0x9e3779b9u, 0x85ebca6bu, 0xc2b2ae35u));
+}
+
+// [0, 1) with 24 bits, exact on CPU and GPU
+fn to_unit(h: vec3<u32>) -> vec3<f32> {
+    return vec3<f32>(h >> vec3(8u)) / 16777216.0;
+}
+
+fn cell_gradient(cell: vec3<i32>, seed: u32) -> vec3<f32> {
+    return to_unit(hash_cell(cell, seed)) * 2.0 - 1.0;
+}
+
+fn gradient_corner(cell: vec3<i32>, f: vec3<f32>, offset: vec3<i32>, seed: u32) -> f32 {
+    return dot(cell_gradient(cell + offset, seed), f - vec3<f32>(offset));
+}
+
+// Perlin style gradient noise, roughly [-1, 1]
+fn gradient_noise(p: vec3<f32>, seed: u32) -> f32 {
+    let floored = floor(p);
+    let cell = vec3<i32>(floored);
+    let f = p - floored;
+    let u = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+
+    let c000 = gradient_corner(cell, f, vec3(0, 0, 0), seed);
+    let c100 = gradient_corner(cell, f, vec3(1, 0, 0), seed);
+    let c010 = gradient_corner(cell, f, vec3(0, 1, 0), seed);
+    let c110 = gradient_corner(cell, f, vec3(1, 1, 0), seed);
+    let c001 = gradient_corner(cell, f, vec3(0, 0, 1), seed);
+    let c101 = gradient_corner(cell, f, vec3(1, 0, 1), seed);
+    let c011 = gradient_corner(cell, f, vec3(0, 1, 1), seed);
+    let c111 = gradient_corner(cell, f, vec3(1, 1, 1), seed);
+
+    let x0 = mix(c000, c100, u.x);
+    let x1 = mix(c010, c110, u.x);
+    let x2 = mix(c001, c101, u.x);
+    let x3 = mix(c011, c111, u.x);
+    return mix(mix(x0, x1, u.y), mix(x2, x3, u.y), u.z);
+}
+
+fn simplex_corner(cell: vec3<i32>, x: vec3<f32>, seed: u32) -> f32 {
+    let t = max(0.6 - dot(x, x), 0.0);
+    let t2 = t * t;
+    return t2 * t2 * dot(cell_gradient(cell, seed), x);
+}
+
+// 3D simplex noise (Gustavson), roughly [-1, 1]
+fn simplex_noise(p: vec3<f32>, seed: u32) -> f32 {
+    let skew = (p.x + p.y + p.z) / 3.0;
+    let floored = floor(p + skew);
+    let cell = vec3<i32>(floored);
+    let unskew = (floored.x + floored.y + floored.z) / 6.0;
+    let x0 = p - floored + unskew;
+
+    let g = select(vec3(0.0), vec3(1.0), x0.xyz >= x0.yzx);
+    let l = 1.0 - g;
+    let i1 = min(g, l.zxy);
+    let i2 = max(g, l.zxy);
+
+    let x1 = x0 - i1 + 1.0 / 6.0;
+    let x2 = x0 - i2 + 2.0 / 6.0;
+    let x3 = x0 - 1.0 + 3.0 / 6.0;
+
+    let n = simplex_corner(cell, x0, seed)
+        + simplex_corner(cell + vec3<i32>(i1), x1, seed)
+        + simplex_corner(cell + vec3<i32>(i2), x2, seed)
+        + simplex_corner(cell + vec3(1), x3, seed);
+    return 32.0 * n;
+}
+
+// Cellular noise, `1 - 2 * F1` so feature points are the positive blobs
+fn worley_noise(p: vec3<f32>, seed: u32) -> f32 {
+    let floored = floor(p);
+    let cell = vec3<i32>(floored);
+    let f = p - floored;
+
+    var min_distance_sq = 8.0;
+    for (var z = -1; z <= 1; z++) {
+        for (var y = -1; y <= 1; y++) {
+            for (var x = -1; x <= 1; x++) {
+                let offset = vec3(x, y, z);
+                let point = vec3<f32>(offset) + to_unit(hash_cell(cell + offset, seed));
+                let delta = point - f;
+                min_distance_sq = min(min_distance_sq, dot(delta, delta));
+            }
+        }
+    }
+    return 1.0 - 2.0 * sqrt(min_distance_sq);
+}
+
+fn base_noise(p: vec3<f32>, kind: u32, seed: u32) -> f32 {
+    switch kind {
+        case NOISE_SIMPLEX: {
+            return simplex_noise(p, seed);
+        }
+        case NOISE_WORLEY: {
+            return worley_noise(p, seed);
+        }
+        default: {
+            return gradient_noise(p, seed);
+        }
+    }
+}
+
+// Fractal Brownian motion, every octave uses its own seed
+fn fbm(p: vec3<f32>, params: NoiseParams) -> f32 {
+    var sum = 0.0;
+    var amplitude = 0.5;
+    var frequency = 1.0;
+    for (var i: u32 = 0; i < params.octaves; i++) {
+        sum += amplitude * base_noise(p * frequency, params.kind, params.seed + i);
+        frequency *= params.lacunarity;
+        amplitude *= params.gain;
+    }
+    return sum;
+}
+
+// Ridged multifractal (Musgrave), ridges are where the base noise crosses zero
+fn ridged(p: vec3<f32>, params: NoiseParams) -> f32 {
+    var sum = 0.0;
+    var amplitude = 0.5;
+    var frequency = 1.0;
+    var weight = 1.0;
+    for (var i: u32 = 0; i < params.octaves; i++) {
+        let ridge = 1.0 - abs(base_noise(p * frequency, params.kind, params.seed + i));
+        let signal = ridge * ridge * weight;
+        weight = clamp(signal * 2.0, 0.0, 1.0);
+        sum += amplitude * signal;
+        frequency *= params.lacunarity;
+        amplitude *= params.gain;
+    }
+    return sum;
+}
+
+// Density from `NoiseDensity`, positive inside
+fn noise_density(pos: vec3<f32>, params: NoiseParams) -> f32 {
+    var p = pos * params.frequency;
+    if (params.warp != 0.0) {
+        let q = pos * params.warp_frequency;
+        let warp = vec3(
+            gradient_noise(q, params.seed ^ 0x1u),
+            gradient_noise(q, params.seed ^ 0x2u),
+            gradient_noise(q, params.seed ^ 0x3u),
+        );
+        p += params.warp * warp;
+    }
+
+    var value: f32;
+    switch params.fractal {
+        case FRACTAL_FBM: {
+            value = fbm(p, params);
+        }
+        case FRACTAL_RIDGED: {
+            value = ridged(p, params);
+        }
+        default: {
+            value = base_noise(p, params.kind, params.seed);
+        }
+    }
+    return value * params.amplitude - pos.y * params.height_gradient;
+}
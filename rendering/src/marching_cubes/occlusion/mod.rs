@@ -0,0 +1,103 @@
+use bevy_math::Vec3;
+use bevy_render::render_resource::ShaderType;
+
+/// Ambient occlusion baked into every vertex by the compute stage.
+///
+/// Rays leave the vertex over the hemisphere around the field gradient and march the density
+/// for [`AmbientOcclusion::radius`] voxels, the closer a ray enters the surface the darker the
+/// vertex. Costs `directions * steps` extra density evaluations per vertex.
+#[derive(Clone, Copy, Debug)]
+pub struct AmbientOcclusion {
+    /// Rays per vertex, zero disables the occlusion.
+    pub directions: u32,
+    /// Density samples along every ray.
+    pub steps: u32,
+    /// Length of the rays in voxels.
+    pub radius: f32,
+    /// Scales the darkening, zero leaves every vertex unoccluded.
+    pub strength: f32,
+}
+
+impl Default for AmbientOcclusion {
+    fn default() -> Self {
+        Self {
+            directions: 8,
+            steps: 4,
+            radius: 4.0,
+            strength: 1.0,
+        }
+    }
+}
+
+impl AmbientOcclusion {
+    pub const DISABLED: Self = Self {
+        directions: 0,
+        steps: 0,
+        radius: 0.0,
+        strength: 0.0,
+    };
+
+    /// Fraction of ambient light reaching `pos`, 1 unoccluded and 0 fully occluded.
+    ///
+    /// Mirrors `ambient_occlusion` in `density.wgsl`, `field` is negative inside.
+    pub fn occlusion(&self, field: impl Fn(Vec3) -> f32, pos: Vec3, voxel_size: f32) -> f32 {
+        if self.directions == 0 || self.steps == 0 {
+            return 1.0;
+        }
+
+        let step = voxel_size * 0.5;
+        let normal = Vec3::new(
+            field(pos + Vec3::X * step) - field(pos - Vec3::X * step),
+            field(pos + Vec3::Y * step) - field(pos - Vec3::Y * step),
+            field(pos + Vec3::Z * step) - field(pos - Vec3::Z * step),
+        )
+        .normalize_or_zero();
+        if normal == Vec3::ZERO {
+            return 1.0;
+        }
+        let (tangent, bitangent) = normal.any_orthonormal_pair();
+
+        let ray_step = self.radius * voxel_size / self.steps as f32;
+        let mut visibility = 0.0;
+        for i in 0..self.directions {
+            let direction = hemisphere_direction(i, self.directions);
+            let direction = tangent * direction.x + bitangent * direction.y + normal * direction.z;
+
+            let hit =
+                (1..=self.steps).find(|&k| field(pos + direction * ray_step * k as f32) < 0.0);
+            visibility += hit.map_or(1.0, |k| (k - 1) as f32 / self.steps as f32);
+        }
+
+        let occluded = 1.0 - visibility / self.directions as f32;
+        (1.0 - self.strength * occluded).clamp(0.0, 1.0)
+    }
+}
+
+/// Cosine weighted spiral over the +z hemisphere, so every ray counts the same.
+fn hemisphere_direction(i: u32, count: u32) -> Vec3 {
+    const GOLDEN_ANGLE: f32 = 2.399_963;
+    let u = (i as f32 + 0.5) / count as f32;
+    let radius = u.sqrt();
+    let (sin, cos) = (i as f32 * GOLDEN_ANGLE).sin_cos();
+    Vec3::new(radius * cos, radius * sin, (1.0 - u).sqrt())
+}
+
+/// `AmbientOcclusionParams` in `density.wgsl`.
+#[derive(ShaderType, Clone, Copy, Default)]
+pub struct AmbientOcclusionUniform {
+    directions: u32,
+    steps: u32,
+    radius: f32,
+    strength: f32,
+}
+
+impl From<AmbientOcclusion> for AmbientOcclusionUniform {
+    fn from(occlusion: AmbientOcclusion) -> Self {
+        Self {
+            directions: occlusion.directions,
+            steps: occlusion.steps,
+            radius: occlusion.radius,
+            strength: occlusion.strength,
+        }
+    }
+}
@@ -0,0 +1,747 @@
+use bevy_app::{App, Plugin, PostUpdate};
+use bevy_asset::{Handle, load_internal_asset, weak_handle};
+use bevy_ecs::change_detection::DetectChanges;
+use bevy_ecs::resource::Resource;
+use bevy_ecs::schedule::IntoScheduleConfigs;
+use bevy_ecs::schedule::common_conditions::resource_changed;
+use bevy_ecs::system::{Res, ResMut};
+use bevy_math::bounding::Aabb3d;
+use bevy_math::{IVec3, UVec3, Vec3};
+use bevy_render::extract_resource::{ExtractResource, ExtractResourcePlugin};
+use bevy_render::render_resource::{Shader, ShaderType, StorageBuffer};
+use bevy_render::renderer::{RenderDevice, RenderQueue};
+use bevy_render::{Render, RenderApp, RenderSet};
+
+use super::noise::{NoiseDensity, NoiseKind, NoiseUniform, hash_cell, to_unit};
+use super::{BRICK_SIZE, DensitySource, VoxelVolume};
+
+pub const TERRAIN_SHADER_HANDLE: Handle<Shader> =
+    weak_handle!("3d7a9e52-c1f8-4b06-9e2d-7f4a6b8c1d95");
+
+// keep the noise layers and tunnels apart for the same seed
+const HEIGHT_SALT: u32 = 1 << 16;
+const OVERHANG_SALT: u32 = 2 << 16;
+const CAVE_SALT: u32 = 3 << 16;
+const TUNNEL_SALT: u32 = 4 << 16;
+
+/// Catmull-Rom samples between two tunnel control points.
+const SEGMENTS_PER_SPAN: u32 = 4;
+
+/// Registers `terrain.wgsl` and uploads the tunnels of [`DensitySource::Terrain`].
+pub struct TerrainPlugin;
+
+impl Plugin for TerrainPlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(
+            app,
+            TERRAIN_SHADER_HANDLE,
+            "terrain.wgsl",
+            Shader::from_wgsl
+        );
+
+        app.init_resource::<TerrainTunnels>();
+        app.add_plugins(ExtractResourcePlugin::<TerrainTunnels>::default());
+        app.add_systems(
+            PostUpdate,
+            update_terrain_tunnels.run_if(resource_changed::<VoxelVolume>),
+        );
+
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app.init_resource::<TunnelBuffer>();
+        render_app.add_systems(
+            Render,
+            prepare_tunnel_buffer.in_set(RenderSet::PrepareResources),
+        );
+    }
+}
+
+/// Worm tunnels wandering through the ground.
+#[derive(Clone, Copy, Debug)]
+pub struct TunnelSettings {
+    /// Size of the world grid cells that may start a tunnel.
+    pub cell_size: f32,
+    /// Probability of a cell starting a tunnel.
+    pub chance: f32,
+    /// Control points along each tunnel, joined by a Catmull-Rom spline.
+    pub control_points: u32,
+    /// Distance between control points.
+    pub step: f32,
+    /// Average radius, varies by ±25% along the tunnel.
+    pub radius: f32,
+    /// Largest heading change between control points, in radians.
+    pub turn: f32,
+}
+
+impl TunnelSettings {
+    /// Distance from the tunnel walls past which tunnels leave the density alone, so the GPU
+    /// only visits the segments near each brick.
+    #[inline]
+    pub fn reach(&self) -> f32 {
+        self.radius * 1.25
+    }
+}
+
+impl Default for TunnelSettings {
+    fn default() -> Self {
+        Self {
+            cell_size: 1.0,
+            chance: 0.5,
+            control_points: 8,
+            step: 0.2,
+            radius: 0.06,
+            turn: 1.2,
+        }
+    }
+}
+
+/// Terrain preset, a 2D heightfield with 3D overhangs, noise caves and worm tunnels carved
+/// out, positive inside.
+///
+/// Everything follows from `seed`, the seeds of the noise layers are replaced by ones derived
+/// from it and their `height_gradient` is ignored. Tunnels are laid out on a world grid, so
+/// volumes covering different parts of the world line up. Evaluated on the GPU by
+/// `terrain.wgsl` and on the CPU by [`TerrainGenerator::density`], both in `f32` with the
+/// same integer hashes. GPUs may fuse multiply-adds and round divisions and square roots
+/// differently, so the densities agree within `1e-4`, not bit for bit. Servers and clients
+/// that need identical terrain, e.g. for collisions, should both evaluate it on the CPU.
+#[derive(Clone, Copy, Debug)]
+pub struct TerrainGenerator {
+    pub seed: u32,
+    /// Ground level before the heightfield is added.
+    pub base_height: f32,
+    /// Heightfield, sampled on the `y = 0` plane.
+    pub height: NoiseDensity,
+    /// 3D noise added to the ground, pushes out overhangs and arches.
+    pub overhang: NoiseDensity,
+    /// 3D noise, carved out where it rises above `cave_threshold`.
+    pub caves: NoiseDensity,
+    pub cave_threshold: f32,
+    /// Depth below the heightfield where caves start.
+    pub cave_roof: f32,
+    pub tunnels: TunnelSettings,
+    /// Depths below the heightfield where the first three material layers end, anything
+    /// deeper is the fourth, see [`TerrainGenerator::material`].
+    pub layer_depths: Vec3,
+}
+
+impl Default for TerrainGenerator {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            base_height: 0.0,
+            height: NoiseDensity {
+                frequency: 1.0,
+                octaves: 6,
+                amplitude: 0.6,
+                ..Default::default()
+            },
+            overhang: NoiseDensity {
+                kind: NoiseKind::Simplex,
+                frequency: 3.0,
+                octaves: 3,
+                amplitude: 0.15,
+                ..Default::default()
+            },
+            caves: NoiseDensity {
+                frequency: 2.5,
+                octaves: 3,
+                amplitude: 1.0,
+                ..Default::default()
+            },
+            cave_threshold: 0.15,
+            cave_roof: 0.1,
+            tunnels: TunnelSettings::default(),
+            layer_depths: Vec3::new(0.03, 0.15, 0.6),
+        }
+    }
+}
+
+impl TerrainGenerator {
+    fn layer(&self, noise: &NoiseDensity, salt: u32) -> NoiseDensity {
+        NoiseDensity {
+            seed: self.seed ^ salt,
+            height_gradient: 0.0,
+            ..*noise
+        }
+    }
+
+    /// Height of the ground above `(x, z)`, without overhangs.
+    pub fn height(&self, pos: Vec3) -> f32 {
+        let plane = Vec3::new(pos.x, 0.0, pos.z);
+        self.base_height + self.layer(&self.height, HEIGHT_SALT).density(plane)
+    }
+
+    /// Density before the tunnels are carved.
+    pub fn solid(&self, pos: Vec3) -> f32 {
+        let depth = self.height(pos) - pos.y;
+        let ground = depth + self.layer(&self.overhang, OVERHANG_SALT).density(pos);
+
+        let caves = self.layer(&self.caves, CAVE_SALT);
+        let cave = (self.cave_threshold - caves.density(pos)) / caves.frequency;
+        ground.min(cave.max(self.cave_roof - depth))
+    }
+
+    /// Terrain density with `tunnels` carved out, see [`TerrainGenerator::tunnels`].
+    ///
+    /// The distance to the tunnels is capped at [`TunnelSettings::reach`].
+    pub fn density(&self, pos: Vec3, tunnels: &[TunnelSegment]) -> f32 {
+        let tunnel = tunnels
+            .iter()
+            .filter(|segment| segment.start_radius > 0.0)
+            .fold(self.tunnels.reach(), |distance, segment| {
+                distance.min(segment.distance(pos))
+            });
+        self.solid(pos).min(tunnel)
+    }
+
+    /// Material layer at `pos`, from 0 at the surface to 3 in the deep.
+    pub fn material(&self, pos: Vec3) -> u32 {
+        let depth = self.height(pos) - pos.y;
+        self.layer_depths
+            .cmple(Vec3::splat(depth))
+            .bitmask()
+            .count_ones()
+    }
+
+    /// Tunnel segments reaching into `bounds`.
+    ///
+    /// Each cell of the [`TunnelSettings::cell_size`] grid may start a tunnel, a random walk
+    /// decided by the cell and `seed` alone.
+    pub fn tunnels(&self, bounds: &Aabb3d) -> Vec<TunnelSegment> {
+        let settings = &self.tunnels;
+        if settings.chance <= 0.0 || settings.control_points < 2 || settings.cell_size <= 0.0 {
+            return Vec::new();
+        }
+
+        let max_radius = settings.radius * 1.25;
+        let reach = settings.step * (settings.control_points - 1) as f32 + max_radius;
+        let min = Vec3::from(bounds.min) - reach;
+        let max = Vec3::from(bounds.max) + reach;
+        let first = (min / settings.cell_size).floor().as_ivec3();
+        let last = (max / settings.cell_size).floor().as_ivec3();
+
+        let mut segments = Vec::new();
+        for z in first.z..=last.z {
+            for y in first.y..=last.y {
+                for x in first.x..=last.x {
+                    let cell = IVec3::new(x, y, z);
+                    let Some(points) = self.tunnel_points(cell) else {
+                        continue;
+                    };
+                    segments.extend(catmull_rom(&points).filter(|segment| {
+                        let (lo, hi) = (
+                            segment.start.min(segment.end),
+                            segment.start.max(segment.end),
+                        );
+                        (lo - max_radius).cmple(bounds.max.into()).all()
+                            && (hi + max_radius).cmpge(bounds.min.into()).all()
+                    }));
+                }
+            }
+        }
+        segments
+    }
+
+    // control points and radii of the tunnel started by `cell`, if any
+    fn tunnel_points(&self, cell: IVec3) -> Option<Vec<(Vec3, f32)>> {
+        let settings = &self.tunnels;
+        let seed = self.seed ^ TUNNEL_SALT;
+        let start = to_unit(hash_cell(cell, seed));
+        if start.x >= settings.chance {
+            return None;
+        }
+
+        let offset = to_unit(hash_cell(cell, seed.wrapping_add(1)));
+        let mut position = (cell.as_vec3() + offset) * settings.cell_size;
+        let mut yaw = start.y * core::f32::consts::TAU;
+        let mut pitch = (start.z - 0.5) * 0.5;
+
+        let mut points = Vec::with_capacity(settings.control_points as usize);
+        for i in 0..settings.control_points {
+            let random = to_unit(hash_cell(cell, seed.wrapping_add(2 + i)));
+            points.push((position, settings.radius * (0.75 + 0.5 * random.z)));
+
+            // mostly horizontal, the pitch is pulled back towards level
+            yaw += (random.x - 0.5) * settings.turn;
+            pitch = (pitch * 0.5 + (random.y - 0.5) * settings.turn).clamp(-0.6, 0.6);
+            let direction = Vec3::new(
+                pitch.cos() * yaw.cos(),
+                pitch.sin(),
+                pitch.cos() * yaw.sin(),
+            );
+            position += direction * settings.step;
+        }
+        Some(points)
+    }
+}
+
+// uniform Catmull-Rom spline through the control points, end points are repeated
+fn catmull_rom(points: &[(Vec3, f32)]) -> impl Iterator<Item = TunnelSegment> + '_ {
+    let last = points.len() - 1;
+    (0..last).flat_map(move |span| {
+        let p0 = points[span.saturating_sub(1)].0;
+        let (p1, r1) = points[span];
+        let (p2, r2) = points[span + 1];
+        let p3 = points[(span + 2).min(last)].0;
+        let sample = move |t: f32| {
+            let t2 = t * t;
+            let t3 = t2 * t;
+            let position = 0.5
+                * (2.0 * p1
+                    + (p2 - p0) * t
+                    + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+                    + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3);
+            (position, r1 + (r2 - r1) * t)
+        };
+
+        (0..SEGMENTS_PER_SPAN).map(move |i| {
+            let (start, start_radius) = sample(i as f32 / SEGMENTS_PER_SPAN as f32);
+            let (end, end_radius) = sample((i + 1) as f32 / SEGMENTS_PER_SPAN as f32);
+            TunnelSegment {
+                start,
+                start_radius,
+                end,
+                end_radius,
+            }
+        })
+    })
+}
+
+/// Piece of a worm tunnel, a capsule whose radius changes along it.
+///
+/// `TunnelSegment` in `density.wgsl`, segments with a zero radius carve nothing.
+#[derive(ShaderType, Clone, Copy, Debug, Default)]
+pub struct TunnelSegment {
+    pub start: Vec3,
+    pub start_radius: f32,
+    pub end: Vec3,
+    pub end_radius: f32,
+}
+
+impl TunnelSegment {
+    /// Bounds of the segment grown by `margin` past its surface.
+    fn aabb(&self, margin: f32) -> Aabb3d {
+        let grow = self.start_radius.max(self.end_radius) + margin;
+        Aabb3d {
+            min: (self.start.min(self.end) - grow).into(),
+            max: (self.start.max(self.end) + grow).into(),
+        }
+    }
+
+    /// Signed distance to the surface of the segment, negative inside.
+    #[inline]
+    pub fn distance(&self, pos: Vec3) -> f32 {
+        let axis = self.end - self.start;
+        let t = ((pos - self.start).dot(axis) / axis.length_squared().max(1e-12)).clamp(0.0, 1.0);
+        pos.distance(self.start + axis * t)
+            - (self.start_radius + (self.end_radius - self.start_radius) * t)
+    }
+}
+
+/// `TerrainParams` in `terrain.wgsl`.
+#[derive(ShaderType, Clone, Copy, Default, Debug, PartialEq)]
+pub struct TerrainUniform {
+    base_height: f32,
+    cave_threshold: f32,
+    cave_roof: f32,
+    layer_depths: Vec3,
+    tunnel_reach: f32,
+    #[align(16)]
+    height: NoiseUniform,
+    #[align(16)]
+    overhang: NoiseUniform,
+    #[align(16)]
+    caves: NoiseUniform,
+}
+
+impl From<&TerrainGenerator> for TerrainUniform {
+    fn from(terrain: &TerrainGenerator) -> Self {
+        Self {
+            base_height: terrain.base_height,
+            cave_threshold: terrain.cave_threshold,
+            cave_roof: terrain.cave_roof,
+            layer_depths: terrain.layer_depths,
+            tunnel_reach: terrain.tunnels.reach(),
+            height: (&terrain.layer(&terrain.height, HEIGHT_SALT)).into(),
+            overhang: (&terrain.layer(&terrain.overhang, OVERHANG_SALT)).into(),
+            caves: (&terrain.layer(&terrain.caves, CAVE_SALT)).into(),
+        }
+    }
+}
+
+/// Tunnels of the current [`DensitySource::Terrain`] volume, binned by brick.
+#[derive(Resource, Clone, Default, ExtractResource)]
+pub struct TerrainTunnels {
+    pub segments: Vec<TunnelSegment>,
+    /// See [`bin_tunnels`].
+    pub bins: Vec<u32>,
+}
+
+/// Segment indices per brick of `volume`, for the segments within `reach` of it.
+///
+/// Starts with the offset of every brick into the indices, then one more for the end of the
+/// last brick, followed by the indices. Samples outside the volume use the nearest brick.
+/// Mirrored by `terrain_density` in `density.wgsl`.
+pub fn bin_tunnels(segments: &[TunnelSegment], volume: &VoxelVolume, reach: f32) -> Vec<u32> {
+    let bins = volume.brick_dims().max(UVec3::ONE);
+    let bin_size = volume.voxel_size * BRICK_SIZE as f32;
+    let origin = Vec3::from(volume.aabb.min);
+    // samples right on a border may land in either brick on the GPU
+    let margin = reach + 0.5 * volume.voxel_size;
+
+    let mut lists = vec![Vec::new(); bins.element_product() as usize];
+    for (index, segment) in segments.iter().enumerate() {
+        if segment.start_radius <= 0.0 {
+            continue;
+        }
+        let aabb = segment.aabb(margin);
+        let first = ((Vec3::from(aabb.min) - origin) / bin_size).floor();
+        let last = ((Vec3::from(aabb.max) - origin) / bin_size).floor();
+        // the outer bricks extend past the volume
+        let last_bin = (bins - 1).as_vec3();
+        if first.cmpgt(last_bin).any() || last.cmplt(Vec3::ZERO).any() {
+            continue;
+        }
+        let first = first.max(Vec3::ZERO).as_uvec3();
+        let last = last.min(last_bin).as_uvec3();
+
+        for z in first.z..=last.z {
+            for y in first.y..=last.y {
+                for x in first.x..=last.x {
+                    lists[(x + bins.x * (y + bins.y * z)) as usize].push(index as u32);
+                }
+            }
+        }
+    }
+
+    let mut offsets = Vec::with_capacity(lists.len() + 1);
+    let mut offset = 0;
+    for list in &lists {
+        offsets.push(offset);
+        offset += list.len() as u32;
+    }
+    offsets.push(offset);
+    offsets.extend(lists.into_iter().flatten());
+    offsets
+}
+
+fn update_terrain_tunnels(
+    voxel_volume: Res<VoxelVolume>,
+    mut terrain_tunnels: ResMut<TerrainTunnels>,
+) {
+    let (segments, bins) = match &voxel_volume.density {
+        DensitySource::Terrain(terrain) => {
+            let segments = terrain.tunnels(&voxel_volume.aabb);
+            let bins = bin_tunnels(&segments, &voxel_volume, terrain.tunnels.reach());
+            (segments, bins)
+        }
+        _ => (Vec::new(), Vec::new()),
+    };
+    // an empty list stays empty, skip the upload
+    if segments.is_empty() && bins.is_empty() && terrain_tunnels.bins.is_empty() {
+        return;
+    }
+    *terrain_tunnels = TerrainTunnels { segments, bins };
+}
+
+#[derive(Resource, Default)]
+pub struct TunnelBuffer {
+    pub buffer: StorageBuffer<Vec<TunnelSegment>>,
+    pub bins: StorageBuffer<Vec<u32>>,
+}
+
+fn prepare_tunnel_buffer(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut tunnel_buffer: ResMut<TunnelBuffer>,
+    tunnels: Res<TerrainTunnels>,
+) {
+    if !tunnels.is_changed() && tunnel_buffer.buffer.buffer().is_some() {
+        return;
+    }
+
+    let buffer = tunnel_buffer.buffer.get_mut();
+    buffer.clone_from(&tunnels.segments);
+    // empty bindings are not allowed, a zero radius segment carves nothing
+    if buffer.is_empty() {
+        buffer.push(TunnelSegment::default());
+    }
+    let bins = tunnel_buffer.bins.get_mut();
+    bins.clone_from(&tunnels.bins);
+    if bins.is_empty() {
+        bins.push(0);
+    }
+
+    tunnel_buffer
+        .buffer
+        .write_buffer(&render_device, &render_queue);
+    tunnel_buffer
+        .bins
+        .write_buffer(&render_device, &render_queue);
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_math::{UVec3, Vec4};
+    use bevy_render::render_resource::{
+        BindGroupEntry, BufferInitDescriptor, BufferUsages, encase,
+    };
+
+    use super::*;
+    use crate::testing::{compose, compute_pipeline, read_buffer, test_device};
+
+    fn volume(terrain: TerrainGenerator) -> VoxelVolume {
+        VoxelVolume {
+            aabb: Aabb3d::new(Vec3::ZERO, Vec3::ONE),
+            voxel_size: 0.0625,
+            density: DensitySource::Terrain(terrain),
+            ..Default::default()
+        }
+    }
+
+    /// Deterministic points spread over the volume and a bit past it.
+    fn points() -> impl Iterator<Item = Vec3> {
+        (0..2000).map(|i| (to_unit(hash_cell(IVec3::splat(i), 99)) - 0.5) * 2.2)
+    }
+
+    fn samples(terrain: &TerrainGenerator) -> (Vec<f32>, Vec<u32>) {
+        let tunnels = terrain.tunnels(&volume(*terrain).aabb);
+        points()
+            .map(|p| (terrain.density(p, &tunnels), terrain.material(p)))
+            .unzip()
+    }
+
+    #[test]
+    fn reproducible_per_seed() {
+        let terrain = TerrainGenerator {
+            seed: 7,
+            ..Default::default()
+        };
+        let bounds = volume(terrain).aabb;
+        let tunnels = terrain.tunnels(&bounds);
+        assert!(!tunnels.is_empty());
+        assert_eq!(
+            format!("{tunnels:?}"),
+            format!("{:?}", terrain.tunnels(&bounds))
+        );
+        assert_eq!(samples(&terrain), samples(&terrain));
+
+        let reseeded = TerrainGenerator { seed: 8, ..terrain };
+        assert_ne!(
+            format!("{tunnels:?}"),
+            format!("{:?}", reseeded.tunnels(&bounds))
+        );
+        let ((density, material), (other_density, other_material)) =
+            (samples(&terrain), samples(&reseeded));
+        let changed = density
+            .iter()
+            .zip(&other_density)
+            .filter(|(a, b)| a != b)
+            .count();
+        assert!(changed > density.len() * 9 / 10, "{changed} changed");
+        assert_ne!(material, other_material);
+    }
+
+    #[test]
+    fn uniform_uses_the_salted_layers() {
+        let terrain = TerrainGenerator {
+            seed: 12345,
+            ..Default::default()
+        };
+        let uniform = TerrainUniform::from(&terrain);
+
+        let salted = |noise: &NoiseDensity, salt: u32| {
+            NoiseUniform::from(&NoiseDensity {
+                seed: 12345 ^ salt,
+                height_gradient: 0.0,
+                ..*noise
+            })
+        };
+        assert_eq!(uniform.height, salted(&terrain.height, HEIGHT_SALT));
+        assert_eq!(uniform.overhang, salted(&terrain.overhang, OVERHANG_SALT));
+        assert_eq!(uniform.caves, salted(&terrain.caves, CAVE_SALT));
+        assert_ne!(uniform.height, NoiseUniform::from(&terrain.height));
+        assert_eq!(uniform.tunnel_reach, terrain.tunnels.reach());
+    }
+
+    /// Density as `terrain_density` in `density.wgsl` sees it, only visiting the binned
+    /// segments.
+    fn binned_density(
+        terrain: &TerrainGenerator,
+        volume: &VoxelVolume,
+        segments: &[TunnelSegment],
+        bins: &[u32],
+        pos: Vec3,
+    ) -> f32 {
+        let dims = volume.brick_dims().max(UVec3::ONE);
+        let bin_size = volume.voxel_size * BRICK_SIZE as f32;
+        let grid = ((pos - Vec3::from(volume.aabb.min)) / bin_size).floor();
+        let bin = grid.clamp(Vec3::ZERO, (dims - 1).as_vec3()).as_uvec3();
+        let index = (bin.x + dims.x * (bin.y + dims.y * bin.z)) as usize;
+        let indices = dims.element_product() as usize + 1;
+
+        let tunnel = (bins[index]..bins[index + 1])
+            .map(|i| segments[bins[indices + i as usize] as usize].distance(pos))
+            .fold(terrain.tunnels.reach(), f32::min);
+        terrain.solid(pos).min(tunnel)
+    }
+
+    #[test]
+    fn binned_tunnels_match_all_tunnels() {
+        let terrain = TerrainGenerator::default();
+        let volume = volume(terrain);
+        let segments = terrain.tunnels(&volume.aabb);
+        let bins = bin_tunnels(&segments, &volume, terrain.tunnels.reach());
+
+        let bin_count = volume.brick_dims().element_product() as usize;
+        let listed = bins.len() - bin_count - 1;
+        assert_eq!(bins[bin_count] as usize, listed);
+        // every brick only sees a fraction of the segments
+        assert!(listed < segments.len() * bin_count / 4, "{listed} listed");
+
+        let mut carved = 0;
+        for pos in points() {
+            let expected = terrain.density(pos, &segments);
+            assert_eq!(
+                binned_density(&terrain, &volume, &segments, &bins, pos),
+                expected,
+                "at {pos}"
+            );
+            carved += (expected < terrain.solid(pos)) as u32;
+        }
+        assert!(carved > 0, "no sample near a tunnel");
+    }
+
+    /// Evaluates `terrain.wgsl` at every point, with all tunnels as `TerrainGenerator::density`.
+    const TERRAIN_TEST_SHADER: &str = "
+#import marching_cubes::terrain::{TerrainParams, terrain_material, terrain_solid, tunnel_distance}
+
+struct TunnelSegment {
+    start: vec3<f32>,
+    start_radius: f32,
+    end: vec3<f32>,
+    end_radius: f32,
+};
+
+@group(0) @binding(0) var<uniform> terrain: TerrainParams;
+@group(0) @binding(1) var<storage, read> tunnels: array<TunnelSegment>;
+@group(0) @binding(2) var<storage, read> points: array<vec4<f32>>;
+@group(0) @binding(3) var<storage, read_write> densities: array<f32>;
+@group(0) @binding(4) var<storage, read_write> materials: array<u32>;
+
+@compute @workgroup_size(64)
+fn evaluate(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let index = invocation_id.x;
+    if (index >= arrayLength(&points)) {
+        return;
+    }
+    let pos = points[index].xyz;
+    var tunnel = terrain.tunnel_reach;
+    for (var i = 0u; i < arrayLength(&tunnels); i++) {
+        let segment = tunnels[i];
+        tunnel = min(tunnel, tunnel_distance(pos, segment.start, segment.start_radius, segment.end, segment.end_radius));
+    }
+    densities[index] = min(terrain_solid(pos, terrain), tunnel);
+    materials[index] = terrain_material(pos, terrain);
+}
+";
+
+    /// Densities and materials of `terrain.wgsl` at `points`.
+    fn gpu_samples(
+        terrain: &TerrainGenerator,
+        tunnels: &[TunnelSegment],
+        points: &[Vec3],
+    ) -> (Vec<f32>, Vec<u32>) {
+        let (render_device, queue) = test_device();
+        let module = compose(
+            &[
+                (include_str!("../noise/noise.wgsl"), "noise.wgsl"),
+                (include_str!("terrain.wgsl"), "terrain.wgsl"),
+            ],
+            (TERRAIN_TEST_SHADER, "terrain_test.wgsl"),
+            &[],
+        );
+        let pipeline = compute_pipeline(&render_device, &module, "evaluate", None);
+
+        let mut uniform = encase::UniformBuffer::new(Vec::new());
+        uniform.write(&TerrainUniform::from(terrain)).unwrap();
+        let mut segments = encase::StorageBuffer::new(Vec::new());
+        segments.write(&tunnels.to_vec()).unwrap();
+        let points: Vec<Vec4> = points.iter().map(|p| p.extend(1.0)).collect();
+        let buffer = |contents: &[u8], usage| {
+            render_device.create_buffer_with_data(&BufferInitDescriptor {
+                label: None,
+                contents,
+                usage: usage | BufferUsages::COPY_SRC,
+            })
+        };
+        let outputs = vec![0u8; points.len() * size_of::<u32>()];
+        let buffers = [
+            buffer(uniform.as_ref(), BufferUsages::UNIFORM),
+            buffer(segments.as_ref(), BufferUsages::STORAGE),
+            buffer(bytemuck::cast_slice(&points), BufferUsages::STORAGE),
+            buffer(&outputs, BufferUsages::STORAGE),
+            buffer(&outputs, BufferUsages::STORAGE),
+        ];
+        let entries: Vec<_> = buffers
+            .iter()
+            .enumerate()
+            .map(|(binding, buffer)| BindGroupEntry {
+                binding: binding as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect();
+        let bind_group = render_device.create_bind_group(
+            None,
+            &pipeline.get_bind_group_layout(0).into(),
+            &entries,
+        );
+
+        let mut encoder = render_device.create_command_encoder(&Default::default());
+        {
+            let mut pass = encoder.begin_compute_pass(&Default::default());
+            pass.set_pipeline(&pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups((points.len() as u32).div_ceil(64), 1, 1);
+        }
+        queue.submit([encoder.finish()]);
+        (
+            read_buffer(&render_device, &queue, &buffers[3]),
+            read_buffer(&render_device, &queue, &buffers[4]),
+        )
+    }
+
+    /// Bound on the difference between the GPU and CPU densities, see [`TerrainGenerator`].
+    const DENSITY_TOLERANCE: f32 = 1e-4;
+
+    #[test]
+    fn gpu_terrain_matches_cpu() {
+        for seed in [0, 7, 12345] {
+            let terrain = TerrainGenerator {
+                seed,
+                ..Default::default()
+            };
+            let tunnels = terrain.tunnels(&volume(terrain).aabb);
+            let points: Vec<Vec3> = points().collect();
+            let (densities, materials) = gpu_samples(&terrain, &tunnels, &points);
+
+            for (i, &pos) in points.iter().enumerate() {
+                let density = terrain.density(pos, &tunnels);
+                assert!(
+                    (densities[i] - density).abs() <= DENSITY_TOLERANCE,
+                    "seed {seed} at {pos}: {} on the GPU, {density} on the CPU",
+                    densities[i]
+                );
+                // the layers only differ where the depth is within rounding of a boundary
+                let depth = terrain.height(pos) - pos.y;
+                let boundary = (terrain.layer_depths - depth).abs().min_element();
+                if boundary > DENSITY_TOLERANCE {
+                    assert_eq!(materials[i], terrain.material(pos), "seed {seed} at {pos}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,46 @@
+#define_import_path marching_cubes::terrain
+
+#import marching_cubes::noise::{NoiseParams, noise_density}
+
+// Terrain preset, every function is mirrored by `TerrainGenerator` in `terrain/mod.rs`
+
+struct TerrainParams {
+    base_height: f32,
+    cave_threshold: f32,
+    cave_roof: f32,
+    layer_depths: vec3<f32>,
+    // tunnels leave the density alone this far past their walls
+    tunnel_reach: f32,
+    // seeds are already derived from the terrain seed
+    @align(16) height: NoiseParams,
+    @align(16) overhang: NoiseParams,
+    @align(16) caves: NoiseParams,
+};
+
+// Height of the ground above (x, z), without overhangs
+fn terrain_height(pos: vec3<f32>, params: TerrainParams) -> f32 {
+    return params.base_height + noise_density(vec3(pos.x, 0.0, pos.z), params.height);
+}
+
+// Density before the tunnels are carved, positive inside
+fn terrain_solid(pos: vec3<f32>, params: TerrainParams) -> f32 {
+    let depth = terrain_height(pos, params) - pos.y;
+    let ground = depth + noise_density(pos, params.overhang);
+
+    let cave = (params.cave_threshold - noise_density(pos, params.caves)) / params.caves.frequency;
+    return min(ground, max(cave, params.cave_roof - depth));
+}
+
+// Material layer, from 0 at the surface to 3 in the deep
+fn terrain_material(pos: vec3<f32>, params: TerrainParams) -> u32 {
+    let depth = terrain_height(pos, params) - pos.y;
+    let below = params.layer_depths <= vec3(depth);
+    return u32(below.x) + u32(below.y) + u32(below.z);
+}
+
+// Signed distance to a capsule whose radius changes along it, negative inside
+fn tunnel_distance(pos: vec3<f32>, start: vec3<f32>, start_radius: f32, end: vec3<f32>, end_radius: f32) -> f32 {
+    let axis = end - start;
+    let t = clamp(dot(pos - start, axis) / max(dot(axis, axis), 1e-12), 0.0, 1.0);
+    return distance(pos, start + axis * t) - mix(start_radius, end_radius, t);
+}
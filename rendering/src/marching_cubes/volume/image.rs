@@ -0,0 +1,293 @@
+use bevy_asset::io::Reader;
+use bevy_asset::{AssetLoader, LoadContext, ron};
+use bevy_image::{Image, ImageLoaderSettings};
+use bevy_math::{UVec2, UVec3, Vec3};
+use serde::{Deserialize, Serialize};
+
+use super::{ScalarVolume, VolumeLoaderError, validate_dims};
+
+/// Builds terrain from a 2D heightmap, `density = height - y`, so ground is above the isovalue 0.
+///
+/// Pixel `(x, z)` of the image is read as a height in `[0, 1]` scaled by
+/// [`HeightmapSettings::max_height`]. Loads `*.heightmap.png` (or any other image
+/// extension supported by `bevy_image`).
+#[derive(Default)]
+pub struct HeightmapVolumeLoader;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HeightmapSettings {
+    /// World space height of a white pixel.
+    pub max_height: f32,
+    /// Number of samples along y, including a layer below zero and a layer above `max_height`.
+    pub height_samples: u32,
+    /// Distance between pixels along x and z.
+    pub spacing: f32,
+    pub origin: [f32; 3],
+}
+
+impl Default for HeightmapSettings {
+    fn default() -> Self {
+        Self {
+            max_height: 1.0,
+            height_samples: 32,
+            spacing: 1.0,
+            origin: [0.0; 3],
+        }
+    }
+}
+
+impl AssetLoader for HeightmapVolumeLoader {
+    type Asset = ScalarVolume;
+    type Settings = HeightmapSettings;
+    type Error = VolumeLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        settings: &Self::Settings,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let path = load_context.path().to_owned();
+        let image = load_context
+            .loader()
+            .with_settings(linear_image_settings)
+            .immediate()
+            .with_reader(reader)
+            .load::<Image>(path)
+            .await?;
+
+        ScalarVolume::from_heightmap(image.get(), settings)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["heightmap.png", "heightmap.jpg", "heightmap.jpeg"]
+    }
+}
+
+/// Stacks 2D image slices into a 3D field.
+///
+/// Loads `*.stack.ron` manifests listing the slice images relative to the manifest,
+/// see [`ImageStackManifest`].
+#[derive(Default)]
+pub struct ImageStackVolumeLoader;
+
+/// ```ron
+/// (
+///     slices: ["slices/000.png", "slices/001.png", "slices/002.png"],
+///     spacing: (1.0, 1.0, 2.5),
+/// )
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ImageStackManifest {
+    /// Slices ordered along z.
+    pub slices: Vec<String>,
+    #[serde(default = "default_spacing")]
+    pub spacing: [f32; 3],
+    #[serde(default)]
+    pub origin: [f32; 3],
+}
+
+fn default_spacing() -> [f32; 3] {
+    [1.0; 3]
+}
+
+impl AssetLoader for ImageStackVolumeLoader {
+    type Asset = ScalarVolume;
+    type Settings = ();
+    type Error = VolumeLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &Self::Settings,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let manifest: ImageStackManifest = ron::de::from_bytes(&bytes)?;
+
+        let mut slices = Vec::with_capacity(manifest.slices.len());
+        for slice in &manifest.slices {
+            let path = load_context
+                .path()
+                .parent()
+                .map(|parent| parent.join(slice))
+                .unwrap_or_else(|| slice.into());
+            let image = load_context
+                .loader()
+                .with_settings(linear_image_settings)
+                .immediate()
+                .load::<Image>(path)
+                .await?;
+            slices.push(image.take());
+        }
+
+        let mut volume = ScalarVolume::from_image_slices(&slices)?;
+        volume.spacing = Vec3::from_array(manifest.spacing);
+        volume.origin = Vec3::from_array(manifest.origin);
+        Ok(volume)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["stack.ron"]
+    }
+}
+
+// images hold data, not colors
+fn linear_image_settings(settings: &mut ImageLoaderSettings) {
+    settings.is_srgb = false;
+}
+
+/// Linear red channel, `[0, 1]` for normalized formats.
+fn pixel_value(image: &Image, x: u32, y: u32) -> Result<f32, VolumeLoaderError> {
+    Ok(image.get_color_at(x, y)?.to_linear().red)
+}
+
+impl ScalarVolume {
+    /// Terrain where `density = height - y`, see [`HeightmapVolumeLoader`].
+    pub fn from_heightmap(
+        image: &Image,
+        settings: &HeightmapSettings,
+    ) -> Result<Self, VolumeLoaderError> {
+        let height_samples = settings.height_samples.max(4);
+        let dims = UVec3::new(image.width(), height_samples, image.height());
+        validate_dims(dims)?;
+
+        // one extra layer at each end keeps the surface closed at height 0 and `max_height`
+        let dy = settings.max_height / (height_samples - 3) as f32;
+
+        let mut heights = Vec::with_capacity((dims.x * dims.z) as usize);
+        for z in 0..dims.z {
+            for x in 0..dims.x {
+                heights.push(pixel_value(image, x, z)? * settings.max_height);
+            }
+        }
+
+        let mut values = Vec::with_capacity(dims.element_product() as usize);
+        for z in 0..dims.z {
+            for y in 0..dims.y {
+                let height = (y as f32 - 1.0) * dy;
+                let row = (z * dims.x) as usize;
+                values.extend(
+                    heights[row..row + dims.x as usize]
+                        .iter()
+                        .map(|h| h - height),
+                );
+            }
+        }
+
+        Ok(Self {
+            dims,
+            spacing: Vec3::new(settings.spacing, dy, settings.spacing),
+            origin: Vec3::from_array(settings.origin) - Vec3::Y * dy,
+            values,
+            materials: None,
+        })
+    }
+
+    /// Stacks equally sized slices along z, image rows are flipped so slices stay upright along y.
+    pub fn from_image_slices(slices: &[Image]) -> Result<Self, VolumeLoaderError> {
+        let size = slices.first().map_or(UVec2::ZERO, Image::size);
+        let dims = size.extend(slices.len() as u32);
+        validate_dims(dims)?;
+
+        let mut values = Vec::with_capacity(dims.element_product() as usize);
+        for (index, slice) in slices.iter().enumerate() {
+            if slice.size() != size {
+                return Err(VolumeLoaderError::SliceSizeMismatch {
+                    index,
+                    expected: size,
+                    found: slice.size(),
+                });
+            }
+            for y in (0..size.y).rev() {
+                for x in 0..size.x {
+                    values.push(pixel_value(slice, x, y)?);
+                }
+            }
+        }
+
+        Ok(Self::new(dims, values))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_asset::RenderAssetUsages;
+    use bevy_render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+
+    use super::*;
+
+    fn gray_image(width: u32, height: u32, pixels: &[u8]) -> Image {
+        Image::new(
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            pixels.to_vec(),
+            TextureFormat::R8Unorm,
+            RenderAssetUsages::default(),
+        )
+    }
+
+    #[test]
+    fn heightmap_density_is_height_minus_y() {
+        // rows are z, columns are x
+        let pixels = [0, 51, 255, 102, 204, 153];
+        let settings = HeightmapSettings {
+            max_height: 2.0,
+            height_samples: 6,
+            spacing: 0.5,
+            origin: [1.0, 0.0, -1.0],
+        };
+        let volume = ScalarVolume::from_heightmap(&gray_image(3, 2, &pixels), &settings).unwrap();
+        assert_eq!(volume.dims, UVec3::new(3, 6, 2));
+        let dy = 2.0 / 3.0;
+        assert_eq!(volume.spacing, Vec3::new(0.5, dy, 0.5));
+        assert_eq!(volume.origin, Vec3::new(1.0, -dy, -1.0));
+
+        for z in 0..2 {
+            for x in 0..3 {
+                let height = pixels[(x + 3 * z) as usize] as f32 / 255.0 * 2.0;
+                // ground below the floor layer, air above the top one
+                assert!(volume.get(UVec3::new(x, 0, z)) > 0.0);
+                assert!(volume.get(UVec3::new(x, 5, z)) < 0.0);
+                for y in [-0.5, 0.0, 0.3, 1.0, 1.9, 2.5] {
+                    let pos = Vec3::new(1.0 + 0.5 * x as f32, y, -1.0 + 0.5 * z as f32);
+                    let density = volume.sample(pos);
+                    assert!((density - (height - y)).abs() < 1e-5, "{pos} {density}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn image_slices_stack_along_z_upright() {
+        let slices = [
+            gray_image(2, 2, &[0, 51, 102, 153]),
+            gray_image(2, 2, &[204, 255, 0, 0]),
+        ];
+        let volume = ScalarVolume::from_image_slices(&slices).unwrap();
+        assert_eq!(volume.dims, UVec3::new(2, 2, 2));
+        // the last image row is the bottom of the slice
+        let expected = [102, 153, 0, 51, 0, 0, 204, 255].map(|v| v as f32 / 255.0);
+        for (value, expected) in volume.values.iter().zip(expected) {
+            assert!((value - expected).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn mismatched_slices_are_rejected() {
+        let slices = [gray_image(2, 2, &[0; 4]), gray_image(3, 2, &[0; 6])];
+        assert!(matches!(
+            ScalarVolume::from_image_slices(&slices),
+            Err(VolumeLoaderError::SliceSizeMismatch { index: 1, .. })
+        ));
+        assert!(matches!(
+            ScalarVolume::from_image_slices(&slices[..1]),
+            Err(VolumeLoaderError::InvalidDims(_))
+        ));
+    }
+}
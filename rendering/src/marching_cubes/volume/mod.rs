@@ -0,0 +1,233 @@
+use bevy_app::{App, Plugin};
+use bevy_asset::{Asset, AssetApp, LoadDirectError, ReadAssetBytesError, ron};
+use bevy_image::TextureAccessError;
+use bevy_math::bounding::Aabb3d;
+use bevy_math::{UVec2, UVec3, Vec3};
+use bevy_reflect::TypePath;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+pub mod image;
+pub mod nrrd;
+pub mod raw;
+
+pub struct ScalarVolumePlugin;
+
+impl Plugin for ScalarVolumePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<ScalarVolume>()
+            .init_asset_loader::<raw::RawVolumeLoader>()
+            .init_asset_loader::<nrrd::NrrdVolumeLoader>()
+            .init_asset_loader::<image::HeightmapVolumeLoader>()
+            .init_asset_loader::<image::ImageStackVolumeLoader>();
+    }
+}
+
+/// Regular grid of scalar samples, e.g. a CT/MRI scan or a simulation snapshot.
+///
+/// Sample `(x, y, z)` lies at `origin + (x, y, z) * spacing` and is stored
+/// x-fastest in `values`.
+#[derive(Asset, TypePath, Clone, Debug)]
+pub struct ScalarVolume {
+    pub dims: UVec3,
+    pub spacing: Vec3,
+    pub origin: Vec3,
+    pub values: Vec<f32>,
+    /// Material id of every sample, in the same order as `values`.
+    pub materials: Option<Vec<u8>>,
+}
+
+impl ScalarVolume {
+    pub fn new(dims: UVec3, values: Vec<f32>) -> Self {
+        assert_eq!(
+            dims.element_product() as usize,
+            values.len(),
+            "ScalarVolume dims do not match the number of values"
+        );
+        Self {
+            dims,
+            spacing: Vec3::ONE,
+            origin: Vec3::ZERO,
+            values,
+            materials: None,
+        }
+    }
+
+    pub fn with_materials(mut self, materials: Vec<u8>) -> Self {
+        assert_eq!(
+            self.values.len(),
+            materials.len(),
+            "ScalarVolume materials do not match the number of values"
+        );
+        self.materials = Some(materials);
+        self
+    }
+
+    #[inline]
+    pub fn index(&self, coord: UVec3) -> usize {
+        (coord.x + self.dims.x * (coord.y + self.dims.y * coord.z)) as usize
+    }
+
+    #[inline]
+    pub fn get(&self, coord: UVec3) -> f32 {
+        self.values[self.index(coord)]
+    }
+
+    /// World space bounds spanned by the samples.
+    pub fn aabb(&self) -> Aabb3d {
+        let size = self.dims.saturating_sub(UVec3::ONE).as_vec3() * self.spacing;
+        Aabb3d {
+            min: self.origin.into(),
+            max: (self.origin + size).into(),
+        }
+    }
+
+    /// Trilinearly interpolated value at world position `pos`, clamped to the volume bounds.
+    ///
+    /// Mirrors `sampled_density` in `density.wgsl`.
+    pub fn sample(&self, pos: Vec3) -> f32 {
+        let last = self.dims.saturating_sub(UVec3::ONE);
+        let grid = ((pos - self.origin) / self.spacing).clamp(Vec3::ZERO, last.as_vec3());
+        let base = grid.floor().as_uvec3().min(last.saturating_sub(UVec3::ONE));
+        let t = grid - base.as_vec3();
+
+        let mut corners = [0.0; 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            let offset = UVec3::new(i as u32 & 1, (i as u32 & 2) >> 1, (i as u32 & 4) >> 2);
+            *corner = self.get((base + offset).min(last));
+        }
+
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let x0 = lerp(corners[0], corners[1], t.x);
+        let x1 = lerp(corners[2], corners[3], t.x);
+        let x2 = lerp(corners[4], corners[5], t.x);
+        let x3 = lerp(corners[6], corners[7], t.x);
+        lerp(lerp(x0, x1, t.y), lerp(x2, x3, t.y), t.z)
+    }
+
+    /// Material id of the sample nearest to `pos`, 0 without materials.
+    ///
+    /// Mirrors `sampled_material` in `density.wgsl`.
+    pub fn material(&self, pos: Vec3) -> u32 {
+        let Some(materials) = &self.materials else {
+            return 0;
+        };
+        let last = self.dims.saturating_sub(UVec3::ONE);
+        let grid = ((pos - self.origin) / self.spacing).clamp(Vec3::ZERO, last.as_vec3());
+        let nearest = (grid + 0.5).floor().as_uvec3().min(last);
+        materials[self.index(nearest)] as u32
+    }
+}
+
+/// Storage type of a single sample in a volume file.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SampleFormat {
+    #[default]
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+    F64,
+}
+
+impl SampleFormat {
+    pub fn size(self) -> usize {
+        match self {
+            SampleFormat::U8 | SampleFormat::I8 => 1,
+            SampleFormat::U16 | SampleFormat::I16 => 2,
+            SampleFormat::U32 | SampleFormat::I32 | SampleFormat::F32 => 4,
+            SampleFormat::F64 => 8,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
+}
+
+#[derive(Debug, Error)]
+pub enum VolumeLoaderError {
+    #[error("could not read volume data: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not read detached volume data: {0}")]
+    ReadAssetBytes(#[from] ReadAssetBytesError),
+    #[error("volume needs at least 2 samples along every axis, got {0}")]
+    InvalidDims(UVec3),
+    #[error("not enough sample data: expected {expected}, found {found}")]
+    SizeMismatch { expected: usize, found: usize },
+    #[error("invalid NRRD header: {0}")]
+    InvalidHeader(String),
+    #[error("unsupported NRRD {0}")]
+    Unsupported(String),
+    #[error("could not load image: {0}")]
+    LoadImage(Box<LoadDirectError>),
+    #[error("could not read image: {0}")]
+    ImageAccess(#[from] TextureAccessError),
+    #[error("invalid image stack manifest: {0}")]
+    Manifest(#[from] ron::de::SpannedError),
+    #[error("image slice {index} is {found}, expected {expected}")]
+    SliceSizeMismatch {
+        index: usize,
+        expected: UVec2,
+        found: UVec2,
+    },
+}
+
+impl From<LoadDirectError> for VolumeLoaderError {
+    fn from(error: LoadDirectError) -> Self {
+        VolumeLoaderError::LoadImage(Box::new(error))
+    }
+}
+
+pub(crate) fn validate_dims(dims: UVec3) -> Result<(), VolumeLoaderError> {
+    if dims.cmplt(UVec3::splat(2)).any() {
+        return Err(VolumeLoaderError::InvalidDims(dims));
+    }
+    Ok(())
+}
+
+/// Converts `count` packed samples to `f32`, keeping their original value range.
+pub(crate) fn decode_samples(
+    bytes: &[u8],
+    count: usize,
+    format: SampleFormat,
+    endianness: Endianness,
+) -> Result<Vec<f32>, VolumeLoaderError> {
+    let size = format.size();
+    let expected = count * size;
+    if bytes.len() < expected {
+        return Err(VolumeLoaderError::SizeMismatch {
+            expected,
+            found: bytes.len(),
+        });
+    }
+
+    let values = bytes[..expected]
+        .chunks_exact(size)
+        .map(|chunk| {
+            let mut buf = [0u8; 8];
+            buf[..size].copy_from_slice(chunk);
+            if endianness == Endianness::Big {
+                buf[..size].reverse();
+            }
+            match format {
+                SampleFormat::U8 => buf[0] as f32,
+                SampleFormat::I8 => buf[0] as i8 as f32,
+                SampleFormat::U16 => u16::from_le_bytes([buf[0], buf[1]]) as f32,
+                SampleFormat::I16 => i16::from_le_bytes([buf[0], buf[1]]) as f32,
+                SampleFormat::U32 => u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f32,
+                SampleFormat::I32 => i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f32,
+                SampleFormat::F32 => f32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]),
+                SampleFormat::F64 => f64::from_le_bytes(buf) as f32,
+            }
+        })
+        .collect();
+
+    Ok(values)
+}
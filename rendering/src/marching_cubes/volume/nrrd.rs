@@ -0,0 +1,485 @@
+use std::io::Read;
+
+use bevy_asset::io::Reader;
+use bevy_asset::{AssetLoader, LoadContext};
+use bevy_math::{BVec3, UVec3, Vec3};
+use flate2::read::GzDecoder;
+
+use super::{
+    Endianness, SampleFormat, ScalarVolume, VolumeLoaderError, decode_samples, validate_dims,
+};
+
+/// Loads 3D scalar volumes from NRRD files (<https://teem.sourceforge.net/nrrd/format.html>).
+///
+/// Supports `raw`, `gzip` and `ascii` encodings and both attached and detached (`data file`) data.
+/// `space directions`/`spacings` and `space origin` are mapped to [`ScalarVolume::spacing`]
+/// and [`ScalarVolume::origin`]. Axes with a negative direction are flipped so the samples keep
+/// their place in space, directions not along their own axis are rejected.
+#[derive(Default)]
+pub struct NrrdVolumeLoader;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Encoding {
+    Raw,
+    Gzip,
+    Ascii,
+}
+
+#[derive(Debug)]
+struct NrrdHeader {
+    dims: Option<UVec3>,
+    format: Option<SampleFormat>,
+    encoding: Encoding,
+    endianness: Endianness,
+    spacing: Vec3,
+    /// Axes pointing down their world axis.
+    flip: BVec3,
+    origin: Vec3,
+    data_file: Option<String>,
+    line_skip: usize,
+    byte_skip: i64,
+}
+
+impl Default for NrrdHeader {
+    fn default() -> Self {
+        Self {
+            dims: None,
+            format: None,
+            encoding: Encoding::Raw,
+            endianness: Endianness::Little,
+            spacing: Vec3::ONE,
+            flip: BVec3::FALSE,
+            origin: Vec3::ZERO,
+            data_file: None,
+            line_skip: 0,
+            byte_skip: 0,
+        }
+    }
+}
+
+impl AssetLoader for NrrdVolumeLoader {
+    type Asset = ScalarVolume;
+    type Settings = ();
+    type Error = VolumeLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &Self::Settings,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+
+        let (header, data_start) = parse_header(&bytes)?;
+        match &header.data_file {
+            Some(file) => {
+                let path = load_context
+                    .path()
+                    .parent()
+                    .map(|parent| parent.join(file))
+                    .unwrap_or_else(|| file.into());
+                let detached = load_context.read_asset_bytes(path).await?;
+                read_volume(&header, &detached)
+            }
+            None => read_volume(&header, &bytes[data_start..]),
+        }
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["nrrd", "nhdr"]
+    }
+}
+
+/// Decodes the samples of `data`, the attached data after the header or the detached file.
+fn read_volume(header: &NrrdHeader, data: &[u8]) -> Result<ScalarVolume, VolumeLoaderError> {
+    let mut data = data;
+    let dims = header
+        .dims
+        .ok_or_else(|| VolumeLoaderError::InvalidHeader("missing `sizes` field".into()))?;
+    let format = header
+        .format
+        .ok_or_else(|| VolumeLoaderError::InvalidHeader("missing `type` field".into()))?;
+    validate_dims(dims)?;
+
+    for _ in 0..header.line_skip {
+        let next = data
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(data.len(), |i| i + 1);
+        data = &data[next..];
+    }
+
+    // the byte skip counts in the decompressed data
+    let decompressed;
+    if header.encoding == Encoding::Gzip {
+        let mut bytes = Vec::new();
+        GzDecoder::new(data).read_to_end(&mut bytes)?;
+        decompressed = bytes;
+        data = &decompressed;
+    }
+
+    let count = dims.element_product() as usize;
+    let mut values = match header.encoding {
+        Encoding::Raw | Encoding::Gzip => {
+            let expected = count * format.size();
+            let skip = if header.byte_skip < 0 {
+                data.len().saturating_sub(expected)
+            } else {
+                header.byte_skip as usize
+            };
+            let data = data.get(skip..).unwrap_or_default();
+            decode_samples(data, count, format, header.endianness)?
+        }
+        Encoding::Ascii => decode_ascii(data, count)?,
+    };
+
+    let mut origin = header.origin;
+    for axis in (0..3).filter(|&axis| header.flip.test(axis)) {
+        flip_axis(&mut values, dims, axis);
+        origin[axis] -= (dims[axis] - 1) as f32 * header.spacing[axis];
+    }
+
+    Ok(ScalarVolume {
+        dims,
+        spacing: header.spacing,
+        origin,
+        values,
+        materials: None,
+    })
+}
+
+/// Reverses the sample order along `axis`.
+fn flip_axis(values: &mut [f32], dims: UVec3, axis: usize) {
+    for index in 0..values.len() {
+        let mut coord = UVec3::new(
+            index as u32 % dims.x,
+            index as u32 / dims.x % dims.y,
+            index as u32 / (dims.x * dims.y),
+        );
+        let flipped = dims[axis] - 1 - coord[axis];
+        // every pair once
+        if flipped <= coord[axis] {
+            continue;
+        }
+        coord[axis] = flipped;
+        let other = (coord.x + dims.x * (coord.y + dims.y * coord.z)) as usize;
+        values.swap(index, other);
+    }
+}
+
+/// Parses the header and returns it together with the offset of the attached data.
+fn parse_header(bytes: &[u8]) -> Result<(NrrdHeader, usize), VolumeLoaderError> {
+    let mut header = NrrdHeader::default();
+    let mut offset = 0;
+    let mut first_line = true;
+
+    while offset < bytes.len() {
+        let end = bytes[offset..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(bytes.len(), |i| offset + i);
+        let line = core::str::from_utf8(&bytes[offset..end])
+            .map_err(|_| VolumeLoaderError::InvalidHeader("header is not valid text".into()))?
+            .trim_end_matches('\r');
+        offset = (end + 1).min(bytes.len());
+
+        if first_line {
+            if !line.starts_with("NRRD000") {
+                return Err(VolumeLoaderError::InvalidHeader(
+                    "missing NRRD magic".into(),
+                ));
+            }
+            first_line = false;
+            continue;
+        }
+
+        // an empty line terminates the header
+        if line.is_empty() {
+            break;
+        }
+        // comments and key/value pairs
+        if line.starts_with('#') || line.contains(":=") {
+            continue;
+        }
+
+        let Some((field, value)) = line.split_once(": ") else {
+            return Err(VolumeLoaderError::InvalidHeader(format!(
+                "malformed line `{line}`"
+            )));
+        };
+        parse_field(&mut header, field.trim(), value.trim())?;
+    }
+
+    Ok((header, offset))
+}
+
+fn parse_field(header: &mut NrrdHeader, field: &str, value: &str) -> Result<(), VolumeLoaderError> {
+    match field {
+        "dimension" if value != "3" => {
+            return Err(VolumeLoaderError::Unsupported(format!(
+                "dimension `{value}`, only 3D volumes are supported"
+            )));
+        }
+        "type" => header.format = Some(parse_type(value)?),
+        "sizes" => header.dims = Some(UVec3::from_array(parse_numbers(field, value)?)),
+        "encoding" => {
+            header.encoding = match value {
+                "raw" => Encoding::Raw,
+                "gzip" | "gz" => Encoding::Gzip,
+                "txt" | "text" | "ascii" => Encoding::Ascii,
+                _ => {
+                    return Err(VolumeLoaderError::Unsupported(format!(
+                        "encoding `{value}`"
+                    )));
+                }
+            };
+        }
+        "endian" => {
+            header.endianness = match value {
+                "little" => Endianness::Little,
+                "big" => Endianness::Big,
+                _ => {
+                    return Err(VolumeLoaderError::InvalidHeader(format!(
+                        "unknown endian `{value}`"
+                    )));
+                }
+            };
+        }
+        "spacings" => {
+            let spacings: [f32; 3] = parse_numbers(field, value)?;
+            let spacing = Vec3::from_array(spacings.map(|s| if s.is_nan() { 1.0 } else { s }));
+            header.spacing = spacing.abs();
+            header.flip = spacing.cmplt(Vec3::ZERO);
+        }
+        "space directions" => {
+            let mut axes = value.split_whitespace();
+            for axis in 0..3 {
+                let Some(direction) = axes.next() else {
+                    return Err(VolumeLoaderError::InvalidHeader(
+                        "expected 3 space directions".into(),
+                    ));
+                };
+                if direction == "none" {
+                    continue;
+                }
+                let direction = Vec3::from_array(parse_vector(field, direction)?);
+                let along = direction[axis];
+                if along == 0.0 || (direction.length() - along.abs()) > along.abs() * 1e-4 {
+                    return Err(VolumeLoaderError::Unsupported(format!(
+                        "space direction {direction} of axis {axis}, only directions along their own axis are supported"
+                    )));
+                }
+                header.spacing[axis] = along.abs();
+                header.flip.set(axis, along < 0.0);
+            }
+        }
+        "space origin" => header.origin = Vec3::from_array(parse_vector(field, value)?),
+        "data file" | "datafile" => {
+            if value.starts_with("LIST") || value.contains('%') {
+                return Err(VolumeLoaderError::Unsupported("multi-file data".into()));
+            }
+            header.data_file = Some(value.to_string());
+        }
+        "line skip" | "lineskip" => header.line_skip = parse_number(field, value)?,
+        "byte skip" | "byteskip" => header.byte_skip = parse_number(field, value)?,
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn parse_type(value: &str) -> Result<SampleFormat, VolumeLoaderError> {
+    let format = match value {
+        "uchar" | "unsigned char" | "uint8" | "uint8_t" => SampleFormat::U8,
+        "signed char" | "int8" | "int8_t" => SampleFormat::I8,
+        "ushort" | "unsigned short" | "unsigned short int" | "uint16" | "uint16_t" => {
+            SampleFormat::U16
+        }
+        "short" | "short int" | "signed short" | "signed short int" | "int16" | "int16_t" => {
+            SampleFormat::I16
+        }
+        "uint" | "unsigned int" | "uint32" | "uint32_t" => SampleFormat::U32,
+        "int" | "signed int" | "int32" | "int32_t" => SampleFormat::I32,
+        "float" => SampleFormat::F32,
+        "double" => SampleFormat::F64,
+        _ => return Err(VolumeLoaderError::Unsupported(format!("type `{value}`"))),
+    };
+    Ok(format)
+}
+
+fn parse_number<T: core::str::FromStr>(field: &str, value: &str) -> Result<T, VolumeLoaderError> {
+    value
+        .parse()
+        .map_err(|_| VolumeLoaderError::InvalidHeader(format!("invalid `{field}` value `{value}`")))
+}
+
+fn parse_numbers<T: core::str::FromStr>(
+    field: &str,
+    value: &str,
+) -> Result<[T; 3], VolumeLoaderError> {
+    let numbers = value
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|s| !s.is_empty())
+        .map(|s| parse_number(field, s))
+        .collect::<Result<Vec<T>, _>>()?;
+
+    numbers
+        .try_into()
+        .map_err(|_| VolumeLoaderError::InvalidHeader(format!("expected 3 values in `{field}`")))
+}
+
+/// Parses a `(x,y,z)` vector.
+fn parse_vector(field: &str, value: &str) -> Result<[f32; 3], VolumeLoaderError> {
+    let inner = value
+        .trim()
+        .strip_prefix('(')
+        .and_then(|v| v.strip_suffix(')'))
+        .ok_or_else(|| VolumeLoaderError::InvalidHeader(format!("invalid vector in `{field}`")))?;
+    parse_numbers(field, inner)
+}
+
+fn decode_ascii(data: &[u8], count: usize) -> Result<Vec<f32>, VolumeLoaderError> {
+    let text = core::str::from_utf8(data)
+        .map_err(|_| VolumeLoaderError::InvalidHeader("ascii data is not valid text".into()))?;
+    let values = text
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|s| !s.is_empty())
+        .take(count)
+        .map(|s| parse_number("data", s))
+        .collect::<Result<Vec<f32>, _>>()?;
+
+    if values.len() < count {
+        return Err(VolumeLoaderError::SizeMismatch {
+            expected: count,
+            found: values.len(),
+        });
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+
+    use super::*;
+
+    /// What the loader does with attached data.
+    fn load(bytes: &[u8]) -> Result<ScalarVolume, VolumeLoaderError> {
+        let (header, data_start) = parse_header(bytes)?;
+        read_volume(&header, &bytes[data_start..])
+    }
+
+    fn nrrd(fields: &str, data: &[u8]) -> Vec<u8> {
+        let mut bytes = format!("NRRD0004\n# comment\n{fields}\n").into_bytes();
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    const VALUES: [u8; 8] = [0, 1, 2, 3, 4, 5, 6, 7];
+
+    #[test]
+    fn raw_attached_data() {
+        let bytes = nrrd(
+            "type: uchar\ndimension: 3\nsizes: 2 2 2\nencoding: raw\n\
+             space directions: (0.5,0,0) (0,2,0) (0,0,1)\nspace origin: (1,2,3)\n",
+            &VALUES,
+        );
+        let volume = load(&bytes).unwrap();
+        assert_eq!(volume.dims, UVec3::splat(2));
+        assert_eq!(volume.spacing, Vec3::new(0.5, 2.0, 1.0));
+        assert_eq!(volume.origin, Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(volume.values, VALUES.map(f32::from));
+    }
+
+    #[test]
+    fn gzip_attached_data() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&VALUES).unwrap();
+        let bytes = nrrd(
+            "type: uint8\ndimension: 3\nsizes: 2 2 2\nencoding: gzip\n",
+            &encoder.finish().unwrap(),
+        );
+        assert_eq!(load(&bytes).unwrap().values, VALUES.map(f32::from));
+    }
+
+    #[test]
+    fn header_only_names_the_data_file() {
+        let bytes = nrrd(
+            "type: short\ndimension: 3\nsizes: 2 2 2\nendian: big\ndata file: ./scan.raw\n",
+            &[],
+        );
+        let (header, data_start) = parse_header(&bytes).unwrap();
+        assert_eq!(data_start, bytes.len());
+        assert_eq!(header.data_file.as_deref(), Some("./scan.raw"));
+
+        let detached: Vec<u8> = (0..8i16).flat_map(|value| (-value).to_be_bytes()).collect();
+        let volume = read_volume(&header, &detached).unwrap();
+        assert_eq!(
+            volume.values,
+            [0.0, -1.0, -2.0, -3.0, -4.0, -5.0, -6.0, -7.0]
+        );
+    }
+
+    #[test]
+    fn negative_directions_flip_the_axis() {
+        let bytes = nrrd(
+            "type: uchar\ndimension: 3\nsizes: 2 2 2\nencoding: raw\n\
+             space directions: (-0.5,0,0) (0,1,0) (0,0,1)\nspace origin: (1,0,0)\n",
+            &VALUES,
+        );
+        let volume = load(&bytes).unwrap();
+        assert_eq!(volume.spacing, Vec3::new(0.5, 1.0, 1.0));
+        assert_eq!(volume.origin, Vec3::new(0.5, 0.0, 0.0));
+        assert_eq!(volume.values, [1.0, 0.0, 3.0, 2.0, 5.0, 4.0, 7.0, 6.0]);
+        // the file's first sample stays at the file's origin
+        assert_eq!(volume.get(UVec3::new(1, 0, 0)), 0.0);
+    }
+
+    #[test]
+    fn malformed_headers_are_rejected() {
+        let fields = "type: uchar\ndimension: 3\nsizes: 2 2 2\n";
+        let errors = [
+            load(b"P3\n2 2\n"),
+            load(&nrrd("type uchar\n", &VALUES)),
+            load(&nrrd("type: uchar\ndimension: 3\n", &VALUES)),
+            load(&nrrd("dimension: 2\n", &VALUES)),
+            load(&nrrd(&format!("{fields}encoding: bzip2\n"), &VALUES)),
+            load(&nrrd(
+                &format!("{fields}space directions: (0,1,0) (1,0,0) (0,0,1)\n"),
+                &VALUES,
+            )),
+            load(&nrrd(
+                &format!("{fields}space directions: (1,1,0) (0,1,0) (0,0,1)\n"),
+                &VALUES,
+            )),
+            load(&nrrd(fields, &VALUES[..7])),
+        ];
+        assert!(matches!(
+            errors[0],
+            Err(VolumeLoaderError::InvalidHeader(_))
+        ));
+        assert!(matches!(
+            errors[1],
+            Err(VolumeLoaderError::InvalidHeader(_))
+        ));
+        assert!(matches!(
+            errors[2],
+            Err(VolumeLoaderError::InvalidHeader(_))
+        ));
+        assert!(matches!(errors[3], Err(VolumeLoaderError::Unsupported(_))));
+        assert!(matches!(errors[4], Err(VolumeLoaderError::Unsupported(_))));
+        assert!(matches!(errors[5], Err(VolumeLoaderError::Unsupported(_))));
+        assert!(matches!(errors[6], Err(VolumeLoaderError::Unsupported(_))));
+        assert!(matches!(
+            errors[7],
+            Err(VolumeLoaderError::SizeMismatch {
+                expected: 8,
+                found: 7
+            })
+        ));
+    }
+}
@@ -0,0 +1,146 @@
+use bevy_asset::io::Reader;
+use bevy_asset::{AssetLoader, LoadContext};
+use bevy_math::{UVec3, Vec3};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    Endianness, SampleFormat, ScalarVolume, VolumeLoaderError, decode_samples, validate_dims,
+};
+
+/// Loads headerless `.raw` volumes, the layout has to be given through the settings.
+#[derive(Default)]
+pub struct RawVolumeLoader;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RawVolumeSettings {
+    pub dims: [u32; 3],
+    pub format: SampleFormat,
+    pub endianness: Endianness,
+    pub spacing: [f32; 3],
+    pub origin: [f32; 3],
+    /// Bytes to skip before the first sample.
+    pub header_size: usize,
+}
+
+impl Default for RawVolumeSettings {
+    fn default() -> Self {
+        Self {
+            dims: [0; 3],
+            format: SampleFormat::U8,
+            endianness: Endianness::Little,
+            spacing: [1.0; 3],
+            origin: [0.0; 3],
+            header_size: 0,
+        }
+    }
+}
+
+impl AssetLoader for RawVolumeLoader {
+    type Asset = ScalarVolume;
+    type Settings = RawVolumeSettings;
+    type Error = VolumeLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        settings: &Self::Settings,
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        read_volume(settings, &bytes)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["raw"]
+    }
+}
+
+fn read_volume(
+    settings: &RawVolumeSettings,
+    bytes: &[u8],
+) -> Result<ScalarVolume, VolumeLoaderError> {
+    let dims = UVec3::from_array(settings.dims);
+    validate_dims(dims)?;
+
+    let data = bytes.get(settings.header_size..).unwrap_or_default();
+    let count = dims.element_product() as usize;
+    let values = decode_samples(data, count, settings.format, settings.endianness)?;
+
+    Ok(ScalarVolume {
+        dims,
+        spacing: Vec3::from_array(settings.spacing),
+        origin: Vec3::from_array(settings.origin),
+        values,
+        materials: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(format: SampleFormat, endianness: Endianness) -> RawVolumeSettings {
+        RawVolumeSettings {
+            dims: [2, 2, 2],
+            format,
+            endianness,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn u8_samples_after_the_header() {
+        let mut bytes = vec![0xff; 3];
+        bytes.extend(0..8u8);
+        let settings = RawVolumeSettings {
+            header_size: 3,
+            spacing: [0.5, 1.0, 2.0],
+            origin: [1.0, 2.0, 3.0],
+            ..settings(SampleFormat::U8, Endianness::Little)
+        };
+        let volume = read_volume(&settings, &bytes).unwrap();
+        assert_eq!(volume.dims, UVec3::splat(2));
+        assert_eq!(volume.spacing, Vec3::new(0.5, 1.0, 2.0));
+        assert_eq!(volume.origin, Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(volume.values, [0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0]);
+    }
+
+    #[test]
+    fn wide_samples_keep_their_range() {
+        let values = [0, -1, 300, -300, i16::MIN, i16::MAX, 7, -7];
+        let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_be_bytes()).collect();
+        let volume = read_volume(&settings(SampleFormat::I16, Endianness::Big), &bytes).unwrap();
+        assert_eq!(volume.values, values.map(f32::from));
+
+        let values = [0, 1, 65_535, 65_536, 1 << 24, 42, 9, u32::from(u16::MAX)];
+        let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let volume = read_volume(&settings(SampleFormat::U32, Endianness::Little), &bytes).unwrap();
+        assert_eq!(volume.values, values.map(|v| v as f32));
+
+        let values = [0.0, -1.5, 2.25, 1e-3, -1e6, 0.5, 3.0, -0.0f32];
+        let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_be_bytes()).collect();
+        let volume = read_volume(&settings(SampleFormat::F32, Endianness::Big), &bytes).unwrap();
+        assert_eq!(volume.values, values);
+    }
+
+    #[test]
+    fn short_data_and_flat_dims_are_rejected() {
+        let settings = settings(SampleFormat::U16, Endianness::Little);
+        assert!(matches!(
+            read_volume(&settings, &[0; 15]),
+            Err(VolumeLoaderError::SizeMismatch {
+                expected: 16,
+                found: 15
+            })
+        ));
+        let flat = RawVolumeSettings {
+            dims: [4, 1, 4],
+            ..settings
+        };
+        assert!(matches!(
+            read_volume(&flat, &[0; 64]),
+            Err(VolumeLoaderError::InvalidDims(_))
+        ));
+    }
+}
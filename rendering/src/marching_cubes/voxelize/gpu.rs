@@ -0,0 +1,314 @@
+use bevy_app::{App, Plugin};
+use bevy_asset::{AssetId, Assets, Handle, RenderAssetUsages, load_internal_asset, weak_handle};
+use bevy_ecs::component::Component;
+use bevy_ecs::observer::Trigger;
+use bevy_ecs::resource::Resource;
+use bevy_ecs::schedule::IntoScheduleConfigs;
+use bevy_ecs::system::{Commands, Query, Res, ResMut};
+use bevy_ecs::world::{FromWorld, World};
+use bevy_math::{UVec3, Vec3, Vec4};
+use bevy_render::gpu_readback::{Readback, ReadbackComplete};
+use bevy_render::render_asset::RenderAssets;
+use bevy_render::render_graph::{self, RenderGraph, RenderLabel};
+use bevy_render::render_resource::binding_types::{
+    storage_buffer_read_only_sized, storage_buffer_sized,
+};
+use bevy_render::render_resource::{
+    BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, BufferUsages,
+    CachedComputePipelineId, CachedPipelineState, ComputePassDescriptor, ComputePipelineDescriptor,
+    PipelineCache, Shader, ShaderStages, ShaderType,
+};
+use bevy_render::renderer::{RenderContext, RenderDevice};
+use bevy_render::storage::{GpuShaderStorageBuffer, ShaderStorageBuffer};
+use bevy_render::{Extract, ExtractSchedule, Render, RenderApp, RenderSet};
+
+use super::{VoxelGrid, VoxelizeMesh};
+use crate::marching_cubes::volume::ScalarVolume;
+
+pub const VOXELIZE_SHADER_HANDLE: Handle<Shader> =
+    weak_handle!("7d0c4b61-2f1e-4a8e-9b57-3c2a9e5d41f8");
+
+const WORKGROUP_SIZE: u32 = 4;
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+struct VoxelizeLabel;
+
+pub struct GpuVoxelizePlugin;
+
+/// Present in the main world when voxelization can run on the GPU.
+#[derive(Resource)]
+pub struct GpuVoxelizer;
+
+impl Plugin for GpuVoxelizePlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(
+            app,
+            VOXELIZE_SHADER_HANDLE,
+            "voxelize.wgsl",
+            Shader::from_wgsl
+        );
+
+        app.insert_resource(GpuVoxelizer);
+
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .init_resource::<ExtractedVoxelizeJobs>()
+            .init_resource::<VoxelizeBindGroups>();
+        render_app.add_systems(ExtractSchedule, extract_voxelize_jobs);
+        render_app.add_systems(
+            Render,
+            prepare_voxelize_bind_groups.in_set(RenderSet::PrepareBindGroups),
+        );
+
+        let mut render_graph = render_app.world_mut().resource_mut::<RenderGraph>();
+        render_graph.add_node(VoxelizeLabel, VoxelizeNode::default());
+        render_graph.add_node_edge(VoxelizeLabel, bevy_render::graph::CameraDriverLabel);
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app.init_resource::<VoxelizePipeline>();
+    }
+}
+
+#[derive(ShaderType, Clone, Copy)]
+struct VoxelizeParams {
+    origin: Vec3,
+    spacing: f32,
+    dims: UVec3,
+    triangle_count: u32,
+}
+
+/// Main world side of a GPU voxelization, lives until the densities are read back.
+#[derive(Component)]
+struct VoxelizeJob {
+    params: Handle<ShaderStorageBuffer>,
+    triangles: Handle<ShaderStorageBuffer>,
+    densities: Handle<ShaderStorageBuffer>,
+    grid: VoxelGrid,
+    target: Handle<ScalarVolume>,
+}
+
+pub(super) fn spawn_voxelize_job(
+    commands: &mut Commands,
+    storage_buffers: &mut Assets<ShaderStorageBuffer>,
+    triangles: &[[Vec3; 3]],
+    request: &VoxelizeMesh,
+) {
+    let grid = VoxelGrid::new(triangles, &request.settings);
+
+    let mut params = ShaderStorageBuffer::from(VoxelizeParams {
+        origin: grid.origin,
+        spacing: grid.spacing,
+        dims: grid.dims,
+        triangle_count: triangles.len() as u32,
+    });
+    params.asset_usage = RenderAssetUsages::RENDER_WORLD;
+
+    let vertices: Vec<Vec4> = triangles
+        .iter()
+        .flatten()
+        .map(|vertex| vertex.extend(0.0))
+        .collect();
+
+    // trailing flag, see `voxelize.wgsl`
+    let mut densities = ShaderStorageBuffer::with_size(
+        (grid.dims.element_product() as usize + 1) * size_of::<f32>(),
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    densities.buffer_description.usage |= BufferUsages::COPY_SRC;
+    let densities = storage_buffers.add(densities);
+
+    commands
+        .spawn((
+            Readback::buffer(densities.clone()),
+            VoxelizeJob {
+                params: storage_buffers.add(params),
+                triangles: storage_buffers.add(ShaderStorageBuffer::new(
+                    bytemuck::cast_slice(&vertices),
+                    RenderAssetUsages::RENDER_WORLD,
+                )),
+                densities,
+                grid,
+                target: request.target.clone(),
+            },
+        ))
+        .observe(finish_voxelize_job);
+}
+
+fn finish_voxelize_job(
+    trigger: Trigger<ReadbackComplete>,
+    mut commands: Commands,
+    jobs: Query<&VoxelizeJob>,
+    mut scalar_volumes: ResMut<Assets<ScalarVolume>>,
+) {
+    let entity = trigger.target();
+    let Ok(job) = jobs.get(entity) else {
+        return;
+    };
+
+    let mut values: Vec<f32> = bytemuck::pod_collect_to_vec(&trigger.event().0);
+    let count = job.grid.dims.element_product() as usize;
+    // the first readbacks can happen before the pipeline is ready
+    if values.get(count) != Some(&1.0) {
+        return;
+    }
+    values.truncate(count);
+
+    scalar_volumes.insert(&job.target, job.grid.into_volume(values));
+    commands.entity(entity).despawn();
+}
+
+struct ExtractedVoxelizeJob {
+    params: AssetId<ShaderStorageBuffer>,
+    triangles: AssetId<ShaderStorageBuffer>,
+    densities: AssetId<ShaderStorageBuffer>,
+    dims: UVec3,
+}
+
+#[derive(Resource, Default)]
+struct ExtractedVoxelizeJobs(Vec<ExtractedVoxelizeJob>);
+
+fn extract_voxelize_jobs(mut commands: Commands, jobs: Extract<Query<&VoxelizeJob>>) {
+    let jobs = jobs
+        .iter()
+        .map(|job| ExtractedVoxelizeJob {
+            params: job.params.id(),
+            triangles: job.triangles.id(),
+            densities: job.densities.id(),
+            dims: job.grid.dims,
+        })
+        .collect();
+    commands.insert_resource(ExtractedVoxelizeJobs(jobs));
+}
+
+#[derive(Resource, Default)]
+struct VoxelizeBindGroups(Vec<(BindGroup, UVec3)>);
+
+fn prepare_voxelize_bind_groups(
+    mut commands: Commands,
+    pipeline: Res<VoxelizePipeline>,
+    jobs: Res<ExtractedVoxelizeJobs>,
+    gpu_buffers: Res<RenderAssets<GpuShaderStorageBuffer>>,
+    render_device: Res<RenderDevice>,
+) {
+    let bind_groups = jobs
+        .0
+        .iter()
+        .filter_map(|job| {
+            let params = gpu_buffers.get(job.params)?;
+            let triangles = gpu_buffers.get(job.triangles)?;
+            let densities = gpu_buffers.get(job.densities)?;
+
+            let bind_group = render_device.create_bind_group(
+                Some("voxelize_bind_group"),
+                &pipeline.bind_group_layout,
+                &BindGroupEntries::sequential((
+                    params.buffer.as_entire_buffer_binding(),
+                    triangles.buffer.as_entire_buffer_binding(),
+                    densities.buffer.as_entire_buffer_binding(),
+                )),
+            );
+            Some((bind_group, job.dims))
+        })
+        .collect();
+    commands.insert_resource(VoxelizeBindGroups(bind_groups));
+}
+
+#[derive(Resource)]
+struct VoxelizePipeline {
+    bind_group_layout: BindGroupLayout,
+    pipeline_id: CachedComputePipelineId,
+}
+
+impl FromWorld for VoxelizePipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let bind_group_layout = render_device.create_bind_group_layout(
+            "voxelize_bind_group",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::COMPUTE,
+                (
+                    storage_buffer_read_only_sized(false, Some(VoxelizeParams::min_size())),
+                    storage_buffer_read_only_sized(false, None),
+                    storage_buffer_sized(false, None),
+                ),
+            ),
+        );
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let pipeline_id = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: Some("voxelize_pipeline".into()),
+            layout: vec![bind_group_layout.clone()],
+            push_constant_ranges: Vec::new(),
+            shader: VOXELIZE_SHADER_HANDLE,
+            shader_defs: vec![],
+            entry_point: "voxelize".into(),
+            zero_initialize_workgroup_memory: false,
+        });
+
+        Self {
+            bind_group_layout,
+            pipeline_id,
+        }
+    }
+}
+
+#[derive(Default)]
+struct VoxelizeNode {
+    pipeline_is_ready: bool,
+}
+
+impl render_graph::Node for VoxelizeNode {
+    fn update(&mut self, world: &mut World) {
+        let pipeline = world.resource::<VoxelizePipeline>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+
+        if !self.pipeline_is_ready {
+            match pipeline_cache.get_compute_pipeline_state(pipeline.pipeline_id) {
+                CachedPipelineState::Ok(_) => {
+                    self.pipeline_is_ready = true;
+                }
+                CachedPipelineState::Err(err) => {
+                    panic!("Initializing voxelize.wgsl:\n{err}")
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn run<'w>(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext<'w>,
+        world: &'w World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let bind_groups = world.resource::<VoxelizeBindGroups>();
+        if !self.pipeline_is_ready || bind_groups.0.is_empty() {
+            return Ok(());
+        }
+
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let pipeline = pipeline_cache
+            .get_compute_pipeline(world.resource::<VoxelizePipeline>().pipeline_id)
+            .unwrap();
+
+        // jobs are dispatched every frame until their readback lands, the result is the same
+        let mut pass = render_context
+            .command_encoder()
+            .begin_compute_pass(&ComputePassDescriptor::default());
+        pass.set_pipeline(pipeline);
+        for (bind_group, dims) in &bind_groups.0 {
+            let workgroups = (dims + UVec3::splat(WORKGROUP_SIZE - 1)) / WORKGROUP_SIZE;
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.dispatch_workgroups(workgroups.x, workgroups.y, workgroups.z);
+        }
+
+        Ok(())
+    }
+}
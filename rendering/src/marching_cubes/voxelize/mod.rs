@@ -0,0 +1,478 @@
+use core::f32::consts::PI;
+
+use bevy_app::{App, Plugin, Update};
+use bevy_asset::{AssetServer, Assets, Handle, LoadState};
+use bevy_ecs::component::Component;
+use bevy_ecs::entity::Entity;
+use bevy_ecs::event::{Event, EventReader};
+use bevy_ecs::system::{Commands, Local, Query, Res, ResMut};
+use bevy_math::{UVec3, Vec3};
+use bevy_render::RenderApp;
+use bevy_render::mesh::{Mesh, MeshTrianglesError};
+use bevy_render::storage::ShaderStorageBuffer;
+use bevy_tasks::futures::check_ready;
+use bevy_tasks::{AsyncComputeTaskPool, Task};
+
+use super::volume::ScalarVolume;
+
+pub mod gpu;
+
+/// Converts meshes into signed distance [`ScalarVolume`]s, see [`VoxelizeMesh`].
+pub struct MeshVoxelizePlugin;
+
+impl Plugin for MeshVoxelizePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<VoxelizeMesh>()
+            .add_systems(Update, (handle_voxelize_requests, finish_cpu_voxelize_jobs));
+
+        if app.get_sub_app(RenderApp).is_some() {
+            app.add_plugins(gpu::GpuVoxelizePlugin);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct VoxelizeSettings {
+    /// Distance between samples.
+    pub voxel_size: f32,
+    /// Samples added around the mesh bounds, so the surface is closed at the border.
+    pub padding: u32,
+}
+
+impl Default for VoxelizeSettings {
+    fn default() -> Self {
+        Self {
+            voxel_size: 0.05,
+            padding: 2,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VoxelizeBackend {
+    /// Compute shader, falls back to [`VoxelizeBackend::Cpu`] without a render app.
+    #[default]
+    Gpu,
+    Cpu,
+}
+
+/// Voxelizes `mesh` into the `target` asset, reserve it with [`Assets::reserve_handle`].
+///
+/// The mesh has to keep [`RenderAssetUsages::MAIN_WORLD`](bevy_asset::RenderAssetUsages),
+/// requests wait until it is loaded and are dropped if loading fails. Results arrive a few
+/// frames later, CPU requests run on the [`AsyncComputeTaskPool`].
+#[derive(Event, Clone, Debug)]
+pub struct VoxelizeMesh {
+    pub mesh: Handle<Mesh>,
+    pub target: Handle<ScalarVolume>,
+    pub settings: VoxelizeSettings,
+    pub backend: VoxelizeBackend,
+}
+
+/// Sample grid covering a set of triangles.
+#[derive(Clone, Copy, Debug)]
+pub struct VoxelGrid {
+    pub dims: UVec3,
+    pub spacing: f32,
+    pub origin: Vec3,
+}
+
+impl VoxelGrid {
+    pub fn new(triangles: &[[Vec3; 3]], settings: &VoxelizeSettings) -> Self {
+        let (min, max) = triangles.iter().flatten().fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), &p| (min.min(p), max.max(p)),
+        );
+        let (min, max) = if triangles.is_empty() {
+            (Vec3::ZERO, Vec3::ZERO)
+        } else {
+            (min, max)
+        };
+
+        let padding = settings.padding as f32 * settings.voxel_size;
+        let cells = ((max - min) / settings.voxel_size).ceil().as_uvec3();
+        Self {
+            dims: cells + UVec3::ONE + 2 * settings.padding,
+            spacing: settings.voxel_size,
+            origin: min - padding,
+        }
+    }
+
+    #[inline]
+    pub fn position(&self, coord: UVec3) -> Vec3 {
+        self.origin + coord.as_vec3() * self.spacing
+    }
+
+    /// Wraps `values` sampled on this grid.
+    pub fn into_volume(self, values: Vec<f32>) -> ScalarVolume {
+        let mut volume = ScalarVolume::new(self.dims, values);
+        volume.spacing = Vec3::splat(self.spacing);
+        volume.origin = self.origin;
+        volume
+    }
+}
+
+pub(crate) fn mesh_triangles(mesh: &Mesh) -> Result<Vec<[Vec3; 3]>, MeshTrianglesError> {
+    Ok(mesh
+        .triangles()?
+        .map(|triangle| triangle.vertices)
+        .collect())
+}
+
+/// Signed distance volume of `mesh` on the CPU, positive inside so the surface is at isovalue 0.
+///
+/// Distance is to the closest triangle, the sign comes from the generalized winding
+/// number, which tolerates small holes and self intersections. Mirrors `voxelize.wgsl`.
+pub fn voxelize_mesh(
+    mesh: &Mesh,
+    settings: &VoxelizeSettings,
+) -> Result<ScalarVolume, MeshTrianglesError> {
+    let triangles = mesh_triangles(mesh)?;
+    Ok(voxelize_triangles(&triangles, settings))
+}
+
+pub fn voxelize_triangles(triangles: &[[Vec3; 3]], settings: &VoxelizeSettings) -> ScalarVolume {
+    let grid = VoxelGrid::new(triangles, settings);
+
+    let mut values = Vec::with_capacity(grid.dims.element_product() as usize);
+    for z in 0..grid.dims.z {
+        for y in 0..grid.dims.y {
+            for x in 0..grid.dims.x {
+                values.push(signed_density(
+                    grid.position(UVec3::new(x, y, z)),
+                    triangles,
+                ));
+            }
+        }
+    }
+
+    grid.into_volume(values)
+}
+
+fn signed_density(pos: Vec3, triangles: &[[Vec3; 3]]) -> f32 {
+    let mut min_distance_sq = f32::MAX;
+    let mut winding = 0.0;
+    for &[a, b, c] in triangles {
+        let closest = closest_point_on_triangle(pos, a, b, c);
+        min_distance_sq = min_distance_sq.min(closest.distance_squared(pos));
+        winding += solid_angle(a - pos, b - pos, c - pos);
+    }
+
+    // generalized winding number is ~1 inside and ~0 outside
+    let distance = min_distance_sq.sqrt();
+    if winding / (4.0 * PI) > 0.5 {
+        distance
+    } else {
+        -distance
+    }
+}
+
+/// Closest point on triangle `abc` to `p` (Real-Time Collision Detection, 5.1.5).
+fn closest_point_on_triangle(p: Vec3, a: Vec3, b: Vec3, c: Vec3) -> Vec3 {
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+    let d1 = ab.dot(ap);
+    let d2 = ac.dot(ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+
+    let bp = p - b;
+    let d3 = ab.dot(bp);
+    let d4 = ac.dot(bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+
+    let cp = p - c;
+    let d5 = ab.dot(cp);
+    let d6 = ac.dot(cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    let denom = 1.0 / (va + vb + vc);
+    a + ab * (vb * denom) + ac * (vc * denom)
+}
+
+/// Signed solid angle of triangle `abc` seen from the origin (Van Oosterom and Strackee).
+fn solid_angle(a: Vec3, b: Vec3, c: Vec3) -> f32 {
+    let (la, lb, lc) = (a.length(), b.length(), c.length());
+    let numerator = a.dot(b.cross(c));
+    let denominator = la * lb * lc + a.dot(b) * lc + b.dot(c) * la + c.dot(a) * lb;
+    2.0 * numerator.atan2(denominator)
+}
+
+/// Boolean operation between two density fields, positive inside.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    /// Removes the other field from this one.
+    Subtraction,
+}
+
+impl CsgOp {
+    #[inline]
+    pub fn apply(self, a: f32, b: f32) -> f32 {
+        match self {
+            CsgOp::Union => a.max(b),
+            CsgOp::Intersection => a.min(b),
+            CsgOp::Subtraction => a.min(-b),
+        }
+    }
+}
+
+impl ScalarVolume {
+    /// Combines every sample with the density `other` at the same position.
+    ///
+    /// Signed distance functions are negative inside, pass them negated, e.g.
+    /// `|p| 0.5 - p.length()` for a sphere, or `|p| other.sample(p)` for another volume.
+    pub fn combine(&mut self, op: CsgOp, other: impl Fn(Vec3) -> f32) {
+        for z in 0..self.dims.z {
+            for y in 0..self.dims.y {
+                for x in 0..self.dims.x {
+                    let coord = UVec3::new(x, y, z);
+                    let index = self.index(coord);
+                    let pos = self.origin + coord.as_vec3() * self.spacing;
+                    self.values[index] = op.apply(self.values[index], other(pos));
+                }
+            }
+        }
+    }
+}
+
+/// CPU voxelization running in the background.
+#[derive(Component)]
+struct CpuVoxelizeJob {
+    task: Task<ScalarVolume>,
+    target: Handle<ScalarVolume>,
+}
+
+fn handle_voxelize_requests(
+    mut commands: Commands,
+    mut requests: EventReader<VoxelizeMesh>,
+    mut pending: Local<Vec<VoxelizeMesh>>,
+    meshes: Res<Assets<Mesh>>,
+    asset_server: Res<AssetServer>,
+    mut storage_buffers: ResMut<Assets<ShaderStorageBuffer>>,
+    gpu_voxelizer: Option<Res<gpu::GpuVoxelizer>>,
+) {
+    pending.extend(requests.read().cloned());
+
+    pending.retain(|request| {
+        let Some(mesh) = meshes.get(&request.mesh) else {
+            // meshes added directly to the assets are never loading, keep waiting for them
+            if let LoadState::Failed(err) = asset_server.load_state(&request.mesh) {
+                tracing::error!("Voxelizing mesh {:?}: {err}", request.mesh.id());
+                return false;
+            }
+            return true;
+        };
+
+        let triangles = match mesh_triangles(mesh) {
+            Ok(triangles) => triangles,
+            Err(err) => {
+                tracing::error!("Voxelizing mesh {:?}: {err}", request.mesh.id());
+                return false;
+            }
+        };
+
+        if request.backend == VoxelizeBackend::Gpu
+            && gpu_voxelizer.is_some()
+            && !triangles.is_empty()
+        {
+            gpu::spawn_voxelize_job(&mut commands, &mut storage_buffers, &triangles, request);
+        } else {
+            let settings = request.settings;
+            let task = AsyncComputeTaskPool::get()
+                .spawn(async move { voxelize_triangles(&triangles, &settings) });
+            commands.spawn(CpuVoxelizeJob {
+                task,
+                target: request.target.clone(),
+            });
+        }
+        false
+    });
+}
+
+fn finish_cpu_voxelize_jobs(
+    mut commands: Commands,
+    mut jobs: Query<(Entity, &mut CpuVoxelizeJob)>,
+    mut scalar_volumes: ResMut<Assets<ScalarVolume>>,
+) {
+    for (entity, mut job) in &mut jobs {
+        if let Some(volume) = check_ready(&mut job.task) {
+            scalar_volumes.insert(&job.target, volume);
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_math::primitives::{Cuboid, Sphere};
+    use bevy_render::mesh::{MeshBuilder, Meshable};
+
+    use super::*;
+
+    const SETTINGS: VoxelizeSettings = VoxelizeSettings {
+        voxel_size: 0.05,
+        padding: 2,
+    };
+
+    fn sphere_triangles() -> Vec<[Vec3; 3]> {
+        mesh_triangles(&Sphere::new(0.5).mesh().ico(2).unwrap()).unwrap()
+    }
+
+    fn positions(volume: &ScalarVolume) -> impl Iterator<Item = (Vec3, f32)> + '_ {
+        (0..volume.values.len()).map(|index| {
+            let index = index as u32;
+            let coord = UVec3::new(
+                index % volume.dims.x,
+                index / volume.dims.x % volume.dims.y,
+                index / (volume.dims.x * volume.dims.y),
+            );
+            let pos = volume.origin + coord.as_vec3() * volume.spacing;
+            (pos, volume.get(coord))
+        })
+    }
+
+    #[test]
+    fn cuboid_matches_the_box_distance() {
+        let half_size = Vec3::new(0.5, 0.3, 0.4);
+        let mesh = Cuboid::from_size(half_size * 2.0).mesh().build();
+        let volume = voxelize_mesh(&mesh, &SETTINGS).unwrap();
+
+        assert_eq!(volume.origin, -half_size - 0.1);
+        assert_eq!(volume.spacing, Vec3::splat(0.05));
+        for (pos, density) in positions(&volume) {
+            let q = pos.abs() - half_size;
+            let sdf = q.max(Vec3::ZERO).length() + q.max_element().min(0.0);
+            // positive inside, flat faces make the distance exact
+            assert!((density + sdf).abs() < 1e-4, "{pos} {density} {sdf}");
+        }
+    }
+
+    #[test]
+    fn sphere_matches_the_analytic_distance() {
+        let volume = voxelize_triangles(&sphere_triangles(), &SETTINGS);
+        for (pos, density) in positions(&volume) {
+            let expected = 0.5 - pos.length();
+            assert!(
+                (density - expected).abs() < SETTINGS.voxel_size,
+                "{pos} {density} {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn small_hole_keeps_the_sign() {
+        let mut triangles = sphere_triangles();
+        let [a, b, c] = triangles.swap_remove(0);
+        let hole = (a + b + c) / 3.0;
+        let volume = voxelize_triangles(&triangles, &SETTINGS);
+
+        let mut checked = 0;
+        for (pos, density) in positions(&volume) {
+            // right at the hole or the surface the sign is ambiguous
+            if pos.distance(hole) < 0.15 || (pos.length() - 0.5).abs() < SETTINGS.voxel_size {
+                continue;
+            }
+            assert_eq!(density > 0.0, pos.length() < 0.5, "{pos} {density}");
+            checked += 1;
+        }
+        assert!(checked > volume.values.len() / 2);
+    }
+
+    #[test]
+    fn closest_point_regions() {
+        let (a, b, c) = (Vec3::ZERO, Vec3::X, Vec3::Y);
+        let closest = |p| closest_point_on_triangle(p, a, b, c);
+        // vertices, edges and the face
+        assert_eq!(closest(Vec3::new(-1.0, -1.0, 0.5)), a);
+        assert_eq!(closest(Vec3::new(2.0, -0.5, 0.0)), b);
+        assert_eq!(closest(Vec3::new(-0.5, 2.0, 1.0)), c);
+        assert_eq!(closest(Vec3::new(0.5, -1.0, 1.0)), Vec3::new(0.5, 0.0, 0.0));
+        assert_eq!(
+            closest(Vec3::new(-1.0, 0.25, 0.0)),
+            Vec3::new(0.0, 0.25, 0.0)
+        );
+        assert!(closest(Vec3::new(1.0, 1.0, 3.0)).distance(Vec3::new(0.5, 0.5, 0.0)) < 1e-6);
+        assert_eq!(closest(Vec3::new(0.2, 0.3, -2.0)), Vec3::new(0.2, 0.3, 0.0));
+    }
+
+    #[test]
+    fn solid_angle_of_an_octant() {
+        // an eighth of the sphere, signed by the winding seen from the origin
+        let angle = solid_angle(Vec3::X, Vec3::Y, Vec3::Z);
+        assert!((angle - PI / 2.0).abs() < 1e-5);
+        assert!((solid_angle(Vec3::X, Vec3::Z, Vec3::Y) + PI / 2.0).abs() < 1e-5);
+
+        // a closed mesh winds once around the points inside it
+        let winding = |pos: Vec3| {
+            sphere_triangles()
+                .iter()
+                .map(|&[a, b, c]| solid_angle(a - pos, b - pos, c - pos))
+                .sum::<f32>()
+                / (4.0 * PI)
+        };
+        assert!((winding(Vec3::new(0.1, -0.2, 0.05)) - 1.0).abs() < 1e-4);
+        assert!(winding(Vec3::new(0.6, 0.3, 0.0)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn csg_ops() {
+        assert_eq!(CsgOp::Union.apply(-1.0, 2.0), 2.0);
+        assert_eq!(CsgOp::Intersection.apply(-1.0, 2.0), -1.0);
+        assert_eq!(CsgOp::Subtraction.apply(1.0, 2.0), -2.0);
+        assert_eq!(CsgOp::Subtraction.apply(1.0, -0.5), 0.5);
+    }
+
+    #[test]
+    fn combine_samples_the_other_field() {
+        let sphere = |radius: f32, center: Vec3| move |pos: Vec3| radius - pos.distance(center);
+        let grid = VoxelGrid {
+            dims: UVec3::splat(9),
+            spacing: 0.25,
+            origin: Vec3::splat(-1.0),
+        };
+        let values = (0..grid.dims.element_product())
+            .map(|index| {
+                let coord = UVec3::new(index % 9, index / 9 % 9, index / 81);
+                sphere(0.8, Vec3::ZERO)(grid.position(coord))
+            })
+            .collect();
+        let base = grid.into_volume(values);
+
+        let other = sphere(0.5, Vec3::X * 0.5);
+        for op in [CsgOp::Union, CsgOp::Intersection, CsgOp::Subtraction] {
+            let mut volume = base.clone();
+            volume.combine(op, other);
+            for ((pos, density), &before) in positions(&volume).zip(&base.values) {
+                assert_eq!(density, op.apply(before, other(pos)), "{op:?} {pos}");
+            }
+        }
+        // the center is carved out by the subtraction only
+        let mut carved = base.clone();
+        carved.combine(CsgOp::Subtraction, other);
+        let center = carved.index(UVec3::new(6, 4, 4));
+        assert!(base.values[center] > 0.0 && carved.values[center] < 0.0);
+    }
+}
@@ -0,0 +1,101 @@
+struct VoxelizeParams {
+    origin: vec3<f32>,
+    spacing: f32,
+    dims: vec3<u32>,
+    triangle_count: u32,
+};
+
+@group(0) @binding(0) var<storage, read> params: VoxelizeParams;
+// 3 vertices per triangle, w is unused
+@group(0) @binding(1) var<storage, read> triangles: array<vec4<f32>>;
+// one value per sample, followed by a flag set once the dispatch ran
+@group(0) @binding(2) var<storage, read_write> densities: array<f32>;
+
+const PI: f32 = 3.14159265358979;
+
+// Closest point on triangle abc to p (Real-Time Collision Detection, 5.1.5)
+fn closest_point_on_triangle(p: vec3<f32>, a: vec3<f32>, b: vec3<f32>, c: vec3<f32>) -> vec3<f32> {
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+    let d1 = dot(ab, ap);
+    let d2 = dot(ac, ap);
+    if (d1 <= 0.0 && d2 <= 0.0) {
+        return a;
+    }
+
+    let bp = p - b;
+    let d3 = dot(ab, bp);
+    let d4 = dot(ac, bp);
+    if (d3 >= 0.0 && d4 <= d3) {
+        return b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if (vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0) {
+        return a + ab * (d1 / (d1 - d3));
+    }
+
+    let cp = p - c;
+    let d5 = dot(ab, cp);
+    let d6 = dot(ac, cp);
+    if (d6 >= 0.0 && d5 <= d6) {
+        return c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if (vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0) {
+        return a + ac * (d2 / (d2 - d6));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if (va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0) {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    let denom = 1.0 / (va + vb + vc);
+    return a + ab * (vb * denom) + ac * (vc * denom);
+}
+
+// Signed solid angle of triangle abc seen from the origin (Van Oosterom and Strackee)
+fn solid_angle(a: vec3<f32>, b: vec3<f32>, c: vec3<f32>) -> f32 {
+    let la = length(a);
+    let lb = length(b);
+    let lc = length(c);
+    let numerator = dot(a, cross(b, c));
+    let denominator = la * lb * lc + dot(a, b) * lc + dot(b, c) * la + dot(c, a) * lb;
+    return 2.0 * atan2(numerator, denominator);
+}
+
+@compute @workgroup_size(4, 4, 4)
+fn voxelize(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    if (any(invocation_id >= params.dims)) {
+        return;
+    }
+    let pos = params.origin + vec3<f32>(invocation_id) * params.spacing;
+
+    var min_distance_sq = 3.4e38;
+    var winding = 0.0;
+    for (var i: u32 = 0; i < params.triangle_count; i++) {
+        let a = triangles[3 * i].xyz;
+        let b = triangles[3 * i + 1].xyz;
+        let c = triangles[3 * i + 2].xyz;
+
+        let closest = closest_point_on_triangle(pos, a, b, c);
+        let offset = closest - pos;
+        min_distance_sq = min(min_distance_sq, dot(offset, offset));
+        winding += solid_angle(a - pos, b - pos, c - pos);
+    }
+
+    // generalized winding number is ~1 inside and ~0 outside
+    let inside = winding / (4.0 * PI) > 0.5;
+    let distance = sqrt(min_distance_sq);
+    let idx = invocation_id.x + params.dims.x * (invocation_id.y + params.dims.y * invocation_id.z);
+    // stored as density, positive inside
+    densities[idx] = select(-distance, distance, inside);
+
+    if (all(invocation_id == vec3<u32>(0u))) {
+        let count = params.dims.x * params.dims.y * params.dims.z;
+        densities[count] = 1.0;
+    }
+}
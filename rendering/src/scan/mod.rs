@@ -0,0 +1,635 @@
+use bevy_app::{App, Plugin};
+use bevy_asset::{Handle, load_internal_asset, weak_handle};
+use bevy_ecs::resource::Resource;
+use bevy_ecs::world::{FromWorld, World};
+use bevy_math::UVec3;
+use bevy_render::RenderApp;
+use bevy_render::render_resource::binding_types::{
+    storage_buffer_read_only_sized, storage_buffer_sized, uniform_buffer_sized,
+};
+use bevy_render::render_resource::{
+    BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, Buffer, BufferDescriptor,
+    BufferInitDescriptor, BufferUsages, CachedComputePipelineId, CachedPipelineState, ComputePass,
+    ComputePipeline, ComputePipelineDescriptor, PipelineCache, Shader, ShaderStages,
+};
+use bevy_render::renderer::RenderDevice;
+
+pub const SCAN_SHADER_HANDLE: Handle<Shader> = weak_handle!("8d3f6a1c-7e24-4b95-a0c8-2f5e9b17d436");
+
+/// Invocations of every scan workgroup.
+pub const SCAN_WORKGROUP_SIZE: u32 = 256;
+/// Elements every scan workgroup covers, two per invocation.
+pub const SCAN_BLOCK_SIZE: u32 = 2 * SCAN_WORKGROUP_SIZE;
+// wider dispatches wrap into y
+const MAX_WORKGROUPS_PER_DIMENSION: u32 = 65535;
+
+// must match the entry points in scan.wgsl, indexed by the consts below
+const ENTRY_POINTS: [&str; 4] = ["scan_blocks", "add_block_offsets", "write_total", "compact"];
+const SCAN_BLOCKS: usize = 0;
+const ADD_BLOCK_OFFSETS: usize = 1;
+const WRITE_TOTAL: usize = 2;
+const COMPACT: usize = 3;
+
+/// Exclusive prefix sum and stream compaction of `u32` storage buffers, see [`GpuScan`].
+pub struct ScanPlugin;
+
+impl Plugin for ScanPlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(app, SCAN_SHADER_HANDLE, "scan.wgsl", Shader::from_wgsl);
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app.init_resource::<ScanPipeline>();
+    }
+}
+
+#[derive(Resource)]
+pub struct ScanPipeline {
+    bind_group_layout: BindGroupLayout,
+    pipeline_ids: [CachedComputePipelineId; 4],
+}
+
+impl FromWorld for ScanPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let bind_group_layout = bind_group_layout(world.resource::<RenderDevice>());
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let pipeline_ids = ENTRY_POINTS.map(|entry_point| {
+            pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                label: Some(format!("scan_{entry_point}_pipeline").into()),
+                layout: vec![bind_group_layout.clone()],
+                push_constant_ranges: Vec::new(),
+                shader: SCAN_SHADER_HANDLE,
+                shader_defs: vec![],
+                entry_point: entry_point.into(),
+                zero_initialize_workgroup_memory: false,
+            })
+        });
+
+        Self {
+            bind_group_layout,
+            pipeline_ids,
+        }
+    }
+}
+
+fn bind_group_layout(render_device: &RenderDevice) -> BindGroupLayout {
+    render_device.create_bind_group_layout(
+        "scan_bind_group",
+        &BindGroupLayoutEntries::sequential(
+            ShaderStages::COMPUTE,
+            (
+                uniform_buffer_sized(false, None),
+                storage_buffer_read_only_sized(false, None),
+                storage_buffer_sized(false, None),
+                storage_buffer_sized(false, None),
+            ),
+        ),
+    )
+}
+
+impl ScanPipeline {
+    /// Whether every scan pipeline has compiled, panics on a shader error.
+    pub fn is_ready(&self, pipeline_cache: &PipelineCache) -> bool {
+        self.pipeline_ids
+            .iter()
+            .all(|&id| match pipeline_cache.get_compute_pipeline_state(id) {
+                CachedPipelineState::Ok(_) => true,
+                CachedPipelineState::Err(err) => panic!("Initializing scan.wgsl:\n{err}"),
+                _ => false,
+            })
+    }
+
+    /// Compiled pipelines of the scan entry points, in order, none while compiling.
+    pub fn pipelines<'a>(
+        &self,
+        pipeline_cache: &'a PipelineCache,
+    ) -> Option<Vec<&'a ComputePipeline>> {
+        self.pipeline_ids
+            .iter()
+            .map(|&id| pipeline_cache.get_compute_pipeline(id))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+impl ScanPipeline {
+    /// Layout and pipelines compiled on `render_device` without a pipeline cache, for the tests
+    /// running the scan on a device.
+    pub(crate) fn compile(render_device: &RenderDevice) -> (Self, [ComputePipeline; 4]) {
+        let bind_group_layout = bind_group_layout(render_device);
+        let module = naga::front::wgsl::parse_str(include_str!("scan.wgsl")).unwrap();
+        let pipelines = ENTRY_POINTS.map(|entry_point| {
+            crate::testing::compute_pipeline(
+                render_device,
+                &module,
+                entry_point,
+                Some(&[&bind_group_layout]),
+            )
+        });
+        let pipeline = Self {
+            bind_group_layout,
+            pipeline_ids: [CachedComputePipelineId::INVALID; 4],
+        };
+        (pipeline, pipelines)
+    }
+}
+
+/// Scratch buffers of an exclusive scan over a fixed number of elements.
+///
+/// The scan reads `count` elements from the input and writes `count + 1` to the output, the
+/// exclusive sums followed by the total. Build it once per element count and bind it to the
+/// buffers with [`GpuScan::bind`].
+pub struct GpuScan {
+    count: u32,
+    levels: Vec<ScanLevel>,
+}
+
+/// One level of the scan, the first one scans the input and every other one the block totals of
+/// the level below.
+struct ScanLevel {
+    count: u32,
+    /// `ScanParams` in `scan.wgsl`.
+    params: Buffer,
+    /// Totals of the blocks of this level, the input of the level up.
+    block_sums: Buffer,
+    /// Scanned totals, the output of the level up. The last level is a single block.
+    scanned_sums: Option<Buffer>,
+}
+
+impl GpuScan {
+    pub fn new(render_device: &RenderDevice, count: u32) -> Self {
+        let counts = level_counts(count);
+        let levels = counts
+            .iter()
+            .enumerate()
+            .map(|(level, &level_count)| {
+                let block_count = counts.get(level + 1).copied();
+                ScanLevel {
+                    count: level_count,
+                    params: render_device.create_buffer_with_data(&BufferInitDescriptor {
+                        label: Some("scan_params"),
+                        contents: bytemuck::cast_slice(&[level_count, 0, 0, 0]),
+                        usage: BufferUsages::UNIFORM,
+                    }),
+                    block_sums: storage_buffer(render_device, block_count.unwrap_or(1)),
+                    scanned_sums: block_count.map(|count| storage_buffer(render_device, count)),
+                }
+            })
+            .collect();
+
+        Self { count, levels }
+    }
+
+    #[inline]
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Bind groups scanning `input` into `output`, which holds at least `count + 1` elements.
+    ///
+    /// With `compacted` the indices of the set elements of `input`, a flag of 0 or 1 per element,
+    /// are written to it in order and the total in `output` is their count.
+    pub fn bind(
+        &self,
+        render_device: &RenderDevice,
+        pipeline: &ScanPipeline,
+        input: &Buffer,
+        output: &Buffer,
+        compacted: Option<&Buffer>,
+    ) -> ScanBindGroups {
+        let bind_group = |params: &Buffer, input: &Buffer, output: &Buffer, blocks: &Buffer| {
+            render_device.create_bind_group(
+                Some("scan_bind_group"),
+                &pipeline.bind_group_layout,
+                &BindGroupEntries::sequential((
+                    params.as_entire_binding(),
+                    input.as_entire_binding(),
+                    output.as_entire_binding(),
+                    blocks.as_entire_binding(),
+                )),
+            )
+        };
+
+        let mut scan = Vec::with_capacity(self.levels.len());
+        let mut add = Vec::with_capacity(self.levels.len() - 1);
+        for (index, level) in self.levels.iter().enumerate() {
+            let (level_input, level_output) = match index {
+                0 => (input, output),
+                _ => {
+                    let below = &self.levels[index - 1];
+                    (&below.block_sums, below.scanned_sums.as_ref().unwrap())
+                }
+            };
+            let workgroups = workgroups(level.count, SCAN_BLOCK_SIZE);
+            scan.push((
+                bind_group(&level.params, level_input, level_output, &level.block_sums),
+                workgroups,
+            ));
+            if let Some(scanned_sums) = &level.scanned_sums {
+                add.push((
+                    bind_group(&level.params, level_input, level_output, scanned_sums),
+                    workgroups,
+                ));
+            }
+        }
+
+        let first = &self.levels[0];
+        ScanBindGroups {
+            scan,
+            add,
+            compact: compacted.map(|compacted| {
+                (
+                    bind_group(&first.params, input, output, compacted),
+                    workgroups(self.count, SCAN_WORKGROUP_SIZE),
+                )
+            }),
+        }
+    }
+}
+
+/// Scan of [`GpuScan::bind`], recorded into a compute pass.
+pub struct ScanBindGroups {
+    /// Bind group and workgroups of every level.
+    scan: Vec<(BindGroup, UVec3)>,
+    /// Every level but the last, the block totals are read from the scanned sums.
+    add: Vec<(BindGroup, UVec3)>,
+    compact: Option<(BindGroup, UVec3)>,
+}
+
+impl ScanBindGroups {
+    /// Records the scan and the compaction, false while the pipelines are compiling.
+    ///
+    /// Leaves bind group 0 of `pass` bound to the scan.
+    pub fn dispatch(
+        &self,
+        pass: &mut ComputePass,
+        pipeline_cache: &PipelineCache,
+        pipeline: &ScanPipeline,
+    ) -> bool {
+        let Some(pipelines) = pipeline.pipelines(pipeline_cache) else {
+            return false;
+        };
+        self.record(pass, &pipelines);
+        true
+    }
+
+    /// Records the scan and the compaction with the pipelines of [`ScanPipeline::pipelines`].
+    ///
+    /// Leaves bind group 0 of `pass` bound to the scan.
+    pub fn record(&self, pass: &mut ComputePass, pipelines: &[&ComputePipeline]) {
+        // up the levels, each one leaves its block totals to the next
+        pass.set_pipeline(pipelines[SCAN_BLOCKS]);
+        for (bind_group, workgroups) in &self.scan {
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.dispatch_workgroups(workgroups.x, workgroups.y, workgroups.z);
+        }
+        // and back down, offsetting every block by the blocks before it
+        pass.set_pipeline(pipelines[ADD_BLOCK_OFFSETS]);
+        for (bind_group, workgroups) in self.add.iter().rev() {
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.dispatch_workgroups(workgroups.x, workgroups.y, workgroups.z);
+        }
+
+        pass.set_pipeline(pipelines[WRITE_TOTAL]);
+        pass.set_bind_group(0, &self.scan[0].0, &[]);
+        pass.dispatch_workgroups(1, 1, 1);
+
+        if let Some((bind_group, workgroups)) = &self.compact {
+            pass.set_pipeline(pipelines[COMPACT]);
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.dispatch_workgroups(workgroups.x, workgroups.y, workgroups.z);
+        }
+    }
+}
+
+fn storage_buffer(render_device: &RenderDevice, count: u32) -> Buffer {
+    render_device.create_buffer(&BufferDescriptor {
+        label: Some("scan_block_sums"),
+        size: (size_of::<u32>() * count.max(1) as usize) as u64,
+        usage: BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    })
+}
+
+/// Element count of every level, the last one fits into a single block.
+fn level_counts(count: u32) -> Vec<u32> {
+    let mut counts = vec![count];
+    while let Some(&count) = counts.last().filter(|&&count| count > SCAN_BLOCK_SIZE) {
+        counts.push(count.div_ceil(SCAN_BLOCK_SIZE));
+    }
+    counts
+}
+
+/// Workgroups covering `count` elements, `per_workgroup` each.
+fn workgroups(count: u32, per_workgroup: u32) -> UVec3 {
+    let workgroups = count.div_ceil(per_workgroup);
+    if workgroups <= MAX_WORKGROUPS_PER_DIMENSION {
+        UVec3::new(workgroups, 1, 1)
+    } else {
+        UVec3::new(
+            MAX_WORKGROUPS_PER_DIMENSION,
+            workgroups.div_ceil(MAX_WORKGROUPS_PER_DIMENSION),
+            1,
+        )
+    }
+}
+
+/// Exclusive prefix sum of `values` followed by their total, what [`GpuScan`] writes.
+pub fn exclusive_scan(values: &[u32]) -> Vec<u32> {
+    let mut sum = 0;
+    let mut sums = Vec::with_capacity(values.len() + 1);
+    for &value in values {
+        sums.push(sum);
+        sum += value;
+    }
+    sums.push(sum);
+    sums
+}
+
+/// Indices of the non-zero `values` in order, what [`GpuScan`] compacts from the flags.
+pub fn compact(values: &[u32]) -> Vec<u32> {
+    (0..values.len() as u32)
+        .filter(|&index| values[index as usize] != 0)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{read_buffer, test_device};
+
+    /// `scan_blocks` of one workgroup, the same up and down sweeps over the block.
+    fn scan_block(block: &[u32]) -> (Vec<u32>, u32) {
+        let mut sums = vec![0; SCAN_BLOCK_SIZE as usize];
+        sums[..block.len()].copy_from_slice(block);
+
+        let invocations = 0..SCAN_WORKGROUP_SIZE as usize;
+        let mut offset = 1;
+        let mut pairs = SCAN_BLOCK_SIZE as usize >> 1;
+        while pairs > 0 {
+            for local in invocations.clone().take(pairs) {
+                let left = offset * (2 * local + 1) - 1;
+                let right = offset * (2 * local + 2) - 1;
+                sums[right] += sums[left];
+            }
+            offset <<= 1;
+            pairs >>= 1;
+        }
+
+        let total = sums[SCAN_BLOCK_SIZE as usize - 1];
+        sums[SCAN_BLOCK_SIZE as usize - 1] = 0;
+        let mut pairs = 1;
+        while pairs < SCAN_BLOCK_SIZE as usize {
+            offset >>= 1;
+            for local in invocations.clone().take(pairs) {
+                let left = offset * (2 * local + 1) - 1;
+                let right = offset * (2 * local + 2) - 1;
+                let sum = sums[left];
+                sums[left] = sums[right];
+                sums[right] += sum;
+            }
+            pairs <<= 1;
+        }
+
+        sums.truncate(block.len());
+        (sums, total)
+    }
+
+    /// The dispatches of [`ScanBindGroups::dispatch`] run on the CPU.
+    fn gpu_scan(values: &[u32]) -> (Vec<u32>, Vec<u32>) {
+        let counts = level_counts(values.len() as u32);
+        let mut inputs = vec![values.to_vec()];
+        let mut outputs = Vec::new();
+        for &count in &counts {
+            let input = inputs.last().unwrap();
+            assert_eq!(input.len(), count as usize);
+            let workgroups = workgroups(count, SCAN_BLOCK_SIZE);
+            let mut output = vec![0; count as usize];
+            let mut block_sums = vec![0; count.div_ceil(SCAN_BLOCK_SIZE).max(1) as usize];
+            for block in 0..workgroups.x * workgroups.y {
+                let first = (block * SCAN_BLOCK_SIZE) as usize;
+                if first >= input.len() {
+                    continue;
+                }
+                let last = (first + SCAN_BLOCK_SIZE as usize).min(input.len());
+                let (sums, total) = scan_block(&input[first..last]);
+                output[first..last].copy_from_slice(&sums);
+                block_sums[block as usize] = total;
+            }
+            outputs.push(output);
+            inputs.push(block_sums);
+        }
+
+        for level in (0..counts.len() - 1).rev() {
+            let scanned_sums = outputs[level + 1].clone();
+            for (index, value) in outputs[level].iter_mut().enumerate() {
+                *value += scanned_sums[index / SCAN_BLOCK_SIZE as usize];
+            }
+        }
+
+        let mut output = outputs.swap_remove(0);
+        output.push(match values.last() {
+            Some(&last) => output.last().unwrap() + last,
+            None => 0,
+        });
+
+        let total = *output.last().unwrap() as usize;
+        let mut compacted = vec![u32::MAX; total];
+        for (index, &value) in values.iter().enumerate() {
+            if value != 0 {
+                compacted[output[index] as usize] = index as u32;
+            }
+        }
+        (output, compacted)
+    }
+
+    /// Deterministic values with runs of zeros, like the vertex counts of the cells.
+    fn values(count: usize) -> Vec<u32> {
+        let mut state = 0x9e37_79b9_u32;
+        (0..count)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                if state.is_multiple_of(3) {
+                    state % 16
+                } else {
+                    0
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn levels_end_in_a_single_block() {
+        assert_eq!(level_counts(0), [0]);
+        assert_eq!(level_counts(SCAN_BLOCK_SIZE), [SCAN_BLOCK_SIZE]);
+        assert_eq!(level_counts(SCAN_BLOCK_SIZE + 1), [SCAN_BLOCK_SIZE + 1, 2]);
+        let count = SCAN_BLOCK_SIZE.pow(3) + 1;
+        assert_eq!(
+            level_counts(count),
+            [count, SCAN_BLOCK_SIZE.pow(2) + 1, SCAN_BLOCK_SIZE + 1, 2]
+        );
+    }
+
+    #[test]
+    fn workgroups_wrap_past_the_dispatch_limit() {
+        assert_eq!(workgroups(0, SCAN_BLOCK_SIZE), UVec3::new(0, 1, 1));
+        assert_eq!(workgroups(1, SCAN_BLOCK_SIZE), UVec3::new(1, 1, 1));
+        let count = (MAX_WORKGROUPS_PER_DIMENSION + 1) * SCAN_WORKGROUP_SIZE;
+        let wrapped = workgroups(count, SCAN_WORKGROUP_SIZE);
+        assert_eq!(wrapped, UVec3::new(MAX_WORKGROUPS_PER_DIMENSION, 2, 1));
+        assert!(wrapped.x * wrapped.y * SCAN_WORKGROUP_SIZE >= count);
+    }
+
+    #[test]
+    fn block_scan_is_exclusive() {
+        let block = values(SCAN_BLOCK_SIZE as usize);
+        let (sums, total) = scan_block(&block);
+        let expected = exclusive_scan(&block);
+        assert_eq!(sums, expected[..block.len()]);
+        assert_eq!(total, expected[block.len()]);
+    }
+
+    #[test]
+    fn scan_matches_cpu_for_any_length() {
+        let block = SCAN_BLOCK_SIZE as usize;
+        for count in [
+            0,
+            1,
+            2,
+            block - 1,
+            block,
+            block + 1,
+            3 * block + 7,
+            block * block,
+            block * block + 1,
+            block * block * 2 + 3,
+        ] {
+            let values = values(count);
+            let (output, _) = gpu_scan(&values);
+            assert_eq!(output, exclusive_scan(&values), "scan of {count} values");
+
+            let flags: Vec<u32> = values.iter().map(|&value| (value != 0) as u32).collect();
+            let (_, compacted) = gpu_scan(&flags);
+            assert_eq!(compacted, compact(&values), "compaction of {count} values");
+        }
+    }
+
+    #[test]
+    fn compaction_keeps_order() {
+        let flags = [0, 1, 0, 0, 1, 1, 0];
+        assert_eq!(exclusive_scan(&flags), [0, 0, 1, 1, 1, 2, 3, 3]);
+        assert_eq!(compact(&flags), [1, 4, 5]);
+        assert_eq!(gpu_scan(&flags).1, [1, 4, 5]);
+    }
+
+    #[test]
+    fn shader_matches_the_constants() {
+        let module = naga::front::wgsl::parse_str(include_str!("scan.wgsl")).unwrap();
+        naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::default(),
+        )
+        .validate(&module)
+        .unwrap();
+
+        let entry_points: Vec<_> = module
+            .entry_points
+            .iter()
+            .map(|entry_point| entry_point.name.as_str())
+            .collect();
+        assert_eq!(entry_points, ENTRY_POINTS);
+        for entry_point in &module.entry_points {
+            let expected = match entry_point.name.as_str() {
+                "write_total" => 1,
+                _ => SCAN_WORKGROUP_SIZE,
+            };
+            assert_eq!(entry_point.workgroup_size, [expected, 1, 1]);
+        }
+
+        let constant = |name: &str| {
+            let (_, constant) = module
+                .constants
+                .iter()
+                .find(|(_, constant)| constant.name.as_deref() == Some(name))
+                .unwrap();
+            match module.global_expressions[constant.init] {
+                naga::Expression::Literal(naga::Literal::U32(value)) => value,
+                ref expression => panic!("{name} is {expression:?}"),
+            }
+        };
+        assert_eq!(constant("WORKGROUP_SIZE"), SCAN_WORKGROUP_SIZE);
+        assert_eq!(constant("BLOCK_SIZE"), SCAN_BLOCK_SIZE);
+    }
+
+    /// Runs [`GpuScan`] over `values` on the device, the scan and the compacted indices.
+    fn run_gpu_scan(
+        render_device: &RenderDevice,
+        queue: &wgpu::Queue,
+        pipeline: &ScanPipeline,
+        pipelines: &[ComputePipeline; 4],
+        values: &[u32],
+    ) -> (Vec<u32>, Vec<u32>) {
+        let count = values.len() as u32;
+        let input = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(values),
+            usage: BufferUsages::STORAGE,
+        });
+        let buffer = |count: u32, usage: BufferUsages| {
+            render_device.create_buffer(&BufferDescriptor {
+                label: None,
+                size: (size_of::<u32>() * count.max(1) as usize) as u64,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+        let output = buffer(count + 1, BufferUsages::STORAGE | BufferUsages::COPY_SRC);
+        let compacted = buffer(count, BufferUsages::STORAGE | BufferUsages::COPY_SRC);
+
+        let scan = GpuScan::new(render_device, count);
+        let bind_groups = scan.bind(render_device, pipeline, &input, &output, Some(&compacted));
+        let mut encoder = render_device.create_command_encoder(&Default::default());
+        bind_groups.record(
+            &mut encoder.begin_compute_pass(&Default::default()),
+            &pipelines.each_ref(),
+        );
+        queue.submit([encoder.finish()]);
+
+        let output: Vec<u32> = read_buffer(render_device, queue, &output);
+        let mut compacted = read_buffer(render_device, queue, &compacted);
+        compacted.truncate(*output.last().unwrap() as usize);
+        (output, compacted)
+    }
+
+    #[test]
+    fn gpu_scan_matches_cpu() {
+        let (render_device, queue) = test_device();
+        let (pipeline, pipelines) = ScanPipeline::compile(&render_device);
+
+        // single blocks, several blocks and three levels
+        let block = SCAN_BLOCK_SIZE as usize;
+        for count in [
+            1,
+            block - 1,
+            block,
+            block + 1,
+            3 * block + 7,
+            block * block + 1,
+        ] {
+            let values = values(count);
+            let (output, _) = run_gpu_scan(&render_device, &queue, &pipeline, &pipelines, &values);
+            assert_eq!(output, exclusive_scan(&values), "scan of {count} values");
+
+            let flags: Vec<u32> = values.iter().map(|&value| (value != 0) as u32).collect();
+            let (_, compacted) =
+                run_gpu_scan(&render_device, &queue, &pipeline, &pipelines, &flags);
+            assert_eq!(compacted, compact(&values), "compaction of {count} values");
+        }
+    }
+}
@@ -0,0 +1,133 @@
+// Exclusive prefix sum and stream compaction of u32 storage buffers of any length, dispatched by
+// `GpuScan`. Every workgroup scans a block of `BLOCK_SIZE` elements, the block totals are scanned
+// the same way one level up and added back to their blocks.
+
+// Must match `SCAN_WORKGROUP_SIZE`
+const WORKGROUP_SIZE: u32 = 256u;
+// Must match `SCAN_BLOCK_SIZE`, two elements per invocation
+const BLOCK_SIZE: u32 = 512u;
+
+// Must match the params of every `ScanLevel`, written by `GpuScan::new`
+struct ScanParams {
+    count: u32,
+};
+
+@group(0) @binding(0) var<uniform> params: ScanParams;
+@group(0) @binding(1) var<storage, read> input: array<u32>;
+// `count + 1` elements on the first level, the last one receives the total
+@group(0) @binding(2) var<storage, read_write> output: array<u32>;
+// block totals written by `scan_blocks`, the scanned totals of the level up read by
+// `add_block_offsets` and the compacted indices written by `compact`
+@group(0) @binding(3) var<storage, read_write> blocks: array<u32>;
+
+var<workgroup> partial_sums: array<u32, BLOCK_SIZE>;
+
+// Dispatches wider than the workgroup limit wrap into y
+fn workgroup_index(workgroup_id: vec3<u32>, num_workgroups: vec3<u32>) -> u32 {
+    return workgroup_id.x + workgroup_id.y * num_workgroups.x;
+}
+
+// Blelloch scan of one block in workgroup memory, mirrored by `scan_block` in `scan/mod.rs`
+@compute @workgroup_size(256)
+fn scan_blocks(
+    @builtin(local_invocation_index) local: u32,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>,
+) {
+    let block = workgroup_index(workgroup_id, num_workgroups);
+    let first = block * BLOCK_SIZE;
+    let a = local * 2u;
+    let b = a + 1u;
+    partial_sums[a] = 0u;
+    partial_sums[b] = 0u;
+    if (first + a < params.count) {
+        partial_sums[a] = input[first + a];
+    }
+    if (first + b < params.count) {
+        partial_sums[b] = input[first + b];
+    }
+
+    // up-sweep, every pair sums into its right element
+    var offset = 1u;
+    for (var pairs = BLOCK_SIZE >> 1u; pairs > 0u; pairs >>= 1u) {
+        workgroupBarrier();
+        if (local < pairs) {
+            let left = offset * (a + 1u) - 1u;
+            let right = offset * (a + 2u) - 1u;
+            partial_sums[right] += partial_sums[left];
+        }
+        offset <<= 1u;
+    }
+
+    workgroupBarrier();
+    if (local == 0u) {
+        if (first < params.count) {
+            blocks[block] = partial_sums[BLOCK_SIZE - 1u];
+        }
+        partial_sums[BLOCK_SIZE - 1u] = 0u;
+    }
+
+    // down-sweep, the left element takes the sum before it
+    for (var pairs = 1u; pairs < BLOCK_SIZE; pairs <<= 1u) {
+        offset >>= 1u;
+        workgroupBarrier();
+        if (local < pairs) {
+            let left = offset * (a + 1u) - 1u;
+            let right = offset * (a + 2u) - 1u;
+            let sum = partial_sums[left];
+            partial_sums[left] = partial_sums[right];
+            partial_sums[right] += sum;
+        }
+    }
+
+    workgroupBarrier();
+    if (first + a < params.count) {
+        output[first + a] = partial_sums[a];
+    }
+    if (first + b < params.count) {
+        output[first + b] = partial_sums[b];
+    }
+}
+
+// Offsets every block by the scanned totals of the blocks before it
+@compute @workgroup_size(256)
+fn add_block_offsets(
+    @builtin(local_invocation_index) local: u32,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>,
+) {
+    let block = workgroup_index(workgroup_id, num_workgroups);
+    let first = block * BLOCK_SIZE;
+    if (first >= params.count) {
+        return;
+    }
+    let block_offset = blocks[block];
+    for (var i = local; i < BLOCK_SIZE; i += WORKGROUP_SIZE) {
+        if (first + i < params.count) {
+            output[first + i] += block_offset;
+        }
+    }
+}
+
+// Appends the total after the last exclusive sum
+@compute @workgroup_size(1)
+fn write_total() {
+    if (params.count == 0u) {
+        output[0] = 0u;
+    } else {
+        output[params.count] = output[params.count - 1u] + input[params.count - 1u];
+    }
+}
+
+// Writes the index of every set flag to its scanned offset, keeping their order
+@compute @workgroup_size(256)
+fn compact(
+    @builtin(local_invocation_index) local: u32,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>,
+) {
+    let index = workgroup_index(workgroup_id, num_workgroups) * WORKGROUP_SIZE + local;
+    if (index < params.count && input[index] != 0u) {
+        blocks[output[index]] = index;
+    }
+}
@@ -0,0 +1,131 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use bevy_render::render_resource::{
+    BindGroupLayout, Buffer, BufferDescriptor, BufferUsages, ComputePipeline, Maintain, MapMode,
+    PipelineLayoutDescriptor, RawComputePipelineDescriptor, ShaderModuleDescriptor, ShaderSource,
+};
+use bevy_render::renderer::RenderDevice;
+use naga_oil::compose::{
+    ComposableModuleDescriptor, Composer, NagaModuleDescriptor, ShaderDefValue,
+};
+
+/// The default adapter.
+///
+/// GPU tests fail on machines without one instead of passing untested, a software adapter
+/// such as llvmpipe is enough.
+pub(crate) fn test_adapter() -> wgpu::Adapter {
+    let instance = wgpu::Instance::default();
+    bevy_tasks::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))
+        .expect("GPU tests need an adapter")
+}
+
+/// Device and queue of the [`test_adapter`].
+pub(crate) fn test_device() -> (RenderDevice, wgpu::Queue) {
+    adapter_device(&test_adapter())
+}
+
+/// Device and queue of `adapter` with its limits, like the renderer requests them.
+pub(crate) fn adapter_device(adapter: &wgpu::Adapter) -> (RenderDevice, wgpu::Queue) {
+    let descriptor = wgpu::DeviceDescriptor {
+        required_limits: adapter.limits(),
+        ..Default::default()
+    };
+    let (device, queue) = bevy_tasks::block_on(adapter.request_device(&descriptor, None))
+        .expect("GPU tests need a device");
+    (RenderDevice::from(device), queue)
+}
+
+/// `source` with its `imports`, as the pipeline cache composes it.
+///
+/// Imports are `(source, file_path)` pairs and have to come after the modules they import.
+pub(crate) fn compose(
+    imports: &[(&str, &str)],
+    (source, file_path): (&str, &str),
+    shader_defs: &[(&str, ShaderDefValue)],
+) -> naga::Module {
+    let shader_defs: HashMap<_, _> = shader_defs
+        .iter()
+        .map(|&(name, value)| (name.into(), value))
+        .collect();
+    let mut composer = Composer::non_validating();
+    for &(source, file_path) in imports {
+        let added = composer
+            .add_composable_module(ComposableModuleDescriptor {
+                source,
+                file_path,
+                shader_defs: shader_defs.clone(),
+                ..Default::default()
+            })
+            .map(|_| ());
+        if let Err(err) = added {
+            panic!("{}", err.emit_to_string(&composer));
+        }
+    }
+    composer
+        .make_naga_module(NagaModuleDescriptor {
+            source,
+            file_path,
+            shader_defs,
+            ..Default::default()
+        })
+        .unwrap_or_else(|err| panic!("{}", err.emit_to_string(&composer)))
+}
+
+/// Pipeline of `entry_point` in `module`, with the bind group layouts derived from the shader
+/// without `bind_group_layouts`.
+pub(crate) fn compute_pipeline(
+    render_device: &RenderDevice,
+    module: &naga::Module,
+    entry_point: &str,
+    bind_group_layouts: Option<&[&BindGroupLayout]>,
+) -> ComputePipeline {
+    let device = render_device.wgpu_device();
+    let shader = device.create_shader_module(ShaderModuleDescriptor {
+        label: Some(entry_point),
+        source: ShaderSource::Naga(Cow::Owned(module.clone())),
+    });
+    let layout = bind_group_layouts.map(|bind_group_layouts| {
+        let bind_group_layouts: Vec<_> = bind_group_layouts
+            .iter()
+            .map(|&layout| layout.value())
+            .collect();
+        device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some(entry_point),
+            bind_group_layouts: &bind_group_layouts,
+            push_constant_ranges: &[],
+        })
+    });
+    ComputePipeline::from(
+        device.create_compute_pipeline(&RawComputePipelineDescriptor {
+            label: Some(entry_point),
+            layout: layout.as_ref(),
+            module: &shader,
+            entry_point: Some(entry_point),
+            compilation_options: Default::default(),
+            cache: None,
+        }),
+    )
+}
+
+/// Copies `buffer` back from the device, it needs [`BufferUsages::COPY_SRC`].
+pub(crate) fn read_buffer<T: bytemuck::Pod>(
+    render_device: &RenderDevice,
+    queue: &wgpu::Queue,
+    buffer: &Buffer,
+) -> Vec<T> {
+    let readback = render_device.create_buffer(&BufferDescriptor {
+        label: None,
+        size: buffer.size(),
+        usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let mut encoder = render_device.create_command_encoder(&Default::default());
+    encoder.copy_buffer_to_buffer(buffer, 0, &readback, 0, buffer.size());
+    queue.submit([encoder.finish()]);
+
+    let slice = readback.slice(..);
+    slice.map_async(MapMode::Read, |result| result.unwrap());
+    render_device.poll(Maintain::Wait);
+    bytemuck::pod_collect_to_vec(&slice.get_mapped_range())
+}